@@ -2,11 +2,12 @@ use std::fs;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     fs::create_dir("src/pb").unwrap_or(());
+    // Clients of the chunker, detector and generation services
     tonic_build::configure()
         .build_client(true)
-        .build_server(false) // Only clients needed
+        .build_server(false)
         .out_dir("src/pb")
-        .include_file("mod.rs")
+        .include_file("clients.rs")
         .compile_protos(
             &[
                 "protos/caikit_runtime_Chunkers.proto",
@@ -18,6 +19,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &["protos"],
         )
         .unwrap_or_else(|e| panic!("protobuf compilation failed: {e}"));
+    // Server of the orchestrator service, and its client for tests
+    tonic_build::configure()
+        .build_client(true)
+        .build_server(true)
+        .out_dir("src/pb")
+        .include_file("servers.rs")
+        .compile_protos(&["protos/orchestrator.proto"], &["protos"])
+        .unwrap_or_else(|e| panic!("protobuf compilation failed: {e}"));
+    fs::write(
+        "src/pb/mod.rs",
+        "// This file is @generated by build.rs.\ninclude!(\"clients.rs\");\ninclude!(\"servers.rs\");\n",
+    )?;
 
     Ok(())
 }
@@ -17,22 +17,31 @@
 
 use std::{fmt::Display, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use tracing::{error, warn};
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     #[clap(default_value = "8033", long, env)]
     pub http_port: u16,
     #[clap(default_value = "8034", long, env)]
     pub health_http_port: u16,
+    #[clap(long, env)]
+    pub grpc_port: Option<u16>,
     #[clap(
         default_value = "config/config.yaml",
         long,
-        env = "ORCHESTRATOR_CONFIG"
+        alias = "config",
+        env = "ORCHESTRATOR_CONFIG",
+        global = true
     )]
     pub config_path: PathBuf,
+    /// Reject unknown config keys instead of ignoring them with a warning
+    #[clap(default_value_t = false, long, env, global = true)]
+    pub strict_config: bool,
     #[clap(long, env)]
     pub tls_cert_path: Option<PathBuf>,
     #[clap(long, env)]
@@ -68,6 +77,56 @@ pub struct Args {
     // TODO: Add timeout and header OTLP variables
 }
 
+/// Offline commands, the server is run if no command is given.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run detectors over a JSONL file without starting the server
+    Detect(DetectArgs),
+    /// Validate config, exiting with a non-zero status on failure
+    ValidateConfig(ValidateConfigArgs),
+    /// Print a JSON Schema of the config file
+    ConfigSchema(ConfigSchemaArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DetectArgs {
+    /// Detectors to run, comma-separated
+    #[clap(long, required = true, value_delimiter = ',')]
+    pub detectors: Vec<String>,
+    /// Input JSONL file, `-` to read from stdin
+    #[clap(long)]
+    pub input: PathBuf,
+    /// Output JSONL file
+    #[clap(long)]
+    pub output: PathBuf,
+    /// Field of input records containing the text to run detectors on
+    #[clap(default_value = "content", long)]
+    pub content_field: String,
+    /// Field of input records containing the record ID
+    #[clap(default_value = "id", long)]
+    pub id_field: String,
+    /// Number of records to process concurrently
+    #[clap(default_value = "10", long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub concurrency: u16,
+    /// Number of records between progress reports
+    #[clap(default_value = "1000", long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub progress_interval: u64,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ValidateConfigArgs {
+    /// Probe the health of each configured service
+    #[clap(default_value_t = false, long)]
+    pub probe: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConfigSchemaArgs {
+    /// Output file, the schema is printed to stdout if omitted
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OtlpExport {
     Traces,
@@ -0,0 +1,425 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Offline commands run without starting the server
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{Context, bail};
+use futures::{StreamExt, future, stream};
+use http::HeaderMap;
+use serde_json::Value;
+use tracing::{Instrument, info, info_span};
+
+use crate::{
+    args::{ConfigSchemaArgs, DetectArgs, ValidateConfigArgs},
+    config::{OrchestratorConfig, TlsConfig},
+    health::HealthStatus,
+    models::{
+        BatchContentDetectionResult, BatchItemError, DetectorParams,
+        TextContentDetectionHttpRequest, ValidationError,
+    },
+    orchestrator::{
+        Orchestrator,
+        handlers::{Handle, TextContentDetectionTask},
+    },
+    server,
+    utils::{json::to_nd_string, tls::build_client_config, trace::current_trace_id},
+};
+
+/// Summary of a `detect` run.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DetectSummary {
+    /// Number of records processed
+    pub processed: u64,
+    /// Number of records that failed
+    pub failed: u64,
+}
+
+/// Runs detectors over each record of a JSONL file and writes a result per line.
+///
+/// Records are objects with the text in `content_field` or plain JSON strings.
+/// Results are written in input order, with `index` set to the input line number.
+pub async fn detect(
+    config: OrchestratorConfig,
+    args: DetectArgs,
+) -> Result<DetectSummary, anyhow::Error> {
+    let unknown = args
+        .detectors
+        .iter()
+        .filter(|detector_id| !config.detectors.contains_key(*detector_id))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        bail!("detectors not found in config: {}", unknown.join(", "));
+    }
+    let detectors = args
+        .detectors
+        .iter()
+        .map(|detector_id| (detector_id.clone(), DetectorParams::new()))
+        .collect::<HashMap<_, _>>();
+
+    let input: Box<dyn BufRead + Send> = if args.input.as_os_str() == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = File::open(&args.input)
+            .with_context(|| format!("failed to open input {}", args.input.display()))?;
+        Box::new(BufReader::new(file))
+    };
+    let mut output = BufWriter::new(
+        File::create(&args.output)
+            .with_context(|| format!("failed to create output {}", args.output.display()))?,
+    );
+
+    let orchestrator = Orchestrator::new(config, false).await?;
+    info!(detectors = ?args.detectors, input = ?args.input, "detection started");
+    let started = Instant::now();
+    let mut summary = DetectSummary::default();
+    let mut results = stream::iter(input.lines().enumerate())
+        // Skip blank lines
+        .filter(|(_, line)| {
+            let blank = line.as_ref().is_ok_and(|line| line.trim().is_empty());
+            future::ready(!blank)
+        })
+        .map(|(index, line)| {
+            let orchestrator = &orchestrator;
+            let detectors = detectors.clone();
+            let args = &args;
+            async move {
+                let line = line.with_context(|| format!("failed to read line {}", index + 1))?;
+                Ok::<_, anyhow::Error>(
+                    detect_record(orchestrator, detectors, args, index, &line).await,
+                )
+            }
+            .instrument(info_span!("detect_record", index))
+        })
+        .buffered(args.concurrency.into());
+    while let Some(result) = results.next().await {
+        let result = result?;
+        summary.processed += 1;
+        if result.error.is_some() {
+            summary.failed += 1;
+        }
+        output.write_all(to_nd_string(&result)?.as_bytes())?;
+        if summary.processed % args.progress_interval == 0 {
+            let elapsed = started.elapsed().as_secs_f64();
+            info!(
+                processed = summary.processed,
+                failed = summary.failed,
+                records_per_second = summary.processed as f64 / elapsed,
+                "progress"
+            );
+        }
+    }
+    output.flush()?;
+    info!(
+        processed = summary.processed,
+        failed = summary.failed,
+        elapsed = ?started.elapsed(),
+        output = ?args.output,
+        "detection completed"
+    );
+    Ok(summary)
+}
+
+/// Runs detectors on a single input record.
+async fn detect_record(
+    orchestrator: &Orchestrator,
+    detectors: HashMap<String, DetectorParams>,
+    args: &DetectArgs,
+    index: usize,
+    line: &str,
+) -> BatchContentDetectionResult {
+    let (id, content) = match parse_record(line, &args.content_field, &args.id_field) {
+        Ok(record) => record,
+        Err(error) => return error_result(index, None, error.into()),
+    };
+    let request = TextContentDetectionHttpRequest { content, detectors };
+    if let Err(error) = request.validate() {
+        return error_result(index, id, error.into());
+    }
+    let task = TextContentDetectionTask::new(current_trace_id(), request, HeaderMap::new());
+    match orchestrator.handle(task).await {
+        Ok(result) => BatchContentDetectionResult {
+            index,
+            id,
+            detections: Some(result.detections),
+            error: None,
+        },
+        Err(error) => error_result(index, id, error.into()),
+    }
+}
+
+fn error_result(
+    index: usize,
+    id: Option<String>,
+    error: server::Error,
+) -> BatchContentDetectionResult {
+    BatchContentDetectionResult {
+        index,
+        id,
+        detections: None,
+        error: Some(BatchItemError::from(error)),
+    }
+}
+
+/// Returns the ID and content of an input record.
+fn parse_record(
+    line: &str,
+    content_field: &str,
+    id_field: &str,
+) -> Result<(Option<String>, String), ValidationError> {
+    let value: Value = serde_json::from_str(line)
+        .map_err(|error| ValidationError::Invalid(format!("invalid record: {error}")))?;
+    match value {
+        Value::String(content) => Ok((None, content)),
+        Value::Object(mut record) => {
+            let id = match record.remove(id_field) {
+                Some(Value::String(id)) => Some(id),
+                Some(Value::Null) | None => None,
+                Some(id) => Some(id.to_string()),
+            };
+            match record.remove(content_field) {
+                Some(Value::String(content)) => Ok((id, content)),
+                _ => Err(ValidationError::Invalid(format!(
+                    "record `{content_field}` must be a string"
+                ))),
+            }
+        }
+        _ => Err(ValidationError::Invalid(
+            "record must be an object or string".into(),
+        )),
+    }
+}
+
+/// Level of a config check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckLevel {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Display for CheckLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckLevel::Ok => write!(f, "OK"),
+            CheckLevel::Warning => write!(f, "WARNING"),
+            CheckLevel::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// Report of a `validate-config` run.
+#[derive(Debug, Clone)]
+pub struct ConfigReport {
+    /// Path of the validated config
+    pub path: PathBuf,
+    /// Checks in the order they ran
+    pub checks: Vec<(CheckLevel, String)>,
+}
+
+impl ConfigReport {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            checks: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, level: CheckLevel, message: impl Into<String>) {
+        self.checks.push((level, message.into()));
+    }
+
+    fn count(&self, level: CheckLevel) -> usize {
+        self.checks.iter().filter(|(l, _)| *l == level).count()
+    }
+
+    /// Returns `true` if no check failed.
+    pub fn passed(&self) -> bool {
+        self.count(CheckLevel::Error) == 0
+    }
+}
+
+impl Display for ConfigReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Validating config {}", self.path.display())?;
+        for (level, message) in &self.checks {
+            writeln!(f, "  {:<8} {message}", level.to_string())?;
+        }
+        let warnings = self.count(CheckLevel::Warning);
+        if self.passed() {
+            write!(f, "Config validation passed with {warnings} warning(s)")
+        } else {
+            let errors = self.count(CheckLevel::Error);
+            write!(
+                f,
+                "Config validation failed with {errors} error(s) and {warnings} warning(s)"
+            )
+        }
+    }
+}
+
+/// Loads and validates config, checks TLS files and optionally probes services.
+pub async fn validate_config(path: &Path, strict: bool, args: ValidateConfigArgs) -> ConfigReport {
+    let mut report = ConfigReport::new(path);
+    let config = match OrchestratorConfig::load(path, strict).await {
+        Ok(config) => {
+            report.push(CheckLevel::Ok, "config is valid");
+            config
+        }
+        Err(error) => {
+            report.push(CheckLevel::Error, format!("config is invalid: {error}"));
+            return report;
+        }
+    };
+    if let Some(tls_configs) = &config.tls {
+        let mut names = tls_configs.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            match check_tls_config(&tls_configs[name]).await {
+                Ok(()) => report.push(CheckLevel::Ok, format!("tls config `{name}` is valid")),
+                Err(error) => report.push(
+                    CheckLevel::Error,
+                    format!("tls config `{name}` is invalid: {error}"),
+                ),
+            }
+        }
+    }
+    for warning in config.warnings() {
+        report.push(CheckLevel::Warning, warning);
+    }
+    if args.probe {
+        if !report.passed() {
+            // Clients cannot be created with invalid TLS configs
+            report.push(CheckLevel::Error, "skipped health probe due to errors");
+            return report;
+        }
+        match Orchestrator::new(config, false).await {
+            Ok(orchestrator) => {
+                let health = orchestrator.client_health(true).await;
+                let mut services = health.iter().collect::<Vec<_>>();
+                services.sort_by_key(|(service, _)| *service);
+                for (service, result) in services {
+                    let level = match result.status {
+                        HealthStatus::Healthy => CheckLevel::Ok,
+                        HealthStatus::Unknown => CheckLevel::Warning,
+                        HealthStatus::Unhealthy => CheckLevel::Error,
+                    };
+                    report.push(level, format!("service `{service}` is {result}"));
+                }
+            }
+            Err(error) => report.push(
+                CheckLevel::Error,
+                format!("failed to create clients: {error}"),
+            ),
+        }
+    }
+    report
+}
+
+/// Writes a JSON Schema of the config file.
+pub fn config_schema(args: ConfigSchemaArgs) -> Result<(), anyhow::Error> {
+    let schema = serde_json::to_string_pretty(&OrchestratorConfig::json_schema())?;
+    match &args.output {
+        Some(output) => std::fs::write(output, schema)
+            .with_context(|| format!("failed to write schema to {}", output.display()))?,
+        None => println!("{schema}"),
+    }
+    Ok(())
+}
+
+/// Checks that files of a TLS config exist and can be loaded.
+async fn check_tls_config(tls_config: &TlsConfig) -> Result<(), String> {
+    let Some(cert_path) = &tls_config.cert_path else {
+        return Err("`cert_path` is required".into());
+    };
+    let paths = [
+        Some(cert_path),
+        tls_config.key_path.as_ref(),
+        tls_config.client_ca_cert_path.as_ref(),
+    ];
+    for path in paths.into_iter().flatten() {
+        if !path.is_file() {
+            return Err(format!("file {} not found", path.display()));
+        }
+    }
+    build_client_config(tls_config)
+        .await
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_record() {
+        assert_eq!(
+            parse_record(r#"{"id": "a", "content": "hi"}"#, "content", "id").unwrap(),
+            (Some("a".into()), "hi".into())
+        );
+        assert_eq!(
+            parse_record(r#"{"id": 7, "text": "hi"}"#, "text", "id").unwrap(),
+            (Some("7".into()), "hi".into())
+        );
+        assert_eq!(
+            parse_record(r#""hi""#, "content", "id").unwrap(),
+            (None, "hi".into())
+        );
+        assert!(parse_record(r#"{"id": "a"}"#, "content", "id").is_err());
+        assert!(parse_record("[1, 2]", "content", "id").is_err());
+        assert!(parse_record("not json", "content", "id").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_config() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let resources: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "resources"]
+            .iter()
+            .collect();
+        let tls_config = TlsConfig {
+            cert_path: Some(resources.join("localhost.crt")),
+            key_path: Some(resources.join("localhost.key")),
+            client_ca_cert_path: None,
+            insecure: None,
+        };
+        assert!(check_tls_config(&tls_config).await.is_ok());
+        let tls_config = TlsConfig {
+            cert_path: Some(resources.join("missing.crt")),
+            ..tls_config
+        };
+        assert!(
+            check_tls_config(&tls_config)
+                .await
+                .is_err_and(|error| error.contains("not found"))
+        );
+
+        let args = ValidateConfigArgs { probe: false };
+        let report = validate_config(Path::new("tests/test_config.yaml"), true, args.clone()).await;
+        assert!(report.passed(), "{report}");
+        let report = validate_config(Path::new("tests/missing.yaml"), false, args).await;
+        assert!(!report.passed());
+        assert_eq!(report.checks.len(), 1);
+    }
+}
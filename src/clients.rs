@@ -21,23 +21,36 @@ use std::{
     collections::{HashMap, hash_map},
     fmt::Debug,
     pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
     time::Duration,
 };
 
 use async_trait::async_trait;
 use axum::http::{Extensions, HeaderMap};
-use futures::Stream;
+use futures::{Stream, future::Either};
 use ginepro::{LoadBalancedChannel, ResolutionStrategy};
+use hyper::StatusCode;
 use hyper_timeout::TimeoutConnector;
-use hyper_util::rt::TokioExecutor;
-use tonic::{Request, metadata::MetadataMap};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::ServerName;
+use tokio::{
+    net::TcpStream,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tokio_rustls::TlsConnector;
+use tonic::{
+    Request,
+    metadata::MetadataMap,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+};
 use tower::{ServiceBuilder, timeout::TimeoutLayer};
-use tracing::Span;
+use tracing::{Span, debug};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use url::Url;
 
 use crate::{
-    config::{ServiceConfig, Tls},
+    config::{ServiceConfig, Tls, TlsConfig},
     health::HealthCheckResult,
     utils::{tls, trace::with_traceparent_header},
 };
@@ -74,6 +87,7 @@ const DEFAULT_GRPC_PROBE_INTERVAL_SEC: u64 = 10;
 const DEFAULT_RES_STRATEGY_TIMEOUT_SEC: u64 = 10;
 const DEFAULT_HTTP2_KEEP_ALIVE_INTERVAL: u64 = 30;
 const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 30;
+const DEFAULT_QUEUE_TIMEOUT_MS: u64 = 30_000;
 
 pub type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
 
@@ -133,79 +147,145 @@ impl dyn Client {
 
 /// A map containing different types of clients.
 #[derive(Default)]
-pub struct ClientMap(HashMap<String, Box<dyn Client>>);
+pub struct ClientMap {
+    clients: HashMap<String, Box<dyn Client>>,
+    limits: HashMap<String, ConcurrencyLimit>,
+}
 
 impl ClientMap {
     /// Creates an empty `ClientMap`.
     #[inline]
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self::default()
     }
 
     /// Inserts a client into the map.
     #[inline]
     pub fn insert<V: Client>(&mut self, key: String, value: V) {
-        self.0.insert(key, Box::new(value));
+        self.clients.insert(key, Box::new(value));
     }
 
     /// Returns a reference to the client trait object.
     #[inline]
     pub fn get(&self, key: &str) -> Option<&dyn Client> {
-        self.0.get(key).map(|v| v.as_ref())
+        self.clients.get(key).map(|v| v.as_ref())
     }
 
     /// Returns a mutable reference to the client trait object.
     #[inline]
     pub fn get_mut(&mut self, key: &str) -> Option<&mut dyn Client> {
-        self.0.get_mut(key).map(|v| v.as_mut())
+        self.clients.get_mut(key).map(|v| v.as_mut())
     }
 
     /// Downcasts and returns a reference to the concrete client type.
     #[inline]
     pub fn get_as<V: Client>(&self, key: &str) -> Option<&V> {
-        self.0.get(key)?.downcast_ref::<V>()
+        self.clients.get(key)?.downcast_ref::<V>()
     }
 
     /// Downcasts and returns a mutable reference to the concrete client type.
     #[inline]
     pub fn get_mut_as<V: Client>(&mut self, key: &str) -> Option<&mut V> {
-        self.0.get_mut(key)?.downcast_mut::<V>()
+        self.clients.get_mut(key)?.downcast_mut::<V>()
     }
 
-    /// Removes a client from the map.
+    /// Removes a client and its concurrency limit from the map.
     #[inline]
     pub fn remove(&mut self, key: &str) -> Option<Box<dyn Client>> {
-        self.0.remove(key)
+        self.limits.remove(key);
+        self.clients.remove(key)
+    }
+
+    /// Sets a concurrency limit shared by all requests to a client.
+    #[inline]
+    pub fn set_limit(&mut self, key: String, limit: ConcurrencyLimit) {
+        self.limits.insert(key, limit);
+    }
+
+    /// Returns the concurrency limit of a client.
+    #[inline]
+    pub fn limit(&self, key: &str) -> Option<&ConcurrencyLimit> {
+        self.limits.get(key)
+    }
+
+    /// Waits for an in-flight slot for a client.
+    ///
+    /// Returns `None` if the client's concurrency is unlimited, otherwise a
+    /// permit that releases the slot when dropped.
+    pub async fn acquire(&self, key: &str) -> Result<Option<OwnedSemaphorePermit>, Error> {
+        match self.limits.get(key) {
+            Some(limit) => limit.acquire(key).await.map(Some),
+            None => Ok(None),
+        }
     }
 
     /// An iterator visiting all key-value pairs in arbitrary order.
     #[inline]
     pub fn iter(&self) -> hash_map::Iter<'_, String, Box<dyn Client>> {
-        self.0.iter()
+        self.clients.iter()
     }
 
     /// An iterator visiting all keys in arbitrary order.
     #[inline]
     pub fn keys(&self) -> hash_map::Keys<'_, String, Box<dyn Client>> {
-        self.0.keys()
+        self.clients.keys()
     }
 
     /// An iterator visiting all values in arbitrary order.
     #[inline]
     pub fn values(&self) -> hash_map::Values<'_, String, Box<dyn Client>> {
-        self.0.values()
+        self.clients.values()
     }
 
     /// Returns the number of elements in the map.
     #[inline]
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.clients.len()
     }
 
     /// Returns `true` if the map contains no elements.
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.clients.is_empty()
+    }
+}
+
+/// Limits the number of requests in flight to a client across all tasks.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    max_in_flight: usize,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimit {
+    /// Creates a limit, waiting at most 30 seconds for a slot if `queue_timeout` is not set.
+    pub fn new(max_in_flight: usize, queue_timeout: Option<Duration>) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            queue_timeout: queue_timeout.unwrap_or(Duration::from_millis(DEFAULT_QUEUE_TIMEOUT_MS)),
+        }
+    }
+
+    /// Returns the number of requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.max_in_flight - self.semaphore.available_permits()
+    }
+
+    /// Waits for an in-flight slot, failing if the queue timeout elapses.
+    pub async fn acquire(&self, client_id: &str) -> Result<OwnedSemaphorePermit, Error> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        debug!(%client_id, max_in_flight = self.max_in_flight, "waiting for in-flight slot");
+        let acquire = self.semaphore.clone().acquire_owned();
+        let result = tokio::time::timeout(self.queue_timeout, acquire)
+            .await
+            .map_err(|_| Error::QueueTimeout {
+                client_id: client_id.to_string(),
+            })?;
+        Ok(result.expect("semaphore is never closed"))
     }
 }
 
@@ -262,8 +342,8 @@ pub async fn create_http_client(
 pub async fn create_grpc_client<C: Debug + Clone>(
     default_port: u16,
     service_config: &ServiceConfig,
-    new: fn(OtelGrpcService<LoadBalancedChannel>) -> C,
-) -> C {
+    new: fn(OtelGrpcService<GrpcChannel>) -> C,
+) -> Result<C, Error> {
     let port = service_config.port.unwrap_or(default_port);
     let protocol = match service_config.tls {
         Some(_) => "https",
@@ -293,53 +373,140 @@ pub async fn create_grpc_client<C: Debug + Clone>(
         },
         _ => ResolutionStrategy::Lazy,
     };
-    let mut builder = LoadBalancedChannel::builder((service_config.hostname.clone(), port))
-        .dns_probe_interval(grpc_dns_probe_interval)
-        .connect_timeout(connect_timeout)
-        .timeout(request_timeout)
-        .keep_alive_while_idle(true)
-        .keep_alive_timeout(Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT))
-        .http2_keep_alive_interval(Duration::from_secs(DEFAULT_HTTP2_KEEP_ALIVE_INTERVAL))
-        .resolution_strategy(resolution_strategy);
-    let client_tls_config = if let Some(Tls::Config(tls_config)) = &service_config.tls {
-        let cert_path = tls_config.cert_path.as_ref().unwrap().as_path();
-        let key_path = tls_config.key_path.as_ref().unwrap().as_path();
+    let keep_alive_timeout = Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT);
+    let http2_keep_alive_interval = Duration::from_secs(DEFAULT_HTTP2_KEEP_ALIVE_INTERVAL);
+    let tls_config = match &service_config.tls {
+        Some(Tls::Config(tls_config)) => Some(tls_config),
+        Some(_) => panic!("unexpected unresolved TLS in client builder"),
+        None => None,
+    };
+    let channel = if let Some(tls_config) = tls_config
+        && service_config.grpc_tls_reload.unwrap_or(false)
+    {
+        // The connector always connects over TLS, so the endpoint uses `http` to
+        // prevent tonic from requiring its own TLS config
+        let endpoint = Endpoint::from_shared(format!("http://{}:{port}", service_config.hostname))
+            .map_err(|error| Error::Grpc {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: format!("invalid gRPC endpoint: {error}"),
+            })?
+            .connect_timeout(connect_timeout)
+            .timeout(request_timeout)
+            .keep_alive_while_idle(true)
+            .keep_alive_timeout(keep_alive_timeout)
+            .http2_keep_alive_interval(http2_keep_alive_interval);
+        GrpcChannel::Tls(create_tls_channel(endpoint, tls_config).await?)
+    } else {
+        let mut builder = LoadBalancedChannel::builder((service_config.hostname.clone(), port))
+            .dns_probe_interval(grpc_dns_probe_interval)
+            .connect_timeout(connect_timeout)
+            .timeout(request_timeout)
+            .keep_alive_while_idle(true)
+            .keep_alive_timeout(keep_alive_timeout)
+            .http2_keep_alive_interval(http2_keep_alive_interval)
+            .resolution_strategy(resolution_strategy);
+        if let Some(tls_config) = tls_config {
+            builder = builder.with_tls(create_grpc_tls_config(tls_config).await?);
+        }
+        let channel = builder.channel().await.map_err(|error| Error::Grpc {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!("error creating grpc client: {error}"),
+        })?;
+        GrpcChannel::LoadBalanced(channel)
+    };
+
+    // Adds tower::Service wrapper to allow for enable middleware layers to be added
+    let channel = ServiceBuilder::new().layer(OtelGrpcLayer).service(channel);
+    Ok(new(channel))
+}
+
+/// Creates a load balanced gRPC channel TLS config, TLS files are loaded once.
+async fn create_grpc_tls_config(tls_config: &TlsConfig) -> Result<ClientTlsConfig, Error> {
+    let mut client_tls_config = ClientTlsConfig::new()
+        .with_native_roots()
+        .with_webpki_roots();
+    if let Some(cert_path) = &tls_config.cert_path {
         let cert_pem = tokio::fs::read(cert_path)
             .await
-            .unwrap_or_else(|error| panic!("error reading cert from {cert_path:?}: {error}"));
-        let key_pem = tokio::fs::read(key_path)
+            .map_err(|error| tls::Error::FailedReadCerts(error).into_client_error())?;
+        tls::record_pem_certs(cert_path, &cert_pem, true);
+        let key_pem = match &tls_config.key_path {
+            Some(key_path) => tokio::fs::read(key_path)
+                .await
+                .map_err(|error| tls::Error::FailedReadKey(error).into_client_error())?,
+            // The key may be bundled with the cert
+            None => cert_pem.clone(),
+        };
+        client_tls_config = client_tls_config.identity(Identity::from_pem(cert_pem, key_pem));
+    }
+    if let Some(client_ca_cert_path) = &tls_config.client_ca_cert_path {
+        let client_ca_cert_pem = tokio::fs::read(client_ca_cert_path)
             .await
-            .unwrap_or_else(|error| panic!("error reading key from {key_path:?}: {error}"));
-        let identity = tonic::transport::Identity::from_pem(cert_pem, key_pem);
-        let mut client_tls_config = tonic::transport::ClientTlsConfig::new()
-            .identity(identity)
-            .with_native_roots()
-            .with_webpki_roots();
-        if let Some(client_ca_cert_path) = &tls_config.client_ca_cert_path {
-            let client_ca_cert_pem =
-                tokio::fs::read(client_ca_cert_path)
-                    .await
-                    .unwrap_or_else(|error| {
-                        panic!("error reading client ca cert from {client_ca_cert_path:?}: {error}")
-                    });
-            client_tls_config = client_tls_config
-                .ca_certificate(tonic::transport::Certificate::from_pem(client_ca_cert_pem));
-        }
-        Some(client_tls_config)
-    } else {
-        None
-    };
-    if let Some(client_tls_config) = client_tls_config {
-        builder = builder.with_tls(client_tls_config);
+            .map_err(|error| tls::Error::FailedReadCaCerts(error).into_client_error())?;
+        tls::record_pem_certs(client_ca_cert_path, &client_ca_cert_pem, true);
+        client_tls_config =
+            client_tls_config.ca_certificate(Certificate::from_pem(client_ca_cert_pem));
     }
-    let channel = builder
-        .channel()
+    Ok(client_tls_config)
+}
+
+/// Creates a gRPC channel that connects over TLS without load balancing.
+///
+/// The client cert and CA certs are reloaded when their files change, see
+/// [`tls::ReloadingClientTls`].
+async fn create_tls_channel(endpoint: Endpoint, tls_config: &TlsConfig) -> Result<Channel, Error> {
+    let mut client_config = tls::build_client_config(tls_config)
         .await
-        .unwrap_or_else(|error| panic!("error creating grpc client: {error}"));
+        .map_err(|e| e.into_client_error())?;
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let connector = tower::service_fn(move |uri: ::http::Uri| {
+        let connector = connector.clone();
+        async move {
+            let host = uri.host().ok_or("missing host")?.to_string();
+            let port = uri.port_u16().ok_or("missing port")?;
+            let server_name = ServerName::try_from(host.clone())?;
+            let stream = TcpStream::connect((host, port)).await?;
+            let stream = connector.connect(server_name, stream).await?;
+            Ok::<_, tower::BoxError>(TokioIo::new(stream))
+        }
+    });
+    Ok(endpoint.connect_with_connector_lazy(connector))
+}
 
-    // Adds tower::Service wrapper to allow for enable middleware layers to be added
-    let channel = ServiceBuilder::new().layer(OtelGrpcLayer).service(channel);
-    new(channel)
+/// gRPC channel to a service.
+///
+/// Channels are load balanced across the resolved addresses of the service, unless they
+/// reload TLS files.
+#[derive(Debug, Clone)]
+pub enum GrpcChannel {
+    LoadBalanced(LoadBalancedChannel),
+    Tls(Channel),
+}
+
+type GrpcRequest = ::http::Request<tonic::body::Body>;
+
+impl tower::Service<GrpcRequest> for GrpcChannel {
+    type Response = <Channel as tower::Service<GrpcRequest>>::Response;
+    type Error = <Channel as tower::Service<GrpcRequest>>::Error;
+    type Future = Either<
+        <LoadBalancedChannel as tower::Service<GrpcRequest>>::Future,
+        <Channel as tower::Service<GrpcRequest>>::Future,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            GrpcChannel::LoadBalanced(channel) => channel.poll_ready(cx),
+            GrpcChannel::Tls(channel) => channel.poll_ready(cx),
+        }
+    }
+
+    fn call(&mut self, request: GrpcRequest) -> Self::Future {
+        match self {
+            GrpcChannel::LoadBalanced(channel) => Either::Left(channel.call(request)),
+            GrpcChannel::Tls(channel) => Either::Right(channel.call(request)),
+        }
+    }
 }
 
 /// Returns `true` if hostname is valid according to [IETF RFC 1123](https://tools.ietf.org/html/rfc1123).
@@ -490,4 +657,28 @@ mod tests {
             assert!(!is_valid_hostname(hostname));
         }
     }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let mut clients = ClientMap::new();
+        clients.set_limit(
+            "detector".into(),
+            ConcurrencyLimit::new(1, Some(Duration::from_millis(10))),
+        );
+        // Clients without a limit are not limited
+        assert!(clients.acquire("other").await.unwrap().is_none());
+
+        let permit = clients.acquire("detector").await.unwrap();
+        assert!(permit.is_some());
+        assert_eq!(clients.limit("detector").unwrap().in_flight(), 1);
+        // Queue timeout elapses while the slot is held
+        let error = clients.acquire("detector").await.unwrap_err();
+        assert_eq!(error.status_code(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+
+        // Queued request proceeds once the slot is released
+        let waiter = clients.acquire("detector");
+        drop(permit);
+        assert!(waiter.await.unwrap().is_some());
+        assert_eq!(clients.limit("detector").unwrap().in_flight(), 0);
+    }
 }
@@ -20,12 +20,11 @@ use std::pin::Pin;
 use async_trait::async_trait;
 use axum::http::HeaderMap;
 use futures::{Future, StreamExt, TryStreamExt};
-use ginepro::LoadBalancedChannel;
 use tonic::{Code, Request, Response, Status, Streaming};
 use tracing::Span;
 
 use super::{
-    BoxStream, Client, Error, create_grpc_client, errors::grpc_to_http_code,
+    BoxStream, Client, Error, GrpcChannel, create_grpc_client, errors::grpc_to_http_code,
     grpc_request_with_headers, otel_grpc::OtelGrpcService,
 };
 use crate::{
@@ -52,18 +51,18 @@ type StreamingTokenizationResult =
 
 #[derive(Clone)]
 pub struct ChunkerClient {
-    client: ChunkersServiceClient<OtelGrpcService<LoadBalancedChannel>>,
-    health_client: HealthClient<OtelGrpcService<LoadBalancedChannel>>,
+    client: ChunkersServiceClient<OtelGrpcService<GrpcChannel>>,
+    health_client: HealthClient<OtelGrpcService<GrpcChannel>>,
 }
 
 impl ChunkerClient {
-    pub async fn new(config: &ServiceConfig) -> Self {
-        let client = create_grpc_client(DEFAULT_PORT, config, ChunkersServiceClient::new).await;
-        let health_client = create_grpc_client(DEFAULT_PORT, config, HealthClient::new).await;
-        Self {
+    pub async fn new(config: &ServiceConfig) -> Result<Self, Error> {
+        let client = create_grpc_client(DEFAULT_PORT, config, ChunkersServiceClient::new).await?;
+        let health_client = create_grpc_client(DEFAULT_PORT, config, HealthClient::new).await?;
+        Ok(Self {
             client,
             health_client,
-        }
+        })
     }
 
     pub async fn tokenization_task_predict(
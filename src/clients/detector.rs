@@ -31,10 +31,10 @@ use super::{
 };
 use crate::{
     clients::{
-        Client, HttpClient, create_http_client,
+        Client, ConcurrencyLimit, HttpClient, create_http_client,
         openai::{Message, Tool},
     },
-    config::ServiceConfig,
+    config::{BatchingConfig, ServiceConfig},
     health::HealthCheckResult,
     models::{DetectionResult, DetectorParams, EvidenceObj, Metadata},
 };
@@ -47,10 +47,14 @@ pub const CHAT_DETECTOR_ENDPOINT: &str = "/api/v1/text/chat";
 pub const CONTEXT_DOC_DETECTOR_ENDPOINT: &str = "/api/v1/text/context/doc";
 pub const GENERATION_DETECTOR_ENDPOINT: &str = "/api/v1/text/generation";
 
+mod batcher;
+use batcher::TextContentsBatcher;
+
 #[derive(Clone)]
 pub struct DetectorClient {
     client: HttpClient,
     health_client: Option<HttpClient>,
+    batcher: Option<TextContentsBatcher>,
 }
 
 impl DetectorClient {
@@ -67,9 +71,33 @@ impl DetectorClient {
         Ok(Self {
             client,
             health_client,
+            batcher: None,
         })
     }
 
+    /// Enables batching of text contents requests across tasks.
+    ///
+    /// If `limit` is set, it applies to batched requests rather than callers.
+    pub fn with_batching(
+        mut self,
+        model_id: String,
+        config: &BatchingConfig,
+        limit: Option<ConcurrencyLimit>,
+    ) -> Self {
+        self.batcher = Some(TextContentsBatcher::new(
+            self.clone(),
+            model_id,
+            config,
+            limit,
+        ));
+        self
+    }
+
+    /// Returns `true` if text contents requests are batched.
+    pub fn is_batching(&self) -> bool {
+        self.batcher.is_some()
+    }
+
     async fn post<U: ResponseBody>(
         &self,
         model_id: &str,
@@ -103,6 +131,18 @@ impl DetectorClient {
         model_id: &str,
         request: ContentAnalysisRequest,
         headers: HeaderMap,
+    ) -> Result<Vec<Vec<ContentAnalysisResponse>>, Error> {
+        match &self.batcher {
+            Some(batcher) => batcher.text_contents(request, headers).await,
+            None => self.send_text_contents(model_id, request, headers).await,
+        }
+    }
+
+    async fn send_text_contents(
+        &self,
+        model_id: &str,
+        request: ContentAnalysisRequest,
+        headers: HeaderMap,
     ) -> Result<Vec<Vec<ContentAnalysisResponse>>, Error> {
         let url = self.client.endpoint(CONTENTS_DETECTOR_ENDPOINT);
         info!("sending text content detector request to {}", url);
@@ -0,0 +1,338 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Micro-batching of text contents detector requests
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use hyper::StatusCode;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Instant, sleep_until},
+};
+use tracing::{Instrument, Span, debug, info, info_span};
+
+use super::{ContentAnalysisRequest, ContentAnalysisResponse, DetectorClient};
+use crate::{
+    clients::{ConcurrencyLimit, Error},
+    config::BatchingConfig,
+    models::DetectorParams,
+};
+
+type BatchResult = Result<Vec<Vec<ContentAnalysisResponse>>, Error>;
+
+/// Coalesces text contents requests with identical parameters and headers
+/// from concurrent tasks into a single detector request.
+#[derive(Clone)]
+pub struct TextContentsBatcher {
+    tx: mpsc::Sender<BatchItem>,
+}
+
+impl TextContentsBatcher {
+    /// Spawns a batcher task sending batched requests with `client`.
+    ///
+    /// If `limit` is set, an in-flight slot is acquired for each batched request.
+    pub fn new(
+        client: DetectorClient,
+        model_id: String,
+        config: &BatchingConfig,
+        limit: Option<ConcurrencyLimit>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(1024);
+        let max_wait = Duration::from_millis(config.max_wait_ms);
+        let max_size = config.max_size;
+        tokio::spawn(
+            run_batcher(client, model_id, max_wait, max_size, limit, rx).in_current_span(),
+        );
+        Self { tx }
+    }
+
+    /// Sends a request to be batched and waits for its results.
+    pub async fn text_contents(
+        &self,
+        request: ContentAnalysisRequest,
+        headers: HeaderMap,
+    ) -> BatchResult {
+        let (response_tx, response_rx) = oneshot::channel();
+        let item = BatchItem {
+            request,
+            headers,
+            response_tx,
+            span: Span::current(),
+        };
+        if self.tx.send(item).await.is_err() {
+            return Err(batcher_closed());
+        }
+        response_rx.await.unwrap_or_else(|_| Err(batcher_closed()))
+    }
+}
+
+struct BatchItem {
+    request: ContentAnalysisRequest,
+    headers: HeaderMap,
+    response_tx: oneshot::Sender<BatchResult>,
+    /// Span of the requesting task
+    span: Span,
+}
+
+/// Pending requests with identical parameters and headers.
+struct Batch {
+    params: DetectorParams,
+    headers: HeaderMap,
+    items: Vec<BatchItem>,
+    size: usize,
+    deadline: Instant,
+}
+
+impl Batch {
+    fn new(item: BatchItem, max_wait: Duration) -> Self {
+        Self {
+            params: item.request.detector_params.clone(),
+            headers: item.headers.clone(),
+            size: item.request.contents.len(),
+            items: vec![item],
+            deadline: Instant::now() + max_wait,
+        }
+    }
+
+    fn accepts(&self, item: &BatchItem) -> bool {
+        self.params == item.request.detector_params && self.headers == item.headers
+    }
+
+    fn push(&mut self, item: BatchItem) {
+        self.size += item.request.contents.len();
+        self.items.push(item);
+    }
+}
+
+async fn run_batcher(
+    client: DetectorClient,
+    model_id: String,
+    max_wait: Duration,
+    max_size: usize,
+    limit: Option<ConcurrencyLimit>,
+    mut rx: mpsc::Receiver<BatchItem>,
+) {
+    let mut batches: Vec<Batch> = Vec::new();
+    loop {
+        let next_deadline = batches.iter().map(|batch| batch.deadline).min();
+        tokio::select! {
+            item = rx.recv() => match item {
+                Some(item) => {
+                    // Add to a batch with identical params and headers
+                    let index = match batches.iter().position(|batch| batch.accepts(&item)) {
+                        Some(index) => {
+                            batches[index].push(item);
+                            index
+                        }
+                        None => {
+                            batches.push(Batch::new(item, max_wait));
+                            batches.len() - 1
+                        }
+                    };
+                    if batches[index].size >= max_size {
+                        let batch = batches.swap_remove(index);
+                        send_batch(&client, &model_id, limit.clone(), batch);
+                    }
+                }
+                None => {
+                    // All senders dropped, send pending batches and exit
+                    for batch in batches.drain(..) {
+                        send_batch(&client, &model_id, limit.clone(), batch);
+                    }
+                    info!(%model_id, "batcher closed");
+                    return;
+                }
+            },
+            _ = sleep_until(next_deadline.unwrap_or_else(Instant::now)),
+                if next_deadline.is_some() =>
+            {
+                // Send batches that have waited long enough
+                let now = Instant::now();
+                let (ready, pending): (Vec<_>, Vec<_>) =
+                    batches.into_iter().partition(|batch| batch.deadline <= now);
+                batches = pending;
+                for batch in ready {
+                    send_batch(&client, &model_id, limit.clone(), batch);
+                }
+            }
+        }
+    }
+}
+
+/// Spawns a task to send a batched request and split the results.
+///
+/// The request is sent in a child span of the first request's span, linked to the
+/// spans of the other requests.
+fn send_batch(
+    client: &DetectorClient,
+    model_id: &str,
+    limit: Option<ConcurrencyLimit>,
+    batch: Batch,
+) {
+    let client = client.clone();
+    let model_id = model_id.to_string();
+    let span = info_span!(
+        parent: &batch.items[0].span,
+        "batched_text_contents",
+        %model_id,
+        requests = batch.items.len()
+    );
+    for item in &batch.items[1..] {
+        span.follows_from(&item.span);
+    }
+    tokio::spawn(
+        async move {
+            let mut contents = Vec::with_capacity(batch.size);
+            let mut waiters = Vec::with_capacity(batch.items.len());
+            for item in batch.items {
+                waiters.push((item.request.contents.len(), item.response_tx));
+                contents.extend(item.request.contents);
+            }
+            debug!(
+                %model_id,
+                requests = waiters.len(),
+                contents = contents.len(),
+                "sending batched request"
+            );
+            let request = ContentAnalysisRequest::new(contents, batch.params);
+            match send(&client, &model_id, limit.as_ref(), request, batch.headers).await {
+                Ok(mut responses) if responses.len() == batch.size => {
+                    // Split results back to each request in order
+                    for (len, response_tx) in waiters {
+                        let rest = responses.split_off(len);
+                        let _ = response_tx.send(Ok(responses));
+                        responses = rest;
+                    }
+                }
+                Ok(responses) => {
+                    let error = Error::Http {
+                        code: StatusCode::INTERNAL_SERVER_ERROR,
+                        message: format!(
+                            "detector returned {} results for {} contents",
+                            responses.len(),
+                            batch.size
+                        ),
+                    };
+                    for (_, response_tx) in waiters {
+                        let _ = response_tx.send(Err(error.clone()));
+                    }
+                }
+                Err(error) => {
+                    for (_, response_tx) in waiters {
+                        let _ = response_tx.send(Err(error.clone()));
+                    }
+                }
+            }
+        }
+        .instrument(span),
+    );
+}
+
+async fn send(
+    client: &DetectorClient,
+    model_id: &str,
+    limit: Option<&ConcurrencyLimit>,
+    request: ContentAnalysisRequest,
+    headers: HeaderMap,
+) -> BatchResult {
+    let _permit = match limit {
+        Some(limit) => Some(limit.acquire(model_id).await?),
+        None => None,
+    };
+    client.send_text_contents(model_id, request, headers).await
+}
+
+fn batcher_closed() -> Error {
+    Error::Http {
+        code: StatusCode::INTERNAL_SERVER_ERROR,
+        message: "detector batcher closed".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mocktail::prelude::*;
+
+    use super::*;
+    use crate::{config::ServiceConfig, models::Metadata};
+
+    fn response(text: &str) -> Vec<ContentAnalysisResponse> {
+        vec![ContentAnalysisResponse {
+            start: 0,
+            end: text.len(),
+            text: text.into(),
+            detection: "has_text".into(),
+            detection_type: "fake".into(),
+            detector_id: None,
+            score: 1.0,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_text_contents_batcher() -> Result<(), anyhow::Error> {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let mut mocks = MockSet::new();
+        mocks.mock(|when, then| {
+            when.post()
+                .path("/api/v1/text/contents")
+                .json(ContentAnalysisRequest::new(
+                    vec!["a".into(), "b".into(), "c".into()],
+                    DetectorParams::default(),
+                ));
+            then.json(vec![response("a"), response("b"), response("c")]);
+        });
+        let server = MockServer::new_http("batched_detector").with_mocks(mocks);
+        server.start().await?;
+
+        let service = ServiceConfig {
+            hostname: "localhost".into(),
+            port: Some(server.addr().unwrap().port()),
+            ..Default::default()
+        };
+        let config = BatchingConfig {
+            max_wait_ms: 50,
+            max_size: 32,
+        };
+        let client = DetectorClient::new(&service, None).await?.with_batching(
+            "batched_detector".into(),
+            &config,
+            None,
+        );
+
+        // Concurrent requests with identical params are sent as a single request
+        let (first, second) = tokio::join!(
+            client.text_contents(
+                "batched_detector",
+                ContentAnalysisRequest::new(vec!["a".into()], DetectorParams::default()),
+                HeaderMap::new(),
+            ),
+            client.text_contents(
+                "batched_detector",
+                ContentAnalysisRequest::new(
+                    vec!["b".into(), "c".into()],
+                    DetectorParams::default()
+                ),
+                HeaderMap::new(),
+            ),
+        );
+        assert_eq!(first?, vec![response("a")]);
+        assert_eq!(second?, vec![response("b"), response("c")]);
+        Ok(())
+    }
+}
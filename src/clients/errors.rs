@@ -27,6 +27,8 @@ pub enum Error {
     Http { code: StatusCode, message: String },
     #[error("model not found: {model_id}")]
     ModelNotFound { model_id: String },
+    #[error("timed out waiting for capacity on `{client_id}`")]
+    QueueTimeout { client_id: String },
 }
 
 impl Error {
@@ -40,6 +42,8 @@ impl Error {
             Error::Http { code, .. } => *code,
             // Return 404 for model not found
             Error::ModelNotFound { .. } => StatusCode::NOT_FOUND,
+            // Return 503 when a client has no capacity
+            Error::QueueTimeout { .. } => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
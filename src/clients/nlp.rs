@@ -18,12 +18,11 @@
 use async_trait::async_trait;
 use axum::http::HeaderMap;
 use futures::{StreamExt, TryStreamExt};
-use ginepro::LoadBalancedChannel;
 use tonic::{Code, Request};
 use tracing::{Span, debug, instrument};
 
 use super::{
-    BoxStream, Client, Error, create_grpc_client, errors::grpc_to_http_code,
+    BoxStream, Client, Error, GrpcChannel, create_grpc_client, errors::grpc_to_http_code,
     grpc_request_with_headers, otel_grpc::OtelGrpcService,
 };
 use crate::{
@@ -49,18 +48,18 @@ const MODEL_ID_HEADER_NAME: &str = "mm-model-id";
 
 #[derive(Clone)]
 pub struct NlpClient {
-    client: NlpServiceClient<OtelGrpcService<LoadBalancedChannel>>,
-    health_client: HealthClient<OtelGrpcService<LoadBalancedChannel>>,
+    client: NlpServiceClient<OtelGrpcService<GrpcChannel>>,
+    health_client: HealthClient<OtelGrpcService<GrpcChannel>>,
 }
 
 impl NlpClient {
-    pub async fn new(config: &ServiceConfig) -> Self {
-        let client = create_grpc_client(DEFAULT_PORT, config, NlpServiceClient::new).await;
-        let health_client = create_grpc_client(DEFAULT_PORT, config, HealthClient::new).await;
-        Self {
+    pub async fn new(config: &ServiceConfig) -> Result<Self, Error> {
+        let client = create_grpc_client(DEFAULT_PORT, config, NlpServiceClient::new).await?;
+        let health_client = create_grpc_client(DEFAULT_PORT, config, HealthClient::new).await?;
+        Ok(Self {
             client,
             health_client,
-        }
+        })
     }
 
     #[instrument(skip_all, fields(model_id))]
@@ -22,6 +22,7 @@ use eventsource_stream::Eventsource;
 use futures::StreamExt;
 use http_body_util::BodyExt;
 use hyper::{HeaderMap, StatusCode};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{Map, Value};
 use tokio::sync::mpsc;
@@ -33,9 +34,9 @@ use super::{
     http::{HttpClientExt, RequestBody},
 };
 use crate::{
-    config::ServiceConfig,
+    config::{MessageSelection, ServiceConfig},
     health::HealthCheckResult,
-    models::{DetectionWarningReason, DetectorParams, ValidationError},
+    models::{DetectionWarningReason, DetectorParams, MESSAGES_PARAM, ValidationError},
     orchestrator,
 };
 
@@ -285,6 +286,12 @@ pub struct ChatCompletionsRequest {
     /// Detector config.
     #[serde(default, skip_serializing)]
     pub detectors: DetectorConfig,
+    /// Output streaming mode.
+    #[serde(default, skip_serializing)]
+    pub stream_mode: StreamMode,
+    /// Session ID to track conversation risk across requests.
+    #[serde(default, skip_serializing)]
+    pub session_id: Option<String>,
     /// Stream parameter.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
@@ -307,6 +314,27 @@ impl ChatCompletionsRequest {
                 "`messages` must not be empty".into(),
             ));
         }
+        if self.session_id.as_ref().is_some_and(|id| id.is_empty()) {
+            return Err(ValidationError::Invalid(
+                "`session_id` must not be empty".into(),
+            ));
+        }
+
+        if let Some(detector_id) = self
+            .detectors
+            .input
+            .iter()
+            .find_map(|(detector_id, params)| {
+                params
+                    .get(MESSAGES_PARAM)
+                    .is_some_and(|v| MessageSelection::deserialize(v).is_err())
+                    .then_some(detector_id)
+            })
+        {
+            return Err(ValidationError::Invalid(format!(
+                "invalid `{MESSAGES_PARAM}` parameter for detector `{detector_id}`: expected `last`, `all_user`, `all` or `{{\"roles\": [...]}}`"
+            )));
+        }
 
         if !self.detectors.input.is_empty() {
             // Content of type Array is not supported yet
@@ -317,8 +345,8 @@ impl ChatCompletionsRequest {
                 ));
             }
 
-            // As text_content detections only run on last message at the moment, only the last
-            // message is being validated.
+            // Input detectors are applied to the last message by default, so only the last
+            // message is validated. Other selected messages without text content are skipped.
             if self.messages.last().unwrap().is_text_content_empty() {
                 return Err(ValidationError::Invalid(
                     "if input detectors are provided, `content` must not be empty on last message"
@@ -329,6 +357,14 @@ impl ChatCompletionsRequest {
 
         Ok(())
     }
+
+    /// Returns `true` if `response_format` requests JSON output.
+    pub fn json_output(&self) -> bool {
+        self.extra
+            .get("response_format")
+            .and_then(|v| ResponseFormat::deserialize(v).ok())
+            .is_some_and(|format| matches!(format.r#type.as_str(), "json_object" | "json_schema"))
+    }
 }
 
 /// Completions (legacy) request.
@@ -419,6 +455,19 @@ pub struct DetectorConfig {
     pub output: HashMap<String, DetectorParams>,
 }
 
+/// Output streaming mode.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+    /// Chunks are released with their output detections.
+    #[default]
+    Default,
+    /// Chunks are released only if they have no output detections.
+    /// The first chunk with detections is replaced with a refusal
+    /// message and the stream is terminated.
+    Gated,
+}
+
 /// Response format.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseFormat {
@@ -488,7 +537,7 @@ pub struct StreamOptions {
 }
 
 /// Role.
-#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     #[default]
@@ -987,6 +1036,12 @@ pub struct CompletionInputDetections {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CompletionOutputDetections {
     pub choice_index: u32,
+    /// JSON Pointer to the string value the detections apply to, set on JSON output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Character offset of the string value in the content, set on JSON output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
     #[serde(default)]
     pub results: Vec<ContentAnalysisResponse>,
 }
@@ -1005,6 +1060,10 @@ impl CompletionDetectionWarning {
             message: message.to_string(),
         }
     }
+
+    pub fn reason(&self) -> &DetectionWarningReason {
+        &self.r#type
+    }
 }
 
 #[cfg(test)]
@@ -1037,6 +1096,8 @@ mod test {
             request,
             ChatCompletionsRequest {
                 detectors,
+                stream_mode: StreamMode::Default,
+                session_id: None,
                 stream: None,
                 model: "test".into(),
                 messages: messages.clone(),
@@ -1054,6 +1115,8 @@ mod test {
             request,
             ChatCompletionsRequest {
                 detectors: DetectorConfig::default(),
+                stream_mode: StreamMode::Default,
+                session_id: None,
                 stream: None,
                 model: "test".into(),
                 messages: messages.clone(),
@@ -18,12 +18,11 @@
 use async_trait::async_trait;
 use axum::http::HeaderMap;
 use futures::{StreamExt, TryStreamExt};
-use ginepro::LoadBalancedChannel;
 use tonic::Code;
 use tracing::Span;
 
 use super::{
-    BoxStream, Client, Error, create_grpc_client, errors::grpc_to_http_code,
+    BoxStream, Client, Error, GrpcChannel, create_grpc_client, errors::grpc_to_http_code,
     grpc_request_with_headers, otel_grpc::OtelGrpcService,
 };
 use crate::{
@@ -41,13 +40,13 @@ const DEFAULT_PORT: u16 = 8033;
 
 #[derive(Clone)]
 pub struct TgisClient {
-    client: GenerationServiceClient<OtelGrpcService<LoadBalancedChannel>>,
+    client: GenerationServiceClient<OtelGrpcService<GrpcChannel>>,
 }
 
 impl TgisClient {
-    pub async fn new(config: &ServiceConfig) -> Self {
-        let client = create_grpc_client(DEFAULT_PORT, config, GenerationServiceClient::new).await;
-        Self { client }
+    pub async fn new(config: &ServiceConfig) -> Result<Self, Error> {
+        let client = create_grpc_client(DEFAULT_PORT, config, GenerationServiceClient::new).await?;
+        Ok(Self { client })
     }
 
     pub async fn generate(
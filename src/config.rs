@@ -20,12 +20,13 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use serde::Deserialize;
+use schemars::{JsonSchema, Schema};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    clients::{chunker::DEFAULT_CHUNKER_ID, is_valid_hostname},
-    utils::one_or_many,
+    clients::{chunker::DEFAULT_CHUNKER_ID, is_valid_hostname, openai::Role},
+    utils::{one_or_many, one_or_many_schema},
 };
 
 /// Default allowed headers to passthrough to clients.
@@ -39,13 +40,87 @@ const fn default_detector_concurrent_requests() -> usize {
 const fn default_chunker_concurrent_requests() -> usize {
     5
 }
+/// Default number of batch detection items to process concurrently for a request.
+const fn default_batch_concurrent_items() -> usize {
+    10
+}
+/// Default number of asynchronous jobs to run concurrently.
+const fn default_job_workers() -> usize {
+    2
+}
+/// Default maximum number of asynchronous jobs to keep.
+const fn default_max_jobs() -> usize {
+    1000
+}
+/// Default maximum number of sessions to keep.
+const fn default_max_sessions() -> usize {
+    10000
+}
+/// Default maximum number of messages to keep per chat detection session.
+const fn default_max_session_messages() -> usize {
+    100
+}
+/// Default time in seconds after which an inactive session is removed.
+const fn default_session_ttl() -> u64 {
+    3600
+}
+/// Default factor by which cumulative conversation risk scores decay on each turn.
+const fn default_risk_decay() -> f64 {
+    0.8
+}
+/// Default cumulative conversation risk score at which a conversation is blocked.
+const fn default_risk_limit() -> f64 {
+    2.0
+}
+/// Default number of days before certificate expiry at which warnings are logged.
+const fn default_cert_expiry_warning_days() -> u64 {
+    30
+}
+/// Default header to read API keys from.
+fn default_api_key_header() -> String {
+    "x-api-key".into()
+}
+/// Default allowed clock skew in seconds when validating JWT time claims.
+const fn default_jwt_leeway() -> u64 {
+    60
+}
+/// Default maximum time in milliseconds to wait for detector requests to coalesce.
+const fn default_batch_max_wait_ms() -> u64 {
+    5
+}
+/// Default maximum number of contents in a batched detector request.
+const fn default_batch_max_size() -> usize {
+    32
+}
+/// Default header to read tenants from when rate limiting by tenant.
+fn default_tenant_header() -> String {
+    "x-tenant-id".into()
+}
+/// Default routes subject to the streaming rate limit budget. Batch routes and routes that
+/// only stream with `stream: true` use the unary budget.
+fn default_streaming_routes() -> Vec<String> {
+    [
+        "/api/v1/task/server-streaming-classification-with-text-generation",
+        "/api/v2/text/generation-detection/stream",
+        "/api/v2/text/detection/stream-content",
+        "/api/v2/text/detection/stream-content/ws",
+        "/orchestrator.GuardrailsOrchestrator/StreamDetectTextContent",
+    ]
+    .into_iter()
+    .map(Into::into)
+    .collect()
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("failed to read config from `{path}`: {error}")]
     FailedToReadConfigFile { path: String, error: std::io::Error },
-    #[error("invalid config file: {0}")]
-    InvalidConfigFile(serde_yml::Error),
+    #[error("invalid config file: {}", config_error_message(.path, .error))]
+    InvalidConfigFile {
+        /// Path of the invalid field
+        path: String,
+        error: serde_yml::Error,
+    },
     #[error("tls config `{name}` not found for service `{host}:{port}`")]
     TlsConfigNotFound {
         name: String,
@@ -54,6 +129,8 @@ pub enum Error {
     },
     #[error("no detectors configured")]
     NoDetectorsConfigured,
+    #[error("unknown config keys: {}", .0.join(", "))]
+    UnknownConfigKeys(Vec<String>),
     #[error("chunker `{chunker_id}` not found for detector `{detector_id}`")]
     DetectorChunkerNotFound {
         detector_id: String,
@@ -63,11 +140,26 @@ pub enum Error {
     InvalidGenerationProvider(String),
     #[error("invalid hostname: {0}")]
     InvalidHostname(String),
+    #[error("invalid audit config: {0}")]
+    InvalidAuditConfig(String),
+    #[error("invalid auth config: {0}")]
+    InvalidAuthConfig(String),
+    #[error("invalid rate limit config: {0}")]
+    InvalidRateLimitConfig(String),
+    #[error("invalid concurrency limit: {0}")]
+    InvalidConcurrencyLimit(String),
+    #[error("invalid batching config: {0}")]
+    InvalidBatchingConfig(String),
+    #[error("invalid jobs config: {0}")]
+    InvalidJobsConfig(String),
+    #[error("invalid sessions config: {0}")]
+    InvalidSessionsConfig(String),
 }
 
 /// Configuration for service needed for
 /// orchestrator to communicate with it
-#[derive(Default, Clone, Debug, Deserialize)]
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ServiceConfig {
     /// Hostname for service
     pub hostname: String,
@@ -85,6 +177,9 @@ pub struct ServiceConfig {
     pub resolution_strategy_timeout: Option<u64>,
     /// Max retries for client calls [currently only for grpc generation]
     pub max_retries: Option<usize>,
+    /// Reload gRPC client TLS files when they change. The service is then connected to
+    /// without DNS load balancing, ignoring the gRPC probe interval and resolution strategy
+    pub grpc_tls_reload: Option<bool>,
 }
 
 impl ServiceConfig {
@@ -98,12 +193,13 @@ impl ServiceConfig {
             resolution_strategy: None,
             resolution_strategy_timeout: None,
             max_retries: None,
+            grpc_tls_reload: None,
         }
     }
 }
 
 /// TLS provider
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum Tls {
     Name(String),
@@ -111,7 +207,8 @@ pub enum Tls {
 }
 
 /// Client TLS configuration
-#[derive(Default, Clone, Debug, Deserialize)]
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct TlsConfig {
     pub cert_path: Option<PathBuf>,
     pub key_path: Option<PathBuf>,
@@ -120,7 +217,7 @@ pub struct TlsConfig {
 }
 
 /// Generation service provider
-#[derive(Default, Clone, Copy, Debug, Deserialize)]
+#[derive(Default, Clone, Copy, Debug, Deserialize, JsonSchema)]
 pub enum GenerationProvider {
     #[default]
     #[serde(rename = "tgis")]
@@ -130,7 +227,8 @@ pub enum GenerationProvider {
 }
 
 /// Generation service configuration
-#[derive(Default, Clone, Debug, Deserialize)]
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct GenerationConfig {
     /// Generation service provider
     pub provider: GenerationProvider,
@@ -139,16 +237,19 @@ pub struct GenerationConfig {
 }
 
 /// OpenAI service configuration
-#[derive(Default, Clone, Debug, Deserialize)]
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct OpenAiConfig {
     /// Generation service connection information
     pub service: ServiceConfig,
     /// Generation health service connection information
     pub health_service: Option<ServiceConfig>,
+    /// Message replacing output with detections in gated streaming mode
+    pub refusal_message: Option<String>,
 }
 
 /// Chunker parser type
-#[derive(Default, Clone, Copy, Debug, Deserialize)]
+#[derive(Default, Clone, Copy, Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ChunkerType {
     #[default]
@@ -157,16 +258,22 @@ pub enum ChunkerType {
 }
 
 /// Configuration for each chunker
-#[derive(Default, Clone, Debug, Deserialize)]
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ChunkerConfig {
     /// Chunker type
     pub r#type: ChunkerType,
     /// Chunker service connection information
     pub service: ServiceConfig,
+    /// Maximum number of requests in flight to this chunker across all tasks, unlimited if omitted
+    pub max_in_flight: Option<usize>,
+    /// Time in milliseconds a request may wait for an in-flight slot, 30000 if omitted
+    pub queue_timeout_ms: Option<u64>,
 }
 
 /// Configuration for each detector
-#[derive(Default, Clone, Debug, Deserialize)]
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct DetectorConfig {
     /// Detector service connection information
     pub service: ServiceConfig,
@@ -178,10 +285,72 @@ pub struct DetectorConfig {
     pub default_threshold: f64,
     /// Type of detection this detector performs
     #[serde(rename = "type", deserialize_with = "one_or_many")]
+    #[schemars(schema_with = "one_or_many_schema::<DetectorType>")]
     pub r#type: Vec<DetectorType>,
+    /// Maximum number of requests in flight to this detector across all tasks, unlimited if omitted
+    pub max_in_flight: Option<usize>,
+    /// Time in milliseconds a request may wait for an in-flight slot, 30000 if omitted
+    pub queue_timeout_ms: Option<u64>,
+    /// Batching of text contents requests across tasks, disabled if omitted
+    pub batching: Option<BatchingConfig>,
+    /// Chat messages to apply the detector to as a chat completions input detector
+    #[serde(default)]
+    pub input_messages: MessageSelection,
+    /// Whether to also apply the detector to system messages as a chat completions input detector.
+    /// Detections are cached by system prompt.
+    #[serde(default)]
+    pub input_system_prompts: bool,
+}
+
+/// Chat messages that an input detector is applied to
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageSelection {
+    /// The last message
+    #[default]
+    Last,
+    /// All user messages
+    AllUser,
+    /// All messages
+    All,
+    /// All messages of the given roles
+    Roles(Vec<Role>),
+}
+
+impl MessageSelection {
+    /// Returns `true` if a message with the given role is selected.
+    pub fn selects(&self, role: Option<&Role>, is_last: bool) -> bool {
+        match self {
+            MessageSelection::Last => is_last,
+            MessageSelection::AllUser => role == Some(&Role::User),
+            MessageSelection::All => true,
+            MessageSelection::Roles(roles) => role.is_some_and(|role| roles.contains(role)),
+        }
+    }
+}
+
+/// Detector request batching configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct BatchingConfig {
+    /// Maximum time in milliseconds to wait for requests to coalesce
+    #[serde(default = "default_batch_max_wait_ms")]
+    pub max_wait_ms: u64,
+    /// Maximum number of contents in a batch, requests are sent once reached
+    #[serde(default = "default_batch_max_size")]
+    pub max_size: usize,
 }
 
-#[derive(Default, Clone, Debug, Deserialize, PartialEq)]
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        Self {
+            max_wait_ms: default_batch_max_wait_ms(),
+            max_size: default_batch_max_size(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum DetectorType {
@@ -192,8 +361,249 @@ pub enum DetectorType {
     TextContextDoc,
 }
 
+/// How detected text is written to audit records
+#[derive(Default, Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditTextMode {
+    /// Detected text is recorded as-is
+    Plain,
+    /// Detected text is replaced with its SHA-256 digest
+    #[default]
+    Hash,
+    /// Detected text is omitted
+    Redact,
+}
+
+/// Audit sink configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(deny_unknown_fields)]
+pub enum AuditSinkConfig {
+    /// Appends records to a JSON Lines file
+    File { path: PathBuf },
+    /// Writes records to stdout, one per line
+    Stdout,
+    /// Sends records to an HTTP endpoint as JSON `POST` requests
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        /// Timeout in seconds for request to be handled
+        request_timeout: Option<u64>,
+    },
+}
+
+/// Audit log configuration
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// How detected text is recorded
+    #[serde(default)]
+    pub text: AuditTextMode,
+    /// Sinks to write audit records to
+    pub sinks: Vec<AuditSinkConfig>,
+}
+
+/// JWT bearer token validation configuration
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct JwtConfig {
+    /// Path to JWKS file with keys used to verify token signatures
+    pub jwks_path: PathBuf,
+    /// Expected `iss` claim
+    pub issuer: Option<String>,
+    /// Expected `aud` claim
+    pub audience: Option<String>,
+    /// Allowed clock skew in seconds for `exp` and `nbf` claims
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway: u64,
+    /// Claim to read the tenant from
+    pub tenant_claim: Option<String>,
+    /// Scopes that tokens must be granted, from the `scope` or `scp` claim
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+}
+
+/// Guardrails server authentication configuration
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Path to API keys file
+    pub api_keys_path: Option<PathBuf>,
+    /// Header to read API keys from, API keys are also accepted as bearer tokens
+    #[serde(default = "default_api_key_header")]
+    pub api_key_header: String,
+    /// JWT bearer token validation
+    pub jwt: Option<JwtConfig>,
+}
+
+/// Source of the key that rate limits are tracked by
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(deny_unknown_fields)]
+pub enum RateLimitKey {
+    /// Authenticated principal, requires `auth`
+    #[default]
+    ApiKey,
+    /// Authenticated principal's tenant, or its subject if it has no tenant. The tenant
+    /// header is only used for unauthenticated requests.
+    Tenant {
+        #[serde(default = "default_tenant_header")]
+        header: String,
+    },
+    /// Passthrough header, e.g. a user or client id set by a gateway
+    Header { header: String },
+}
+
+/// Rate limit budget applied per key
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RateLimitBudget {
+    /// Sustained requests per second
+    pub requests_per_second: f64,
+    /// Maximum number of requests allowed in a burst, defaults to `requests_per_second` rounded up
+    pub burst: Option<u32>,
+    /// Maximum number of requests in flight, for streaming routes this is the number of open streams
+    pub max_concurrent: Option<usize>,
+}
+
+/// Guardrails server rate limiting configuration
+#[derive(Default, Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Key to track rate limits by
+    #[serde(default)]
+    pub key: RateLimitKey,
+    /// Budget for unary routes
+    pub unary: Option<RateLimitBudget>,
+    /// Budget for streaming routes
+    pub streaming: Option<RateLimitBudget>,
+    /// Routes subject to the streaming budget
+    #[serde(default = "default_streaming_routes")]
+    pub streaming_routes: Vec<String>,
+}
+
+/// Asynchronous job configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Number of jobs to run concurrently
+    #[serde(default = "default_job_workers")]
+    pub workers: usize,
+    /// Directory to persist jobs and results to, jobs are only kept in memory if omitted
+    pub path: Option<PathBuf>,
+    /// Maximum number of jobs to keep, the oldest finished jobs are removed first
+    #[serde(default = "default_max_jobs")]
+    pub max_jobs: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            workers: default_job_workers(),
+            path: None,
+            max_jobs: default_max_jobs(),
+        }
+    }
+}
+
+/// Conversation session configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// Maximum number of sessions to keep, the least recently used sessions are removed first
+    #[serde(default = "default_max_sessions")]
+    pub max_sessions: usize,
+    /// Maximum number of messages to keep per session, the oldest messages are removed first
+    #[serde(default = "default_max_session_messages")]
+    pub max_messages: usize,
+    /// Time in seconds after which an inactive session is removed
+    #[serde(default = "default_session_ttl")]
+    pub ttl: u64,
+    /// Conversation risk tracking for chat completions, disabled if omitted
+    pub risk: Option<ConversationRiskConfig>,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: default_max_sessions(),
+            max_messages: default_max_session_messages(),
+            ttl: default_session_ttl(),
+            risk: None,
+        }
+    }
+}
+
+/// TLS certificate expiry monitoring configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct CertExpiryConfig {
+    /// Number of days before expiry at which warnings are logged
+    #[serde(default = "default_cert_expiry_warning_days")]
+    pub warning_days: u64,
+    /// Whether `/health` returns 503 when a required certificate has expired, i.e. a
+    /// server certificate or a certificate of a TLS config referenced by a service
+    #[serde(default)]
+    pub unhealthy_on_expiry: bool,
+}
+
+impl Default for CertExpiryConfig {
+    fn default() -> Self {
+        Self {
+            warning_days: default_cert_expiry_warning_days(),
+            unhealthy_on_expiry: false,
+        }
+    }
+}
+
+/// Conversation risk tracking configuration
+///
+/// On each turn, the cumulative score of each input detector is multiplied by `decay`
+/// and the highest score of the detector on the turn is added. A conversation is
+/// blocked once a cumulative score reaches its limit.
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ConversationRiskConfig {
+    /// Factor by which cumulative scores decay on each turn, between 0 and 1
+    #[serde(default = "default_risk_decay")]
+    pub decay: f64,
+    /// Cumulative score at which a conversation is blocked
+    #[serde(default = "default_risk_limit")]
+    pub limit: f64,
+    /// Limits per detector, overriding `limit`
+    #[serde(default)]
+    pub detector_limits: HashMap<String, f64>,
+    /// Whether requests must have a `session_id`, so that risk tracking cannot be
+    /// bypassed by omitting it
+    #[serde(default)]
+    pub require_session_id: bool,
+}
+
+impl Default for ConversationRiskConfig {
+    fn default() -> Self {
+        Self {
+            decay: default_risk_decay(),
+            limit: default_risk_limit(),
+            detector_limits: HashMap::default(),
+            require_session_id: false,
+        }
+    }
+}
+
+impl ConversationRiskConfig {
+    /// Gets the cumulative score limit of a detector.
+    pub fn limit(&self, detector_id: &str) -> f64 {
+        self.detector_limits
+            .get(detector_id)
+            .copied()
+            .unwrap_or(self.limit)
+    }
+}
+
 /// Overall orchestrator server configuration
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct OrchestratorConfig {
     /// Generation service and associated configuration, can be omitted if configuring for generation is not wanted
     pub generation: Option<GenerationConfig>,
@@ -217,11 +627,33 @@ pub struct OrchestratorConfig {
     /// Number of chunker requests to send concurrently for a task.
     #[serde(default = "default_chunker_concurrent_requests")]
     pub chunker_concurrent_requests: usize,
+    /// Number of batch detection items to process concurrently for a request.
+    #[serde(default = "default_batch_concurrent_items")]
+    pub batch_concurrent_items: usize,
+    /// Audit log configuration, can be omitted if auditing is not wanted
+    pub audit: Option<AuditConfig>,
+    /// Guardrails server authentication, can be omitted to allow unauthenticated requests
+    pub auth: Option<AuthConfig>,
+    /// Guardrails server rate limiting, can be omitted if rate limiting is not wanted
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Asynchronous job API, can be omitted if jobs are not wanted
+    pub jobs: Option<JobsConfig>,
+    /// Conversation sessions, can be omitted if sessions are not wanted
+    pub sessions: Option<SessionsConfig>,
+    /// TLS certificate expiry monitoring
+    #[serde(default)]
+    pub cert_expiry: CertExpiryConfig,
+    /// Names of TLS configs referenced by services, recorded when they are applied
+    #[serde(skip)]
+    tls_refs: HashSet<String>,
+    /// Paths of unknown keys that were ignored
+    #[serde(skip)]
+    unknown_keys: Vec<String>,
 }
 
 impl OrchestratorConfig {
-    /// Loads config
-    pub async fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+    /// Loads config, rejecting unknown keys if `strict` is set and warning on them otherwise.
+    pub async fn load(path: impl AsRef<Path>, strict: bool) -> Result<Self, Error> {
         let path = path.as_ref();
         let config_yaml = tokio::fs::read_to_string(path).await.map_err(|error| {
             Error::FailedToReadConfigFile {
@@ -240,8 +672,7 @@ impl OrchestratorConfig {
                 "`chat_completions` is deprecated and will be removed in 1.0. Rename it to `openai`."
             )
         }
-        let mut config: OrchestratorConfig =
-            serde_yml::from_str(&config_yaml).map_err(Error::InvalidConfigFile)?;
+        let mut config = parse_config(&config_yaml, strict)?;
         debug!(?config, "loaded orchestrator config");
 
         if config.generation.is_none() {
@@ -274,35 +705,88 @@ impl OrchestratorConfig {
 
         config.apply_named_tls_configs()?;
         config.validate()?;
+        for warning in config.warnings() {
+            warn!("{warning}");
+        }
 
         Ok(config)
     }
 
+    /// Returns a JSON Schema of the config file.
+    pub fn json_schema() -> Schema {
+        schemars::schema_for!(OrchestratorConfig)
+    }
+
     /// Applies named TLS configs to services.
     fn apply_named_tls_configs(&mut self) -> Result<(), Error> {
         if let Some(tls_configs) = &self.tls {
+            let tls_refs = &mut self.tls_refs;
             // Generation
             if let Some(generation) = &mut self.generation {
-                apply_named_tls_config(&mut generation.service, tls_configs)?;
+                apply_named_tls_config(&mut generation.service, tls_configs, tls_refs)?;
             }
             // Open AI
             if let Some(openai) = &mut self.openai {
-                apply_named_tls_config(&mut openai.service, tls_configs)?;
+                apply_named_tls_config(&mut openai.service, tls_configs, tls_refs)?;
             }
             // Chunkers
             if let Some(chunkers) = &mut self.chunkers {
                 for chunker in chunkers.values_mut() {
-                    apply_named_tls_config(&mut chunker.service, tls_configs)?;
+                    apply_named_tls_config(&mut chunker.service, tls_configs, tls_refs)?;
                 }
             }
             // Detectors
             for detector in self.detectors.values_mut() {
-                apply_named_tls_config(&mut detector.service, tls_configs)?;
+                apply_named_tls_config(&mut detector.service, tls_configs, tls_refs)?;
             }
         }
         Ok(())
     }
 
+    /// Returns `true` if a named TLS config is referenced by a service.
+    pub fn is_tls_config_used(&self, name: &str) -> bool {
+        self.tls_refs.contains(name)
+    }
+
+    /// Returns warnings for config that is valid but likely unintended.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self
+            .unknown_keys
+            .iter()
+            .map(|key| format!("unknown config key `{key}` is ignored"))
+            .collect::<Vec<_>>();
+        if let Some(chunkers) = &self.chunkers {
+            let mut unused = chunkers
+                .keys()
+                .filter(|chunker_id| {
+                    !self
+                        .detectors
+                        .values()
+                        .any(|detector| &detector.chunker_id == *chunker_id)
+                })
+                .collect::<Vec<_>>();
+            unused.sort();
+            warnings.extend(
+                unused.into_iter().map(|chunker_id| {
+                    format!("chunker `{chunker_id}` is not used by any detector")
+                }),
+            );
+        }
+        if let Some(tls_configs) = &self.tls {
+            let mut unused = tls_configs
+                .keys()
+                .filter(|name| !self.tls_refs.contains(*name))
+                .collect::<Vec<_>>();
+            unused.sort();
+            warnings.extend(
+                unused
+                    .into_iter()
+                    .map(|name| format!("tls config `{name}` is not used by any service")),
+            );
+        }
+        warnings
+    }
+
     fn validate(&self) -> Result<(), Error> {
         // Detectors are configured
         if self.detectors.is_empty() {
@@ -314,6 +798,11 @@ impl OrchestratorConfig {
         self.validate_openai_configs()?;
         self.validate_detector_configs()?;
         self.validate_chunker_configs()?;
+        self.validate_audit_config()?;
+        self.validate_auth_config()?;
+        self.validate_rate_limit_config()?;
+        self.validate_jobs_config()?;
+        self.validate_sessions_config()?;
 
         Ok(())
     }
@@ -366,6 +855,25 @@ impl OrchestratorConfig {
                     chunker_id: detector.chunker_id.clone(),
                 });
             }
+            // Concurrency limit is valid
+            if detector.max_in_flight == Some(0) {
+                return Err(Error::InvalidConcurrencyLimit(format!(
+                    "detector `{detector_id}` has a `max_in_flight` of 0"
+                )));
+            }
+            // Batching is valid
+            if let Some(batching) = &detector.batching {
+                if batching.max_size == 0 {
+                    return Err(Error::InvalidBatchingConfig(format!(
+                        "detector `{detector_id}` has a `max_size` of 0"
+                    )));
+                }
+                if !detector.r#type.contains(&DetectorType::TextContents) {
+                    return Err(Error::InvalidBatchingConfig(format!(
+                        "detector `{detector_id}` is not a `text_contents` detector"
+                    )));
+                }
+            }
         }
         Ok(())
     }
@@ -380,6 +888,162 @@ impl OrchestratorConfig {
                         "chunker `{chunker_id}` has an invalid hostname"
                     )));
                 }
+                // Concurrency limit is valid
+                if chunker.max_in_flight == Some(0) {
+                    return Err(Error::InvalidConcurrencyLimit(format!(
+                        "chunker `{chunker_id}` has a `max_in_flight` of 0"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates audit config.
+    fn validate_audit_config(&self) -> Result<(), Error> {
+        if let Some(audit) = &self.audit {
+            if audit.sinks.is_empty() {
+                return Err(Error::InvalidAuditConfig(
+                    "`audit` requires at least one sink".into(),
+                ));
+            }
+            for sink in &audit.sinks {
+                match sink {
+                    AuditSinkConfig::File { path } if path.as_os_str().is_empty() => {
+                        return Err(Error::InvalidAuditConfig(
+                            "file sink has an empty path".into(),
+                        ));
+                    }
+                    AuditSinkConfig::Webhook { url, .. } => {
+                        let valid_url = url::Url::parse(url)
+                            .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+                        if !valid_url {
+                            return Err(Error::InvalidAuditConfig(format!(
+                                "webhook sink has an invalid url: {url}"
+                            )));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates auth config.
+    fn validate_auth_config(&self) -> Result<(), Error> {
+        if let Some(auth) = &self.auth {
+            if auth.api_keys_path.is_none() && auth.jwt.is_none() {
+                return Err(Error::InvalidAuthConfig(
+                    "`auth` requires `api_keys_path` and/or `jwt`".into(),
+                ));
+            }
+            if http::HeaderName::from_bytes(auth.api_key_header.as_bytes()).is_err() {
+                return Err(Error::InvalidAuthConfig(format!(
+                    "invalid `api_key_header`: {}",
+                    auth.api_key_header
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates rate limit config.
+    fn validate_rate_limit_config(&self) -> Result<(), Error> {
+        if let Some(rate_limit) = &self.rate_limit {
+            if rate_limit.unary.is_none() && rate_limit.streaming.is_none() {
+                return Err(Error::InvalidRateLimitConfig(
+                    "`rate_limit` requires `unary` and/or `streaming`".into(),
+                ));
+            }
+            for (name, budget) in [
+                ("unary", &rate_limit.unary),
+                ("streaming", &rate_limit.streaming),
+            ] {
+                if let Some(budget) = budget {
+                    if !(budget.requests_per_second.is_finite() && budget.requests_per_second > 0.0)
+                    {
+                        return Err(Error::InvalidRateLimitConfig(format!(
+                            "`{name}.requests_per_second` must be greater than 0"
+                        )));
+                    }
+                    if budget.burst == Some(0) || budget.max_concurrent == Some(0) {
+                        return Err(Error::InvalidRateLimitConfig(format!(
+                            "`{name}.burst` and `{name}.max_concurrent` must be greater than 0"
+                        )));
+                    }
+                }
+            }
+            match &rate_limit.key {
+                RateLimitKey::ApiKey if self.auth.is_none() => {
+                    return Err(Error::InvalidRateLimitConfig(
+                        "`api_key` key requires `auth` to be configured".into(),
+                    ));
+                }
+                RateLimitKey::Tenant { header } | RateLimitKey::Header { header }
+                    if http::HeaderName::from_bytes(header.as_bytes()).is_err() =>
+                {
+                    return Err(Error::InvalidRateLimitConfig(format!(
+                        "invalid key header: {header}"
+                    )));
+                }
+                RateLimitKey::Header { header }
+                    if !self.passthrough_headers.contains(&header.to_lowercase()) =>
+                {
+                    return Err(Error::InvalidRateLimitConfig(format!(
+                        "key header `{header}` must be in `passthrough_headers`"
+                    )));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates jobs config.
+    fn validate_jobs_config(&self) -> Result<(), Error> {
+        if let Some(jobs) = &self.jobs {
+            if jobs.workers == 0 || jobs.max_jobs == 0 {
+                return Err(Error::InvalidJobsConfig(
+                    "`workers` and `max_jobs` must be greater than 0".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates sessions config.
+    fn validate_sessions_config(&self) -> Result<(), Error> {
+        if let Some(sessions) = &self.sessions
+            && (sessions.max_sessions == 0 || sessions.max_messages == 0 || sessions.ttl == 0)
+        {
+            return Err(Error::InvalidSessionsConfig(
+                "`max_sessions`, `max_messages` and `ttl` must be greater than 0".into(),
+            ));
+        }
+        if let Some(risk) = self
+            .sessions
+            .as_ref()
+            .and_then(|sessions| sessions.risk.as_ref())
+        {
+            if !(0.0..=1.0).contains(&risk.decay) {
+                return Err(Error::InvalidSessionsConfig(
+                    "risk `decay` must be between 0 and 1".into(),
+                ));
+            }
+            if risk.limit <= 0.0 || risk.detector_limits.values().any(|limit| *limit <= 0.0) {
+                return Err(Error::InvalidSessionsConfig(
+                    "risk limits must be greater than 0".into(),
+                ));
+            }
+            if let Some(detector_id) = risk
+                .detector_limits
+                .keys()
+                .find(|detector_id| !self.detectors.contains_key(*detector_id))
+            {
+                return Err(Error::InvalidSessionsConfig(format!(
+                    "risk limit configured for unknown detector `{detector_id}`"
+                )));
             }
         }
         Ok(())
@@ -418,16 +1082,65 @@ impl Default for OrchestratorConfig {
             passthrough_headers: HashSet::default(),
             detector_concurrent_requests: default_detector_concurrent_requests(),
             chunker_concurrent_requests: default_chunker_concurrent_requests(),
+            batch_concurrent_items: default_batch_concurrent_items(),
+            audit: None,
+            auth: None,
+            rate_limit: None,
+            jobs: None,
+            sessions: None,
+            cert_expiry: CertExpiryConfig::default(),
+            tls_refs: HashSet::default(),
+            unknown_keys: Vec::new(),
         }
     }
 }
 
+/// Deserializes config, tracking the path of invalid fields.
+///
+/// Unknown keys are ignored and recorded, or rejected if `strict` is set.
+fn parse_config(config_yaml: &str, strict: bool) -> Result<OrchestratorConfig, Error> {
+    let mut unknown_keys = Vec::new();
+    let mut record_unknown_key = |path: serde_ignored::Path| {
+        // Optional values are shown as `?` segments, which are omitted
+        unknown_keys.push(path.to_string().replace("?.", ""))
+    };
+    let deserializer = serde_ignored::Deserializer::new(
+        serde_yml::Deserializer::from_str(config_yaml),
+        &mut record_unknown_key,
+    );
+    let mut config: OrchestratorConfig =
+        serde_path_to_error::deserialize(deserializer).map_err(|error| {
+            Error::InvalidConfigFile {
+                path: error.path().to_string(),
+                error: error.into_inner(),
+            }
+        })?;
+    if strict && !unknown_keys.is_empty() {
+        return Err(Error::UnknownConfigKeys(unknown_keys));
+    }
+    config.unknown_keys = unknown_keys;
+    Ok(config)
+}
+
+/// Formats a config deserialization error with the path of the invalid field.
+fn config_error_message(path: &str, error: &serde_yml::Error) -> String {
+    let message = error.to_string();
+    // Errors with a known location already include the path
+    if path == "." || message.starts_with(path) {
+        message
+    } else {
+        format!("{path}: {message}")
+    }
+}
+
 /// Applies named TLS config to a service.
 fn apply_named_tls_config(
     service: &mut ServiceConfig,
     tls_configs: &HashMap<String, TlsConfig>,
+    tls_refs: &mut HashSet<String>,
 ) -> Result<(), Error> {
     if let Some(Tls::Name(name)) = &service.tls {
+        tls_refs.insert(name.clone());
         let tls_config = tls_configs
             .get(name)
             .ok_or(Error::TlsConfigNotFound {
@@ -641,6 +1354,89 @@ tls: {}
         assert!(matches!(error, Error::NoDetectorsConfigured))
     }
 
+    #[test]
+    fn test_parse_config_unknown_field() {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_treshold: 0.5
+        "#;
+        // Unknown keys are ignored with a warning by default
+        let config = parse_config(s, false).unwrap();
+        assert_eq!(
+            config.warnings(),
+            ["unknown config key `detectors.hap.default_treshold` is ignored"]
+        );
+
+        let error = parse_config(s, true).expect_err("Unknown field should have been rejected");
+        assert_eq!(
+            error.to_string(),
+            "unknown config keys: detectors.hap.default_treshold"
+        );
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = serde_json::to_value(OrchestratorConfig::json_schema()).unwrap();
+        assert_eq!(schema["additionalProperties"], false);
+        assert!(
+            schema["required"]
+                .as_array()
+                .unwrap()
+                .contains(&"detectors".into())
+        );
+        assert!(schema["properties"]["detectors"].is_object());
+        // Internal fields are not part of the schema
+        assert!(schema["properties"].get("tls_refs").is_none());
+    }
+
+    #[test]
+    fn test_config_warnings() {
+        let s = r#"
+chunkers:
+    sentence-en:
+        type: sentence
+        service:
+            hostname: localhost
+            port: 9000
+    sentence-ja:
+        type: sentence
+        service:
+            hostname: localhost
+            port: 9000
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+            tls: detector
+        chunker_id: sentence-en
+        default_threshold: 0.5
+tls:
+    detector:
+        cert_path: /certs/client.pem
+    unused:
+        cert_path: /certs/client.pem
+        "#;
+        let mut config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        config
+            .apply_named_tls_configs()
+            .expect("Apply named TLS configs should have succeeded");
+        assert_eq!(
+            config.warnings(),
+            vec![
+                "chunker `sentence-ja` is not used by any detector".to_string(),
+                "tls config `unused` is not used by any service".to_string(),
+            ]
+        );
+    }
+
     #[test]
     fn test_deserialize_config_tls_not_found() {
         let s = r#"
@@ -787,4 +1583,251 @@ passthrough_headers:
         );
         Ok(())
     }
+
+    #[test]
+    fn test_deserialize_config_audit() -> Result<(), Error> {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+audit:
+    text: redact
+    sinks:
+        - type: file
+          path: /var/log/orchestrator/audit.jsonl
+        - type: stdout
+        - type: webhook
+          url: https://audit.example.com/records
+          headers:
+              authorization: Bearer token
+        "#;
+        let config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        config.validate()?;
+        let audit = config.audit.unwrap();
+        assert_eq!(audit.text, AuditTextMode::Redact);
+        assert_eq!(audit.sinks.len(), 3);
+        assert!(matches!(
+            &audit.sinks[2],
+            AuditSinkConfig::Webhook { headers, request_timeout: None, .. } if headers.len() == 1
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_config_audit_invalid_webhook_url() {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+audit:
+    sinks:
+        - type: webhook
+          url: audit.example.com
+        "#;
+        let config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        assert_eq!(config.audit.as_ref().unwrap().text, AuditTextMode::Hash);
+        let error = config
+            .validate()
+            .expect_err("Config should not have been validated");
+        assert!(matches!(error, Error::InvalidAuditConfig(_)));
+    }
+
+    #[test]
+    fn test_deserialize_config_auth() -> Result<(), Error> {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+auth:
+    jwt:
+        jwks_path: /certs/jwks.json
+        audience: orchestrator
+        "#;
+        let mut config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        config.validate()?;
+        let auth = config.auth.as_ref().unwrap();
+        assert_eq!(auth.api_key_header, "x-api-key");
+        let jwt = auth.jwt.as_ref().unwrap();
+        assert_eq!(jwt.leeway, 60);
+        assert!(jwt.required_scopes.is_empty());
+
+        config.auth.as_mut().unwrap().jwt = None;
+        let error = config
+            .validate()
+            .expect_err("Config should not have been validated");
+        assert!(matches!(error, Error::InvalidAuthConfig(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_config_rate_limit() -> Result<(), Error> {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+passthrough_headers:
+    - x-client-id
+rate_limit:
+    key:
+        type: header
+        header: x-client-id
+    unary:
+        requests_per_second: 10
+        burst: 20
+    streaming:
+        requests_per_second: 1
+        max_concurrent: 4
+        "#;
+        let mut config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        config.validate()?;
+        let rate_limit = config.rate_limit.as_ref().unwrap();
+        assert_eq!(
+            rate_limit.key,
+            RateLimitKey::Header {
+                header: "x-client-id".into()
+            }
+        );
+        assert_eq!(rate_limit.streaming_routes, default_streaming_routes());
+        assert_eq!(
+            rate_limit.streaming.as_ref().unwrap().max_concurrent,
+            Some(4)
+        );
+
+        // Key header must be a passthrough header
+        config.passthrough_headers.clear();
+        let error = config
+            .validate()
+            .expect_err("Config should not have been validated");
+        assert!(matches!(error, Error::InvalidRateLimitConfig(_)));
+
+        // API key requires auth
+        config.rate_limit.as_mut().unwrap().key = RateLimitKey::ApiKey;
+        let error = config
+            .validate()
+            .expect_err("Config should not have been validated");
+        assert!(matches!(error, Error::InvalidRateLimitConfig(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_config_sessions() -> Result<(), Error> {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+sessions:
+    max_messages: 20
+    risk:
+        detector_limits:
+            hap: 1.5
+        "#;
+        let mut config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        config.validate()?;
+        let sessions = config.sessions.as_ref().unwrap();
+        assert_eq!(sessions.max_sessions, default_max_sessions());
+        assert_eq!(sessions.max_messages, 20);
+        let risk = sessions.risk.as_ref().unwrap();
+        assert_eq!(risk.decay, default_risk_decay());
+        assert_eq!(risk.limit("hap"), 1.5);
+        assert_eq!(risk.limit("other"), default_risk_limit());
+
+        // Decay must be between 0 and 1
+        config
+            .sessions
+            .as_mut()
+            .unwrap()
+            .risk
+            .as_mut()
+            .unwrap()
+            .decay = 1.5;
+        let error = config
+            .validate()
+            .expect_err("Config should not have been validated");
+        assert!(matches!(error, Error::InvalidSessionsConfig(_)));
+
+        // Limits must be configured for known detectors
+        let risk = config.sessions.as_mut().unwrap().risk.as_mut().unwrap();
+        risk.decay = 0.5;
+        risk.detector_limits.insert("unknown".into(), 1.0);
+        let error = config
+            .validate()
+            .expect_err("Config should not have been validated");
+        assert!(matches!(error, Error::InvalidSessionsConfig(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_config_input_messages() -> Result<(), Error> {
+        let s = r#"
+detectors:
+    hap:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9000
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+    pii:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9001
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+        input_messages: all_user
+    jailbreak:
+        type: text_contents
+        service:
+            hostname: localhost
+            port: 9002
+        chunker_id: whole_doc_chunker
+        default_threshold: 0.5
+        input_messages:
+            roles: [user, system]
+        "#;
+        let config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        assert_eq!(config.cert_expiry.warning_days, 30);
+        assert!(!config.cert_expiry.unhealthy_on_expiry);
+        assert_eq!(
+            config.detectors["hap"].input_messages,
+            MessageSelection::Last
+        );
+        assert_eq!(
+            config.detectors["pii"].input_messages,
+            MessageSelection::AllUser
+        );
+        let selection = &config.detectors["jailbreak"].input_messages;
+        assert_eq!(
+            selection,
+            &MessageSelection::Roles(vec![Role::User, Role::System])
+        );
+        assert!(selection.selects(Some(&Role::System), false));
+        assert!(!selection.selects(Some(&Role::Assistant), true));
+        Ok(())
+    }
 }
@@ -18,6 +18,7 @@
 #![allow(clippy::iter_kv_map, clippy::enum_variant_names, async_fn_in_trait)]
 
 pub mod args;
+pub mod cli;
 pub mod clients;
 pub mod config;
 pub mod health;
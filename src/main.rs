@@ -19,7 +19,11 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use clap::Parser;
 use fms_guardrails_orchestr8::{
-    args::Args, config::OrchestratorConfig, orchestrator::Orchestrator, server, utils,
+    args::{Args, Command},
+    cli,
+    config::OrchestratorConfig,
+    orchestrator::Orchestrator,
+    server, utils,
 };
 use tracing::info;
 
@@ -40,6 +44,9 @@ fn main() -> Result<(), anyhow::Error> {
         SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), args.http_port);
     let health_http_addr: SocketAddr =
         SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), args.health_http_port);
+    let grpc_addr: Option<SocketAddr> = args
+        .grpc_port
+        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port));
 
     // Launch Tokio runtime
     tokio::runtime::Builder::new_multi_thread()
@@ -48,12 +55,45 @@ fn main() -> Result<(), anyhow::Error> {
         .unwrap()
         .block_on(async {
             let trace_shutdown = utils::trace::init_tracing(args.clone().into())?;
-            let config = OrchestratorConfig::load(args.config_path).await?;
+            match args.command {
+                Some(Command::Detect(detect_args)) => {
+                    let result = match OrchestratorConfig::load(
+                        args.config_path,
+                        args.strict_config,
+                    )
+                    .await
+                    {
+                        Ok(config) => cli::detect(config, detect_args).await.map(|_| ()),
+                        Err(error) => Err(error.into()),
+                    };
+                    trace_shutdown()?;
+                    return result;
+                }
+                Some(Command::ValidateConfig(validate_args)) => {
+                    let report =
+                        cli::validate_config(&args.config_path, args.strict_config, validate_args)
+                            .await;
+                    println!("{report}");
+                    trace_shutdown()?;
+                    if !report.passed() {
+                        anyhow::bail!("config validation failed");
+                    }
+                    return Ok(());
+                }
+                Some(Command::ConfigSchema(schema_args)) => {
+                    let result = cli::config_schema(schema_args);
+                    trace_shutdown()?;
+                    return result;
+                }
+                None => {}
+            }
+            let config = OrchestratorConfig::load(args.config_path, args.strict_config).await?;
             let orchestrator = Orchestrator::new(config, args.start_up_health_check).await?;
 
-            let (health_handle, guardrails_handle) = server::run(
+            let (health_handle, guardrails_handle, grpc_handle) = server::run(
                 http_addr,
                 health_http_addr,
+                grpc_addr,
                 args.tls_cert_path,
                 args.tls_key_path,
                 args.tls_client_ca_cert_path,
@@ -63,7 +103,11 @@ fn main() -> Result<(), anyhow::Error> {
             .unwrap_or_else(|e| panic!("failed to run server: {e}"));
 
             // Await server shutdown
-            let _ = tokio::join!(health_handle, guardrails_handle);
+            let _ = tokio::join!(health_handle, guardrails_handle, async {
+                if let Some(grpc_handle) = grpc_handle {
+                    let _ = grpc_handle.await;
+                }
+            });
             info!("shutdown complete");
 
             trace_shutdown()
@@ -27,15 +27,24 @@ use crate::{
         detector::{ContentAnalysisResponse, ContextType},
         openai::{Content, ContentType},
     },
+    config::MessageSelection,
     health::HealthCheckCache,
     pb,
+    utils::tls::CertInfo,
 };
 
 pub const THRESHOLD_PARAM: &str = "threshold";
 
+pub const STOP_ON_PARAM: &str = "stop_on";
+
+pub const MESSAGES_PARAM: &str = "messages";
+
 #[derive(Clone, Debug, Serialize)]
 pub struct InfoResponse {
     pub services: HealthCheckCache,
+    /// TLS certificates currently loaded, by path
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub certs: BTreeMap<String, CertInfo>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -61,6 +70,25 @@ impl DetectorParams {
     pub fn pop_threshold(&mut self) -> Option<f64> {
         self.0.remove(THRESHOLD_PARAM).and_then(|v| v.as_f64())
     }
+
+    /// Score at or above which a streaming output detection stops generation.
+    pub fn pop_stop_on(&mut self) -> Option<f64> {
+        self.0.remove(STOP_ON_PARAM).and_then(|v| v.as_f64())
+    }
+
+    /// Chat messages that an input detector is applied to.
+    pub fn pop_messages(&mut self) -> Result<Option<MessageSelection>, ValidationError> {
+        self.0
+            .remove(MESSAGES_PARAM)
+            .map(|v| {
+                serde_json::from_value(v).map_err(|error| {
+                    ValidationError::Invalid(format!(
+                        "invalid `{MESSAGES_PARAM}` parameter: {error}"
+                    ))
+                })
+            })
+            .transpose()
+    }
 }
 
 impl std::ops::Deref for DetectorParams {
@@ -504,6 +532,8 @@ pub enum FinishReason {
     TokenLimit,
     #[serde(rename = "ERROR")]
     Error,
+    #[serde(rename = "GUARDRAILS_STOP")]
+    GuardrailsStop,
 }
 
 pub const UNSUITABLE_INPUT_MESSAGE: &str = "Unsuitable input detected. \
@@ -512,6 +542,15 @@ pub const UNSUITABLE_INPUT_MESSAGE: &str = "Unsuitable input detected. \
 
 pub const UNSUITABLE_OUTPUT_MESSAGE: &str = "Unsuitable output detected.";
 
+pub const UNSUITABLE_CONVERSATION_MESSAGE: &str = "Unsuitable conversation detected. \
+    The cumulative risk of the conversation exceeded the limit, \
+    please start a new conversation.";
+
+/// Chat completion finish reason when generation is stopped by a `stop_on` policy.
+pub const GUARDRAILS_STOP_FINISH_REASON: &str = "guardrails_stop";
+
+pub const DEFAULT_REFUSAL_MESSAGE: &str = "I'm sorry, I can't continue with this response.";
+
 /// Detection warning reason and message.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DetectionWarning {
@@ -557,6 +596,10 @@ pub enum DetectionWarningReason {
     /// Unsuitable text detected on output
     #[serde(rename = "EMPTY_OUTPUT")]
     EmptyOutput,
+
+    /// Cumulative conversation risk exceeded the limit
+    #[serde(rename = "UNSUITABLE_CONVERSATION")]
+    UnsuitableConversation,
 }
 
 /// Generated token information
@@ -928,6 +971,37 @@ pub struct GenerationWithDetectionResult {
     pub input_token_count: u32,
 }
 
+/// The streaming response format of the /api/v2/text/generation-detection/stream endpoint.
+///
+/// Chunk events contain generated text with `text_contents` detections.
+/// The final event contains `text_generation` detections on the full generated text.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerationWithDetectionStreamResult {
+    /// Text generated by the LLM for this chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_text: Option<String>,
+
+    /// Start index of this chunk in the generated text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u32>,
+
+    /// End index of this chunk in the generated text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_index: Option<u32>,
+
+    /// Input length, set on the first chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_token_count: Option<u32>,
+
+    /// Detection results on this chunk
+    #[serde(default)]
+    pub detections: Vec<ContentAnalysisResponse>,
+
+    /// Detection results on the full generated text, set on the final event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_detections: Option<Vec<DetectionResult>>,
+}
+
 /// Detection format received from detectors
 /// This struct does NOT apply to classification endpoints:
 /// /api/v1/task/classification-with-text-generation
@@ -1019,6 +1093,11 @@ pub struct ChatDetectionHttpRequest {
     /// An optional list of tools definitions to analyze with messages
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tools: Vec<clients::openai::Tool>,
+
+    /// An optional session ID. When set, `messages` are appended to the session
+    /// conversation and detectors are run on the accumulated conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
 }
 
 impl ChatDetectionHttpRequest {
@@ -1031,6 +1110,11 @@ impl ChatDetectionHttpRequest {
         if self.messages.is_empty() {
             return Err(ValidationError::Required("messages".into()));
         }
+        if self.session_id.as_ref().is_some_and(|id| id.is_empty()) {
+            return Err(ValidationError::Invalid(
+                "`session_id` cannot be empty".into(),
+            ));
+        }
 
         Ok(())
     }
@@ -1086,6 +1170,13 @@ impl ChatDetectionHttpRequest {
 pub struct ChatDetectionResult {
     /// Detection results
     pub detections: Vec<DetectionResult>,
+    /// Session ID, set if the request is part of a session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Index of the last message in the session conversation,
+    /// detection results apply to the conversation up to and including this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_index: Option<usize>,
 }
 
 /// The request format expected in the /api/v2/text/detect/generated endpoint.
@@ -1205,6 +1296,140 @@ pub struct StreamingContentDetectionResponse {
     pub start_index: u32,
 }
 
+/// The request format expected in the /api/v2/text/detection/content/batch endpoint
+/// for `application/json` requests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchContentDetectionHttpRequest {
+    /// The map of detectors to be used for all items, along with their respective parameters
+    pub detectors: HashMap<String, DetectorParams>,
+    /// The items to run detectors on
+    pub items: Vec<BatchContentDetectionItem>,
+}
+
+impl BatchContentDetectionHttpRequest {
+    /// Upfront validation of user request
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.detectors.is_empty() {
+            return Err(ValidationError::Required("detectors".into()));
+        }
+        if self.items.is_empty() {
+            return Err(ValidationError::Required("items".into()));
+        }
+        validate_detector_params(&self.detectors)?;
+        Ok(())
+    }
+}
+
+/// Item of a /api/v2/text/detection/content/batch request.
+///
+/// For `application/x-ndjson` requests, each line is an item and
+/// `detectors` is required for the first item.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchContentDetectionItem {
+    /// Caller-provided ID used to correlate results
+    pub id: String,
+    /// The content to run detectors on
+    pub content: String,
+    /// The map of detectors to be used for all items, required on the first item.
+    /// Later items may only repeat the detectors of the first item
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detectors: Option<HashMap<String, DetectorParams>>,
+}
+
+impl BatchContentDetectionItem {
+    /// Validates item
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.content.is_empty() {
+            return Err(ValidationError::Invalid(
+                "`content` cannot be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validates and returns detectors of the first item of a ND-JSON request
+    pub fn validate_detectors(&self) -> Result<&HashMap<String, DetectorParams>, ValidationError> {
+        match &self.detectors {
+            Some(detectors) if !detectors.is_empty() => {
+                validate_detector_params(detectors)?;
+                Ok(detectors)
+            }
+            _ => Err(ValidationError::Required("detectors".into())),
+        }
+    }
+}
+
+/// Result of a /api/v2/text/detection/content/batch item, streamed as ND-JSON in input order
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchContentDetectionResult {
+    /// Position of the item in the request
+    pub index: usize,
+    /// ID of the item, if it could be parsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Detection results, if the item succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detections: Option<Vec<ContentAnalysisResponse>>,
+    /// Error, if the item failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<BatchItemError>,
+}
+
+/// Error of a failed batch item
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchItemError {
+    pub code: u16,
+    pub details: String,
+}
+
+/// Status of an asynchronous job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Returns `true` if the job will not make further progress.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// Status and progress of an asynchronous job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobInfo {
+    /// Job ID
+    pub id: String,
+    pub status: JobStatus,
+    /// Number of items submitted
+    pub total: usize,
+    /// Number of items processed
+    pub processed: usize,
+    /// Number of items that failed
+    pub failed: usize,
+    /// Submission time in milliseconds since the Unix epoch
+    pub created_at: u64,
+    /// Start time in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    /// Completion time in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    /// Error, if the job failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Subject of the principal that submitted the job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1410,6 +1635,17 @@ mod tests {
         let mut value = DetectorParams::new();
         assert!(!value.contains_key("threshold"));
         assert_eq!(value.pop_threshold(), None);
+
+        let mut value: DetectorParams = serde_json::from_str(r#"{"messages": "all"}"#)?;
+        assert_eq!(value.pop_messages().ok(), Some(Some(MessageSelection::All)));
+        assert!(!value.contains_key("messages"));
+        assert_eq!(value.pop_messages().ok(), Some(None));
+        // Invalid selections are rejected rather than ignored
+        let mut value: DetectorParams = serde_json::from_str(r#"{"messages": "first"}"#)?;
+        assert!(matches!(
+            value.pop_messages(),
+            Err(ValidationError::Invalid(_))
+        ));
         Ok(())
     }
 }
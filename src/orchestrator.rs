@@ -16,22 +16,30 @@
 */
 pub mod errors;
 pub use errors::Error;
+pub mod audit;
 pub mod common;
 pub mod handlers;
+pub mod sessions;
+pub mod system_prompts;
 pub mod types;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use tokio::{sync::RwLock, time::Instant};
 use tracing::{debug, info};
 
 use crate::{
     clients::{
-        ChunkerClient, ClientMap, DetectorClient, GenerationClient, NlpClient, TgisClient,
-        openai::OpenAiClient,
+        ChunkerClient, ClientMap, ConcurrencyLimit, DetectorClient, GenerationClient, NlpClient,
+        TgisClient, openai::OpenAiClient,
     },
     config::{GenerationProvider, OrchestratorConfig},
     health::HealthCheckCache,
+    orchestrator::{
+        audit::AuditLog,
+        sessions::{InMemorySessionStore, SessionStore},
+        system_prompts::SystemPromptCache,
+    },
 };
 
 const DEFAULT_MAX_RETRIES: usize = 3;
@@ -40,11 +48,20 @@ const DEFAULT_MAX_RETRIES: usize = 3;
 pub struct Context {
     config: OrchestratorConfig,
     clients: ClientMap,
+    audit: AuditLog,
+    sessions: Option<Arc<dyn SessionStore>>,
+    system_prompts: SystemPromptCache,
 }
 
 impl Context {
     pub fn new(config: OrchestratorConfig, clients: ClientMap) -> Self {
-        Self { config, clients }
+        Self {
+            config,
+            clients,
+            audit: AuditLog::default(),
+            sessions: None,
+            system_prompts: SystemPromptCache::default(),
+        }
     }
 }
 
@@ -59,9 +76,46 @@ impl Orchestrator {
     pub async fn new(
         config: OrchestratorConfig,
         start_up_health_check: bool,
+    ) -> Result<Self, Error> {
+        let sessions = config
+            .sessions
+            .as_ref()
+            .map(|sessions| Arc::new(InMemorySessionStore::new(sessions)) as Arc<dyn SessionStore>);
+        Self::build(config, start_up_health_check, sessions).await
+    }
+
+    /// Creates an orchestrator using an external session store.
+    /// Sessions must be enabled in the config.
+    pub async fn with_session_store(
+        config: OrchestratorConfig,
+        start_up_health_check: bool,
+        session_store: Arc<dyn SessionStore>,
+    ) -> Result<Self, Error> {
+        if config.sessions.is_none() {
+            return Err(Error::Other(
+                "a session store requires sessions to be enabled in the config".into(),
+            ));
+        }
+        Self::build(config, start_up_health_check, Some(session_store)).await
+    }
+
+    async fn build(
+        config: OrchestratorConfig,
+        start_up_health_check: bool,
+        sessions: Option<Arc<dyn SessionStore>>,
     ) -> Result<Self, Error> {
         let clients = create_clients(&config).await?;
-        let ctx = Arc::new(Context { config, clients });
+        let audit = match &config.audit {
+            Some(audit) => AuditLog::new(audit)?,
+            None => AuditLog::default(),
+        };
+        let ctx = Arc::new(Context {
+            config,
+            clients,
+            audit,
+            sessions,
+            system_prompts: SystemPromptCache::default(),
+        });
         let orchestrator = Self {
             ctx,
             client_health: Arc::new(RwLock::new(HealthCheckCache::default())),
@@ -124,12 +178,12 @@ async fn create_clients(config: &OrchestratorConfig) -> Result<ClientMap, Error>
             .unwrap_or(DEFAULT_MAX_RETRIES);
         match generation.provider {
             GenerationProvider::Tgis => {
-                let tgis_client = TgisClient::new(&generation.service).await;
+                let tgis_client = TgisClient::new(&generation.service).await?;
                 let generation_client = GenerationClient::tgis(tgis_client, retries);
                 clients.insert("generation".to_string(), generation_client);
             }
             GenerationProvider::Nlp => {
-                let nlp_client = NlpClient::new(&generation.service).await;
+                let nlp_client = NlpClient::new(&generation.service).await?;
                 let generation_client = GenerationClient::nlp(nlp_client, retries);
                 clients.insert("generation".to_string(), generation_client);
             }
@@ -146,17 +200,34 @@ async fn create_clients(config: &OrchestratorConfig) -> Result<ClientMap, Error>
     // Create chunker clients
     if let Some(chunkers) = &config.chunkers {
         for (chunker_id, chunker) in chunkers {
-            let chunker_client = ChunkerClient::new(&chunker.service).await;
+            let chunker_client = ChunkerClient::new(&chunker.service).await?;
             clients.insert(chunker_id.to_string(), chunker_client);
+            if let Some(max_in_flight) = chunker.max_in_flight {
+                let queue_timeout = chunker.queue_timeout_ms.map(Duration::from_millis);
+                clients.set_limit(
+                    chunker_id.to_string(),
+                    ConcurrencyLimit::new(max_in_flight, queue_timeout),
+                );
+            }
         }
     }
 
     // Create detector clients
     for (detector_id, detector) in &config.detectors {
-        clients.insert(
-            detector_id.into(),
-            DetectorClient::new(&detector.service, detector.health_service.as_ref()).await?,
-        );
+        let limit = detector.max_in_flight.map(|max_in_flight| {
+            let queue_timeout = detector.queue_timeout_ms.map(Duration::from_millis);
+            ConcurrencyLimit::new(max_in_flight, queue_timeout)
+        });
+        let mut detector_client =
+            DetectorClient::new(&detector.service, detector.health_service.as_ref()).await?;
+        if let Some(batching) = &detector.batching {
+            detector_client =
+                detector_client.with_batching(detector_id.clone(), batching, limit.clone());
+        }
+        clients.insert(detector_id.into(), detector_client);
+        if let Some(limit) = limit {
+            clients.set_limit(detector_id.into(), limit);
+        }
     }
     Ok(clients)
 }
@@ -0,0 +1,958 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Audit log of guardrail decisions.
+//!
+//! One [`AuditRecord`] is written per task. Records are chained: each record
+//! carries the hash of the previous record and a SHA-256 hash of its own
+//! contents, so that removed or modified records can be detected.
+//!
+//! Records are queued without waiting, sealed in arrival order by a writer task
+//! and written by one task per sink, so that a slow sink does not hold up
+//! requests. Records are dropped before they are sealed when the queue is full.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use opentelemetry::trace::TraceId;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc::{self, error::TrySendError},
+};
+use tracing::{Instrument, debug, error, info, warn};
+
+use super::{Error, common::current_timestamp};
+use crate::{
+    clients::{
+        detector::ContentAnalysisResponse,
+        openai::{
+            ChatCompletion, ChatCompletionChunk, ChatCompletionsResponse, Completion,
+            CompletionDetections, CompletionsResponse,
+        },
+    },
+    config::{AuditConfig, AuditSinkConfig, AuditTextMode},
+    models::{
+        ChatDetectionResult, ClassifiedGeneratedTextResult, ClassifiedGeneratedTextStreamResult,
+        ContextDocsResult, DetectionOnGenerationResult, DetectionResult, DetectionWarningReason,
+        GenerationWithDetectionResult, StreamingContentDetectionResponse,
+        TextContentDetectionResult, TextGenTokenClassificationResults, TokenClassificationResult,
+    },
+    server::Principal,
+};
+
+/// Capacity of the channel between tasks and the audit writer, and of each sink queue.
+const AUDIT_CHANNEL_CAPACITY: usize = 1024;
+/// Default webhook request timeout in seconds.
+const DEFAULT_WEBHOOK_TIMEOUT_SECS: u64 = 5;
+/// Number of bytes read from the end of an existing audit file to resume the hash chain.
+const TAIL_READ_BYTES: u64 = 64 * 1024;
+/// Previous hash of the first record in a chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Final action taken for a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// No detections
+    Allowed,
+    /// Detections were returned to the caller
+    Flagged,
+    /// Generation was not performed due to input detections
+    Blocked,
+    /// Task failed
+    Failed,
+    /// Task was cancelled, e.g. client disconnected
+    Cancelled,
+}
+
+/// Detection as written to an audit record.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditDetection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detector_id: Option<String>,
+    pub detection_type: String,
+    pub detection: String,
+    pub score: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<usize>,
+    /// Detected text, plain or hashed depending on [`AuditTextMode`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl From<&ContentAnalysisResponse> for AuditDetection {
+    fn from(value: &ContentAnalysisResponse) -> Self {
+        Self {
+            detector_id: value.detector_id.clone(),
+            detection_type: value.detection_type.clone(),
+            detection: value.detection.clone(),
+            score: value.score,
+            start: Some(value.start),
+            end: Some(value.end),
+            text: Some(value.text.clone()),
+        }
+    }
+}
+
+impl From<&DetectionResult> for AuditDetection {
+    fn from(value: &DetectionResult) -> Self {
+        Self {
+            detector_id: value.detector_id.clone(),
+            detection_type: value.detection_type.clone(),
+            detection: value.detection.clone(),
+            score: value.score,
+            start: None,
+            end: None,
+            text: None,
+        }
+    }
+}
+
+impl From<&TokenClassificationResult> for AuditDetection {
+    fn from(value: &TokenClassificationResult) -> Self {
+        Self {
+            detector_id: value.detector_id.clone(),
+            detection_type: value.entity_group.clone(),
+            detection: value.entity.clone(),
+            score: value.score,
+            start: Some(value.start as usize),
+            end: Some(value.end as usize),
+            text: Some(value.word.clone()),
+        }
+    }
+}
+
+/// Task timings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditTimings {
+    /// Task start time in milliseconds since the Unix epoch
+    pub started_at: u64,
+    /// Task duration in milliseconds
+    pub duration_ms: u64,
+}
+
+/// Audit record of a single task.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position of the record in the hash chain
+    pub sequence: u64,
+    pub trace_id: String,
+    pub route: String,
+    /// Authenticated caller, if authentication is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub principal: Option<Principal>,
+    /// Detectors and parameters requested
+    pub detectors: serde_json::Value,
+    pub detections: Vec<AuditDetection>,
+    pub action: AuditAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub timings: AuditTimings,
+    /// Hash of the previous record
+    pub prev_hash: String,
+    /// SHA-256 hash of this record, serialized without the `hash` field
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+impl AuditRecord {
+    /// Links the record to the chain and computes its hash.
+    fn seal(&mut self, sequence: u64, prev_hash: String) {
+        self.sequence = sequence;
+        self.prev_hash = prev_hash;
+        self.hash = String::new();
+        self.hash = self.compute_hash();
+    }
+
+    /// Returns `true` if the record hash matches its contents.
+    pub fn verify(&self) -> bool {
+        let mut record = self.clone();
+        record.hash = String::new();
+        record.compute_hash() == self.hash
+    }
+
+    fn compute_hash(&self) -> String {
+        let bytes = serde_json::to_vec(self).expect("audit record serialization failed");
+        to_hex(digest(&SHA256, &bytes).as_ref())
+    }
+}
+
+/// A task response that can be recorded in the audit log.
+pub trait Auditable {
+    /// Returns detections contained in the response.
+    fn audit_detections(&self) -> Vec<AuditDetection>;
+
+    /// Returns `true` if generation was not performed due to input detections.
+    fn blocked(&self) -> bool {
+        false
+    }
+}
+
+impl<T: Auditable> Auditable for Option<T> {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.as_ref()
+            .map(|value| value.audit_detections())
+            .unwrap_or_default()
+    }
+
+    fn blocked(&self) -> bool {
+        self.as_ref().is_some_and(|value| value.blocked())
+    }
+}
+
+impl Auditable for TextContentDetectionResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.iter().map(Into::into).collect()
+    }
+}
+
+impl Auditable for StreamingContentDetectionResponse {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.iter().map(Into::into).collect()
+    }
+}
+
+impl Auditable for ChatDetectionResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.iter().map(Into::into).collect()
+    }
+}
+
+impl Auditable for ContextDocsResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.iter().map(Into::into).collect()
+    }
+}
+
+impl Auditable for DetectionOnGenerationResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.iter().map(Into::into).collect()
+    }
+}
+
+impl Auditable for GenerationWithDetectionResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.iter().map(Into::into).collect()
+    }
+}
+
+impl Auditable for TextGenTokenClassificationResults {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.input
+            .iter()
+            .chain(self.output.iter())
+            .flatten()
+            .map(Into::into)
+            .collect()
+    }
+
+    fn blocked(&self) -> bool {
+        self.input.as_ref().is_some_and(|input| !input.is_empty())
+    }
+}
+
+impl Auditable for ClassifiedGeneratedTextResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.token_classification_results.audit_detections()
+    }
+
+    fn blocked(&self) -> bool {
+        self.token_classification_results.blocked()
+    }
+}
+
+impl Auditable for ClassifiedGeneratedTextStreamResult {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.token_classification_results.audit_detections()
+    }
+
+    fn blocked(&self) -> bool {
+        self.token_classification_results.blocked()
+    }
+}
+
+impl Auditable for CompletionDetections {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.input
+            .iter()
+            .flat_map(|detections| &detections.results)
+            .chain(
+                self.output
+                    .iter()
+                    .flat_map(|detections| &detections.results),
+            )
+            .map(Into::into)
+            .collect()
+    }
+
+    fn blocked(&self) -> bool {
+        !self.input.is_empty()
+    }
+}
+
+impl Auditable for ChatCompletion {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.audit_detections()
+    }
+
+    fn blocked(&self) -> bool {
+        self.detections.blocked()
+            || self
+                .warnings
+                .iter()
+                .any(|warning| warning.reason() == &DetectionWarningReason::UnsuitableConversation)
+    }
+}
+
+impl Auditable for ChatCompletionChunk {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.audit_detections()
+    }
+
+    fn blocked(&self) -> bool {
+        self.detections.blocked()
+            || self
+                .warnings
+                .iter()
+                .any(|warning| warning.reason() == &DetectionWarningReason::UnsuitableConversation)
+    }
+}
+
+impl Auditable for Completion {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        self.detections.audit_detections()
+    }
+
+    fn blocked(&self) -> bool {
+        self.detections.blocked()
+    }
+}
+
+/// Streaming responses are recorded by [`AuditEntry::record_stream`].
+impl Auditable for ChatCompletionsResponse {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        match self {
+            ChatCompletionsResponse::Unary(chat_completion) => chat_completion.audit_detections(),
+            ChatCompletionsResponse::Streaming(_) => Vec::new(),
+        }
+    }
+
+    fn blocked(&self) -> bool {
+        match self {
+            ChatCompletionsResponse::Unary(chat_completion) => chat_completion.blocked(),
+            ChatCompletionsResponse::Streaming(_) => false,
+        }
+    }
+}
+
+/// Streaming responses are recorded by [`AuditEntry::record_stream`].
+impl Auditable for CompletionsResponse {
+    fn audit_detections(&self) -> Vec<AuditDetection> {
+        match self {
+            CompletionsResponse::Unary(completion) => completion.audit_detections(),
+            CompletionsResponse::Streaming(_) => Vec::new(),
+        }
+    }
+
+    fn blocked(&self) -> bool {
+        match self {
+            CompletionsResponse::Unary(completion) => completion.blocked(),
+            CompletionsResponse::Streaming(_) => false,
+        }
+    }
+}
+
+/// Audit log handle.
+///
+/// The default instance is disabled and records nothing.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    tx: Option<mpsc::Sender<AuditRecord>>,
+    text_mode: AuditTextMode,
+}
+
+impl AuditLog {
+    /// Creates an audit log, opening its sinks and spawning the writer and sink tasks.
+    pub fn new(config: &AuditConfig) -> Result<Self, Error> {
+        // Resume hash chain from the last record of a file sink, if any
+        let mut last = None;
+        for sink in &config.sinks {
+            if let AuditSinkConfig::File { path } = sink
+                && let Some(record) = last_record(path)?
+            {
+                last = Some(record);
+                break;
+            }
+        }
+        let (sequence, prev_hash) = match last {
+            Some(record) => (record.sequence + 1, record.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let sinks = config
+            .sinks
+            .iter()
+            .map(AuditSink::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        info!(sinks = sinks.len(), sequence, "audit log enabled");
+
+        let sink_txs = sinks
+            .into_iter()
+            .map(|sink| {
+                let (tx, rx) = mpsc::channel(AUDIT_CHANNEL_CAPACITY);
+                let kind = sink.kind();
+                tokio::spawn(run_sink(rx, sink).in_current_span());
+                (kind, tx)
+            })
+            .collect();
+        let (tx, rx) = mpsc::channel(AUDIT_CHANNEL_CAPACITY);
+        tokio::spawn(run_writer(rx, sink_txs, sequence, prev_hash).in_current_span());
+        Ok(Self {
+            tx: Some(tx),
+            text_mode: config.text,
+        })
+    }
+
+    /// Returns `true` if audit records are written.
+    pub fn enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Starts an audit entry for a task.
+    pub fn entry(
+        &self,
+        trace_id: TraceId,
+        route: &'static str,
+        principal: Option<&Principal>,
+        detectors: &impl Serialize,
+    ) -> AuditEntry {
+        let detectors = if self.enabled() {
+            serde_json::to_value(detectors).unwrap_or_default()
+        } else {
+            serde_json::Value::Null
+        };
+        AuditEntry {
+            tx: self.tx.clone(),
+            text_mode: self.text_mode,
+            trace_id,
+            route,
+            principal: principal.cloned(),
+            detectors,
+            detections: Vec::new(),
+            blocked: false,
+            started_at: current_timestamp(),
+            start: Instant::now(),
+        }
+    }
+}
+
+/// In-progress audit record of a task.
+#[derive(Debug)]
+pub struct AuditEntry {
+    tx: Option<mpsc::Sender<AuditRecord>>,
+    text_mode: AuditTextMode,
+    trace_id: TraceId,
+    route: &'static str,
+    principal: Option<Principal>,
+    detectors: serde_json::Value,
+    detections: Vec<AuditDetection>,
+    blocked: bool,
+    started_at: Duration,
+    start: Instant,
+}
+
+impl AuditEntry {
+    /// Adds detections from a response.
+    pub fn push(&mut self, response: &impl Auditable) {
+        if self.tx.is_none() {
+            return;
+        }
+        self.blocked |= response.blocked();
+        let text_mode = self.text_mode;
+        self.detections.extend(
+            response
+                .audit_detections()
+                .into_iter()
+                .map(|mut detection| {
+                    detection.text = match text_mode {
+                        AuditTextMode::Plain => detection.text,
+                        AuditTextMode::Hash => detection.text.map(|text| {
+                            format!(
+                                "sha256:{}",
+                                to_hex(digest(&SHA256, text.as_bytes()).as_ref())
+                            )
+                        }),
+                        AuditTextMode::Redact => None,
+                    };
+                    detection
+                }),
+        );
+    }
+
+    /// Queues the audit record for writing, dropping it if the queue is full.
+    pub fn finish(self, error: Option<&Error>) {
+        let Some(tx) = self.tx else {
+            return;
+        };
+        let action = match error {
+            Some(Error::Cancelled) => AuditAction::Cancelled,
+            Some(_) => AuditAction::Failed,
+            None if self.blocked => AuditAction::Blocked,
+            None if !self.detections.is_empty() => AuditAction::Flagged,
+            None => AuditAction::Allowed,
+        };
+        let record = AuditRecord {
+            sequence: 0,
+            trace_id: self.trace_id.to_string(),
+            route: self.route.to_string(),
+            principal: self.principal,
+            detectors: self.detectors,
+            detections: self.detections,
+            action,
+            error: error.map(|error| error.to_string()),
+            timings: AuditTimings {
+                started_at: self.started_at.as_millis() as u64,
+                duration_ms: self.start.elapsed().as_millis() as u64,
+            },
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+        match tx.try_send(record) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => warn!(
+                monotonic_counter.audit_record_dropped_count = 1,
+                trace_id = %self.trace_id,
+                "audit log queue full, record dropped"
+            ),
+            Err(TrySendError::Closed(_)) => {
+                error!(trace_id = %self.trace_id, "audit log writer closed, record dropped")
+            }
+        }
+    }
+
+    /// Records the result of a unary task and returns it.
+    pub fn record<T: Auditable>(mut self, result: Result<T, Error>) -> Result<T, Error> {
+        match &result {
+            Ok(response) => {
+                self.push(response);
+                self.finish(None);
+            }
+            Err(error) => self.finish(Some(error)),
+        }
+        result
+    }
+
+    /// Returns a sender that records streaming task responses before forwarding
+    /// them to `response_tx`. The record is written once the returned sender is dropped
+    /// or the client disconnects.
+    pub fn record_stream<T>(
+        mut self,
+        response_tx: mpsc::Sender<Result<T, Error>>,
+    ) -> mpsc::Sender<Result<T, Error>>
+    where
+        T: Auditable + Send + 'static,
+    {
+        if self.tx.is_none() {
+            return response_tx;
+        }
+        let (tx, mut rx) = mpsc::channel::<Result<T, Error>>(1);
+        tokio::spawn(
+            async move {
+                let mut error = None;
+                while let Some(result) = rx.recv().await {
+                    match &result {
+                        Ok(response) => self.push(response),
+                        Err(e) => error = Some(e.clone()),
+                    }
+                    if response_tx.send(result).await.is_err() {
+                        error = Some(Error::Cancelled);
+                        break;
+                    }
+                }
+                self.finish(error.as_ref());
+            }
+            .in_current_span(),
+        );
+        tx
+    }
+}
+
+/// Audit record destination.
+#[derive(Debug)]
+enum AuditSink {
+    File {
+        path: PathBuf,
+        file: tokio::fs::File,
+    },
+    Stdout,
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+        headers: HeaderMap,
+    },
+}
+
+impl AuditSink {
+    fn new(config: &AuditSinkConfig) -> Result<Self, Error> {
+        match config {
+            AuditSinkConfig::File { path } => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|error| {
+                        Error::Other(format!(
+                            "failed to open audit log file `{}`: {error}",
+                            path.display()
+                        ))
+                    })?;
+                Ok(Self::File {
+                    path: path.clone(),
+                    file: tokio::fs::File::from_std(file),
+                })
+            }
+            AuditSinkConfig::Stdout => Ok(Self::Stdout),
+            AuditSinkConfig::Webhook {
+                url,
+                headers,
+                request_timeout,
+            } => {
+                let timeout =
+                    Duration::from_secs(request_timeout.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT_SECS));
+                let client = reqwest::Client::builder()
+                    .timeout(timeout)
+                    .build()
+                    .map_err(|error| {
+                        Error::Other(format!("failed to create audit webhook client: {error}"))
+                    })?;
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| {
+                        match (
+                            HeaderName::from_bytes(name.as_bytes()),
+                            HeaderValue::from_str(value),
+                        ) {
+                            (Ok(name), Ok(value)) => Ok((name, value)),
+                            _ => Err(Error::Other(format!(
+                                "invalid audit webhook header `{name}`"
+                            ))),
+                        }
+                    })
+                    .collect::<Result<HeaderMap, _>>()?;
+                Ok(Self::Webhook {
+                    client,
+                    url: url.clone(),
+                    headers,
+                })
+            }
+        }
+    }
+
+    /// Returns the sink type, used in logs and metrics.
+    fn kind(&self) -> &'static str {
+        match self {
+            AuditSink::File { .. } => "file",
+            AuditSink::Stdout => "stdout",
+            AuditSink::Webhook { .. } => "webhook",
+        }
+    }
+
+    async fn write(&mut self, line: &str) {
+        match self {
+            AuditSink::File { path, file } => {
+                let result = match file.write_all(format!("{line}\n").as_bytes()).await {
+                    Ok(()) => file.flush().await,
+                    Err(error) => Err(error),
+                };
+                if let Err(error) = result {
+                    error!(path = %path.display(), %error, "failed to write audit record");
+                }
+            }
+            AuditSink::Stdout => println!("{line}"),
+            AuditSink::Webhook {
+                client,
+                url,
+                headers,
+            } => {
+                let result = client
+                    .post(url.as_str())
+                    .headers(headers.clone())
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(line.to_string())
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(error) = result {
+                    error!(%url, %error, "failed to send audit record");
+                }
+            }
+        }
+    }
+}
+
+/// Seals audit records in arrival order and queues them to all sinks.
+///
+/// Sealed records are never dropped: a full sink queue holds the writer back, so that
+/// records are dropped before they enter the hash chain once the writer queue is full.
+async fn run_writer(
+    mut rx: mpsc::Receiver<AuditRecord>,
+    sinks: Vec<(&'static str, mpsc::Sender<Arc<str>>)>,
+    mut sequence: u64,
+    mut prev_hash: String,
+) {
+    while let Some(mut record) = rx.recv().await {
+        record.seal(sequence, prev_hash);
+        let line: Arc<str> = serde_json::to_string(&record)
+            .expect("audit record serialization failed")
+            .into();
+        for (sink, tx) in &sinks {
+            if tx.send(line.clone()).await.is_err() {
+                error!(sink, sequence, "audit sink closed, record not written");
+            }
+        }
+        sequence += 1;
+        prev_hash = record.hash;
+    }
+    debug!("audit log writer closed");
+}
+
+/// Writes queued audit records to a sink.
+async fn run_sink(mut rx: mpsc::Receiver<Arc<str>>, mut sink: AuditSink) {
+    while let Some(line) = rx.recv().await {
+        sink.write(&line).await;
+    }
+    debug!(sink = sink.kind(), "audit sink closed");
+}
+
+/// Reads the last record of an existing audit file.
+///
+/// Fails if the file exists but its last record can't be read, as starting a new hash
+/// chain would be indistinguishable from a truncated log.
+fn last_record(path: &Path) -> Result<Option<AuditRecord>, Error> {
+    let read_error = |error: String| {
+        Error::Other(format!(
+            "unable to read last record of audit log file `{}`, move or repair the file to start a new hash chain: {error}",
+            path.display()
+        ))
+    };
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(read_error(error.to_string())),
+    };
+    let mut buf = Vec::new();
+    file.metadata()
+        .and_then(|metadata| {
+            file.seek(SeekFrom::Start(
+                metadata.len().saturating_sub(TAIL_READ_BYTES),
+            ))
+        })
+        .and_then(|_| file.read_to_end(&mut buf))
+        .map_err(|error| read_error(error.to_string()))?;
+    let tail = String::from_utf8_lossy(&buf);
+    let Some(line) = tail.lines().rev().find(|line| !line.trim().is_empty()) else {
+        return Ok(None);
+    };
+    serde_json::from_str::<AuditRecord>(line)
+        .map(Some)
+        .map_err(|error| read_error(error.to_string()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::AuthMethod;
+
+    fn detection(text: &str) -> ContentAnalysisResponse {
+        ContentAnalysisResponse {
+            start: 0,
+            end: text.len(),
+            text: text.into(),
+            detection: "has_angle_brackets".into(),
+            detection_type: "angle_brackets".into(),
+            detector_id: Some("angle_brackets_detector".into()),
+            score: 1.0,
+            evidence: None,
+            metadata: Default::default(),
+        }
+    }
+
+    fn audit_log(text_mode: AuditTextMode) -> (AuditLog, mpsc::Receiver<AuditRecord>) {
+        let (tx, rx) = mpsc::channel(8);
+        let audit = AuditLog {
+            tx: Some(tx),
+            text_mode,
+        };
+        (audit, rx)
+    }
+
+    #[tokio::test]
+    async fn test_record_text_modes() {
+        let response = TextContentDetectionResult {
+            detections: vec![detection("<secret>")],
+        };
+        for (text_mode, expected) in [
+            (AuditTextMode::Plain, Some("<secret>".to_string())),
+            (
+                AuditTextMode::Hash,
+                Some(format!(
+                    "sha256:{}",
+                    to_hex(digest(&SHA256, b"<secret>").as_ref())
+                )),
+            ),
+            (AuditTextMode::Redact, None),
+        ] {
+            let (audit, mut rx) = audit_log(text_mode);
+            let entry = audit.entry(
+                TraceId::INVALID,
+                "/test",
+                None,
+                &["angle_brackets_detector"],
+            );
+            let _ = entry.record(Ok(response.clone()));
+            let record = rx.recv().await.unwrap();
+            assert_eq!(record.action, AuditAction::Flagged);
+            assert_eq!(record.detections.len(), 1);
+            assert_eq!(record.detections[0].text, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_actions() {
+        let (audit, mut rx) = audit_log(AuditTextMode::Redact);
+
+        let principal = Principal {
+            subject: "team-a".into(),
+            tenant: None,
+            method: AuthMethod::ApiKey,
+            scopes: Vec::new(),
+        };
+        let entry = audit.entry(TraceId::INVALID, "/test", Some(&principal), &());
+        let _ = entry.record(Ok(TextContentDetectionResult::default()));
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.action, AuditAction::Allowed);
+        assert_eq!(record.principal, Some(principal));
+
+        let entry = audit.entry(TraceId::INVALID, "/test", None, &());
+        let _ = entry.record(Ok(ClassifiedGeneratedTextResult {
+            token_classification_results: TextGenTokenClassificationResults {
+                input: Some(vec![detection("<input>").into()]),
+                output: None,
+            },
+            ..Default::default()
+        }));
+        assert_eq!(rx.recv().await.unwrap().action, AuditAction::Blocked);
+
+        let entry = audit.entry(TraceId::INVALID, "/test", None, &());
+        let _ =
+            entry.record::<TextContentDetectionResult>(Err(Error::DetectorNotFound("x".into())));
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.action, AuditAction::Failed);
+        assert_eq!(record.error.as_deref(), Some("detector `x` not found"));
+    }
+
+    #[tokio::test]
+    async fn test_record_dropped_when_queue_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let audit = AuditLog {
+            tx: Some(tx),
+            text_mode: AuditTextMode::Hash,
+        };
+        audit
+            .entry(TraceId::INVALID, "/first", None, &())
+            .finish(None);
+        audit
+            .entry(TraceId::INVALID, "/second", None, &())
+            .finish(None);
+        assert_eq!(rx.recv().await.unwrap().route, "/first");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_stream() {
+        let (audit, mut rx) = audit_log(AuditTextMode::Plain);
+        let (response_tx, mut response_rx) = mpsc::channel(8);
+        let entry = audit.entry(TraceId::INVALID, "/test", None, &());
+        let tx = entry.record_stream(response_tx);
+        for text in ["<a>", "<b>"] {
+            let response = StreamingContentDetectionResponse {
+                detections: vec![detection(text)],
+                processed_index: 0,
+                start_index: 0,
+            };
+            tx.send(Ok(response)).await.unwrap();
+        }
+        drop(tx);
+        assert!(response_rx.recv().await.is_some());
+        assert!(response_rx.recv().await.is_some());
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.action, AuditAction::Flagged);
+        assert_eq!(record.detections.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let config = AuditConfig {
+            text: AuditTextMode::Hash,
+            sinks: vec![AuditSinkConfig::File { path: path.clone() }],
+        };
+        // Write records from two audit log instances to verify the chain is resumed
+        for _ in 0..2 {
+            let audit = AuditLog::new(&config).unwrap();
+            let entry = audit.entry(TraceId::INVALID, "/test", None, &());
+            entry.finish(None);
+            drop(audit);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let records = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditRecord>(line).unwrap())
+            .collect::<Vec<_>>();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].sequence, 0);
+        assert_eq!(records[0].prev_hash, GENESIS_HASH);
+        assert_eq!(records[1].sequence, 1);
+        assert_eq!(records[1].prev_hash, records[0].hash);
+        assert!(records.iter().all(|record| record.verify()));
+
+        // Unreadable last records fail startup rather than start a new chain
+        std::fs::write(&path, "{\"sequence\": 1, \"trunc").unwrap();
+        let result = AuditLog::new(&config);
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(result, Err(Error::Other(_))));
+
+        let mut tampered = records[1].clone();
+        tampered.action = AuditAction::Allowed;
+        tampered.route = "/tampered".into();
+        assert!(!tampered.verify());
+    }
+}
@@ -17,8 +17,7 @@
 //! Client helpers
 use futures::{StreamExt, TryStreamExt};
 use http::{HeaderMap, header::CONTENT_TYPE};
-use tokio::sync::broadcast;
-use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, instrument};
 
 use crate::{
@@ -67,16 +66,15 @@ pub async fn chunk(
 pub async fn chunk_stream(
     client: &ChunkerClient,
     chunker_id: ChunkerId,
-    input_rx: broadcast::Receiver<Result<(usize, String), Error>>, // (message_index, text)
+    input_stream: BoxStream<(usize, String)>, // (message_index, text)
 ) -> Result<ChunkStream, Error> {
-    let input_stream = BroadcastStream::new(input_rx)
-        .map(|result| {
-            let (index, text) = result.unwrap().unwrap();
-            BidiStreamingChunkerTokenizationTaskRequest {
+    let input_stream = input_stream
+        .map(
+            |(index, text)| BidiStreamingChunkerTokenizationTaskRequest {
                 text_stream: text,
                 input_index_stream: index as i64,
-            }
-        })
+            },
+        )
         .boxed();
     debug!(%chunker_id, "sending chunk stream request");
     let output_stream = client
@@ -15,12 +15,15 @@
 
 */
 //! Processing tasks
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use futures::{StreamExt, TryStreamExt, future::try_join_all, stream};
+use futures::{StreamExt, TryStreamExt, future, future::try_join_all, stream};
 use http::HeaderMap;
-use tokio::sync::{broadcast, mpsc};
-use tokio_stream::wrappers::ReceiverStream;
+use tokio::sync::{OwnedSemaphorePermit, broadcast, mpsc};
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
 use tracing::{Instrument, debug, instrument};
 
 use super::{client::*, utils::*};
@@ -30,6 +33,7 @@ use crate::{
         detector::{ContextType, DetectorClient},
         openai,
     },
+    config::DetectorType,
     models::DetectorParams,
     orchestrator::{Context, Error, types::*},
 };
@@ -67,6 +71,7 @@ pub async fn chunks(
                                     .clients
                                     .get_as::<ChunkerClient>(&chunker_id)
                                     .ok_or_else(|| Error::ChunkerNotFound(chunker_id.clone()))?;
+                                let _permit = acquire_chunker(&ctx, &chunker_id).await?;
                                 let chunks = chunk(client, chunker_id.clone(), text)
                                     .await?
                                     .into_iter()
@@ -125,7 +130,14 @@ pub async fn chunk_streams(
                 .clients
                 .get_as::<ChunkerClient>(&chunker_id)
                 .ok_or_else(|| Error::ChunkerNotFound(chunker_id.clone()))?;
-            chunk_stream(client, chunker_id.clone(), input_broadcast_rx).await
+            let input_stream = BroadcastStream::new(input_broadcast_rx)
+                .map(|result| result.unwrap().unwrap())
+                .boxed();
+            if ctx.clients.limit(&chunker_id).is_some() {
+                limited_chunk_stream(ctx.clone(), client, chunker_id.clone(), input_stream).await
+            } else {
+                chunk_stream(client, chunker_id.clone(), input_stream).await
+            }
         }?;
         // Create chunk broadcast channel
         let chunk_broadcast_tx = broadcast_stream(chunk_stream);
@@ -136,6 +148,116 @@ pub async fn chunk_streams(
     Ok(streams.into_iter().collect())
 }
 
+/// In-flight slot of a chunk stream.
+#[derive(Default)]
+struct ChunkStreamSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    /// Index of the last input sent, set until a chunk covering it is received
+    pending_index: Option<usize>,
+}
+
+/// Opens a chunk stream to a chunker client with a concurrency limit.
+///
+/// An in-flight slot is held while chunk requests are pending, from when an input is sent
+/// until a chunk covering it is received, rather than for the lifetime of the stream. A
+/// stream holds at most one slot. Failing to acquire a slot ends the input stream and
+/// sends the error on the chunk stream.
+async fn limited_chunk_stream(
+    ctx: Arc<Context>,
+    client: &ChunkerClient,
+    chunker_id: ChunkerId,
+    input_stream: BoxStream<(usize, String)>,
+) -> Result<ChunkStream, Error> {
+    let slot = Arc::new(Mutex::new(ChunkStreamSlot::default()));
+    let (error_tx, error_rx) = mpsc::channel(1);
+    let input_stream = {
+        let chunker_id = chunker_id.clone();
+        let slot = slot.clone();
+        input_stream
+            .then(move |(index, text)| {
+                let ctx = ctx.clone();
+                let chunker_id = chunker_id.clone();
+                let slot = slot.clone();
+                let error_tx = error_tx.clone();
+                async move {
+                    let has_permit = slot.lock().unwrap().permit.is_some();
+                    if !has_permit {
+                        match acquire_chunker(&ctx, &chunker_id).await {
+                            Ok(permit) => slot.lock().unwrap().permit = permit,
+                            Err(error) => {
+                                let _ = error_tx.send(error).await;
+                                return None;
+                            }
+                        }
+                    }
+                    slot.lock().unwrap().pending_index = Some(index);
+                    Some((index, text))
+                }
+            })
+            .take_while(|input| future::ready(input.is_some()))
+            .filter_map(future::ready)
+            .boxed()
+    };
+    let chunks = chunk_stream(client, chunker_id, input_stream)
+        .await?
+        .inspect(move |result| {
+            if let Ok(chunk) = result {
+                let mut slot = slot.lock().unwrap();
+                if slot
+                    .pending_index
+                    .is_some_and(|index| chunk.input_end_index >= index)
+                {
+                    // Release the slot once all inputs sent are chunked
+                    slot.pending_index = None;
+                    slot.permit = None;
+                }
+            }
+        });
+    let error_stream = ReceiverStream::new(error_rx).map(Err);
+    Ok(stream::select(chunks, error_stream).boxed())
+}
+
+/// Waits for an in-flight slot for a chunker client.
+async fn acquire_chunker(
+    ctx: &Context,
+    chunker_id: &ChunkerId,
+) -> Result<Option<OwnedSemaphorePermit>, Error> {
+    ctx.clients
+        .acquire(chunker_id)
+        .await
+        .map_err(|error| Error::ChunkerRequestFailed {
+            id: chunker_id.clone(),
+            error,
+        })
+}
+
+/// Waits for an in-flight slot for a detector client.
+async fn acquire_detector(
+    ctx: &Context,
+    detector_id: &DetectorId,
+) -> Result<Option<OwnedSemaphorePermit>, Error> {
+    ctx.clients
+        .acquire(detector_id)
+        .await
+        .map_err(|error| Error::DetectorRequestFailed {
+            id: detector_id.clone(),
+            error,
+        })
+}
+
+/// Waits for an in-flight slot for a text contents detector client.
+/// Batching clients acquire slots for batched requests instead.
+async fn acquire_text_contents_detector(
+    ctx: &Context,
+    client: &DetectorClient,
+    detector_id: &DetectorId,
+) -> Result<Option<OwnedSemaphorePermit>, Error> {
+    if client.is_batching() {
+        return Ok(None);
+    }
+    acquire_detector(ctx, detector_id).await
+}
+
 fn whole_doc_chunk(offset: usize, text: String) -> Chunks {
     vec![Chunk {
         start: offset,
@@ -157,7 +279,7 @@ fn whole_doc_chunk_stream(
             // Collect input channel
             // Alternatively, wrap receiver in BroadcastStream and collect() via StreamExt
             let mut inputs = Vec::new();
-            while let Ok(input) = input_broadcast_rx.recv().await.unwrap() {
+            while let Ok(Ok(input)) = input_broadcast_rx.recv().await {
                 inputs.push(input);
             }
             // Build chunk
@@ -211,6 +333,7 @@ pub async fn text_contents_detections(
             let threshold = params.pop_threshold().unwrap_or(default_threshold);
             async move {
                 let client = ctx.clients.get_as::<DetectorClient>(&detector_id).unwrap();
+                let _permit = acquire_text_contents_detector(&ctx, client, &detector_id).await?;
                 let detections = detect_text_contents(
                     client,
                     headers,
@@ -268,6 +391,17 @@ pub async fn text_contents_detection_streams(
                         Ok(chunk) => {
                             let client =
                                 ctx.clients.get_as::<DetectorClient>(&detector_id).unwrap();
+                            let _permit =
+                                match acquire_text_contents_detector(&ctx, client, &detector_id)
+                                    .await
+                                {
+                                    Ok(permit) => permit,
+                                    Err(error) => {
+                                        // Send error to detection channel
+                                        let _ = detection_tx.send(Err(error)).await;
+                                        continue;
+                                    }
+                                };
                             match detect_text_contents(
                                 client,
                                 headers.clone(),
@@ -339,6 +473,7 @@ pub async fn text_generation_detections(
             let threshold = params.pop_threshold().unwrap_or(default_threshold);
             async move {
                 let client = ctx.clients.get_as::<DetectorClient>(&detector_id).unwrap();
+                let _permit = acquire_detector(&ctx, &detector_id).await?;
                 let detections = detect_text_generation(
                     client,
                     headers,
@@ -362,6 +497,73 @@ pub async fn text_generation_detections(
     Ok(detections)
 }
 
+/// Runs text contents and text generation detectors on the whole text of each output.
+/// Returns a vec of (input_id, detections).
+///
+/// Text generation detectors use `prompt`, their detections apply to the whole text.
+#[instrument(skip_all)]
+pub async fn whole_doc_output_detections(
+    ctx: Arc<Context>,
+    headers: HeaderMap,
+    detectors: HashMap<DetectorId, DetectorParams>,
+    prompt: String,
+    inputs: Vec<(u32, String)>, // (input_id, text)
+) -> Result<Vec<(u32, Detections)>, Error> {
+    // Split detectors into text contents and text generation detectors
+    let (detectors, generation_detectors): (HashMap<_, _>, HashMap<_, _>) =
+        detectors.into_iter().partition(|(detector_id, _)| {
+            ctx.config
+                .detector(detector_id)
+                .is_some_and(|config| config.r#type.contains(&DetectorType::TextContents))
+        });
+    // Process detections concurrently for inputs
+    stream::iter(inputs)
+        .map(|(input_id, text)| {
+            let ctx = ctx.clone();
+            let headers = headers.clone();
+            let detectors = detectors.clone();
+            let generation_detectors = generation_detectors.clone();
+            let prompt = prompt.clone();
+            async move {
+                let mut detections = Detections::new();
+                if !detectors.is_empty() {
+                    let (_, contents_detections) = text_contents_detections(
+                        ctx.clone(),
+                        headers.clone(),
+                        detectors,
+                        input_id,
+                        vec![(0, text.clone())],
+                    )
+                    .await?;
+                    detections.extend(contents_detections);
+                }
+                if !generation_detectors.is_empty() {
+                    let generation_detections = text_generation_detections(
+                        ctx,
+                        headers,
+                        generation_detectors,
+                        prompt,
+                        text.clone(),
+                    )
+                    .await?;
+                    // Text generation detections apply to the whole text
+                    let end = text.chars().count();
+                    detections.extend(generation_detections.into_iter().map(|mut detection| {
+                        detection.start = Some(0);
+                        detection.end = Some(end);
+                        detection.text = Some(text.clone());
+                        detection
+                    }));
+                }
+                Ok::<_, Error>((input_id, detections))
+            }
+            .in_current_span()
+        })
+        .buffer_unordered(ctx.config.detector_concurrent_requests)
+        .try_collect::<Vec<_>>()
+        .await
+}
+
 /// Spawns text chat detection tasks.
 /// Returns a vec of detections.
 #[instrument(skip_all)]
@@ -392,6 +594,7 @@ pub async fn text_chat_detections(
             let threshold = params.pop_threshold().unwrap_or(default_threshold);
             async move {
                 let client = ctx.clients.get_as::<DetectorClient>(&detector_id).unwrap();
+                let _permit = acquire_detector(&ctx, &detector_id).await?;
                 let detections = detect_text_chat(
                     client,
                     headers,
@@ -449,6 +652,7 @@ pub async fn text_context_detections(
                 let threshold = params.pop_threshold().unwrap_or(default_threshold);
                 async move {
                     let client = ctx.clients.get_as::<DetectorClient>(&detector_id).unwrap();
+                    let _permit = acquire_detector(&ctx, &detector_id).await?;
                     let detections = detect_text_context(
                         client,
                         headers,
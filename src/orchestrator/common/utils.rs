@@ -22,7 +22,10 @@ use crate::{
     clients::chunker::DEFAULT_CHUNKER_ID,
     config::{DetectorConfig, DetectorType},
     models::DetectorParams,
-    orchestrator::{Context, Error},
+    orchestrator::{
+        Context, Error,
+        types::{Detection, Detections},
+    },
 };
 
 /// Slices chars between start and end indices.
@@ -116,6 +119,115 @@ pub fn configure_mock_servers(
     };
 }
 
+/// Returns `true` if a detector is applied to the whole output of a stream,
+/// i.e. it uses `whole_doc_chunker` or does not support text contents detection.
+pub fn is_whole_output_detector(
+    detector_id: &str,
+    orchestrator_detectors: &HashMap<String, DetectorConfig>,
+) -> bool {
+    orchestrator_detectors
+        .get(detector_id)
+        .is_some_and(|detector_config| {
+            detector_config.chunker_id == DEFAULT_CHUNKER_ID
+                || !detector_config.r#type.contains(&DetectorType::TextContents)
+        })
+}
+
+/// Removes `stop_on` params from detector params.
+/// Returns a map of detector_id->score at or above which generation is stopped.
+pub fn pop_stop_on(detectors: &mut HashMap<String, DetectorParams>) -> HashMap<String, f64> {
+    detectors
+        .iter_mut()
+        .filter_map(|(detector_id, params)| {
+            params
+                .pop_stop_on()
+                .map(|score| (detector_id.clone(), score))
+        })
+        .collect()
+}
+
+/// Returns the first detection triggering a `stop_on` policy, if any.
+pub fn find_stop_detection<'a>(
+    stop_on: &HashMap<String, f64>,
+    detections: &'a Detections,
+) -> Option<&'a Detection> {
+    detections.iter().find(|detection| {
+        detection
+            .detector_id
+            .as_ref()
+            .and_then(|detector_id| stop_on.get(detector_id))
+            .is_some_and(|score| detection.score >= *score)
+    })
+}
+
+/// Returns the string leaf values of a JSON value with their JSON Pointer paths,
+/// in document order. Object keys are not included.
+pub fn json_string_values(value: &serde_json::Value) -> Vec<(String, &str)> {
+    fn collect<'a>(
+        value: &'a serde_json::Value,
+        path: String,
+        values: &mut Vec<(String, &'a str)>,
+    ) {
+        match value {
+            serde_json::Value::String(s) => values.push((path, s)),
+            serde_json::Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    collect(item, format!("{path}/{index}"), values);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for (key, item) in map {
+                    // Escape reference tokens as specified by RFC 6901
+                    let key = key.replace('~', "~0").replace('/', "~1");
+                    collect(item, format!("{path}/{key}"), values);
+                }
+            }
+            _ => (),
+        }
+    }
+    let mut values = Vec::new();
+    collect(value, String::new(), &mut values);
+    values
+}
+
+/// Returns the character offsets of string leaf values in a JSON document, in document
+/// order. Offsets point to the first character after the opening quote.
+pub fn json_string_offsets(json: &str) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    // Start of the last closed string, until it is known whether it is a key
+    let mut start = 0;
+    let mut pending = None;
+    for (index, c) in json.chars().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                pending = Some(start);
+            }
+            continue;
+        }
+        if let Some(offset) = pending {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c != ':' {
+                offsets.push(offset);
+            }
+            pending = None;
+        }
+        if c == '"' {
+            in_string = true;
+            start = index + 1;
+        }
+    }
+    offsets.extend(pending);
+    offsets
+}
+
 /// Validates requested detectors.
 pub fn validate_detectors<'a>(
     detectors: impl IntoIterator<Item = (&'a String, &'a DetectorParams)>,
@@ -181,6 +293,62 @@ mod tests {
         assert_eq!(slice_codepoints(s, 3, 4), "界");
     }
 
+    #[test]
+    fn test_stop_on() {
+        let mut pii_params = DetectorParams::new();
+        pii_params.insert("stop_on".into(), 0.8.into());
+        let mut detectors = HashMap::from([
+            ("pii".to_string(), pii_params),
+            ("hap".to_string(), DetectorParams::new()),
+        ]);
+        let stop_on = pop_stop_on(&mut detectors);
+        assert_eq!(stop_on, HashMap::from([("pii".to_string(), 0.8)]));
+        assert!(detectors["pii"].is_empty());
+
+        let detection = |detector_id: &str, score: f64| Detection {
+            detector_id: Some(detector_id.into()),
+            score,
+            ..Default::default()
+        };
+        let detections: Detections = vec![detection("hap", 0.9), detection("pii", 0.5)].into();
+        assert!(find_stop_detection(&stop_on, &detections).is_none());
+        let detections: Detections = vec![detection("hap", 0.9), detection("pii", 0.8)].into();
+        assert_eq!(
+            find_stop_detection(&stop_on, &detections),
+            Some(&detection("pii", 0.8))
+        );
+    }
+
+    #[test]
+    fn test_json_string_values() {
+        let value = serde_json::json!({
+            "name": "Alice",
+            "age": 30,
+            "tags": ["a", 1, "b"],
+            "a/b": { "c~d": "escaped" },
+        });
+        assert_eq!(
+            json_string_values(&value),
+            vec![
+                ("/name".to_string(), "Alice"),
+                ("/tags/0".to_string(), "a"),
+                ("/tags/2".to_string(), "b"),
+                ("/a~1b/c~0d".to_string(), "escaped"),
+            ]
+        );
+        assert_eq!(
+            json_string_values(&serde_json::json!("root")),
+            vec![(String::new(), "root")]
+        );
+    }
+
+    #[test]
+    fn test_json_string_offsets() {
+        let json = r#"{"name": "Alice", "tags": ["a\"b", 1, "ü"], "k": {"x":"y"}}"#;
+        assert_eq!(json_string_offsets(json), vec![10, 28, 39, 55]);
+        assert_eq!(json_string_offsets(r#" "root" "#), vec![2]);
+    }
+
     #[test]
     fn test_validate_detectors() -> Result<(), Error> {
         let orchestrator_detectors = HashMap::from([
@@ -27,6 +27,8 @@ pub mod streaming_content_detection;
 pub use streaming_content_detection::StreamingContentDetectionTask;
 pub mod generation_with_detection;
 pub use generation_with_detection::GenerationWithDetectionTask;
+pub mod streaming_generation_with_detection;
+pub use streaming_generation_with_detection::StreamingGenerationWithDetectionTask;
 pub mod chat_detection;
 pub use chat_detection::ChatDetectionTask;
 pub mod context_docs_detection;
@@ -35,6 +37,8 @@ pub mod detection_on_generation;
 pub use detection_on_generation::DetectionOnGenerationTask;
 pub mod text_content_detection;
 pub use text_content_detection::TextContentDetectionTask;
+pub mod batch_content_detection;
+pub use batch_content_detection::BatchContentDetectionTask;
 
 use super::Error;
 
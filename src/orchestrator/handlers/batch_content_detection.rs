@@ -0,0 +1,198 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+use std::{collections::HashMap, sync::Arc};
+
+use futures::StreamExt;
+use http::HeaderMap;
+use opentelemetry::trace::TraceId;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{Instrument, info, instrument};
+
+use super::Handle;
+use crate::{
+    config::DetectorType,
+    models::{BatchContentDetectionItem, DetectorParams, TextContentDetectionResult},
+    orchestrator::{
+        Context, Error, Orchestrator,
+        common::{self, validate_detectors},
+        types::BoxStream,
+    },
+    server::Principal,
+};
+
+const BATCH_CONTENT_DETECTION_ROUTE: &str = "/api/v2/text/detection/content/batch";
+
+impl Handle<BatchContentDetectionTask> for Orchestrator {
+    type Response = ReceiverStream<BatchItemResult>;
+
+    #[instrument(
+        name = "batch_content_detection",
+        skip_all,
+        fields(trace_id = ?task.trace_id, headers = ?task.headers)
+    )]
+    async fn handle(&self, task: BatchContentDetectionTask) -> Result<Self::Response, Error> {
+        let ctx = self.ctx.clone();
+        let trace_id = task.trace_id;
+        info!(%trace_id, config = ?task.detectors, "task started");
+
+        validate_detectors(
+            &task.detectors,
+            &ctx.config.detectors,
+            &[DetectorType::TextContents],
+            true,
+        )?;
+
+        // Create response channel
+        let (response_tx, response_rx) = mpsc::channel::<BatchItemResult>(128);
+
+        tokio::spawn(
+            async move {
+                let concurrency = ctx.config.batch_concurrent_items;
+                let headers = task.headers;
+                let detectors = task.detectors;
+                let principal = task.principal;
+                // Process items concurrently, results are returned in input order
+                let mut results = task
+                    .items
+                    .map(|(index, result)| {
+                        handle_item(
+                            ctx.clone(),
+                            trace_id,
+                            principal.clone(),
+                            headers.clone(),
+                            detectors.clone(),
+                            index,
+                            result,
+                        )
+                    })
+                    .buffered(concurrency);
+                let mut count = 0;
+                while let Some(result) = results.next().await {
+                    if response_tx.send(result).await.is_err() {
+                        info!(%trace_id, "task completed: client disconnected");
+                        return;
+                    }
+                    count += 1;
+                }
+                info!(%trace_id, count, "task completed");
+            }
+            .in_current_span(),
+        );
+
+        Ok(ReceiverStream::new(response_rx))
+    }
+}
+
+/// Runs detections on a batch item.
+#[instrument(skip_all, fields(index))]
+async fn handle_item(
+    ctx: Arc<Context>,
+    trace_id: TraceId,
+    principal: Option<Principal>,
+    headers: HeaderMap,
+    detectors: HashMap<String, DetectorParams>,
+    index: usize,
+    item: Result<BatchContentDetectionItem, Error>,
+) -> BatchItemResult {
+    let item = match item {
+        Ok(item) => item,
+        Err(error) => {
+            return BatchItemResult {
+                index,
+                id: None,
+                result: Err(error),
+            };
+        }
+    };
+    if let Err(error) = item.validate() {
+        return BatchItemResult {
+            index,
+            id: Some(item.id),
+            result: Err(error.into()),
+        };
+    }
+    let audit = ctx.audit.entry(
+        trace_id,
+        BATCH_CONTENT_DETECTION_ROUTE,
+        principal.as_ref(),
+        &detectors,
+    );
+    let result = common::text_contents_detections(
+        ctx.clone(),
+        headers,
+        detectors,
+        0,
+        vec![(0, item.content)],
+    )
+    .await
+    .map(|(_, detections)| TextContentDetectionResult {
+        detections: detections.into(),
+    });
+    BatchItemResult {
+        index,
+        id: Some(item.id),
+        result: audit.record(result),
+    }
+}
+
+/// Result of a batch item.
+#[derive(Debug)]
+pub struct BatchItemResult {
+    /// Position of the item in the request
+    pub index: usize,
+    /// ID of the item, if it could be parsed
+    pub id: Option<String>,
+    /// Detection result
+    pub result: Result<TextContentDetectionResult, Error>,
+}
+
+pub struct BatchContentDetectionTask {
+    /// Trace ID
+    pub trace_id: TraceId,
+    /// Headers
+    pub headers: HeaderMap,
+    /// Detectors configuration, shared by all items
+    pub detectors: HashMap<String, DetectorParams>,
+    /// Items to run detections on
+    pub items: BoxStream<(usize, Result<BatchContentDetectionItem, Error>)>,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
+}
+
+impl BatchContentDetectionTask {
+    pub fn new(
+        trace_id: TraceId,
+        headers: HeaderMap,
+        detectors: HashMap<String, DetectorParams>,
+        items: BoxStream<(usize, Result<BatchContentDetectionItem, Error>)>,
+    ) -> Self {
+        Self {
+            trace_id,
+            headers,
+            detectors,
+            items,
+            principal: None,
+        }
+    }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
+}
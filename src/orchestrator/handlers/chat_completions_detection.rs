@@ -14,14 +14,34 @@
  limitations under the License.
 
 */
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use futures::{StreamExt, TryStreamExt, future::try_join_all, stream};
 use http::HeaderMap;
 use opentelemetry::trace::TraceId;
-use tracing::instrument;
+use tracing::{Instrument, debug, instrument, warn};
 
 use super::Handle;
 use crate::{
-    clients::openai::{ChatCompletionsRequest, ChatCompletionsResponse},
-    orchestrator::{Error, Orchestrator},
+    clients::openai::{
+        ChatCompletionsRequest, ChatCompletionsResponse, CompletionDetectionWarning,
+        CompletionDetections, CompletionInputDetections, Role,
+    },
+    config::MessageSelection,
+    models::{
+        DetectionWarningReason, DetectorParams, THRESHOLD_PARAM, UNSUITABLE_CONVERSATION_MESSAGE,
+        UNSUITABLE_INPUT_MESSAGE,
+    },
+    orchestrator::{
+        Context, Error, Orchestrator, common,
+        sessions::SessionKey,
+        system_prompts::SystemPromptCache,
+        types::{ChatMessageIterator, Detections},
+    },
+    server::Principal,
 };
 
 pub mod streaming;
@@ -37,9 +57,18 @@ impl Handle<ChatCompletionsDetectionTask> for Orchestrator {
     )]
     async fn handle(&self, task: ChatCompletionsDetectionTask) -> Result<Self::Response, Error> {
         let ctx = self.ctx.clone();
+        let audit = ctx.audit.entry(
+            task.trace_id,
+            "/api/v2/chat/completions-detection",
+            task.principal.as_ref(),
+            &task.request.detectors,
+        );
         match task.request.stream {
-            Some(true) => streaming::handle_streaming(ctx, task).await,
-            _ => unary::handle_unary(ctx, task).await,
+            Some(true) => streaming::handle_streaming(ctx, task, audit).await,
+            _ => {
+                let result = unary::handle_unary(ctx, task).await;
+                audit.record(result)
+            }
         }
     }
 }
@@ -52,6 +81,8 @@ pub struct ChatCompletionsDetectionTask {
     pub request: ChatCompletionsRequest,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl ChatCompletionsDetectionTask {
@@ -60,6 +91,315 @@ impl ChatCompletionsDetectionTask {
             trace_id,
             request,
             headers,
+            principal: None,
+        }
+    }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
+}
+
+/// Input detections on the selected messages of a chat completions request.
+#[derive(Debug, Default)]
+pub struct InputDetections {
+    /// Detections above threshold by message index, messages without detections are omitted
+    pub detections: Vec<(u32, Detections)>,
+    /// Detector whose cumulative conversation risk reached its limit, set if the conversation is blocked
+    pub blocked_by: Option<String>,
+}
+
+impl InputDetections {
+    /// Returns `true` if there are no detections and the conversation is not blocked.
+    pub fn is_empty(&self) -> bool {
+        self.detections.is_empty() && self.blocked_by.is_none()
+    }
+
+    /// Converts to completion detections and warnings.
+    pub fn into_completion_detections(
+        self,
+    ) -> (
+        Option<CompletionDetections>,
+        Vec<CompletionDetectionWarning>,
+    ) {
+        let mut warnings = Vec::new();
+        if !self.detections.is_empty() {
+            warnings.push(CompletionDetectionWarning::new(
+                DetectionWarningReason::UnsuitableInput,
+                UNSUITABLE_INPUT_MESSAGE,
+            ));
+        }
+        if self.blocked_by.is_some() {
+            warnings.push(CompletionDetectionWarning::new(
+                DetectionWarningReason::UnsuitableConversation,
+                UNSUITABLE_CONVERSATION_MESSAGE,
+            ));
+        }
+        let input = self
+            .detections
+            .into_iter()
+            .map(|(message_index, detections)| CompletionInputDetections {
+                message_index,
+                results: detections.into(),
+            })
+            .collect::<Vec<_>>();
+        let detections = (!input.is_empty()).then(|| CompletionDetections {
+            input,
+            ..Default::default()
+        });
+        (detections, warnings)
+    }
+}
+
+/// Runs input detectors on their selected messages.
+///
+/// Detectors are applied to the last message unless another selection is set with the
+/// `messages` detector parameter or the detector's `input_messages` config. Detectors with
+/// `input_system_prompts` enabled are also applied to system messages, detections on system
+/// messages are cached by system prompt.
+///
+/// If the request has a session ID, the highest score of each detector across new messages is
+/// added to the cumulative conversation risk of the session, including scores below threshold.
+/// Messages beyond the message count of the previous turn are new; if the request does not
+/// extend the previous history, e.g. it only sends the latest message, all messages are new.
+/// Sessions are scoped to the authenticated caller. Blocked conversations are not sent to
+/// detectors.
+#[instrument(skip_all)]
+pub async fn input_detections(
+    ctx: Arc<Context>,
+    task: &ChatCompletionsDetectionTask,
+    mut detectors: HashMap<String, DetectorParams>,
+) -> Result<InputDetections, Error> {
+    let risk_config = ctx
+        .config
+        .sessions
+        .as_ref()
+        .and_then(|sessions| sessions.risk.as_ref());
+    let mut risk = None;
+    match (&task.request.session_id, risk_config) {
+        (Some(session_id), _) => {
+            let (Some(sessions), Some(risk_config)) = (&ctx.sessions, risk_config) else {
+                return Err(Error::Validation(
+                    "`session_id` is not supported as conversation risk tracking is not enabled"
+                        .into(),
+                ));
+            };
+            let key = SessionKey::new(task.principal.as_ref(), session_id.as_str());
+            let session = sessions.get(&key).await?.unwrap_or_default();
+            if let Some(detector_id) = &session.risk.blocked_by {
+                return Ok(InputDetections {
+                    detections: Vec::new(),
+                    blocked_by: Some(detector_id.clone()),
+                });
+            }
+            // Messages of the previous turn are only skipped if the request extends its history
+            let new_messages = if task.request.messages.len() > session.risk.messages {
+                session.risk.messages
+            } else {
+                0
+            };
+            risk = Some((key, sessions, risk_config, new_messages));
+        }
+        (None, Some(risk_config)) if risk_config.require_session_id => {
+            return Err(Error::Validation(
+                "`session_id` is required as conversation risk tracking is enabled".into(),
+            ));
+        }
+        _ => (),
+    }
+
+    // Run detectors without threshold to track scores below threshold
+    let mut thresholds = HashMap::new();
+    if risk.is_some() {
+        for (detector_id, params) in detectors.iter_mut() {
+            let default_threshold = ctx
+                .config
+                .detector(detector_id)
+                .map(|config| config.default_threshold)
+                .unwrap_or_default();
+            let threshold = params.pop_threshold().unwrap_or(default_threshold);
+            thresholds.insert(detector_id.clone(), threshold);
+            params.insert(THRESHOLD_PARAM.into(), 0.0.into());
+        }
+    }
+
+    // Run detectors on selected messages
+    let inputs = select_messages(&ctx, &task.request, detectors)?;
+    let mut results = stream::iter(inputs)
+        .map(|(message_index, (text, detectors))| {
+            let ctx = ctx.clone();
+            let headers = task.headers.clone();
+            async move {
+                if task.request.messages[message_index as usize].role == Role::System {
+                    system_prompt_detections(ctx, headers, detectors, message_index, text).await
+                } else {
+                    common::text_contents_detections(
+                        ctx,
+                        headers,
+                        detectors,
+                        message_index,
+                        vec![(0, text)],
+                    )
+                    .await
+                }
+            }
+            .in_current_span()
+        })
+        .buffer_unordered(ctx.config.detector_concurrent_requests)
+        .try_collect::<Vec<_>>()
+        .await?;
+    results.sort_by_key(|(message_index, _)| *message_index);
+
+    let mut blocked_by = None;
+    if let Some((key, sessions, risk_config, new_messages)) = risk {
+        // Update conversation risk with the highest score of each detector on new messages
+        let mut turn_scores = thresholds
+            .keys()
+            .map(|detector_id| (detector_id.clone(), 0.0))
+            .collect::<HashMap<_, _>>();
+        for detection in results
+            .iter()
+            .filter(|(message_index, _)| *message_index as usize >= new_messages)
+            .flat_map(|(_, detections)| detections.iter())
+        {
+            if let Some(score) = detection
+                .detector_id
+                .as_ref()
+                .and_then(|detector_id| turn_scores.get_mut(detector_id))
+            {
+                *score = score.max(detection.score);
+            }
+        }
+        // Update the current session, as turns of the conversation may overlap
+        let message_count = task.request.messages.len();
+        let session = sessions
+            .update(&key, &mut |session| {
+                session.risk.update(turn_scores.clone(), risk_config);
+                session.risk.messages = message_count;
+            })
+            .await?;
+        blocked_by = session.risk.blocked_by;
+        if let Some(detector_id) = &blocked_by {
+            warn!(
+                trace_id = %task.trace_id,
+                session_id = key.session_id,
+                detector_id,
+                "conversation blocked: cumulative risk reached limit"
+            );
+        }
+    }
+
+    // Apply thresholds
+    let detections = results
+        .into_iter()
+        .map(|(message_index, detections)| {
+            let detections = detections
+                .into_iter()
+                .filter(|detection| {
+                    detection
+                        .detector_id
+                        .as_ref()
+                        .and_then(|detector_id| thresholds.get(detector_id))
+                        .is_none_or(|threshold| detection.score >= *threshold)
+                })
+                .collect::<Detections>();
+            (message_index, detections)
+        })
+        .filter(|(_, detections)| !detections.is_empty())
+        .collect();
+    Ok(InputDetections {
+        detections,
+        blocked_by,
+    })
+}
+
+/// Runs input detectors on a system message, using cached detections of the system prompt
+/// where available.
+async fn system_prompt_detections(
+    ctx: Arc<Context>,
+    headers: HeaderMap,
+    detectors: HashMap<String, DetectorParams>,
+    message_index: u32,
+    text: String,
+) -> Result<(u32, Detections), Error> {
+    let results = try_join_all(detectors.into_iter().map(|(detector_id, params)| {
+        let ctx = ctx.clone();
+        let headers = headers.clone();
+        let text = text.clone();
+        async move {
+            let key = SystemPromptCache::key(&detector_id, &params, &text);
+            if let Some(detections) = ctx.system_prompts.get(&key) {
+                debug!(detector_id, "using cached system prompt detections");
+                return Ok(detections);
+            }
+            let (_, detections) = common::text_contents_detections(
+                ctx.clone(),
+                headers,
+                HashMap::from([(detector_id, params)]),
+                message_index,
+                vec![(0, text)],
+            )
+            .await?;
+            ctx.system_prompts.insert(key, detections.clone());
+            Ok::<_, Error>(detections)
+        }
+    }))
+    .await?;
+    let mut detections = results.into_iter().flatten().collect::<Detections>();
+    detections.sort_by_key(|detection| detection.start);
+    Ok((message_index, detections))
+}
+
+/// Selects the messages each input detector is applied to.
+/// Returns the text and detectors of each selected message by message index.
+fn select_messages(
+    ctx: &Context,
+    request: &ChatCompletionsRequest,
+    detectors: HashMap<String, DetectorParams>,
+) -> Result<BTreeMap<u32, (String, HashMap<String, DetectorParams>)>, Error> {
+    let messages = request.messages().collect::<Vec<_>>();
+    let Some((last_index, last_role)) =
+        messages.last().map(|message| (message.index, message.role))
+    else {
+        return Err(Error::Validation("No messages provided".into()));
+    };
+    let mut inputs: BTreeMap<u32, (String, HashMap<String, DetectorParams>)> = BTreeMap::new();
+    for (detector_id, mut params) in detectors {
+        let config = ctx.config.detector(&detector_id);
+        let selection = params
+            .pop_messages()?
+            .or_else(|| config.map(|config| config.input_messages.clone()))
+            .unwrap_or_default();
+        let system_prompts = config.is_some_and(|config| config.input_system_prompts);
+        if selection == MessageSelection::Last
+            && !matches!(
+                last_role,
+                Some(Role::User) | Some(Role::Assistant) | Some(Role::System)
+            )
+        {
+            return Err(Error::Validation(
+                "Last message role must be user, assistant, or system".into(),
+            ));
+        }
+        for message in &messages {
+            let is_system = message.role == Some(&Role::System);
+            if !selection.selects(message.role, message.index == last_index)
+                && !(system_prompts && is_system)
+            {
+                continue;
+            }
+            // Messages without text content are skipped
+            let Some(text) = message.text.filter(|text| !text.is_empty()) else {
+                continue;
+            };
+            inputs
+                .entry(message.index)
+                .or_insert_with(|| (text.to_string(), HashMap::new()))
+                .1
+                .insert(detector_id.clone(), params.clone());
         }
     }
+    Ok(inputs)
 }
@@ -14,24 +14,29 @@
  limitations under the License.
 
 */
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
-use futures::{StreamExt, TryStreamExt, stream};
+use futures::StreamExt;
 use opentelemetry::trace::TraceId;
 use tokio::sync::mpsc;
 use tracing::{Instrument, debug, error, info, instrument, warn};
 use uuid::Uuid;
 
-use super::ChatCompletionsDetectionTask;
+use super::{ChatCompletionsDetectionTask, input_detections};
 use crate::{
     clients::openai::*,
     config::DetectorType,
     models::{
-        DetectionWarningReason, DetectorParams, UNSUITABLE_INPUT_MESSAGE, UNSUITABLE_OUTPUT_MESSAGE,
+        DEFAULT_REFUSAL_MESSAGE, DetectionWarningReason, DetectorParams,
+        GUARDRAILS_STOP_FINISH_REASON, UNSUITABLE_OUTPUT_MESSAGE,
     },
     orchestrator::{
         Context, Error,
-        common::{self, text_contents_detections, validate_detectors},
+        audit::AuditEntry,
+        common::{self, validate_detectors},
         types::{
             ChatCompletionStream, ChatMessageIterator, Chunk, CompletionBatcher, CompletionState,
             DetectionBatchStream, Detections,
@@ -42,6 +47,7 @@ use crate::{
 pub async fn handle_streaming(
     ctx: Arc<Context>,
     task: ChatCompletionsDetectionTask,
+    audit: AuditEntry,
 ) -> Result<ChatCompletionsResponse, Error> {
     let trace_id = task.trace_id;
     let detectors = task.request.detectors.clone();
@@ -50,18 +56,45 @@ pub async fn handle_streaming(
     // Create response channel
     let (response_tx, response_rx) =
         mpsc::channel::<Result<Option<ChatCompletionChunk>, Error>>(128);
+    let response_tx = audit.record_stream(response_tx);
 
     tokio::spawn(
         async move {
             let input_detectors = detectors.input;
             let output_detectors = detectors.output;
 
+            // Text generation detectors are supported on output detection
+            // as they are applied to the whole output at the end of the stream
             if let Err(error) = validate_detectors(
-                input_detectors.iter().chain(output_detectors.iter()),
+                &input_detectors,
                 &ctx.config.detectors,
                 &[DetectorType::TextContents],
                 true,
-            ) {
+            )
+            .and_then(|_| {
+                validate_detectors(
+                    &output_detectors,
+                    &ctx.config.detectors,
+                    &[DetectorType::TextContents, DetectorType::TextGeneration],
+                    true,
+                )
+            }) {
+                let _ = response_tx.send(Err(error)).await;
+                // Send None to signal completion
+                let _ = response_tx.send(Ok(None)).await;
+                return;
+            }
+
+            // Gated mode releases chunks as they pass detection, so
+            // detectors applied to the whole output are not supported
+            if task.request.stream_mode == StreamMode::Gated
+                && let Some(detector_id) = output_detectors.keys().find(|detector_id| {
+                    common::is_whole_output_detector(detector_id, &ctx.config.detectors)
+                })
+            {
+                let error = Error::Validation(format!(
+                    "detector `{detector_id}` is applied to the whole output, which is not supported in gated stream mode"
+                ));
                 let _ = response_tx.send(Err(error)).await;
                 // Send None to signal completion
                 let _ = response_tx.send(Ok(None)).await;
@@ -142,57 +175,22 @@ async fn handle_input_detection(
     let trace_id = task.trace_id;
     let model_id = task.request.model.clone();
 
-    // Input detectors are only applied to the last message
-    // Get the last message
-    let messages = task.request.messages();
-    let message = if let Some(message) = messages.last() {
-        message
-    } else {
-        return Err(Error::Validation("No messages provided".into()));
-    };
-    // Validate role
-    if !matches!(
-        message.role,
-        Some(Role::User) | Some(Role::Assistant) | Some(Role::System)
-    ) {
-        return Err(Error::Validation(
-            "Last message role must be user, assistant, or system".into(),
-        ));
-    }
-    let input_id = message.index;
-    let input_text = message.text.map(|s| s.to_string()).unwrap_or_default();
-    let detections = match common::text_contents_detections(
-        ctx.clone(),
-        task.headers.clone(),
-        detectors.clone(),
-        input_id,
-        vec![(0, input_text)],
-    )
-    .await
-    {
-        Ok((_, detections)) => detections,
+    let input_detections = match input_detections(ctx.clone(), task, detectors).await {
+        Ok(input_detections) => input_detections,
         Err(error) => {
             error!(%trace_id, %error, "task failed: error processing input detections");
             return Err(error);
         }
     };
-    if !detections.is_empty() {
+    if !input_detections.is_empty() {
         // Build chat completion chunk with input detections
+        let (detections, warnings) = input_detections.into_completion_detections();
         let chunk = ChatCompletionChunk {
             id: Uuid::new_v4().simple().to_string(),
             model: model_id,
             created: common::current_timestamp().as_secs() as i64,
-            detections: Some(CompletionDetections {
-                input: vec![CompletionInputDetections {
-                    message_index: message.index,
-                    results: detections.into(),
-                }],
-                ..Default::default()
-            }),
-            warnings: vec![CompletionDetectionWarning::new(
-                DetectionWarningReason::UnsuitableInput,
-                UNSUITABLE_INPUT_MESSAGE,
-            )],
+            detections,
+            warnings,
             ..Default::default()
         };
         Ok(Some(chunk))
@@ -206,21 +204,24 @@ async fn handle_input_detection(
 async fn handle_output_detection(
     ctx: Arc<Context>,
     task: &ChatCompletionsDetectionTask,
-    detectors: HashMap<String, DetectorParams>,
+    mut detectors: HashMap<String, DetectorParams>,
     chat_completion_stream: ChatCompletionStream,
     response_tx: mpsc::Sender<Result<Option<ChatCompletionChunk>, Error>>,
 ) {
     let trace_id = task.trace_id;
     let request = task.request.clone();
+    // Get stop_on policy, applied to detections on chunks
+    let stop_on = common::pop_stop_on(&mut detectors);
     // Split output detectors into 2 groups:
     // 1) Output Detectors: Applied to chunks. Detections are returned in batches.
     // 2) Whole Doc Output Detectors: Applied to concatenated chunks (whole doc) after the chat completion stream has been consumed.
-    // Currently, this is any detector that uses "whole_doc_chunker".
+    // This is any detector that uses "whole_doc_chunker" or is a text generation detector.
     let (whole_doc_detectors, detectors): (HashMap<_, _>, HashMap<_, _>) =
         detectors.into_iter().partition(|(detector_id, _)| {
-            ctx.config.get_chunker_id(detector_id).unwrap() == "whole_doc_chunker"
+            common::is_whole_output_detector(detector_id, &ctx.config.detectors)
         });
     let completion_state = Arc::new(CompletionState::new());
+    let mut terminated = HashSet::new();
 
     if !detectors.is_empty() {
         // Set up streaming detection pipeline
@@ -259,23 +260,39 @@ async fn handle_output_detection(
         }
 
         // Spawn task to consume chat completions stream and send choice text to detection pipeline
-        tokio::spawn(process_chat_completion_stream(
+        let chat_completion_task = tokio::spawn(process_chat_completion_stream(
             trace_id,
             chat_completion_stream,
             Some(completion_state.clone()),
             Some(input_txs),
             None,
         ));
+        // In gated mode, chunks with detections are replaced with a refusal message
+        let refusal_message = (request.stream_mode == StreamMode::Gated).then(|| {
+            ctx.config
+                .openai
+                .as_ref()
+                .and_then(|openai| openai.refusal_message.clone())
+                .unwrap_or_else(|| DEFAULT_REFUSAL_MESSAGE.into())
+        });
         // Process detection streams and await completion
         let detection_batch_stream =
             DetectionBatchStream::new(CompletionBatcher::new(detectors.len()), detection_streams);
-        process_detection_batch_stream(
+        terminated = process_detection_batch_stream(
             trace_id,
             completion_state.clone(),
             detection_batch_stream,
+            n,
+            refusal_message,
+            &stop_on,
             response_tx.clone(),
         )
         .await;
+        if terminated.len() == n {
+            // Cancel the chat completions stream and skip final message
+            chat_completion_task.abort();
+            return;
+        }
     } else {
         // We only have whole doc detectors, so the streaming detection pipeline is disabled
         // Consume chat completions stream and await completion
@@ -300,12 +317,13 @@ async fn handle_output_detection(
             ..Default::default()
         };
         if !whole_doc_detectors.is_empty() {
-            // Handle whole doc output detection
+            // Handle whole doc output detection, excluding terminated choices
             match handle_whole_doc_output_detection(
                 ctx.clone(),
                 task,
                 whole_doc_detectors,
                 completion_state,
+                &terminated,
             )
             .await
             {
@@ -423,11 +441,13 @@ async fn handle_whole_doc_output_detection(
     task: &ChatCompletionsDetectionTask,
     detectors: HashMap<String, DetectorParams>,
     completion_state: Arc<CompletionState<ChatCompletionChunk>>,
+    terminated: &HashSet<u32>,
 ) -> Result<(CompletionDetections, Vec<CompletionDetectionWarning>), Error> {
-    // Create vec of choice_index->inputs, where inputs contains the concatenated text for the choice
+    // Create vec of choice_index->text, where text is the concatenated text for the choice
     let choice_inputs = completion_state
         .completions
         .iter()
+        .filter(|entry| !terminated.contains(entry.key()))
         .map(|entry| {
             let choice_index = *entry.key();
             let text = entry
@@ -440,29 +460,33 @@ async fn handle_whole_doc_output_detection(
                         .unwrap_or_default()
                 })
                 .collect::<String>();
-            let inputs = vec![(0usize, text)];
-            (choice_index, inputs)
+            (choice_index, text)
         })
         .collect::<Vec<_>>();
-    // Process detections concurrently for choices
-    let choice_detections = stream::iter(choice_inputs)
-        .map(|(choice_index, inputs)| {
-            text_contents_detections(
-                ctx.clone(),
-                task.headers.clone(),
-                detectors.clone(),
-                choice_index,
-                inputs,
-            )
-        })
-        .buffer_unordered(ctx.config.detector_concurrent_requests)
-        .try_collect::<Vec<_>>()
-        .await?;
+    // Text generation detectors use the last user message as prompt
+    let prompt = task
+        .request
+        .messages()
+        .iter()
+        .rev()
+        .find(|message| matches!(message.role, Some(Role::User)))
+        .and_then(|message| message.text.map(|s| s.to_string()))
+        .unwrap_or_default();
+    let choice_detections = common::whole_doc_output_detections(
+        ctx,
+        task.headers.clone(),
+        detectors,
+        prompt,
+        choice_inputs,
+    )
+    .await?;
     // Build output detections
     let output = choice_detections
         .into_iter()
         .map(|(choice_index, detections)| CompletionOutputDetections {
             choice_index,
+            path: None,
+            offset: None,
             results: detections.into(),
         })
         .collect::<Vec<_>>();
@@ -519,6 +543,8 @@ fn output_detection_response(
         chat_completion.detections = Some(CompletionDetections {
             output: vec![CompletionOutputDetections {
                 choice_index,
+                path: None,
+                offset: None,
                 results: detections.into(),
             }],
             ..Default::default()
@@ -552,24 +578,60 @@ fn merge_logprobs(chat_completions: &[ChatCompletionChunk]) -> Option<ChatComple
 }
 
 /// Consumes a detection batch stream, builds responses, and sends them to a response channel.
+///
+/// If a refusal message is provided (gated mode), the first chunk with detections
+/// of a choice is replaced with the refusal message and the choice is terminated.
+/// If a detection triggers the `stop_on` policy, the chunk is sent with a
+/// guardrails finish reason and the choice is terminated. Other choices continue,
+/// the stream is terminated once all `n` choices are terminated.
+/// Returns the choices terminated early.
 async fn process_detection_batch_stream(
     trace_id: TraceId,
     completion_state: Arc<CompletionState<ChatCompletionChunk>>,
     mut detection_batch_stream: DetectionBatchStream,
+    n: usize,
+    refusal_message: Option<String>,
+    stop_on: &HashMap<String, f64>,
     response_tx: mpsc::Sender<Result<Option<ChatCompletionChunk>, Error>>,
-) {
+) -> HashSet<u32> {
+    let mut terminated = HashSet::with_capacity(n);
     while let Some(result) = detection_batch_stream.next().await {
         match result {
+            Ok((choice_index, _, _)) if terminated.contains(&choice_index) => {
+                // Skip chunks of terminated choices
+                continue;
+            }
             Ok((choice_index, chunk, detections)) => {
                 let input_end_index = chunk.input_end_index;
+                let refused = refusal_message.is_some() && !detections.is_empty();
+                let stop_detection = common::find_stop_detection(stop_on, &detections).cloned();
                 match output_detection_response(&completion_state, choice_index, chunk, detections)
                 {
+                    Ok(mut chat_completion) if refused => {
+                        // Replace chunk content with refusal message and terminate
+                        let choice = &mut chat_completion.choices[0];
+                        choice.delta.content = None;
+                        choice.delta.refusal = refusal_message.clone();
+                        choice.logprobs = None;
+                        choice.finish_reason = Some("content_filter".into());
+                        info!(%trace_id, %choice_index, "choice completed: output refused in gated mode");
+                        let _ = response_tx.send(Ok(Some(chat_completion))).await;
+                        terminated.insert(choice_index);
+                    }
+                    Ok(mut chat_completion) if stop_detection.is_some() => {
+                        // Set guardrails finish reason and terminate
+                        chat_completion.choices[0].finish_reason =
+                            Some(GUARDRAILS_STOP_FINISH_REASON.into());
+                        info!(%trace_id, %choice_index, ?stop_detection, "choice completed: generation stopped by stop_on policy");
+                        let _ = response_tx.send(Ok(Some(chat_completion))).await;
+                        terminated.insert(choice_index);
+                    }
                     Ok(chat_completion) => {
                         // Send chat completion to response channel
                         debug!(%trace_id, %choice_index, ?chat_completion, "sending chat completion chunk to response channel");
                         if response_tx.send(Ok(Some(chat_completion))).await.is_err() {
                             info!(%trace_id, "task completed: client disconnected");
-                            return;
+                            return terminated;
                         }
                         // If this is the final chat completion chunk with content, send chat completion chunk with finish reason
                         let chat_completions =
@@ -596,7 +658,7 @@ async fn process_detection_batch_stream(
                         let _ = response_tx.send(Err(error)).await;
                         // Send None to signal completion
                         let _ = response_tx.send(Ok(None)).await;
-                        return;
+                        return terminated;
                     }
                 }
             }
@@ -606,9 +668,14 @@ async fn process_detection_batch_stream(
                 let _ = response_tx.send(Err(error)).await;
                 // Send None to signal completion
                 let _ = response_tx.send(Ok(None)).await;
-                return;
+                return terminated;
             }
         }
+        if terminated.len() == n {
+            info!(%trace_id, "task completed: all choices terminated");
+            return terminated;
+        }
     }
     info!(%trace_id, "task completed: detection batch stream closed");
+    terminated
 }
@@ -16,21 +16,19 @@
 */
 use std::{collections::HashMap, sync::Arc};
 
-use futures::future::try_join_all;
+use futures::{StreamExt, TryStreamExt, future::try_join_all, stream};
+use http::HeaderMap;
 use tracing::{Instrument, error, info, instrument};
 use uuid::Uuid;
 
-use super::ChatCompletionsDetectionTask;
+use super::{ChatCompletionsDetectionTask, input_detections};
 use crate::{
     clients::openai::*,
     config::DetectorType,
-    models::{
-        DetectionWarningReason, DetectorParams, UNSUITABLE_INPUT_MESSAGE, UNSUITABLE_OUTPUT_MESSAGE,
-    },
+    models::{DetectionWarningReason, DetectorParams, UNSUITABLE_OUTPUT_MESSAGE},
     orchestrator::{
         Context, Error,
         common::{self, validate_detectors},
-        types::ChatMessageIterator,
     },
 };
 
@@ -97,59 +95,22 @@ async fn handle_input_detection(
     let trace_id = task.trace_id;
     let model_id = task.request.model.clone();
 
-    // Input detectors are only applied to the last message
-    // If this changes, the empty content validation in [`ChatCompletionsRequest::validate`]
-    // should also change.
-    // Get the last message
-    let messages = task.request.messages();
-    let message = if let Some(message) = messages.last() {
-        message
-    } else {
-        return Err(Error::Validation("No messages provided".into()));
-    };
-    // Validate role
-    if !matches!(
-        message.role,
-        Some(Role::User) | Some(Role::Assistant) | Some(Role::System)
-    ) {
-        return Err(Error::Validation(
-            "Last message role must be user, assistant, or system".into(),
-        ));
-    }
-    let input_id = message.index;
-    let input_text = message.text.map(|s| s.to_string()).unwrap_or_default();
-    let detections = match common::text_contents_detections(
-        ctx.clone(),
-        task.headers.clone(),
-        detectors.clone(),
-        input_id,
-        vec![(0, input_text)],
-    )
-    .await
-    {
-        Ok((_, detections)) => detections,
+    let input_detections = match input_detections(ctx.clone(), task, detectors).await {
+        Ok(input_detections) => input_detections,
         Err(error) => {
             error!(%trace_id, %error, "task failed: error processing input detections");
             return Err(error);
         }
     };
-    if !detections.is_empty() {
+    if !input_detections.is_empty() {
         // Build chat completion with input detections
+        let (detections, warnings) = input_detections.into_completion_detections();
         let chat_completion = ChatCompletion {
             id: Uuid::new_v4().simple().to_string(),
             model: model_id,
             created: common::current_timestamp().as_secs() as i64,
-            detections: Some(CompletionDetections {
-                input: vec![CompletionInputDetections {
-                    message_index: message.index,
-                    results: detections.into(),
-                }],
-                ..Default::default()
-            }),
-            warnings: vec![CompletionDetectionWarning::new(
-                DetectionWarningReason::UnsuitableInput,
-                UNSUITABLE_INPUT_MESSAGE,
-            )],
+            detections,
+            warnings,
             ..Default::default()
         };
         Ok(Some(chat_completion))
@@ -166,6 +127,8 @@ async fn handle_output_detection(
     detectors: HashMap<String, DetectorParams>,
     mut chat_completion: ChatCompletion,
 ) -> Result<ChatCompletion, Error> {
+    // If JSON output is requested, detectors are applied to string values of the output
+    let json_output = task.request.json_output();
     let mut tasks = Vec::with_capacity(chat_completion.choices.len());
     for choice in &chat_completion.choices {
         if choice
@@ -185,43 +148,121 @@ async fn handle_output_detection(
                 ));
             continue;
         }
-        let input_id = choice.index;
-        let input_text = choice.message.content.clone().unwrap_or_default();
+        let choice_index = choice.index;
+        let content = choice.message.content.clone().unwrap_or_default();
         tasks.push(tokio::spawn(
-            common::text_contents_detections(
+            choice_detections(
                 ctx.clone(),
                 task.headers.clone(),
                 detectors.clone(),
-                input_id,
-                vec![(0, input_text)],
+                choice_index,
+                content,
+                json_output,
             )
             .in_current_span(),
         ));
     }
-    let detections = try_join_all(tasks)
+    let output = try_join_all(tasks)
         .await?
         .into_iter()
-        .collect::<Result<Vec<_>, Error>>()?;
-    if !detections.is_empty() {
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if !output.is_empty() {
         // Update chat completion with detections
-        let output = detections
-            .into_iter()
-            .filter(|(_, detections)| !detections.is_empty())
-            .map(|(input_id, detections)| CompletionOutputDetections {
-                choice_index: input_id,
-                results: detections.into(),
-            })
-            .collect::<Vec<_>>();
-        if !output.is_empty() {
-            chat_completion.detections = Some(CompletionDetections {
-                output,
-                ..Default::default()
-            });
-            chat_completion.warnings = vec![CompletionDetectionWarning::new(
-                DetectionWarningReason::UnsuitableOutput,
-                UNSUITABLE_OUTPUT_MESSAGE,
-            )];
-        }
+        chat_completion.detections = Some(CompletionDetections {
+            output,
+            ..Default::default()
+        });
+        chat_completion.warnings = vec![CompletionDetectionWarning::new(
+            DetectionWarningReason::UnsuitableOutput,
+            UNSUITABLE_OUTPUT_MESSAGE,
+        )];
     }
     Ok(chat_completion)
 }
+
+/// Runs output detectors on the content of a choice.
+///
+/// For JSON output, detectors are applied to each string value and detections are
+/// returned per value with its JSON Pointer path and its offset in the content.
+/// Detection offsets are relative to the value. Content that is not valid JSON is
+/// handled as text.
+async fn choice_detections(
+    ctx: Arc<Context>,
+    headers: HeaderMap,
+    detectors: HashMap<String, DetectorParams>,
+    choice_index: u32,
+    content: String,
+    json_output: bool,
+) -> Result<Vec<CompletionOutputDetections>, Error> {
+    let value = json_output
+        .then(|| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .flatten();
+    let Some(value) = value else {
+        let (_, detections) = common::text_contents_detections(
+            ctx,
+            headers,
+            detectors,
+            choice_index,
+            vec![(0, content)],
+        )
+        .await?;
+        return Ok((!detections.is_empty())
+            .then(|| CompletionOutputDetections {
+                choice_index,
+                path: None,
+                offset: None,
+                results: detections.into(),
+            })
+            .into_iter()
+            .collect());
+    };
+    let values = common::json_string_values(&value);
+    // Offsets are not reported if values can't be matched, e.g. with duplicate keys
+    let offsets = common::json_string_offsets(&content);
+    let offsets = (offsets.len() == values.len()).then_some(offsets);
+    let values = values
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (_, text))| !text.is_empty())
+        .map(|(index, (path, text))| {
+            let offset = offsets.as_ref().map(|offsets| offsets[index]);
+            (index, path, offset, text.to_string())
+        })
+        .collect::<Vec<_>>();
+    let mut results = stream::iter(values)
+        .map(|(index, path, offset, text)| {
+            let ctx = ctx.clone();
+            let headers = headers.clone();
+            let detectors = detectors.clone();
+            async move {
+                let (_, detections) = common::text_contents_detections(
+                    ctx,
+                    headers,
+                    detectors,
+                    choice_index,
+                    vec![(0, text)],
+                )
+                .await?;
+                Ok::<_, Error>((index, path, offset, detections))
+            }
+            .in_current_span()
+        })
+        .buffer_unordered(ctx.config.detector_concurrent_requests)
+        .try_collect::<Vec<_>>()
+        .await?;
+    // Return detections in document order
+    results.sort_by_key(|(index, ..)| *index);
+    Ok(results
+        .into_iter()
+        .filter(|(.., detections)| !detections.is_empty())
+        .map(|(_, path, offset, detections)| CompletionOutputDetections {
+            choice_index,
+            path: Some(path),
+            offset,
+            results: detections.into(),
+        })
+        .collect())
+}
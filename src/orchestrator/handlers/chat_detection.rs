@@ -14,7 +14,7 @@
  limitations under the License.
 
 */
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use http::HeaderMap;
 use opentelemetry::trace::TraceId;
@@ -26,9 +26,11 @@ use crate::{
     config::DetectorType,
     models::{ChatDetectionHttpRequest, ChatDetectionResult, DetectorParams},
     orchestrator::{
-        Error, Orchestrator,
+        Context, Error, Orchestrator,
         common::{self, validate_detectors},
+        sessions::SessionKey,
     },
+    server::Principal,
 };
 
 impl Handle<ChatDetectionTask> for Orchestrator {
@@ -43,14 +45,30 @@ impl Handle<ChatDetectionTask> for Orchestrator {
         let ctx = self.ctx.clone();
         let trace_id = task.trace_id;
         info!(%trace_id, config = ?task.detectors, "task started");
-
-        validate_detectors(
+        let audit = ctx.audit.entry(
+            trace_id,
+            "/api/v2/text/detection/chat",
+            task.principal.as_ref(),
             &task.detectors,
-            &ctx.config.detectors,
-            &[DetectorType::TextChat],
-            true,
-        )?;
+        );
+        let result = handle_detection(ctx, task).await;
+        audit.record(result)
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle_detection(
+    ctx: Arc<Context>,
+    task: ChatDetectionTask,
+) -> Result<ChatDetectionResult, Error> {
+    validate_detectors(
+        &task.detectors,
+        &ctx.config.detectors,
+        &[DetectorType::TextChat],
+        true,
+    )?;
 
+    let Some(session_id) = task.session_id else {
         // Handle detection
         let detections = common::text_chat_detections(
             ctx,
@@ -60,11 +78,54 @@ impl Handle<ChatDetectionTask> for Orchestrator {
             task.tools,
         )
         .await?;
-
-        Ok(ChatDetectionResult {
+        return Ok(ChatDetectionResult {
             detections: detections.into(),
+            ..Default::default()
+        });
+    };
+
+    // Handle session detection
+    let (Some(sessions), Some(sessions_config)) = (&ctx.sessions, &ctx.config.sessions) else {
+        return Err(Error::Validation(
+            "`session_id` is not supported as sessions are not enabled".into(),
+        ));
+    };
+    let key = SessionKey::new(task.principal.as_ref(), session_id.as_str());
+    // Build conversation from session history and new messages
+    let session = sessions.get(&key).await?.unwrap_or_default();
+    let messages = session
+        .messages
+        .iter()
+        .chain(task.messages.iter())
+        .cloned()
+        .collect::<Vec<_>>();
+    let detections = common::text_chat_detections(
+        ctx.clone(),
+        task.headers,
+        task.detectors,
+        messages,
+        task.tools,
+    )
+    .await?;
+    // Append new messages to the current session after successful detection,
+    // so that a failed request can be retried with the same messages and
+    // messages of overlapping turns are not lost
+    let max_messages = sessions_config.max_messages;
+    let mut new_messages = Some(task.messages);
+    let session = sessions
+        .update(&key, &mut |session| {
+            if let Some(messages) = new_messages.take() {
+                session.append_messages(messages, max_messages);
+            }
         })
-    }
+        .await?;
+    let message_index = session.len() - 1;
+
+    Ok(ChatDetectionResult {
+        detections: detections.into(),
+        session_id: Some(session_id),
+        message_index: Some(message_index),
+    })
 }
 
 #[derive(Debug)]
@@ -77,8 +138,12 @@ pub struct ChatDetectionTask {
     pub messages: Vec<openai::Message>,
     /// Tools
     pub tools: Vec<openai::Tool>,
+    /// Session ID
+    pub session_id: Option<String>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl ChatDetectionTask {
@@ -88,7 +153,15 @@ impl ChatDetectionTask {
             detectors: request.detectors,
             messages: request.messages,
             tools: request.tools,
+            session_id: request.session_id,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
@@ -34,6 +34,7 @@ use crate::{
         Context, Error, Orchestrator,
         common::{self, validate_detectors},
     },
+    server::Principal,
 };
 
 impl Handle<ClassificationWithGenTask> for Orchestrator {
@@ -48,54 +49,70 @@ impl Handle<ClassificationWithGenTask> for Orchestrator {
         let ctx = self.ctx.clone();
         let trace_id = task.trace_id;
         info!(%trace_id, config = ?task.guardrails_config, "task started");
-        let input_detectors = task.guardrails_config.input_detectors();
-        let output_detectors = task.guardrails_config.output_detectors();
-
-        validate_detectors(
-            input_detectors.iter().chain(output_detectors.iter()),
-            &ctx.config.detectors,
-            &[DetectorType::TextContents],
-            true,
-        )?;
-
-        if !input_detectors.is_empty() {
-            // Handle input detection
-            match handle_input_detection(ctx.clone(), &task, input_detectors).await {
-                Ok(Some(response)) => {
-                    info!(%trace_id, "task completed: returning response with input detections");
-                    // Return response with input detections and terminate
-                    return Ok(response);
-                }
-                Ok(None) => (), // No input detections
-                Err(error) => {
-                    // Input detections failed
-                    return Err(error);
-                }
+        let audit = ctx.audit.entry(
+            trace_id,
+            "/api/v1/task/classification-with-text-generation",
+            task.principal.as_ref(),
+            &task.guardrails_config,
+        );
+        let result = handle_classification_with_gen(ctx, task).await;
+        audit.record(result)
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle_classification_with_gen(
+    ctx: Arc<Context>,
+    task: ClassificationWithGenTask,
+) -> Result<ClassifiedGeneratedTextResult, Error> {
+    let trace_id = task.trace_id;
+    let input_detectors = task.guardrails_config.input_detectors();
+    let output_detectors = task.guardrails_config.output_detectors();
+
+    validate_detectors(
+        input_detectors.iter().chain(output_detectors.iter()),
+        &ctx.config.detectors,
+        &[DetectorType::TextContents],
+        true,
+    )?;
+
+    if !input_detectors.is_empty() {
+        // Handle input detection
+        match handle_input_detection(ctx.clone(), &task, input_detectors).await {
+            Ok(Some(response)) => {
+                info!(%trace_id, "task completed: returning response with input detections");
+                // Return response with input detections and terminate
+                return Ok(response);
+            }
+            Ok(None) => (), // No input detections
+            Err(error) => {
+                // Input detections failed
+                return Err(error);
             }
         }
+    }
 
-        // Handle generation
-        let client = ctx
-            .clients
-            .get_as::<GenerationClient>("generation")
-            .unwrap();
-        let generation = common::generate(
-            client,
-            task.headers.clone(),
-            task.model_id.clone(),
-            task.inputs.clone(),
-            task.text_gen_parameters.clone(),
-        )
-        .await?;
-
-        if !output_detectors.is_empty() {
-            // Handle output detection
-            handle_output_detection(ctx.clone(), task, output_detectors, generation).await
-        } else {
-            // No output detectors, return generation
-            info!(%trace_id, "task completed: returning generation response");
-            Ok(generation)
-        }
+    // Handle generation
+    let client = ctx
+        .clients
+        .get_as::<GenerationClient>("generation")
+        .unwrap();
+    let generation = common::generate(
+        client,
+        task.headers.clone(),
+        task.model_id.clone(),
+        task.inputs.clone(),
+        task.text_gen_parameters.clone(),
+    )
+    .await?;
+
+    if !output_detectors.is_empty() {
+        // Handle output detection
+        handle_output_detection(ctx.clone(), task, output_detectors, generation).await
+    } else {
+        // No output detectors, return generation
+        info!(%trace_id, "task completed: returning generation response");
+        Ok(generation)
     }
 }
 
@@ -206,6 +223,8 @@ pub struct ClassificationWithGenTask {
     pub text_gen_parameters: Option<GuardrailsTextGenerationParameters>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl ClassificationWithGenTask {
@@ -217,6 +236,13 @@ impl ClassificationWithGenTask {
             guardrails_config: request.guardrail_config.unwrap_or_default(),
             text_gen_parameters: request.text_gen_parameters,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
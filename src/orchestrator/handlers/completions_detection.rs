@@ -22,6 +22,7 @@ use super::Handle;
 use crate::{
     clients::openai::{CompletionsRequest, CompletionsResponse},
     orchestrator::{Error, Orchestrator},
+    server::Principal,
 };
 
 pub mod streaming;
@@ -37,9 +38,18 @@ impl Handle<CompletionsDetectionTask> for Orchestrator {
     )]
     async fn handle(&self, task: CompletionsDetectionTask) -> Result<Self::Response, Error> {
         let ctx = self.ctx.clone();
+        let audit = ctx.audit.entry(
+            task.trace_id,
+            "/api/v2/text/completions-detection",
+            task.principal.as_ref(),
+            &task.request.detectors,
+        );
         match task.request.stream {
-            Some(true) => streaming::handle_streaming(ctx, task).await,
-            _ => unary::handle_unary(ctx, task).await,
+            Some(true) => streaming::handle_streaming(ctx, task, audit).await,
+            _ => {
+                let result = unary::handle_unary(ctx, task).await;
+                audit.record(result)
+            }
         }
     }
 }
@@ -52,6 +62,8 @@ pub struct CompletionsDetectionTask {
     pub request: CompletionsRequest,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl CompletionsDetectionTask {
@@ -60,6 +72,13 @@ impl CompletionsDetectionTask {
             trace_id,
             request,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
@@ -16,7 +16,7 @@
 */
 use std::{collections::HashMap, sync::Arc};
 
-use futures::{StreamExt, TryStreamExt, stream};
+use futures::StreamExt;
 use opentelemetry::trace::TraceId;
 use tokio::sync::mpsc;
 use tracing::{Instrument, debug, error, info, instrument, warn};
@@ -31,7 +31,8 @@ use crate::{
     },
     orchestrator::{
         Context, Error,
-        common::{self, text_contents_detections, validate_detectors},
+        audit::AuditEntry,
+        common::{self, validate_detectors},
         types::{
             Chunk, CompletionBatcher, CompletionState, CompletionStream, DetectionBatchStream,
             Detections,
@@ -42,6 +43,7 @@ use crate::{
 pub async fn handle_streaming(
     ctx: Arc<Context>,
     task: CompletionsDetectionTask,
+    audit: AuditEntry,
 ) -> Result<CompletionsResponse, Error> {
     let trace_id = task.trace_id;
     let detectors = task.request.detectors.clone();
@@ -49,18 +51,29 @@ pub async fn handle_streaming(
 
     // Create response channel
     let (response_tx, response_rx) = mpsc::channel::<Result<Option<Completion>, Error>>(128);
+    let response_tx = audit.record_stream(response_tx);
 
     tokio::spawn(
         async move {
             let input_detectors = detectors.input;
             let output_detectors = detectors.output;
 
+            // Text generation detectors are supported on output detection
+            // as they are applied to the whole output at the end of the stream
             if let Err(error) = validate_detectors(
-                input_detectors.iter().chain(output_detectors.iter()),
+                &input_detectors,
                 &ctx.config.detectors,
                 &[DetectorType::TextContents],
                 true,
-            ) {
+            )
+            .and_then(|_| {
+                validate_detectors(
+                    &output_detectors,
+                    &ctx.config.detectors,
+                    &[DetectorType::TextContents, DetectorType::TextGeneration],
+                    true,
+                )
+            }) {
                 let _ = response_tx.send(Err(error)).await;
                 // Send None to signal completion
                 let _ = response_tx.send(Ok(None)).await;
@@ -197,10 +210,10 @@ async fn handle_output_detection(
     // Split output detectors into 2 groups:
     // 1) Output Detectors: Applied to chunks. Detections are returned in batches.
     // 2) Whole Doc Output Detectors: Applied to concatenated chunks (whole doc) after the completion stream has been consumed.
-    // Currently, this is any detector that uses "whole_doc_chunker".
+    // This is any detector that uses "whole_doc_chunker" or is a text generation detector.
     let (whole_doc_detectors, detectors): (HashMap<_, _>, HashMap<_, _>) =
         detectors.into_iter().partition(|(detector_id, _)| {
-            ctx.config.get_chunker_id(detector_id).unwrap() == "whole_doc_chunker"
+            common::is_whole_output_detector(detector_id, &ctx.config.detectors)
         });
     let completion_state = Arc::new(CompletionState::new());
 
@@ -404,7 +417,7 @@ async fn handle_whole_doc_output_detection(
     detectors: HashMap<String, DetectorParams>,
     completion_state: Arc<CompletionState<Completion>>,
 ) -> Result<(CompletionDetections, Vec<CompletionDetectionWarning>), Error> {
-    // Create vec of choice_index->inputs, where inputs contains the concatenated text for the choice
+    // Create vec of choice_index->text, where text is the concatenated text for the choice
     let choice_inputs = completion_state
         .completions
         .iter()
@@ -420,29 +433,26 @@ async fn handle_whole_doc_output_detection(
                         .unwrap_or_default()
                 })
                 .collect::<String>();
-            let inputs = vec![(0usize, text)];
-            (choice_index, inputs)
+            (choice_index, text)
         })
         .collect::<Vec<_>>();
-    // Process detections concurrently for choices
-    let choice_detections = stream::iter(choice_inputs)
-        .map(|(choice_index, inputs)| {
-            text_contents_detections(
-                ctx.clone(),
-                task.headers.clone(),
-                detectors.clone(),
-                choice_index,
-                inputs,
-            )
-        })
-        .buffer_unordered(ctx.config.detector_concurrent_requests)
-        .try_collect::<Vec<_>>()
-        .await?;
+    // Text generation detectors use the completion prompt
+    let prompt = task.request.prompt.clone();
+    let choice_detections = common::whole_doc_output_detections(
+        ctx,
+        task.headers.clone(),
+        detectors,
+        prompt,
+        choice_inputs,
+    )
+    .await?;
     // Build output detections
     let output = choice_detections
         .into_iter()
         .map(|(choice_index, detections)| CompletionOutputDetections {
             choice_index,
+            path: None,
+            offset: None,
             results: detections.into(),
         })
         .collect::<Vec<_>>();
@@ -495,6 +505,8 @@ fn output_detection_response(
         completion.detections = Some(CompletionDetections {
             output: vec![CompletionOutputDetections {
                 choice_index,
+                path: None,
+                offset: None,
                 results: detections.into(),
             }],
             ..Default::default()
@@ -197,6 +197,8 @@ async fn handle_output_detection(
             .filter(|(_, detections)| !detections.is_empty())
             .map(|(input_id, detections)| CompletionOutputDetections {
                 choice_index: input_id,
+                path: None,
+                offset: None,
                 results: detections.into(),
             })
             .collect::<Vec<_>>();
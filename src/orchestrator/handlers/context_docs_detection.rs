@@ -14,7 +14,7 @@
  limitations under the License.
 
 */
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use http::HeaderMap;
 use opentelemetry::trace::TraceId;
@@ -26,9 +26,10 @@ use crate::{
     config::DetectorType,
     models::{ContextDocsHttpRequest, ContextDocsResult, DetectorParams},
     orchestrator::{
-        Error, Orchestrator,
+        Context, Error, Orchestrator,
         common::{self, validate_detectors},
     },
+    server::Principal,
 };
 
 impl Handle<ContextDocsDetectionTask> for Orchestrator {
@@ -43,29 +44,43 @@ impl Handle<ContextDocsDetectionTask> for Orchestrator {
         let ctx = self.ctx.clone();
         let trace_id = task.trace_id;
         info!(%trace_id, config = ?task.detectors, "task started");
-
-        validate_detectors(
+        let audit = ctx.audit.entry(
+            trace_id,
+            "/api/v2/text/detection/context",
+            task.principal.as_ref(),
             &task.detectors,
-            &ctx.config.detectors,
-            &[DetectorType::TextContextDoc],
-            true,
-        )?;
+        );
+        let result = handle_detection(ctx, task).await;
+        audit.record(result)
+    }
+}
 
-        // Handle detection
-        let detections = common::text_context_detections(
-            ctx,
-            task.headers,
-            task.detectors,
-            task.content,
-            task.context_type,
-            task.context,
-        )
-        .await?;
+#[instrument(skip_all)]
+async fn handle_detection(
+    ctx: Arc<Context>,
+    task: ContextDocsDetectionTask,
+) -> Result<ContextDocsResult, Error> {
+    validate_detectors(
+        &task.detectors,
+        &ctx.config.detectors,
+        &[DetectorType::TextContextDoc],
+        true,
+    )?;
 
-        Ok(ContextDocsResult {
-            detections: detections.into(),
-        })
-    }
+    // Handle detection
+    let detections = common::text_context_detections(
+        ctx,
+        task.headers,
+        task.detectors,
+        task.content,
+        task.context_type,
+        task.context,
+    )
+    .await?;
+
+    Ok(ContextDocsResult {
+        detections: detections.into(),
+    })
 }
 
 #[derive(Debug)]
@@ -82,6 +97,8 @@ pub struct ContextDocsDetectionTask {
     pub detectors: HashMap<String, DetectorParams>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl ContextDocsDetectionTask {
@@ -93,6 +110,13 @@ impl ContextDocsDetectionTask {
             context: request.context,
             detectors: request.detectors,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
@@ -14,7 +14,7 @@
  limitations under the License.
 
 */
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use http::HeaderMap;
 use opentelemetry::trace::TraceId;
@@ -25,9 +25,10 @@ use crate::{
     config::DetectorType,
     models::{DetectionOnGeneratedHttpRequest, DetectionOnGenerationResult, DetectorParams},
     orchestrator::{
-        Error, Orchestrator,
+        Context, Error, Orchestrator,
         common::{self, validate_detectors},
     },
+    server::Principal,
 };
 
 impl Handle<DetectionOnGenerationTask> for Orchestrator {
@@ -42,28 +43,42 @@ impl Handle<DetectionOnGenerationTask> for Orchestrator {
         let ctx = self.ctx.clone();
         let trace_id = task.trace_id;
         info!(%trace_id, config = ?task.detectors, "task started");
-
-        validate_detectors(
+        let audit = ctx.audit.entry(
+            trace_id,
+            "/api/v2/text/detection/generated",
+            task.principal.as_ref(),
             &task.detectors,
-            &ctx.config.detectors,
-            &[DetectorType::TextGeneration],
-            true,
-        )?;
+        );
+        let result = handle_detection(ctx, task).await;
+        audit.record(result)
+    }
+}
 
-        // Handle detection
-        let detections = common::text_generation_detections(
-            ctx,
-            task.headers,
-            task.detectors,
-            task.prompt,
-            task.generated_text,
-        )
-        .await?;
+#[instrument(skip_all)]
+async fn handle_detection(
+    ctx: Arc<Context>,
+    task: DetectionOnGenerationTask,
+) -> Result<DetectionOnGenerationResult, Error> {
+    validate_detectors(
+        &task.detectors,
+        &ctx.config.detectors,
+        &[DetectorType::TextGeneration],
+        true,
+    )?;
 
-        Ok(DetectionOnGenerationResult {
-            detections: detections.into(),
-        })
-    }
+    // Handle detection
+    let detections = common::text_generation_detections(
+        ctx,
+        task.headers,
+        task.detectors,
+        task.prompt,
+        task.generated_text,
+    )
+    .await?;
+
+    Ok(DetectionOnGenerationResult {
+        detections: detections.into(),
+    })
 }
 
 #[derive(Debug)]
@@ -78,6 +93,8 @@ pub struct DetectionOnGenerationTask {
     pub detectors: HashMap<String, DetectorParams>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl DetectionOnGenerationTask {
@@ -92,6 +109,13 @@ impl DetectionOnGenerationTask {
             generated_text: request.generated_text,
             detectors: request.detectors,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
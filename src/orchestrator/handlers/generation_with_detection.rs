@@ -14,7 +14,7 @@
  limitations under the License.
 
 */
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use http::HeaderMap;
 use opentelemetry::trace::TraceId;
@@ -29,9 +29,10 @@ use crate::{
         GuardrailsTextGenerationParameters,
     },
     orchestrator::{
-        Error, Orchestrator,
+        Context, Error, Orchestrator,
         common::{self, validate_detectors},
     },
+    server::Principal,
 };
 
 impl Handle<GenerationWithDetectionTask> for Orchestrator {
@@ -46,45 +47,59 @@ impl Handle<GenerationWithDetectionTask> for Orchestrator {
         let ctx = self.ctx.clone();
         let trace_id = task.trace_id;
         info!(%trace_id, config = ?task.detectors, "task started");
-
-        validate_detectors(
+        let audit = ctx.audit.entry(
+            trace_id,
+            "/api/v2/text/generation-detection",
+            task.principal.as_ref(),
             &task.detectors,
-            &ctx.config.detectors,
-            &[DetectorType::TextGeneration],
-            true,
-        )?;
+        );
+        let result = handle_generation_with_detection(ctx, task).await;
+        audit.record(result)
+    }
+}
 
-        // Handle generation
-        let client = ctx
-            .clients
-            .get_as::<GenerationClient>("generation")
-            .unwrap();
-        let generation = common::generate(
-            client,
-            task.headers.clone(),
-            task.model_id.clone(),
-            task.prompt.clone(),
-            task.text_gen_parameters.clone(),
-        )
-        .await?;
-        let generated_text = generation.generated_text.unwrap_or_default();
+#[instrument(skip_all)]
+async fn handle_generation_with_detection(
+    ctx: Arc<Context>,
+    task: GenerationWithDetectionTask,
+) -> Result<GenerationWithDetectionResult, Error> {
+    validate_detectors(
+        &task.detectors,
+        &ctx.config.detectors,
+        &[DetectorType::TextGeneration],
+        true,
+    )?;
 
-        // Handle detection
-        let detections = common::text_generation_detections(
-            ctx,
-            task.headers,
-            task.detectors,
-            task.prompt,
-            generated_text.clone(),
-        )
-        .await?;
+    // Handle generation
+    let client = ctx
+        .clients
+        .get_as::<GenerationClient>("generation")
+        .unwrap();
+    let generation = common::generate(
+        client,
+        task.headers.clone(),
+        task.model_id.clone(),
+        task.prompt.clone(),
+        task.text_gen_parameters.clone(),
+    )
+    .await?;
+    let generated_text = generation.generated_text.unwrap_or_default();
 
-        Ok(GenerationWithDetectionResult {
-            generated_text,
-            input_token_count: generation.input_token_count,
-            detections: detections.into(),
-        })
-    }
+    // Handle detection
+    let detections = common::text_generation_detections(
+        ctx,
+        task.headers,
+        task.detectors,
+        task.prompt,
+        generated_text.clone(),
+    )
+    .await?;
+
+    Ok(GenerationWithDetectionResult {
+        generated_text,
+        input_token_count: generation.input_token_count,
+        detections: detections.into(),
+    })
 }
 
 #[derive(Debug)]
@@ -101,6 +116,8 @@ pub struct GenerationWithDetectionTask {
     pub text_gen_parameters: Option<GuardrailsTextGenerationParameters>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl GenerationWithDetectionTask {
@@ -116,6 +133,13 @@ impl GenerationWithDetectionTask {
             detectors: request.detectors,
             text_gen_parameters: request.text_gen_parameters,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
@@ -16,7 +16,7 @@
 */
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::{Arc, RwLock},
 };
 
@@ -29,20 +29,22 @@ use tracing::{Instrument, error, info, instrument};
 
 use super::Handle;
 use crate::{
-    clients::GenerationClient,
+    clients::{GenerationClient, chunker::DEFAULT_CHUNKER_ID},
     config::DetectorType,
     models::{
-        ClassifiedGeneratedTextStreamResult, DetectionWarning, DetectorParams, GuardrailsConfig,
-        GuardrailsHttpRequest, GuardrailsTextGenerationParameters,
+        ClassifiedGeneratedTextStreamResult, DetectionWarning, DetectorParams, FinishReason,
+        GuardrailsConfig, GuardrailsHttpRequest, GuardrailsTextGenerationParameters,
         TextGenTokenClassificationResults,
     },
     orchestrator::{
         Context, Error, Orchestrator,
         common::{self, validate_detectors},
         types::{
-            Chunk, DetectionBatchStream, Detections, GenerationStream, MaxProcessedIndexBatcher,
+            Chunk, ChunkerGroup, DetectionBatchStream, Detections, GenerationStream,
+            SpanAlignedBatcher,
         },
     },
+    server::Principal,
 };
 
 impl Handle<StreamingClassificationWithGenTask> for Orchestrator {
@@ -66,6 +68,15 @@ impl Handle<StreamingClassificationWithGenTask> for Orchestrator {
         // Create response channel
         let (response_tx, response_rx) =
             mpsc::channel::<Result<ClassifiedGeneratedTextStreamResult, Error>>(128);
+        let response_tx = ctx
+            .audit
+            .entry(
+                task.trace_id,
+                "/api/v1/task/server-streaming-classification-with-text-generation",
+                task.principal.as_ref(),
+                &task.guardrails_config,
+            )
+            .record_stream(response_tx);
 
         tokio::spawn(async move {
             let trace_id = task.trace_id;
@@ -87,16 +98,13 @@ impl Handle<StreamingClassificationWithGenTask> for Orchestrator {
             }
 
             // Output detectors validation
-            // Disallow `whole_doc_chunker` detectors on output detection
-            // for now until results of these detectors are handled as
-            // planned for chat completions, with detection results
-            // provided separately at the end but not blocking other
-            // detection results that may be provided on smaller chunks
+            // Allow `whole_doc_chunker` detectors on output detection
+            // as their results are provided separately at the end
             if let Err(error) = validate_detectors(
                 &output_detectors,
                 &ctx.config.detectors,
                 &[DetectorType::TextContents],
-                false,
+                true,
             ) {
                 let _ = response_tx.send(Err(error)).await;
                 return;
@@ -228,44 +236,105 @@ async fn handle_input_detection(
 async fn handle_output_detection(
     ctx: Arc<Context>,
     task: StreamingClassificationWithGenTask,
-    detectors: HashMap<String, DetectorParams>,
+    mut detectors: HashMap<String, DetectorParams>,
     mut generation_stream: GenerationStream,
     response_tx: mpsc::Sender<Result<ClassifiedGeneratedTextStreamResult, Error>>,
 ) {
     let trace_id = task.trace_id;
-    // Create input channel for detection pipeline
-    let (input_tx, input_rx) = mpsc::channel(128);
+    // Get stop_on policy
+    let stop_on = common::pop_stop_on(&mut detectors);
     // Create shared generations
     let generations: Arc<RwLock<Vec<ClassifiedGeneratedTextStreamResult>>> =
         Arc::new(RwLock::new(Vec::new()));
-    // Create detection streams
-    let detection_streams = common::text_contents_detection_streams(
-        ctx,
-        task.headers.clone(),
-        detectors.clone(),
-        0,
-        input_rx,
-    )
-    .await;
+    // Group detectors by chunker
+    let mut detector_groups: BTreeMap<String, HashMap<String, DetectorParams>> = BTreeMap::new();
+    for (detector_id, params) in detectors {
+        let chunker_id = ctx.config.get_chunker_id(&detector_id).unwrap();
+        detector_groups
+            .entry(chunker_id)
+            .or_default()
+            .insert(detector_id, params);
+    }
+    // Create input channels and detection streams for each group,
+    // using the group index as input_id to identify the group in the batcher
+    let mut input_txs = Vec::with_capacity(detector_groups.len());
+    let mut chunker_groups = Vec::with_capacity(detector_groups.len());
+    let mut detection_streams = Ok(Vec::new());
+    for (group_index, (chunker_id, detectors)) in detector_groups.into_iter().enumerate() {
+        let (input_tx, input_rx) = mpsc::channel(128);
+        input_txs.push(input_tx);
+        chunker_groups.push(ChunkerGroup::new(
+            detectors.len(),
+            chunker_id == DEFAULT_CHUNKER_ID,
+        ));
+        match common::text_contents_detection_streams(
+            ctx.clone(),
+            task.headers.clone(),
+            detectors,
+            group_index as u32,
+            input_rx,
+        )
+        .await
+        {
+            Ok(streams) => {
+                if let Ok(detection_streams) = detection_streams.as_mut() {
+                    detection_streams.extend(streams);
+                }
+            }
+            Err(error) => detection_streams = Err(error),
+        }
+    }
 
-    // Spawn task to process detection streams
-    tokio::spawn({
+    // Spawn task to consume generations
+    let generation_task = tokio::spawn({
         let generations = generations.clone();
+        async move {
+            while let Some((index, result)) = generation_stream.next().await {
+                match result {
+                    Ok(generation) => {
+                        // Send generated text to input channels
+                        let input = (index, generation.generated_text.clone().unwrap_or_default());
+                        for input_tx in &input_txs {
+                            let _ = input_tx.send(Ok(input.clone())).await;
+                        }
+                        // Update shared generations
+                        generations.write().unwrap().push(generation);
+                    }
+                    Err(error) => {
+                        // Send error to input channels
+                        for input_tx in &input_txs {
+                            let _ = input_tx.send(Err(error.clone())).await;
+                        }
+                        // TODO: catch generation errors here to terminate all tasks?
+                    }
+                }
+            }
+        }
+        .in_current_span()
+    });
+
+    // Spawn task to process detection streams
+    tokio::spawn(
         async move {
             match detection_streams {
                 Ok(detection_streams) => {
                     // Create detection batch stream
                     let detection_batch_stream = DetectionBatchStream::new(
-                        MaxProcessedIndexBatcher::new(detectors.len()),
+                        SpanAlignedBatcher::new(chunker_groups),
                         detection_streams,
                     );
-                    process_detection_batch_stream(
+                    let stopped = process_detection_batch_stream(
                         trace_id,
                         generations,
                         detection_batch_stream,
+                        &stop_on,
                         response_tx,
                     )
                     .await;
+                    if stopped {
+                        // Cancel the generation stream
+                        generation_task.abort();
+                    }
                 }
                 Err(error) => {
                     error!(%trace_id, %error, "task failed: error creating detection streams");
@@ -274,29 +343,6 @@ async fn handle_output_detection(
                 }
             }
         }
-        .in_current_span()
-    });
-
-    // Spawn task to consume generations
-    tokio::spawn(
-        async move {
-            while let Some((index, result)) = generation_stream.next().await {
-                match result {
-                    Ok(generation) => {
-                        // Send generated text to input channel
-                        let input = (index, generation.generated_text.clone().unwrap_or_default());
-                        let _ = input_tx.send(Ok(input)).await;
-                        // Update shared generations
-                        generations.write().unwrap().push(generation);
-                    }
-                    Err(error) => {
-                        // Send error to input channel
-                        let _ = input_tx.send(Err(error)).await;
-                        // TODO: catch generation errors here to terminate all tasks?
-                    }
-                }
-            }
-        }
         .in_current_span(),
     );
 }
@@ -329,33 +375,48 @@ async fn forward_generation_stream(
 }
 
 /// Consumes a detection batch stream, builds responses, and sends them to a response channel.
+///
+/// If a detection triggers the `stop_on` policy, the response is sent with a
+/// guardrails finish reason and the stream is terminated.
+/// Returns `true` if the stream was stopped by the `stop_on` policy.
 #[instrument(skip_all)]
 async fn process_detection_batch_stream(
     trace_id: TraceId,
     generations: Arc<RwLock<Vec<ClassifiedGeneratedTextStreamResult>>>,
     mut detection_batch_stream: DetectionBatchStream,
+    stop_on: &HashMap<String, f64>,
     response_tx: mpsc::Sender<Result<ClassifiedGeneratedTextStreamResult, Error>>,
-) {
+) -> bool {
     while let Some(result) = detection_batch_stream.next().await {
         match result {
             Ok((_, chunk, detections)) => {
+                let stop_detection = common::find_stop_detection(stop_on, &detections).cloned();
                 // Create response for this batch with output detections
-                let response = output_detection_response(&generations, chunk, detections).unwrap();
+                let mut response =
+                    output_detection_response(&generations, chunk, detections).unwrap();
+                if stop_detection.is_some() {
+                    // Set guardrails finish reason and terminate
+                    response.finish_reason = Some(FinishReason::GuardrailsStop);
+                    info!(%trace_id, ?stop_detection, "task completed: generation stopped by stop_on policy");
+                    let _ = response_tx.send(Ok(response)).await;
+                    return true;
+                }
                 // Send message to response channel
                 if response_tx.send(Ok(response)).await.is_err() {
                     info!(%trace_id, "task completed: client disconnected");
-                    return;
+                    return false;
                 }
             }
             Err(error) => {
                 error!(%trace_id, %error, "task failed: error received from detection batch stream");
                 // Send error to response channel and terminate
                 let _ = response_tx.send(Err(error)).await;
-                return;
+                return false;
             }
         }
     }
     info!(%trace_id, "task completed: detection batch stream closed");
+    false
 }
 
 /// Builds a response with output detections.
@@ -413,6 +474,8 @@ pub struct StreamingClassificationWithGenTask {
     pub text_gen_parameters: Option<GuardrailsTextGenerationParameters>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl StreamingClassificationWithGenTask {
@@ -424,6 +487,13 @@ impl StreamingClassificationWithGenTask {
             guardrails_config: request.guardrail_config.unwrap_or_default(),
             text_gen_parameters: request.text_gen_parameters,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
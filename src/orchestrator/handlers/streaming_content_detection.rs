@@ -32,11 +32,14 @@ use crate::{
         common::{self, validate_detectors},
         types::{BoxStream, DetectionBatchStream, MaxProcessedIndexBatcher},
     },
+    server::Principal,
 };
 
 type InputStream =
     Pin<Box<dyn Stream<Item = (usize, Result<StreamingContentDetectionRequest, Error>)> + Send>>;
 
+const STREAMING_CONTENT_DETECTION_ROUTE: &str = "/api/v2/text/detection/stream-content";
+
 impl Handle<StreamingContentDetectionTask> for Orchestrator {
     type Response = ReceiverStream<Result<StreamingContentDetectionResponse, Error>>;
 
@@ -56,16 +59,34 @@ impl Handle<StreamingContentDetectionTask> for Orchestrator {
             async move {
                 let trace_id = task.trace_id;
                 let headers = task.headers;
+                let principal = task.principal;
                 let mut input_stream = Box::pin(task.input_stream.peekable());
                 let detectors = match extract_detectors(&mut input_stream).await {
                     Ok(detectors) => detectors,
                     Err(error) => {
                         error!(%error, "error extracting detectors from first message");
+                        ctx.audit
+                            .entry(
+                                trace_id,
+                                STREAMING_CONTENT_DETECTION_ROUTE,
+                                principal.as_ref(),
+                                &(),
+                            )
+                            .finish(Some(&error));
                         let _ = response_tx.send(Err(error)).await;
                         return;
                     }
                 };
                 info!(%trace_id, config = ?detectors, "task started");
+                let response_tx = ctx
+                    .audit
+                    .entry(
+                        trace_id,
+                        STREAMING_CONTENT_DETECTION_ROUTE,
+                        principal.as_ref(),
+                        &detectors,
+                    )
+                    .record_stream(response_tx);
 
                 if let Err(error) = validate_detectors(
                     &detectors,
@@ -213,6 +234,8 @@ pub struct StreamingContentDetectionTask {
     pub detectors: HashMap<String, DetectorParams>,
     /// Input stream to run detections on
     pub input_stream: BoxStream<(usize, Result<StreamingContentDetectionRequest, Error>)>,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl StreamingContentDetectionTask {
@@ -226,6 +249,13 @@ impl StreamingContentDetectionTask {
             headers,
             detectors: HashMap::default(),
             input_stream,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
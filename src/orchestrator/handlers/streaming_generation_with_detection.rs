@@ -0,0 +1,374 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use futures::StreamExt;
+use http::HeaderMap;
+use opentelemetry::trace::TraceId;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{Instrument, error, info, instrument};
+
+use super::Handle;
+use crate::{
+    clients::GenerationClient,
+    config::DetectorType,
+    models::{
+        ClassifiedGeneratedTextStreamResult, DetectorParams, GenerationWithDetectionHttpRequest,
+        GenerationWithDetectionStreamResult, GuardrailsTextGenerationParameters,
+    },
+    orchestrator::{
+        Context, Error, Orchestrator,
+        common::{self, validate_detectors},
+        types::{DetectionBatchStream, GenerationStream, MaxProcessedIndexBatcher},
+    },
+    server::Principal,
+};
+
+impl Handle<StreamingGenerationWithDetectionTask> for Orchestrator {
+    type Response = ReceiverStream<Result<GenerationWithDetectionStreamResult, Error>>;
+
+    #[instrument(
+        name = "streaming_generation_with_detection",
+        skip_all,
+        fields(trace_id = ?task.trace_id, model_id = task.model_id, headers = ?task.headers)
+    )]
+    async fn handle(
+        &self,
+        task: StreamingGenerationWithDetectionTask,
+    ) -> Result<Self::Response, Error> {
+        let ctx = self.ctx.clone();
+
+        // Create response channel
+        let (response_tx, response_rx) =
+            mpsc::channel::<Result<GenerationWithDetectionStreamResult, Error>>(128);
+        let response_tx = ctx
+            .audit
+            .entry(
+                task.trace_id,
+                "/api/v2/text/generation-detection/stream",
+                task.principal.as_ref(),
+                &task.detectors,
+            )
+            .record_stream(response_tx);
+
+        tokio::spawn(
+            async move {
+                let trace_id = task.trace_id;
+                info!(%trace_id, config = ?task.detectors, "task started");
+
+                if let Err(error) = validate_detectors(
+                    &task.detectors,
+                    &ctx.config.detectors,
+                    &[DetectorType::TextContents, DetectorType::TextGeneration],
+                    true,
+                ) {
+                    let _ = response_tx.send(Err(error)).await;
+                    return;
+                }
+
+                // Split detectors into 2 groups:
+                // 1) Text contents detectors: applied to chunks of the generation stream.
+                // 2) Text generation detectors: applied to the prompt and full generated text
+                // after the generation stream has been consumed.
+                let (text_contents_detectors, text_generation_detectors): (HashMap<_, _>, HashMap<_, _>) =
+                    task.detectors.clone().into_iter().partition(|(detector_id, _)| {
+                        ctx.config.detectors[detector_id]
+                            .r#type
+                            .contains(&DetectorType::TextContents)
+                    });
+                // Whole doc detectors cannot be applied to chunks
+                if let Err(error) = validate_detectors(
+                    &text_contents_detectors,
+                    &ctx.config.detectors,
+                    &[DetectorType::TextContents],
+                    false,
+                ) {
+                    let _ = response_tx.send(Err(error)).await;
+                    return;
+                }
+
+                // Create generation stream
+                let client = ctx
+                    .clients
+                    .get_as::<GenerationClient>("generation")
+                    .unwrap();
+                let generation_stream = match common::generate_stream(
+                    client,
+                    task.headers.clone(),
+                    task.model_id.clone(),
+                    task.prompt.clone(),
+                    task.text_gen_parameters.clone(),
+                )
+                .await
+                {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        error!(%trace_id, %error, "task failed: error creating generation stream");
+                        // Send error to response channel and terminate
+                        let _ = response_tx.send(Err(error)).await;
+                        return;
+                    }
+                };
+
+                // Create shared generations
+                let generations: Arc<RwLock<Vec<ClassifiedGeneratedTextStreamResult>>> =
+                    Arc::new(RwLock::new(Vec::new()));
+
+                let completed = if text_contents_detectors.is_empty() {
+                    // No chunk detectors, forward generation stream to response stream
+                    forward_generation_stream(
+                        trace_id,
+                        generation_stream,
+                        generations.clone(),
+                        response_tx.clone(),
+                    )
+                    .await
+                } else {
+                    handle_chunk_detection(
+                        ctx.clone(),
+                        &task,
+                        text_contents_detectors,
+                        generation_stream,
+                        generations.clone(),
+                        response_tx.clone(),
+                    )
+                    .await
+                };
+
+                if completed && !text_generation_detectors.is_empty() {
+                    // Handle text generation detection on full generated text
+                    let generated_text = generations
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .filter_map(|generation| generation.generated_text.as_deref())
+                        .collect::<String>();
+                    let result = match common::text_generation_detections(
+                        ctx,
+                        task.headers,
+                        text_generation_detectors,
+                        task.prompt,
+                        generated_text,
+                    )
+                    .await
+                    {
+                        Ok(detections) => Ok(GenerationWithDetectionStreamResult {
+                            generation_detections: Some(detections.into()),
+                            ..Default::default()
+                        }),
+                        Err(error) => {
+                            error!(%trace_id, %error, "task failed: error received from text generation detections");
+                            Err(error)
+                        }
+                    };
+                    // Send final message to response channel
+                    let _ = response_tx.send(result).await;
+                }
+                info!(%trace_id, "task completed");
+            }
+            .in_current_span(),
+        );
+
+        Ok(ReceiverStream::new(response_rx))
+    }
+}
+
+/// Runs text contents detectors on chunks of a generation stream.
+/// Returns `true` if the detection batch stream completed without errors.
+#[instrument(skip_all)]
+async fn handle_chunk_detection(
+    ctx: Arc<Context>,
+    task: &StreamingGenerationWithDetectionTask,
+    detectors: HashMap<String, DetectorParams>,
+    mut generation_stream: GenerationStream,
+    generations: Arc<RwLock<Vec<ClassifiedGeneratedTextStreamResult>>>,
+    response_tx: mpsc::Sender<Result<GenerationWithDetectionStreamResult, Error>>,
+) -> bool {
+    let trace_id = task.trace_id;
+    let n_detectors = detectors.len();
+    // Create input channel for detection pipeline
+    let (input_tx, input_rx) = mpsc::channel(128);
+    // Create detection streams
+    let detection_streams = match common::text_contents_detection_streams(
+        ctx,
+        task.headers.clone(),
+        detectors,
+        0,
+        input_rx,
+    )
+    .await
+    {
+        Ok(detection_streams) => detection_streams,
+        Err(error) => {
+            error!(%trace_id, %error, "task failed: error creating detection streams");
+            // Send error to response channel and terminate
+            let _ = response_tx.send(Err(error)).await;
+            return false;
+        }
+    };
+
+    // Spawn task to consume generations
+    tokio::spawn({
+        let generations = generations.clone();
+        async move {
+            while let Some((index, result)) = generation_stream.next().await {
+                match result {
+                    Ok(generation) => {
+                        // Send generated text to input channel
+                        let input = (index, generation.generated_text.clone().unwrap_or_default());
+                        let _ = input_tx.send(Ok(input)).await;
+                        // Update shared generations
+                        generations.write().unwrap().push(generation);
+                    }
+                    Err(error) => {
+                        // Send error to input channel
+                        let _ = input_tx.send(Err(error)).await;
+                    }
+                }
+            }
+        }
+        .in_current_span()
+    });
+
+    // Process detection batch stream
+    let mut detection_batch_stream = DetectionBatchStream::new(
+        MaxProcessedIndexBatcher::new(n_detectors),
+        detection_streams,
+    );
+    while let Some(result) = detection_batch_stream.next().await {
+        match result {
+            Ok((_, chunk, detections)) => {
+                let input_token_count = if chunk.input_start_index == 0 {
+                    generations
+                        .read()
+                        .unwrap()
+                        .first()
+                        .map(|generation| generation.input_token_count)
+                } else {
+                    None
+                };
+                let response = GenerationWithDetectionStreamResult {
+                    generated_text: Some(chunk.text),
+                    start_index: Some(chunk.start as u32),
+                    processed_index: Some(chunk.end as u32),
+                    input_token_count,
+                    detections: detections.into(),
+                    generation_detections: None,
+                };
+                // Send message to response channel
+                if response_tx.send(Ok(response)).await.is_err() {
+                    info!(%trace_id, "task completed: client disconnected");
+                    return false;
+                }
+            }
+            Err(error) => {
+                error!(%trace_id, %error, "task failed: error received from detection batch stream");
+                // Send error to response channel and terminate
+                let _ = response_tx.send(Err(error)).await;
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Consumes a generation stream, forwarding generated text to a response channel.
+/// Returns `true` if the generation stream completed without errors.
+#[instrument(skip_all)]
+async fn forward_generation_stream(
+    trace_id: TraceId,
+    mut generation_stream: GenerationStream,
+    generations: Arc<RwLock<Vec<ClassifiedGeneratedTextStreamResult>>>,
+    response_tx: mpsc::Sender<Result<GenerationWithDetectionStreamResult, Error>>,
+) -> bool {
+    let mut processed_index = 0;
+    while let Some((index, result)) = generation_stream.next().await {
+        match result {
+            Ok(generation) => {
+                let generated_text = generation.generated_text.clone().unwrap_or_default();
+                let start_index = processed_index;
+                processed_index += generated_text.chars().count() as u32;
+                let response = GenerationWithDetectionStreamResult {
+                    generated_text: Some(generated_text),
+                    start_index: Some(start_index),
+                    processed_index: Some(processed_index),
+                    input_token_count: (index == 0).then_some(generation.input_token_count),
+                    ..Default::default()
+                };
+                generations.write().unwrap().push(generation);
+                // Send message to response channel
+                if response_tx.send(Ok(response)).await.is_err() {
+                    info!(%trace_id, "task completed: client disconnected");
+                    return false;
+                }
+            }
+            Err(error) => {
+                error!(%trace_id, %error, "task failed: error received from generation stream");
+                // Send error to response channel and terminate
+                let _ = response_tx.send(Err(error)).await;
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[derive(Debug)]
+pub struct StreamingGenerationWithDetectionTask {
+    /// Trace ID
+    pub trace_id: TraceId,
+    /// Model ID
+    pub model_id: String,
+    /// Prompt text
+    pub prompt: String,
+    /// Detectors configuration
+    pub detectors: HashMap<String, DetectorParams>,
+    /// Text generation parameters
+    pub text_gen_parameters: Option<GuardrailsTextGenerationParameters>,
+    /// Headers
+    pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
+}
+
+impl StreamingGenerationWithDetectionTask {
+    pub fn new(
+        trace_id: TraceId,
+        request: GenerationWithDetectionHttpRequest,
+        headers: HeaderMap,
+    ) -> Self {
+        Self {
+            trace_id,
+            model_id: request.model_id,
+            prompt: request.prompt,
+            detectors: request.detectors,
+            text_gen_parameters: request.text_gen_parameters,
+            headers,
+            principal: None,
+        }
+    }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
+}
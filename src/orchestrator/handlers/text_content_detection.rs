@@ -14,7 +14,7 @@
  limitations under the License.
 
 */
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use http::HeaderMap;
 use opentelemetry::trace::TraceId;
@@ -25,9 +25,10 @@ use crate::{
     config::DetectorType,
     models::{DetectorParams, TextContentDetectionHttpRequest, TextContentDetectionResult},
     orchestrator::{
-        Error, Orchestrator,
+        Context, Error, Orchestrator,
         common::{self, validate_detectors},
     },
+    server::Principal,
 };
 
 impl Handle<TextContentDetectionTask> for Orchestrator {
@@ -42,28 +43,42 @@ impl Handle<TextContentDetectionTask> for Orchestrator {
         let ctx = self.ctx.clone();
         let trace_id = task.trace_id;
         info!(%trace_id, config = ?task.detectors, "task started");
-
-        validate_detectors(
+        let audit = ctx.audit.entry(
+            trace_id,
+            "/api/v2/text/detection/content",
+            task.principal.as_ref(),
             &task.detectors,
-            &ctx.config.detectors,
-            &[DetectorType::TextContents],
-            true,
-        )?;
+        );
+        let result = handle_detection(ctx, task).await;
+        audit.record(result)
+    }
+}
 
-        // Handle detection
-        let (_, detections) = common::text_contents_detections(
-            ctx,
-            task.headers,
-            task.detectors,
-            0,
-            vec![(0, task.content)],
-        )
-        .await?;
+#[instrument(skip_all)]
+async fn handle_detection(
+    ctx: Arc<Context>,
+    task: TextContentDetectionTask,
+) -> Result<TextContentDetectionResult, Error> {
+    validate_detectors(
+        &task.detectors,
+        &ctx.config.detectors,
+        &[DetectorType::TextContents],
+        true,
+    )?;
 
-        Ok(TextContentDetectionResult {
-            detections: detections.into(),
-        })
-    }
+    // Handle detection
+    let (_, detections) = common::text_contents_detections(
+        ctx,
+        task.headers,
+        task.detectors,
+        0,
+        vec![(0, task.content)],
+    )
+    .await?;
+
+    Ok(TextContentDetectionResult {
+        detections: detections.into(),
+    })
 }
 
 #[derive(Debug)]
@@ -76,6 +91,8 @@ pub struct TextContentDetectionTask {
     pub detectors: HashMap<String, DetectorParams>,
     /// Headers
     pub headers: HeaderMap,
+    /// Authenticated caller
+    pub principal: Option<Principal>,
 }
 
 impl TextContentDetectionTask {
@@ -89,6 +106,13 @@ impl TextContentDetectionTask {
             content: request.content,
             detectors: request.detectors,
             headers,
+            principal: None,
         }
     }
+
+    /// Sets the authenticated caller.
+    pub fn with_principal(mut self, principal: Option<Principal>) -> Self {
+        self.principal = principal;
+        self
+    }
 }
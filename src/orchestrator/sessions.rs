@@ -0,0 +1,377 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Conversation sessions.
+//!
+//! A session holds the state of a conversation across requests: the chat
+//! detection history, so that clients only need to send new messages on each
+//! turn, and the conversation risk state of chat completions.
+//!
+//! Sessions are kept in a [`SessionStore`] by [`SessionKey`], which scopes the
+//! client-provided session ID to the authenticated caller. [`InMemorySessionStore`]
+//! is used by default; external stores can be provided with
+//! [`Orchestrator::with_session_store`](super::Orchestrator::with_session_store).
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::Error;
+use crate::{
+    clients::openai::Message,
+    config::{ConversationRiskConfig, SessionsConfig},
+    server::Principal,
+    utils::lru::LruMap,
+};
+
+/// Key of a session: the session ID scoped to the authenticated caller, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct SessionKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub session_id: String,
+}
+
+impl SessionKey {
+    pub fn new(principal: Option<&Principal>, session_id: impl Into<String>) -> Self {
+        Self {
+            tenant: principal.and_then(|principal| principal.tenant.clone()),
+            subject: principal.map(|principal| principal.subject.clone()),
+            session_id: session_id.into(),
+        }
+    }
+}
+
+/// Store of conversation sessions.
+#[async_trait]
+pub trait SessionStore: Debug + Send + Sync + 'static {
+    /// Gets a session, returning `None` if it does not exist or has expired.
+    async fn get(&self, key: &SessionKey) -> Result<Option<Session>, Error>;
+
+    /// Inserts or replaces a session.
+    async fn put(&self, key: &SessionKey, session: Session) -> Result<(), Error>;
+
+    /// Atomically applies `f` to the current session, or to a new session if it does not
+    /// exist or has expired, and stores the result. Returns the updated session.
+    ///
+    /// Concurrent updates of a session must not be lost. Stores using compare-and-swap
+    /// may call `f` more than once.
+    async fn update(
+        &self,
+        key: &SessionKey,
+        f: &mut (dyn FnMut(&mut Session) + Send),
+    ) -> Result<Session, Error>;
+
+    /// Removes a session, returning `true` if it existed.
+    async fn remove(&self, key: &SessionKey) -> Result<bool, Error>;
+}
+
+/// State of a conversation.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// Retained chat detection messages, the oldest messages are removed first
+    #[serde(default)]
+    pub messages: VecDeque<Message>,
+    /// Number of messages removed from the start of the conversation
+    #[serde(default)]
+    pub offset: usize,
+    /// Conversation risk state
+    #[serde(default)]
+    pub risk: ConversationRisk,
+}
+
+impl Session {
+    /// Returns the number of messages in the conversation, including removed messages.
+    pub fn len(&self) -> usize {
+        self.offset + self.messages.len()
+    }
+
+    /// Returns `true` if the conversation has no messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends messages, removing the oldest messages to retain at most `max_messages`.
+    pub fn append_messages(&mut self, messages: Vec<Message>, max_messages: usize) {
+        self.messages.extend(messages);
+        while self.messages.len() > max_messages {
+            self.messages.pop_front();
+            self.offset += 1;
+        }
+    }
+}
+
+/// Cumulative risk scores of a conversation.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationRisk {
+    /// Cumulative score per detector
+    #[serde(default)]
+    pub scores: HashMap<String, f64>,
+    /// Number of turns
+    #[serde(default)]
+    pub turns: usize,
+    /// Number of messages of the last scored request
+    #[serde(default)]
+    pub messages: usize,
+    /// Detector whose cumulative score reached its limit, set once the conversation is blocked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked_by: Option<String>,
+}
+
+impl ConversationRisk {
+    /// Updates cumulative scores with the highest score of each detector on a turn.
+    /// Returns the detector blocking the conversation, if any.
+    pub fn update(
+        &mut self,
+        turn_scores: HashMap<String, f64>,
+        config: &ConversationRiskConfig,
+    ) -> Option<&str> {
+        if self.blocked_by.is_none() {
+            for score in self.scores.values_mut() {
+                *score *= config.decay;
+            }
+            for (detector_id, score) in turn_scores {
+                *self.scores.entry(detector_id).or_default() += score;
+            }
+            self.turns += 1;
+            // Sort by detector id for a deterministic result
+            let mut scores = self.scores.iter().collect::<Vec<_>>();
+            scores.sort_by(|a, b| a.0.cmp(b.0));
+            self.blocked_by = scores
+                .into_iter()
+                .find(|(detector_id, score)| **score >= config.limit(detector_id))
+                .map(|(detector_id, _)| detector_id.clone());
+        }
+        self.blocked_by.as_deref()
+    }
+}
+
+/// Bounded in-memory session store.
+///
+/// Sessions are removed when inactive for longer than the configured TTL, and the
+/// least recently used session is removed when the store is full.
+#[derive(Debug)]
+pub struct InMemorySessionStore {
+    sessions: Mutex<LruMap<SessionKey, Session>>,
+    max_sessions: usize,
+    ttl: Duration,
+}
+
+impl InMemorySessionStore {
+    pub fn new(config: &SessionsConfig) -> Self {
+        Self {
+            sessions: Mutex::new(LruMap::new()),
+            max_sessions: config.max_sessions,
+            ttl: Duration::from_secs(config.ttl),
+        }
+    }
+
+    /// Removes expired sessions and, if the store is full, the least recently used
+    /// session to make room for `key`.
+    ///
+    /// Expired sessions are the least recently used, so each call only visits sessions
+    /// it removes.
+    fn make_room(&self, sessions: &mut LruMap<SessionKey, Session>, key: &SessionKey) {
+        while sessions
+            .peek_lru()
+            .is_some_and(|(_, _, last_active)| last_active.elapsed() >= self.ttl)
+        {
+            sessions.pop_lru();
+        }
+        if !sessions.contains_key(key)
+            && sessions.len() >= self.max_sessions
+            && let Some((lru_key, _)) = sessions.pop_lru()
+        {
+            debug!(
+                session_id = lru_key.session_id,
+                "removing least recently used session"
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn get(&self, key: &SessionKey) -> Result<Option<Session>, Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let active = sessions
+            .peek(key)
+            .is_some_and(|(_, last_active)| last_active.elapsed() < self.ttl);
+        Ok(if active {
+            sessions.get_mut(key).cloned()
+        } else {
+            None
+        })
+    }
+
+    async fn put(&self, key: &SessionKey, session: Session) -> Result<(), Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.make_room(&mut sessions, key);
+        sessions.insert(key.clone(), session);
+        Ok(())
+    }
+
+    async fn update(
+        &self,
+        key: &SessionKey,
+        f: &mut (dyn FnMut(&mut Session) + Send),
+    ) -> Result<Session, Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.make_room(&mut sessions, key);
+        if !sessions.contains_key(key) {
+            sessions.insert(key.clone(), Session::default());
+        }
+        let session = sessions.get_mut(key).unwrap();
+        f(session);
+        Ok(session.clone())
+    }
+
+    async fn remove(&self, key: &SessionKey) -> Result<bool, Error> {
+        Ok(self.sessions.lock().unwrap().remove(key).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::openai::{Content, Role},
+        server::AuthMethod,
+    };
+
+    fn message(text: &str) -> Message {
+        Message {
+            role: Role::User,
+            content: Some(Content::Text(text.into())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_append_messages() {
+        let mut session = Session::default();
+        assert!(session.is_empty());
+
+        session.append_messages(vec![message("1")], 2);
+        assert_eq!(session.messages, [message("1")]);
+        assert_eq!(session.offset, 0);
+
+        // Oldest messages are removed once `max_messages` is exceeded
+        session.append_messages(vec![message("2"), message("3")], 2);
+        assert_eq!(session.messages, [message("2"), message("3")]);
+        assert_eq!(session.offset, 1);
+        assert_eq!(session.len(), 3);
+    }
+
+    #[test]
+    fn test_conversation_risk() {
+        let config = ConversationRiskConfig {
+            decay: 0.5,
+            limit: 1.0,
+            detector_limits: HashMap::from([("b".into(), 2.0)]),
+            ..Default::default()
+        };
+        let mut risk = ConversationRisk::default();
+        assert_eq!(
+            risk.update(
+                HashMap::from([("a".into(), 0.6), ("b".into(), 0.9)]),
+                &config
+            ),
+            None
+        );
+        // a: 0.6 * 0.5 + 0.6 = 0.9, b: 0.9 * 0.5 + 0.9 = 1.35
+        assert_eq!(
+            risk.update(
+                HashMap::from([("a".into(), 0.6), ("b".into(), 0.9)]),
+                &config
+            ),
+            None
+        );
+        // a: 0.9 * 0.5 + 0.6 = 1.05
+        assert_eq!(
+            risk.update(HashMap::from([("a".into(), 0.6)]), &config),
+            Some("a")
+        );
+        assert_eq!(risk.turns, 3);
+        // Blocked conversations remain blocked
+        assert_eq!(risk.update(HashMap::new(), &config), Some("a"));
+        assert_eq!(risk.turns, 3);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_session_store() -> Result<(), Error> {
+        let store = InMemorySessionStore::new(&SessionsConfig {
+            max_sessions: 2,
+            ..Default::default()
+        });
+        let session = Session {
+            messages: [message("1")].into(),
+            ..Default::default()
+        };
+        let (a, b, c) = (
+            SessionKey::new(None, "a"),
+            SessionKey::new(None, "b"),
+            SessionKey::new(None, "c"),
+        );
+        store.put(&a, session.clone()).await?;
+        store.put(&b, session.clone()).await?;
+        assert_eq!(store.get(&a).await?, Some(session.clone()));
+        // "b" is the least recently used session
+        store.put(&c, session.clone()).await?;
+        assert!(store.get(&a).await?.is_some());
+        assert!(store.get(&b).await?.is_none());
+        assert!(store.get(&c).await?.is_some());
+
+        assert!(store.remove(&a).await?);
+        assert!(store.get(&a).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_session_store_update() -> Result<(), Error> {
+        let store = InMemorySessionStore::new(&SessionsConfig::default());
+        let principal = Principal {
+            subject: "alice".into(),
+            tenant: None,
+            method: AuthMethod::ApiKey,
+            scopes: Vec::new(),
+        };
+        let key = SessionKey::new(Some(&principal), "a");
+        let session = store
+            .update(&key, &mut |session| {
+                session.append_messages(vec![message("1")], 10)
+            })
+            .await?;
+        assert_eq!(session.messages, [message("1")]);
+        store
+            .update(&key, &mut |session| {
+                session.append_messages(vec![message("2")], 10)
+            })
+            .await?;
+        assert_eq!(store.get(&key).await?.map(|session| session.len()), Some(2));
+        // Sessions are scoped to the caller
+        assert!(store.get(&SessionKey::new(None, "a")).await?.is_none());
+        Ok(())
+    }
+}
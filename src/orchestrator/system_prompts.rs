@@ -0,0 +1,118 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Cache of system prompt detections.
+//!
+//! System prompts are long and rarely change across requests, so input detections
+//! on system messages are cached by a hash of the detector, its parameters and the
+//! prompt text. Detectors are called once per distinct system prompt.
+use std::sync::Mutex;
+
+use ring::digest::{SHA256, digest};
+use tracing::debug;
+
+use super::types::Detections;
+use crate::{models::DetectorParams, utils::lru::LruMap};
+
+/// Default maximum number of cached detections.
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Key of cached system prompt detections.
+pub type CacheKey = [u8; 32];
+
+/// Bounded cache of system prompt detections by prompt hash.
+///
+/// The least recently used entry is removed when the cache is full.
+#[derive(Debug)]
+pub struct SystemPromptCache {
+    entries: Mutex<LruMap<CacheKey, Detections>>,
+    max_entries: usize,
+}
+
+impl SystemPromptCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(LruMap::new()),
+            max_entries,
+        }
+    }
+
+    /// Returns the cache key of a detector applied to a system prompt.
+    pub fn key(detector_id: &str, params: &DetectorParams, prompt: &str) -> CacheKey {
+        let params = serde_json::to_string(&**params).unwrap_or_default();
+        let input = [detector_id, params.as_str(), prompt].join("\0");
+        digest(&SHA256, input.as_bytes())
+            .as_ref()
+            .try_into()
+            .expect("SHA-256 digest is 32 bytes")
+    }
+
+    /// Gets cached detections.
+    pub fn get(&self, key: &CacheKey) -> Option<Detections> {
+        self.entries.lock().unwrap().get_mut(key).cloned()
+    }
+
+    /// Inserts detections, removing the least recently used entry if full.
+    pub fn insert(&self, key: CacheKey, detections: Detections) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key)
+            && entries.len() >= self.max_entries
+            && entries.pop_lru().is_some()
+        {
+            debug!("removed least recently used system prompt detections");
+        }
+        entries.insert(key, detections);
+    }
+}
+
+impl Default for SystemPromptCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::types::Detection;
+
+    #[test]
+    fn test_system_prompt_cache() {
+        let cache = SystemPromptCache::new(2);
+        let params = DetectorParams::new();
+        let a = SystemPromptCache::key("hap", &params, "You are a helpful assistant.");
+        let b = SystemPromptCache::key("pii", &params, "You are a helpful assistant.");
+        let c = SystemPromptCache::key("hap", &params, "You are a pirate.");
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+
+        let detections = Detections::from_iter([Detection {
+            score: 0.9,
+            ..Default::default()
+        }]);
+        cache.insert(a, detections.clone());
+        cache.insert(b, Detections::default());
+        assert_eq!(
+            cache.get(&a).map(|detections| detections.len()),
+            Some(detections.len())
+        );
+        // `b` is the least recently used entry
+        cache.insert(c, Detections::default());
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+    }
+}
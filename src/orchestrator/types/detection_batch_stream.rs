@@ -79,6 +79,7 @@ impl DetectionBatchStream {
                                 },
                                 None => {
                                     debug!("detections stream has completed");
+                                    batcher_manager.finish().await;
                                     stream_completed = true;
                                 },
                             }
@@ -121,6 +122,7 @@ enum DetectionBatcherMessage {
         chunk: Chunk,
         detections: Detections,
     },
+    Finish,
     Pop {
         response_tx: oneshot::Sender<Option<Batch>>,
     },
@@ -154,6 +156,10 @@ where
                     debug!(%input_id, ?chunk, ?detections, "handling push request");
                     self.batcher.push(input_id, chunk, detections)
                 }
+                DetectionBatcherMessage::Finish => {
+                    debug!("handling finish request");
+                    self.batcher.finish()
+                }
                 DetectionBatcherMessage::Pop { response_tx } => {
                     debug!("handling pop request");
                     let batch = self.batcher.pop_batch();
@@ -198,6 +204,11 @@ impl DetectionBatcherManagerHandle {
             .await;
     }
 
+    /// Signals the batcher that all detection streams have completed.
+    pub async fn finish(&self) {
+        let _ = self.tx.send(DetectionBatcherMessage::Finish).await;
+    }
+
     /// Removes the next batch of detections from the batcher, if ready.
     pub async fn pop(&self) -> Option<Batch> {
         let (response_tx, response_rx) = oneshot::channel();
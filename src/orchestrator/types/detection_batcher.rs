@@ -18,6 +18,8 @@ pub mod completion;
 pub use completion::*;
 pub mod max_processed_index;
 pub use max_processed_index::*;
+pub mod span_aligned;
+pub use span_aligned::*;
 
 use super::{Chunk, Detections};
 
@@ -32,6 +34,9 @@ pub trait DetectionBatcher: std::fmt::Debug + Clone + Send + 'static {
     /// Removes the next batch of detections, if ready.
     fn pop_batch(&mut self) -> Option<Batch>;
 
+    /// Signals that all detection streams have completed.
+    fn finish(&mut self) {}
+
     /// Returns `true` if the batcher state is empty.
     fn is_empty(&self) -> bool;
 }
@@ -0,0 +1,440 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+use std::collections::{BTreeMap, VecDeque};
+
+use super::{Batch, Chunk, DetectionBatcher, Detections};
+
+/// A group of detectors sharing a chunker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkerGroup {
+    /// Number of detectors in the group
+    pub n_detectors: usize,
+    /// Whether the group uses the whole document chunker
+    pub whole_doc: bool,
+}
+
+impl ChunkerGroup {
+    pub fn new(n_detectors: usize, whole_doc: bool) -> Self {
+        Self {
+            n_detectors,
+            whole_doc,
+        }
+    }
+}
+
+/// A batcher for detectors using different chunkers.
+///
+/// Detectors are partitioned into chunker groups and the `input_id`
+/// of each push identifies the group (its index). Within a group,
+/// a chunk is ready once detections from all of its detectors are
+/// received. A batch is popped for the text up to an offset once
+/// every group has ready chunks covering that offset, so a batch
+/// contains the detections of all chunks ending at or before it.
+///
+/// For example, with a sentence group and a paragraph group, a batch
+/// for the first sentence is popped once the paragraph containing it
+/// has been processed, and the paragraph detections are returned with
+/// the batch of its last sentence.
+///
+/// A batch chunk starts where the previous batch ended, so text already
+/// returned with smaller chunks is not returned again with larger ones.
+/// Detections are relative to the start of the batch chunk, detections
+/// starting in text of a previous batch are clamped to its start. Results
+/// of the whole document group are held and returned in a final batch
+/// once all detection streams have completed.
+#[derive(Debug, Clone)]
+pub struct SpanAlignedBatcher {
+    groups: Vec<GroupState>,
+    /// End offset and input end index of the last span batch
+    emitted: Option<(usize, usize)>,
+    finished: bool,
+}
+
+#[derive(Debug, Clone)]
+struct GroupState {
+    group: ChunkerGroup,
+    /// Chunks awaiting detections from all detectors in the group
+    pending: BTreeMap<Chunk, Vec<Detections>>,
+    /// Chunks with detections from all detectors in the group
+    ready: VecDeque<(Chunk, Detections)>,
+    /// End of the text covered by ready chunks
+    covered: usize,
+}
+
+impl GroupState {
+    /// Moves chunks with detections from all detectors to ready.
+    fn promote(&mut self) {
+        while self
+            .pending
+            .first_key_value()
+            .is_some_and(|(_, detections)| detections.len() >= self.group.n_detectors)
+        {
+            let (chunk, detections) = self.pending.pop_first().unwrap();
+            self.covered = self.covered.max(chunk.end);
+            self.ready
+                .push_back((chunk, detections.into_iter().flatten().collect()));
+        }
+    }
+
+    /// Moves all chunks to ready, including incomplete ones.
+    fn flush(&mut self) {
+        while let Some((chunk, detections)) = self.pending.pop_first() {
+            self.covered = self.covered.max(chunk.end);
+            self.ready
+                .push_back((chunk, detections.into_iter().flatten().collect()));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.ready.is_empty()
+    }
+}
+
+impl SpanAlignedBatcher {
+    pub fn new(groups: Vec<ChunkerGroup>) -> Self {
+        Self {
+            groups: groups
+                .into_iter()
+                .map(|group| GroupState {
+                    group,
+                    pending: BTreeMap::default(),
+                    ready: VecDeque::default(),
+                    covered: 0,
+                })
+                .collect(),
+            emitted: None,
+            finished: false,
+        }
+    }
+
+    /// Returns the offset of the next batch, if ready.
+    fn next_offset(&self) -> Option<usize> {
+        let span_groups = self.groups.iter().filter(|state| !state.group.whole_doc);
+        // Next offset is the end of the nearest ready chunk
+        let offset = span_groups
+            .clone()
+            .filter_map(|state| state.ready.front().map(|(chunk, _)| chunk.end))
+            .min()?;
+        // It must be covered by all groups, unless the streams have completed
+        if self.finished || span_groups.clone().all(|state| state.covered >= offset) {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the batch of chunks ending at or before an offset.
+    fn pop_span_batch(&mut self, offset: usize) -> Batch {
+        let mut chunks = Vec::new();
+        for state in self
+            .groups
+            .iter_mut()
+            .filter(|state| !state.group.whole_doc)
+        {
+            while state
+                .ready
+                .front()
+                .is_some_and(|(chunk, _)| chunk.end <= offset)
+            {
+                chunks.push(state.ready.pop_front().unwrap());
+            }
+        }
+        chunks.sort_by_key(|(chunk, _)| (chunk.start, chunk.end));
+        // Start after the text and input of the previous batch
+        let mut start = chunks.first().map(|(chunk, _)| chunk.start).unwrap();
+        let mut input_start_index = usize::MAX;
+        if let Some((emitted_end, emitted_input_end_index)) = self.emitted {
+            start = start.max(emitted_end);
+            input_start_index = emitted_input_end_index + 1;
+        }
+        let mut batch_chunk = Chunk {
+            input_start_index,
+            input_end_index: 0,
+            start,
+            end: start,
+            text: String::new(),
+        };
+        let mut detections = Detections::new();
+        for (chunk, chunk_detections) in chunks {
+            if self.emitted.is_none() {
+                batch_chunk.input_start_index =
+                    batch_chunk.input_start_index.min(chunk.input_start_index);
+            }
+            batch_chunk.input_end_index = batch_chunk.input_end_index.max(chunk.input_end_index);
+            if chunk.end > batch_chunk.end {
+                // Append the part of the chunk text not yet covered
+                let skip = batch_chunk.end.saturating_sub(chunk.start);
+                batch_chunk.text.extend(chunk.text.chars().skip(skip));
+                batch_chunk.end = chunk.end;
+            }
+            // Make detections relative to the batch chunk
+            let shift = |offset: usize| (chunk.start + offset).saturating_sub(start);
+            detections.extend(chunk_detections.into_iter().map(|mut detection| {
+                detection.start = detection.start.map(shift);
+                detection.end = detection.end.map(shift);
+                detection
+            }));
+        }
+        batch_chunk.input_start_index = batch_chunk
+            .input_start_index
+            .min(batch_chunk.input_end_index);
+        self.emitted = Some((batch_chunk.end, batch_chunk.input_end_index));
+        (0, batch_chunk, detections)
+    }
+
+    /// Removes the batch of whole document detections.
+    fn pop_whole_doc_batch(&mut self) -> Option<Batch> {
+        let state = self
+            .groups
+            .iter_mut()
+            .find(|state| state.group.whole_doc && !state.ready.is_empty())?;
+        let (chunk, detections) = state.ready.pop_front().unwrap();
+        Some((0, chunk, detections))
+    }
+}
+
+impl DetectionBatcher for SpanAlignedBatcher {
+    fn push(&mut self, group_index: u32, chunk: Chunk, detections: Detections) {
+        if let Some(state) = self.groups.get_mut(group_index as usize) {
+            state.pending.entry(chunk).or_default().push(detections);
+            state.promote();
+        }
+    }
+
+    fn pop_batch(&mut self) -> Option<Batch> {
+        if let Some(offset) = self.next_offset() {
+            return Some(self.pop_span_batch(offset));
+        }
+        if self.finished {
+            // Whole document detections are returned last
+            return self.pop_whole_doc_batch();
+        }
+        None
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        for state in self.groups.iter_mut() {
+            state.flush();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.groups.iter().all(|state| state.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    use super::*;
+    use crate::orchestrator::{
+        Error,
+        types::{Detection, DetectionBatchStream},
+    };
+
+    fn chunk(input_start_index: usize, input_end_index: usize, start: usize, text: &str) -> Chunk {
+        Chunk {
+            input_start_index,
+            input_end_index,
+            start,
+            end: start + text.chars().count(),
+            text: text.into(),
+        }
+    }
+
+    fn detection(detector_id: &str, start: usize, end: usize) -> Detections {
+        vec![Detection {
+            start: Some(start),
+            end: Some(end),
+            detector_id: Some(detector_id.into()),
+            detection_type: detector_id.into(),
+            score: 0.9,
+            ..Default::default()
+        }]
+        .into()
+    }
+
+    #[test]
+    fn test_batcher_with_sentence_and_paragraph_chunks() {
+        let sentences = [
+            chunk(0, 1, 0, "Hello there. "),
+            chunk(2, 3, 13, "How are you?"),
+        ];
+        let paragraph = chunk(0, 3, 0, "Hello there. How are you?");
+
+        // Group 0: 2 sentence detectors, group 1: 1 paragraph detector
+        let mut batcher = SpanAlignedBatcher::new(vec![
+            ChunkerGroup::new(2, false),
+            ChunkerGroup::new(1, false),
+        ]);
+
+        // Push sentence-1 detections for both sentence detectors
+        batcher.push(0, sentences[0].clone(), detection("pii", 6, 11));
+        batcher.push(0, sentences[0].clone(), Detections::default());
+
+        // The paragraph detector has not covered sentence-1 yet
+        // pop_batch() should return None
+        assert!(batcher.pop_batch().is_none());
+
+        // Push sentence-2 detections and paragraph detections
+        batcher.push(0, sentences[1].clone(), Detections::default());
+        batcher.push(0, sentences[1].clone(), Detections::default());
+        batcher.push(1, paragraph.clone(), detection("tone", 13, 25));
+
+        // pop_batch() should return sentence-1 with 1 pii detection
+        let (_, chunk, detections) = batcher.pop_batch().unwrap();
+        assert_eq!(chunk, sentences[0]);
+        assert_eq!(chunk.text, sentences[0].text);
+        assert_eq!(detections, detection("pii", 6, 11));
+
+        // pop_batch() should return the rest of the paragraph, i.e. sentence-2,
+        // with 1 tone detection relative to its start
+        let (_, chunk, detections) = batcher.pop_batch().unwrap();
+        assert_eq!(chunk, sentences[1]);
+        assert_eq!(chunk.text, "How are you?");
+        assert_eq!(detections, detection("tone", 0, 12));
+
+        assert!(batcher.pop_batch().is_none());
+        assert!(batcher.is_empty());
+    }
+
+    #[test]
+    fn test_batcher_shifts_detections_to_batch_start() {
+        let sentences = [chunk(0, 0, 0, "One. "), chunk(1, 1, 5, "Two. ")];
+        let paragraphs = [chunk(0, 1, 0, "One. Two. ")];
+
+        let mut batcher = SpanAlignedBatcher::new(vec![
+            ChunkerGroup::new(1, false),
+            ChunkerGroup::new(1, false),
+        ]);
+
+        // Paragraph group has covered both sentences
+        batcher.push(1, paragraphs[0].clone(), detection("tone", 3, 8));
+        batcher.push(0, sentences[0].clone(), Detections::default());
+
+        // Sentence-1 batch has no detections
+        let (_, chunk, detections) = batcher.pop_batch().unwrap();
+        assert_eq!(chunk, sentences[0]);
+        assert!(detections.is_empty());
+
+        // Sentence-2 has not been processed yet
+        assert!(batcher.pop_batch().is_none());
+        batcher.push(0, sentences[1].clone(), detection("pii", 0, 3));
+
+        // Sentence-2 batch starts after sentence-1, the tone detection
+        // starting in sentence-1 is shifted and clamped to the batch start
+        let (_, chunk, detections) = batcher.pop_batch().unwrap();
+        assert_eq!(chunk, sentences[1]);
+        assert_eq!(chunk.text, "Two. ");
+        assert_eq!(detections.len(), 2);
+        assert!(detections.iter().any(|detection| {
+            detection.detector_id == Some("pii".into())
+                && detection.start == Some(0)
+                && detection.end == Some(3)
+        }));
+        assert!(detections.iter().any(|detection| {
+            detection.detector_id == Some("tone".into())
+                && detection.start == Some(0)
+                && detection.end == Some(3)
+        }));
+    }
+
+    #[test]
+    fn test_batcher_returns_whole_doc_detections_last() {
+        let sentence = chunk(0, 1, 0, "Hi <there>");
+        let whole_doc = chunk(0, 1, 0, "Hi <there>");
+
+        let mut batcher = SpanAlignedBatcher::new(vec![
+            ChunkerGroup::new(1, false),
+            ChunkerGroup::new(2, true),
+        ]);
+
+        // Whole doc detections arrive first
+        batcher.push(1, whole_doc.clone(), detection("angle_brackets", 3, 10));
+        batcher.push(1, whole_doc.clone(), Detections::default());
+        assert!(batcher.pop_batch().is_none());
+
+        // Sentence batch is not blocked by the whole doc group
+        batcher.push(0, sentence.clone(), Detections::default());
+        let (_, chunk, detections) = batcher.pop_batch().unwrap();
+        assert_eq!(chunk, sentence);
+        assert!(detections.is_empty());
+
+        // Whole doc detections are held until finished
+        assert!(batcher.pop_batch().is_none());
+        assert!(!batcher.is_empty());
+        batcher.finish();
+        let (_, chunk, detections) = batcher.pop_batch().unwrap();
+        assert_eq!(chunk, whole_doc);
+        assert_eq!(detections, detection("angle_brackets", 3, 10));
+        assert!(batcher.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_detection_batch_stream() -> Result<(), Error> {
+        let sentences = [chunk(0, 0, 0, "One. "), chunk(1, 1, 5, "Two.")];
+        let whole_doc = chunk(0, 1, 0, "One. Two.");
+
+        // Create detection channels and streams
+        let (sentence_tx, sentence_rx) =
+            mpsc::channel::<Result<(u32, Chunk, Detections), Error>>(4);
+        let (whole_doc_tx, whole_doc_rx) =
+            mpsc::channel::<Result<(u32, Chunk, Detections), Error>>(4);
+        let streams = vec![
+            ReceiverStream::new(sentence_rx).boxed(),
+            ReceiverStream::new(whole_doc_rx).boxed(),
+        ];
+        let batcher = SpanAlignedBatcher::new(vec![
+            ChunkerGroup::new(1, false),
+            ChunkerGroup::new(1, true),
+        ]);
+        let mut detection_batch_stream = DetectionBatchStream::new(batcher, streams);
+
+        for sentence in &sentences {
+            let _ = sentence_tx
+                .send(Ok((0, sentence.clone(), Detections::default())))
+                .await;
+        }
+        let _ = whole_doc_tx
+            .send(Ok((1, whole_doc.clone(), detection("tone", 0, 9))))
+            .await;
+        drop(sentence_tx);
+        drop(whole_doc_tx);
+
+        // Sentence batches are returned in order, followed by whole doc detections
+        let batches = detection_batch_stream
+            .map(|result| result.map(|(_, chunk, detections)| (chunk, detections.len())))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            batches,
+            vec![
+                (sentences[0].clone(), 0),
+                (sentences[1].clone(), 0),
+                (whole_doc, 1)
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -16,38 +16,101 @@
 */
 use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
+use axum::{extract::Request, middleware};
 use tokio::{net::TcpListener, signal};
+use tonic::service::Routes;
 use tower_http::trace::TraceLayer;
 use tracing::info;
 
-use crate::orchestrator::Orchestrator;
+use crate::{orchestrator::Orchestrator, utils::cert_expiry};
 
+mod auth;
 mod errors;
+mod grpc;
+mod jobs;
+mod rate_limit;
 mod routes;
 mod tls;
+mod websocket;
+use auth::Authenticator;
+pub use auth::{AuthMethod, Principal};
 pub use errors::Error;
-use tls::{configure_tls, serve_with_tls};
+use grpc::{GuardrailsOrchestratorServer, GuardrailsService};
+use jobs::JobManager;
+use rate_limit::RateLimiter;
+use tls::{ReloadingServerConfig, configure_tls, serve_with_tls};
 
 /// Configures and runs orchestrator servers.
+///
+/// The gRPC server is only run if `grpc_addr` is provided.
+#[allow(clippy::type_complexity)]
 pub async fn run(
     guardrails_addr: SocketAddr,
     health_addr: SocketAddr,
+    grpc_addr: Option<SocketAddr>,
     tls_cert_path: Option<PathBuf>,
     tls_key_path: Option<PathBuf>,
     tls_client_ca_cert_path: Option<PathBuf>,
     orchestrator: Orchestrator,
-) -> Result<(tokio::task::JoinHandle<()>, tokio::task::JoinHandle<()>), Error> {
-    let state = Arc::new(ServerState::new(orchestrator));
+) -> Result<
+    (
+        tokio::task::JoinHandle<()>,
+        tokio::task::JoinHandle<()>,
+        Option<tokio::task::JoinHandle<()>>,
+    ),
+    Error,
+> {
+    let mut state = ServerState::new(orchestrator);
+    if let Some(jobs_config) = &state.orchestrator.config().jobs {
+        state.jobs = Some(Arc::new(JobManager::new(jobs_config).await?));
+    }
+    let state = Arc::new(state);
+    // TLS config is shared by the guardrails and gRPC servers
+    let tls_config = configure_tls(tls_cert_path, tls_key_path, tls_client_ca_cert_path)?;
+    if let Some(tls_config) = &tls_config {
+        tls_config.clone().spawn_reloader();
+    }
+    let authenticator = state
+        .orchestrator
+        .config()
+        .auth
+        .as_ref()
+        .map(Authenticator::load)
+        .transpose()?
+        .map(Arc::new);
+    // Rate limits are shared by the guardrails and gRPC servers
+    let limiter = state
+        .orchestrator
+        .config()
+        .rate_limit
+        .as_ref()
+        .map(|config| Arc::new(RateLimiter::new(config)));
     let health_handle = run_health_server(health_addr, state.clone()).await?;
+    let grpc_handle = match grpc_addr {
+        Some(grpc_addr) => Some(
+            run_grpc_server(
+                grpc_addr,
+                tls_config.clone(),
+                authenticator.clone(),
+                limiter.clone(),
+                state.clone(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
     let guardrails_handle = run_guardrails_server(
         guardrails_addr,
-        tls_cert_path,
-        tls_key_path,
-        tls_client_ca_cert_path,
-        state,
+        tls_config,
+        authenticator,
+        limiter,
+        state.clone(),
     )
     .await?;
-    Ok((health_handle, guardrails_handle))
+    // Server certs are recorded when TLS is configured
+    cert_expiry::record_config_certs(state.orchestrator.config());
+    cert_expiry::spawn_monitor(state.orchestrator.config().cert_expiry.clone());
+    Ok((health_handle, guardrails_handle, grpc_handle))
 }
 
 /// Configures and runs health server.
@@ -68,13 +131,23 @@ async fn run_health_server(
 /// Configures and runs guardrails server.
 async fn run_guardrails_server(
     addr: SocketAddr,
-    tls_cert_path: Option<PathBuf>,
-    tls_key_path: Option<PathBuf>,
-    tls_client_ca_cert_path: Option<PathBuf>,
+    tls_config: Option<Arc<ReloadingServerConfig>>,
+    authenticator: Option<Arc<Authenticator>>,
+    limiter: Option<Arc<RateLimiter>>,
     state: Arc<ServerState>,
 ) -> Result<tokio::task::JoinHandle<()>, Error> {
     info!("starting guardrails server on {addr}");
-    let router = routes::guardrails_router(state);
+    let mut router = routes::guardrails_router(state);
+    // Rate limit layer is added first so that it runs after authentication
+    if let Some(limiter) = limiter {
+        router = router.layer(middleware::from_fn_with_state(limiter, rate_limit::limit));
+    }
+    if let Some(authenticator) = authenticator {
+        router = router.layer(middleware::from_fn_with_state(
+            authenticator,
+            auth::authenticate,
+        ));
+    }
     let app = router.layer(
         TraceLayer::new_for_http()
             .make_span_with(crate::utils::trace::incoming_request_span)
@@ -83,7 +156,6 @@ async fn run_guardrails_server(
             .on_eos(crate::utils::trace::on_outgoing_eos),
     );
     let listener = TcpListener::bind(&addr).await?;
-    let tls_config = configure_tls(tls_cert_path, tls_key_path, tls_client_ca_cert_path);
     let shutdown_signal = shutdown_signal();
     if let Some(tls_config) = tls_config {
         Ok(serve_with_tls(app, listener, tls_config, shutdown_signal))
@@ -96,6 +168,57 @@ async fn run_guardrails_server(
     }
 }
 
+/// Configures and runs guardrails gRPC server.
+///
+/// Requests are authenticated and rate limited using the gRPC method path, e.g.
+/// `/orchestrator.GuardrailsOrchestrator/DetectTextContent`.
+///
+/// The service is served as an axum router, so that it shares the reloading TLS
+/// config and middleware of the guardrails server.
+async fn run_grpc_server(
+    addr: SocketAddr,
+    tls_config: Option<Arc<ReloadingServerConfig>>,
+    authenticator: Option<Arc<Authenticator>>,
+    limiter: Option<Arc<RateLimiter>>,
+    state: Arc<ServerState>,
+) -> Result<tokio::task::JoinHandle<()>, Error> {
+    info!("starting guardrails gRPC server on {addr}");
+    let service = GuardrailsService::new(state);
+    let mut router = Routes::new(GuardrailsOrchestratorServer::new(service)).into_axum_router();
+    // Rate limit layer is added first so that it runs after authentication
+    if let Some(limiter) = limiter {
+        router = router.layer(middleware::from_fn_with_state(
+            limiter,
+            rate_limit::limit_grpc,
+        ));
+    }
+    if let Some(authenticator) = authenticator {
+        router = router.layer(middleware::from_fn_with_state(
+            authenticator,
+            grpc::authenticate,
+        ));
+    }
+    let app = router.layer(
+        TraceLayer::new_for_grpc().make_span_with(|request: &Request| {
+            tracing::info_span!(
+                "grpc_request",
+                request_path = request.uri().path().to_string()
+            )
+        }),
+    );
+    let listener = TcpListener::bind(&addr).await?;
+    let shutdown_signal = shutdown_signal();
+    if let Some(tls_config) = tls_config {
+        Ok(serve_with_tls(app, listener, tls_config, shutdown_signal))
+    } else {
+        let server =
+            axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown_signal);
+        Ok(tokio::task::spawn(async {
+            server.await.expect("guardrails gRPC server crashed!")
+        }))
+    }
+}
+
 /// Shutdown signal handler
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -122,11 +245,15 @@ async fn shutdown_signal() {
 /// Server shared state
 pub struct ServerState {
     orchestrator: Orchestrator,
+    jobs: Option<Arc<JobManager>>,
 }
 
 impl ServerState {
     pub fn new(orchestrator: Orchestrator) -> Self {
-        Self { orchestrator }
+        Self {
+            orchestrator,
+            jobs: None,
+        }
     }
 }
 
@@ -145,6 +272,7 @@ mod tests {
             None,
             None,
             None,
+            None,
             Orchestrator::default(),
         )
         .await;
@@ -160,14 +288,16 @@ mod tests {
     async fn test_run_with_tls() -> Result<(), Error> {
         let guardrails_addr: SocketAddr = "0.0.0.0:50104".parse().unwrap();
         let health_addr: SocketAddr = "0.0.0.0:50105".parse().unwrap();
+        let grpc_addr: SocketAddr = "0.0.0.0:50106".parse().unwrap();
         let resources: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "resources"]
             .iter()
             .collect();
         let tls_cert_path = resources.join("localhost.crt");
         let tls_key_path = resources.join("localhost.key");
-        let (_health_handle, guardrails_handle) = run(
+        let (_health_handle, guardrails_handle, grpc_handle) = run(
             guardrails_addr,
             health_addr,
+            Some(grpc_addr),
             Some(tls_cert_path),
             Some(tls_key_path),
             None,
@@ -178,6 +308,7 @@ mod tests {
         // Ensure guardrails server task is still running
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         assert!(!guardrails_handle.is_finished());
+        assert!(grpc_handle.is_some_and(|handle| !handle.is_finished()));
 
         Ok(())
     }
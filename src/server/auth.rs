@@ -0,0 +1,691 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Header, Validation,
+    errors::ErrorKind,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, KeyAlgorithm, PublicKeyUse},
+};
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::config::{AuthConfig, JwtConfig};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read `{path}`: {error}")]
+    FailedToReadFile { path: String, error: std::io::Error },
+    #[error("invalid API keys file `{path}`: {error}")]
+    InvalidApiKeysFile { path: String, error: String },
+    #[error("invalid JWKS file `{path}`: {error}")]
+    InvalidJwksFile { path: String, error: String },
+}
+
+/// Authenticated caller identity.
+///
+/// Added to request extensions by the auth middleware.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Principal {
+    /// API key subject or JWT `sub` claim
+    pub subject: String,
+    /// Tenant, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// How the caller was authenticated
+    pub method: AuthMethod,
+    /// Scopes granted to the caller
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    ApiKey,
+    Jwt,
+}
+
+/// API keys file entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiKey {
+    /// Key, in plain text or as `sha256:<hex digest>`
+    key: String,
+    subject: String,
+    #[serde(default)]
+    tenant: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// Routes the key is allowed to access, all routes if omitted
+    #[serde(default)]
+    routes: Option<Vec<String>>,
+}
+
+/// Authenticates guardrails server requests.
+#[derive(Debug)]
+pub struct Authenticator {
+    /// API keys by SHA-256 digest
+    api_keys: HashMap<Vec<u8>, ApiKey>,
+    api_key_header: HeaderName,
+    jwt: Option<JwtValidator>,
+}
+
+impl Authenticator {
+    /// Loads API keys and JWKS files.
+    pub fn load(config: &AuthConfig) -> Result<Self, Error> {
+        let api_keys = match &config.api_keys_path {
+            Some(path) => load_api_keys(path)?,
+            None => HashMap::new(),
+        };
+        let jwt = config.jwt.as_ref().map(JwtValidator::load).transpose()?;
+        info!(
+            api_keys = api_keys.len(),
+            jwks_keys = jwt.as_ref().map(|jwt| jwt.keys.len()).unwrap_or_default(),
+            "authentication enabled"
+        );
+        Ok(Self {
+            api_keys,
+            // Validated on config load
+            api_key_header: HeaderName::from_bytes(config.api_key_header.as_bytes())
+                .expect("invalid api key header"),
+            jwt,
+        })
+    }
+
+    /// Authenticates a request to `path`, returning the caller's principal.
+    pub fn authenticate(&self, headers: &HeaderMap, path: &str) -> Result<Principal, super::Error> {
+        if let Some(key) = headers.get(&self.api_key_header) {
+            let key = key.to_str().map_err(|_| unauthorized("invalid API key"))?;
+            return self.authenticate_api_key(key, path);
+        }
+        let token = match headers.get(AUTHORIZATION) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::trim)
+                .ok_or_else(|| unauthorized("invalid authorization header"))?,
+            None => return Err(unauthorized("missing credentials")),
+        };
+        match &self.jwt {
+            Some(jwt) if token.split('.').count() == 3 => jwt.authenticate(token),
+            _ => self.authenticate_api_key(token, path),
+        }
+    }
+
+    fn authenticate_api_key(&self, key: &str, path: &str) -> Result<Principal, super::Error> {
+        let api_key = self
+            .api_keys
+            .get(digest(&SHA256, key.as_bytes()).as_ref())
+            .ok_or_else(|| unauthorized("invalid API key"))?;
+        if let Some(routes) = &api_key.routes {
+            if !routes.iter().any(|route| route == path) {
+                return Err(forbidden(format!(
+                    "`{}` is not allowed to access `{path}`",
+                    api_key.subject
+                )));
+            }
+        }
+        Ok(Principal {
+            subject: api_key.subject.clone(),
+            tenant: api_key.tenant.clone(),
+            method: AuthMethod::ApiKey,
+            scopes: api_key.scopes.clone(),
+        })
+    }
+}
+
+/// Auth middleware for the guardrails router.
+pub async fn authenticate(
+    State(authenticator): State<Arc<Authenticator>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match authenticator.authenticate(request.headers(), request.uri().path()) {
+        Ok(principal) => {
+            debug!(subject = %principal.subject, method = ?principal.method, "request authenticated");
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Err(error) => {
+            debug!(%error, "request authentication failed");
+            let unauthorized = error.code == StatusCode::UNAUTHORIZED;
+            let mut response = error.into_response();
+            if unauthorized {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            response
+        }
+    }
+}
+
+fn unauthorized(details: impl Into<String>) -> super::Error {
+    super::Error {
+        code: StatusCode::UNAUTHORIZED,
+        details: details.into(),
+    }
+}
+
+fn forbidden(details: impl Into<String>) -> super::Error {
+    super::Error {
+        code: StatusCode::FORBIDDEN,
+        details: details.into(),
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|error| Error::FailedToReadFile {
+        path: path.display().to_string(),
+        error,
+    })
+}
+
+fn load_api_keys(path: &Path) -> Result<HashMap<Vec<u8>, ApiKey>, Error> {
+    let invalid = |error: String| Error::InvalidApiKeysFile {
+        path: path.display().to_string(),
+        error,
+    };
+    let api_keys: Vec<ApiKey> =
+        serde_yml::from_str(&read_file(path)?).map_err(|error| invalid(error.to_string()))?;
+    api_keys
+        .into_iter()
+        .map(|api_key| -> Result<_, Error> {
+            let key_digest = match api_key.key.strip_prefix("sha256:") {
+                Some(hex) => decode_hex(hex).ok_or_else(|| {
+                    invalid(format!("invalid sha256 digest for `{}`", api_key.subject))
+                })?,
+                None => digest(&SHA256, api_key.key.as_bytes()).as_ref().to_vec(),
+            };
+            Ok((key_digest, api_key))
+        })
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != 64 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| -> Option<u8> { u8::from_str_radix(hex.get(i..i + 2)?, 16).ok() })
+        .collect()
+}
+
+/// JSON Web Key set. Keys are parsed individually so that unsupported keys can be skipped.
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<serde_json::Value>,
+}
+
+/// Public key used to verify token signatures.
+struct JwtKey {
+    kid: Option<String>,
+    /// Algorithms the key may be used with, from the JWK `alg` or key type
+    algorithms: Vec<Algorithm>,
+    key: DecodingKey,
+}
+
+impl std::fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithms", &self.algorithms)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TryFrom<&Jwk> for JwtKey {
+    type Error = String;
+
+    fn try_from(jwk: &Jwk) -> Result<Self, Self::Error> {
+        if jwk
+            .common
+            .public_key_use
+            .as_ref()
+            .is_some_and(|r#use| *r#use != PublicKeyUse::Signature)
+        {
+            return Err("key is not a signing key".into());
+        }
+        let key_type_algorithms = match &jwk.algorithm {
+            AlgorithmParameters::RSA(_) => vec![
+                Algorithm::RS256,
+                Algorithm::RS384,
+                Algorithm::RS512,
+                Algorithm::PS256,
+                Algorithm::PS384,
+                Algorithm::PS512,
+            ],
+            AlgorithmParameters::EllipticCurve(params) => match params.curve {
+                EllipticCurve::P256 => vec![Algorithm::ES256],
+                EllipticCurve::P384 => vec![Algorithm::ES384],
+                _ => return Err(format!("unsupported curve `{:?}`", params.curve)),
+            },
+            AlgorithmParameters::OctetKeyPair(params) => match params.curve {
+                EllipticCurve::Ed25519 => vec![Algorithm::EdDSA],
+                _ => return Err(format!("unsupported curve `{:?}`", params.curve)),
+            },
+            AlgorithmParameters::OctetKey(_) => {
+                return Err("symmetric keys are not supported".into());
+            }
+        };
+        let algorithms = match &jwk.common.key_algorithm {
+            Some(key_algorithm) => {
+                let algorithm = signing_algorithm(key_algorithm)
+                    .filter(|algorithm| key_type_algorithms.contains(algorithm))
+                    .ok_or_else(|| {
+                        format!("algorithm `{key_algorithm:?}` is not supported for this key")
+                    })?;
+                vec![algorithm]
+            }
+            None => key_type_algorithms,
+        };
+        let key = DecodingKey::from_jwk(jwk).map_err(|error| error.to_string())?;
+        Ok(Self {
+            kid: jwk.common.key_id.clone(),
+            algorithms,
+            key,
+        })
+    }
+}
+
+/// Returns the signature algorithm of a JWK `alg`, `None` for encryption algorithms.
+fn signing_algorithm(key_algorithm: &KeyAlgorithm) -> Option<Algorithm> {
+    match key_algorithm {
+        KeyAlgorithm::RS256 => Some(Algorithm::RS256),
+        KeyAlgorithm::RS384 => Some(Algorithm::RS384),
+        KeyAlgorithm::RS512 => Some(Algorithm::RS512),
+        KeyAlgorithm::PS256 => Some(Algorithm::PS256),
+        KeyAlgorithm::PS384 => Some(Algorithm::PS384),
+        KeyAlgorithm::PS512 => Some(Algorithm::PS512),
+        KeyAlgorithm::ES256 => Some(Algorithm::ES256),
+        KeyAlgorithm::ES384 => Some(Algorithm::ES384),
+        KeyAlgorithm::EdDSA => Some(Algorithm::EdDSA),
+        _ => None,
+    }
+}
+
+/// Validates JWT bearer tokens against a local JWKS.
+#[derive(Debug)]
+struct JwtValidator {
+    keys: Vec<JwtKey>,
+    config: JwtConfig,
+}
+
+impl JwtValidator {
+    fn load(config: &JwtConfig) -> Result<Self, Error> {
+        let path = &config.jwks_path;
+        let invalid = |error: String| Error::InvalidJwksFile {
+            path: path.display().to_string(),
+            error,
+        };
+        let jwks: JwkSet =
+            serde_json::from_str(&read_file(path)?).map_err(|error| invalid(error.to_string()))?;
+        let mut keys = Vec::with_capacity(jwks.keys.len());
+        for entry in jwks.keys {
+            let kid = entry
+                .get("kid")
+                .and_then(|kid| kid.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let key = serde_json::from_value::<Jwk>(entry)
+                .map_err(|error| error.to_string())
+                .and_then(|jwk| JwtKey::try_from(&jwk));
+            match key {
+                Ok(key) => keys.push(key),
+                Err(error) => warn!(%kid, %error, "skipping JWKS key"),
+            }
+        }
+        if keys.is_empty() {
+            return Err(invalid("no supported signing keys".into()));
+        }
+        Ok(Self {
+            keys,
+            config: config.clone(),
+        })
+    }
+
+    fn authenticate(&self, token: &str) -> Result<Principal, super::Error> {
+        let claims = self.verify(token).map_err(unauthorized)?;
+        let subject = claims
+            .get("sub")
+            .and_then(|sub| sub.as_str())
+            .ok_or_else(|| unauthorized("token is missing `sub` claim"))?
+            .to_string();
+        let tenant = self
+            .config
+            .tenant_claim
+            .as_ref()
+            .and_then(|claim| claims.get(claim))
+            .and_then(|tenant| tenant.as_str())
+            .map(String::from);
+        let scopes = match (claims.get("scope"), claims.get("scp")) {
+            (Some(serde_json::Value::String(scope)), _) => {
+                scope.split_whitespace().map(String::from).collect()
+            }
+            (_, Some(serde_json::Value::Array(scp))) => scp
+                .iter()
+                .filter_map(|scope| scope.as_str().map(String::from))
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Some(scope) = self
+            .config
+            .required_scopes
+            .iter()
+            .find(|scope| !scopes.contains(scope))
+        {
+            return Err(forbidden(format!(
+                "`{subject}` is missing required scope `{scope}`"
+            )));
+        }
+        Ok(Principal {
+            subject,
+            tenant,
+            method: AuthMethod::Jwt,
+            scopes,
+        })
+    }
+
+    /// Returns the key for a token header. Tokens without a `kid` are only
+    /// accepted when the JWKS has a single key.
+    fn key(&self, header: &Header) -> Result<&JwtKey, String> {
+        let key = match &header.kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|key| key.kid.as_ref() == Some(kid))
+                .ok_or_else(|| format!("unknown signing key `{kid}`"))?,
+            None => match self.keys.as_slice() {
+                [key] => key,
+                _ => return Err("token is missing `kid` header".into()),
+            },
+        };
+        if !key.algorithms.contains(&header.alg) {
+            return Err(format!(
+                "algorithm `{:?}` is not allowed for the signing key",
+                header.alg
+            ));
+        }
+        Ok(key)
+    }
+
+    /// Verifies the token signature and registered claims, returning its claims.
+    fn verify(&self, token: &str) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| "invalid token")?;
+        let key = self.key(&header)?;
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway;
+        validation.validate_nbf = true;
+        validation.set_required_spec_claims(&["exp"]);
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let token = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+            token,
+            &key.key,
+            &validation,
+        )
+        .map_err(|error| match error.kind() {
+            ErrorKind::InvalidSignature => "invalid token signature".to_string(),
+            ErrorKind::ExpiredSignature => "token has expired".to_string(),
+            ErrorKind::ImmatureSignature => "token is not yet valid".to_string(),
+            ErrorKind::InvalidIssuer => "invalid token issuer".to_string(),
+            ErrorKind::InvalidAudience => "invalid token audience".to_string(),
+            ErrorKind::MissingRequiredClaim(claim) => {
+                format!("token is missing `{claim}` claim")
+            }
+            _ => "invalid token".to_string(),
+        })?;
+        Ok(token.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use jsonwebtoken::EncodingKey;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn write_temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{name}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn sign(pkcs8: &[u8], kid: Option<&str>, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = kid.map(String::from);
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_ed_der(pkcs8)).unwrap()
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        headers
+    }
+
+    fn jwt_authenticator(key_pair: &Ed25519KeyPair) -> Authenticator {
+        let jwks = json!({
+            "keys": [
+                { "kty": "RSA", "kid": "unused", "use": "enc", "n": "AQAB", "e": "AQAB" },
+                {
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "kid": "test",
+                    "use": "sig",
+                    "alg": "EdDSA",
+                    "x": URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+                },
+            ]
+        });
+        let jwks_path = write_temp_file("jwks.json", &jwks.to_string());
+        let config = AuthConfig {
+            api_keys_path: None,
+            api_key_header: "x-api-key".into(),
+            jwt: Some(JwtConfig {
+                jwks_path: jwks_path.clone(),
+                issuer: Some("https://issuer.example.com".into()),
+                audience: Some("orchestrator".into()),
+                leeway: 0,
+                tenant_claim: Some("tenant".into()),
+                required_scopes: vec!["guardrails".into()],
+            }),
+        };
+        let authenticator = Authenticator::load(&config).unwrap();
+        let _ = std::fs::remove_file(jwks_path);
+        authenticator
+    }
+
+    #[test]
+    fn test_api_key_authentication() {
+        let api_keys = format!(
+            r#"
+- key: team-a-key
+  subject: team-a
+  tenant: a
+- key: sha256:{}
+  subject: team-b
+  routes:
+    - /api/v2/text/detection/content
+"#,
+            digest(&SHA256, b"team-b-key")
+                .as_ref()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        );
+        let api_keys_path = write_temp_file("api-keys.yaml", &api_keys);
+        let config = AuthConfig {
+            api_keys_path: Some(api_keys_path.clone()),
+            api_key_header: "x-api-key".into(),
+            jwt: None,
+        };
+        let authenticator = Authenticator::load(&config).unwrap();
+        let _ = std::fs::remove_file(api_keys_path);
+        let path = "/api/v2/text/detection/content";
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("team-a-key"));
+        let principal = authenticator.authenticate(&headers, path).unwrap();
+        assert_eq!(principal.subject, "team-a");
+        assert_eq!(principal.tenant.as_deref(), Some("a"));
+        assert_eq!(principal.method, AuthMethod::ApiKey);
+
+        // API keys are accepted as bearer tokens
+        let principal = authenticator
+            .authenticate(&bearer("team-b-key"), path)
+            .unwrap();
+        assert_eq!(principal.subject, "team-b");
+
+        let error = authenticator
+            .authenticate(&bearer("team-b-key"), "/api/v2/text/detection/chat")
+            .unwrap_err();
+        assert_eq!(error.code, StatusCode::FORBIDDEN);
+
+        let error = authenticator
+            .authenticate(&bearer("unknown-key"), path)
+            .unwrap_err();
+        assert_eq!(error.code, StatusCode::UNAUTHORIZED);
+
+        let error = authenticator
+            .authenticate(&HeaderMap::new(), path)
+            .unwrap_err();
+        assert_eq!(error.code, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.details, "missing credentials");
+    }
+
+    #[test]
+    fn test_jwt_authentication() {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let authenticator = jwt_authenticator(&key_pair);
+        let path = "/api/v2/text/detection/content";
+        let claims = json!({
+            "sub": "user-1",
+            "iss": "https://issuer.example.com",
+            "aud": ["orchestrator", "other"],
+            "exp": now() + 300,
+            "tenant": "a",
+            "scope": "openid guardrails",
+        });
+
+        let token = sign(pkcs8.as_ref(), Some("test"), claims.clone());
+        let principal = authenticator.authenticate(&bearer(&token), path).unwrap();
+        assert_eq!(
+            principal,
+            Principal {
+                subject: "user-1".into(),
+                tenant: Some("a".into()),
+                method: AuthMethod::Jwt,
+                scopes: vec!["openid".into(), "guardrails".into()],
+            }
+        );
+
+        // Tampered payload
+        let (_, sig) = token.rsplit_once('.').unwrap();
+        let mut tampered_claims = claims.clone();
+        tampered_claims["sub"] = "admin".into();
+        let tampered = sign(pkcs8.as_ref(), Some("test"), tampered_claims);
+        let (message, _) = tampered.rsplit_once('.').unwrap();
+        let error = authenticator
+            .authenticate(&bearer(&format!("{message}.{sig}")), path)
+            .unwrap_err();
+        assert_eq!(error.code, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.details, "invalid token signature");
+
+        let mut expired = claims.clone();
+        expired["exp"] = (now() - 10).into();
+        let error = authenticator
+            .authenticate(&bearer(&sign(pkcs8.as_ref(), Some("test"), expired)), path)
+            .unwrap_err();
+        assert_eq!(error.details, "token has expired");
+
+        let mut wrong_audience = claims.clone();
+        wrong_audience["aud"] = "other".into();
+        let error = authenticator
+            .authenticate(
+                &bearer(&sign(pkcs8.as_ref(), Some("test"), wrong_audience)),
+                path,
+            )
+            .unwrap_err();
+        assert_eq!(error.details, "invalid token audience");
+
+        let mut missing_scope = claims.clone();
+        missing_scope["scope"] = "openid".into();
+        let error = authenticator
+            .authenticate(
+                &bearer(&sign(pkcs8.as_ref(), Some("test"), missing_scope)),
+                path,
+            )
+            .unwrap_err();
+        assert_eq!(error.code, StatusCode::FORBIDDEN);
+
+        let error = authenticator
+            .authenticate(
+                &bearer(&sign(pkcs8.as_ref(), Some("unknown"), claims.clone())),
+                path,
+            )
+            .unwrap_err();
+        assert_eq!(error.code, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.details, "unknown signing key `unknown`");
+
+        // Tokens without a `kid` are verified with the only signing key
+        let principal = authenticator
+            .authenticate(&bearer(&sign(pkcs8.as_ref(), None, claims)), path)
+            .unwrap();
+        assert_eq!(principal.subject, "user-1");
+    }
+}
@@ -24,7 +24,10 @@ use axum::{
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
 
-use crate::{models::ValidationError, orchestrator};
+use crate::{
+    models::{BatchItemError, ValidationError},
+    orchestrator,
+};
 
 /// High-level errors to return to clients.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -59,6 +62,15 @@ impl IntoResponse for Error {
     }
 }
 
+impl From<Error> for BatchItemError {
+    fn from(value: Error) -> Self {
+        Self {
+            code: value.code.as_u16(),
+            details: value.details,
+        }
+    }
+}
+
 impl From<JsonRejection> for Error {
     fn from(value: JsonRejection) -> Self {
         use JsonRejection::*;
@@ -74,6 +86,24 @@ impl From<JsonRejection> for Error {
     }
 }
 
+impl From<super::auth::Error> for Error {
+    fn from(value: super::auth::Error) -> Self {
+        Self {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            details: format!("auth error: {value}"),
+        }
+    }
+}
+
+impl From<super::tls::TlsError> for Error {
+    fn from(value: super::tls::TlsError) -> Self {
+        Self {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            details: format!("tls error: {value}"),
+        }
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
         Self {
@@ -132,3 +162,19 @@ impl From<orchestrator::Error> for Error {
         }
     }
 }
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        use tonic::Code;
+        let code = match value.code {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        Self::new(code, value.details)
+    }
+}
@@ -0,0 +1,611 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! gRPC API for the orchestrator, served next to the HTTP API.
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{extract::State, middleware::Next};
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
+use http::HeaderMap;
+use prost_types::value::Kind;
+use tonic::{Extensions, Request, Response, Status, Streaming, metadata::MetadataMap};
+use tracing::debug;
+
+use super::{Authenticator, Error, Principal, ServerState, routes::filter_headers};
+use crate::{
+    clients::{
+        detector::{ContentAnalysisResponse, ContextType},
+        openai::{Content, Message, Role},
+    },
+    models::{
+        BatchContentDetectionHttpRequest, BatchContentDetectionItem, BatchContentDetectionResult,
+        BatchItemError, ChatDetectionHttpRequest, ChatDetectionResult, ContextDocsHttpRequest,
+        ContextDocsResult, DetectionResult, DetectorParams, Evidence, EvidenceObj,
+        GenerationWithDetectionHttpRequest, GenerationWithDetectionResult,
+        StreamingContentDetectionRequest, StreamingContentDetectionResponse,
+        TextContentDetectionHttpRequest, TextContentDetectionResult,
+    },
+    orchestrator::{self, handlers::*},
+    pb::orchestrator::{self as pb, guardrails_orchestrator_server::GuardrailsOrchestrator},
+    utils::trace::current_trace_id,
+};
+
+pub use pb::guardrails_orchestrator_server::GuardrailsOrchestratorServer;
+
+/// Implements the orchestrator gRPC service using the same task handlers as the HTTP API.
+pub struct GuardrailsService {
+    state: Arc<ServerState>,
+}
+
+impl GuardrailsService {
+    pub fn new(state: Arc<ServerState>) -> Self {
+        Self { state }
+    }
+
+    /// Returns the passthrough headers of a request and the caller authenticated
+    /// by the [`authenticate`] middleware.
+    fn headers(
+        &self,
+        metadata: MetadataMap,
+        extensions: &Extensions,
+    ) -> (HeaderMap, Option<Principal>) {
+        let headers = filter_headers(
+            &self.state.orchestrator.config().passthrough_headers,
+            metadata.into_headers(),
+        );
+        (headers, extensions.get::<Principal>().cloned())
+    }
+}
+
+/// Auth middleware for the gRPC router.
+///
+/// Requests are authenticated using the gRPC method path, e.g.
+/// `/orchestrator.GuardrailsOrchestrator/DetectTextContent`.
+pub async fn authenticate(
+    State(authenticator): State<Arc<Authenticator>>,
+    mut request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    match authenticator.authenticate(request.headers(), request.uri().path()) {
+        Ok(principal) => {
+            debug!(subject = %principal.subject, method = ?principal.method, "request authenticated");
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Err(error) => {
+            debug!(%error, "request authentication failed");
+            Status::from(error).into_http()
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl GuardrailsOrchestrator for GuardrailsService {
+    type DetectTextContentBatchStream =
+        BoxStream<'static, Result<pb::BatchContentDetectionResult, Status>>;
+    type StreamDetectTextContentStream =
+        BoxStream<'static, Result<pb::StreamingContentDetectionResponse, Status>>;
+
+    async fn detect_text_content(
+        &self,
+        request: Request<pb::TextContentDetectionRequest>,
+    ) -> Result<Response<pb::TextContentDetectionResponse>, Status> {
+        let trace_id = current_trace_id();
+        let (metadata, extensions, request) = request.into_parts();
+        let (headers, principal) = self.headers(metadata, &extensions);
+        let request = TextContentDetectionHttpRequest::try_from(request)?;
+        request.validate().map_err(Error::from)?;
+        let task =
+            TextContentDetectionTask::new(trace_id, request, headers).with_principal(principal);
+        match self.state.orchestrator.handle(task).await {
+            Ok(response) => Ok(Response::new(response.into())),
+            Err(error) => Err(Error::from(error).into()),
+        }
+    }
+
+    async fn detect_text_content_batch(
+        &self,
+        request: Request<pb::BatchContentDetectionRequest>,
+    ) -> Result<Response<Self::DetectTextContentBatchStream>, Status> {
+        let trace_id = current_trace_id();
+        let (metadata, extensions, request) = request.into_parts();
+        let (headers, principal) = self.headers(metadata, &extensions);
+        let request = BatchContentDetectionHttpRequest::try_from(request)?;
+        request.validate().map_err(Error::from)?;
+        let items = stream::iter(request.items.into_iter().map(Ok).enumerate()).boxed();
+        let task = BatchContentDetectionTask::new(trace_id, headers, request.detectors, items)
+            .with_principal(principal);
+        let response_stream = self
+            .state
+            .orchestrator
+            .handle(task)
+            .await
+            .map_err(Error::from)?;
+        let output_stream = response_stream
+            .map(|item| Ok(super::routes::batch_item_result(item).into()))
+            .boxed();
+        Ok(Response::new(output_stream))
+    }
+
+    async fn stream_detect_text_content(
+        &self,
+        request: Request<Streaming<pb::StreamingContentDetectionRequest>>,
+    ) -> Result<Response<Self::StreamDetectTextContentStream>, Status> {
+        let trace_id = current_trace_id();
+        let (metadata, extensions, input_stream) = request.into_parts();
+        let (headers, principal) = self.headers(metadata, &extensions);
+        let input_stream = input_stream
+            .map(|result| match result {
+                Ok(message) => {
+                    let message =
+                        StreamingContentDetectionRequest::try_from(message).map_err(|status| {
+                            orchestrator::Error::Validation(status.message().into())
+                        })?;
+                    message.validate()?;
+                    Ok(message)
+                }
+                Err(status) => Err(orchestrator::Error::Validation(status.message().into())),
+            })
+            .enumerate()
+            .boxed();
+        let task = StreamingContentDetectionTask::new(trace_id, headers, input_stream)
+            .with_principal(principal);
+        let response_stream = self
+            .state
+            .orchestrator
+            .handle(task)
+            .await
+            .map_err(Error::from)?;
+        let output_stream = response_stream
+            .map(|result| match result {
+                Ok(response) => Ok(response.into()),
+                // Convert orchestrator::Error to server::Error
+                Err(error) => Err(Error::from(error).into()),
+            })
+            .boxed();
+        Ok(Response::new(output_stream))
+    }
+
+    async fn detect_chat(
+        &self,
+        request: Request<pb::ChatDetectionRequest>,
+    ) -> Result<Response<pb::ChatDetectionResponse>, Status> {
+        let trace_id = current_trace_id();
+        let (metadata, extensions, request) = request.into_parts();
+        let (headers, principal) = self.headers(metadata, &extensions);
+        let request = ChatDetectionHttpRequest::try_from(request)?;
+        request.validate_for_text().map_err(Error::from)?;
+        let task = ChatDetectionTask::new(trace_id, request, headers).with_principal(principal);
+        match self.state.orchestrator.handle(task).await {
+            Ok(response) => Ok(Response::new(response.into())),
+            Err(error) => Err(Error::from(error).into()),
+        }
+    }
+
+    async fn detect_context_docs(
+        &self,
+        request: Request<pb::ContextDocsDetectionRequest>,
+    ) -> Result<Response<pb::ContextDocsDetectionResponse>, Status> {
+        let trace_id = current_trace_id();
+        let (metadata, extensions, request) = request.into_parts();
+        let (headers, principal) = self.headers(metadata, &extensions);
+        let request = ContextDocsHttpRequest::try_from(request)?;
+        request.validate().map_err(Error::from)?;
+        let task =
+            ContextDocsDetectionTask::new(trace_id, request, headers).with_principal(principal);
+        match self.state.orchestrator.handle(task).await {
+            Ok(response) => Ok(Response::new(response.into())),
+            Err(error) => Err(Error::from(error).into()),
+        }
+    }
+
+    async fn generation_with_detection(
+        &self,
+        request: Request<pb::GenerationWithDetectionRequest>,
+    ) -> Result<Response<pb::GenerationWithDetectionResponse>, Status> {
+        let trace_id = current_trace_id();
+        let (metadata, extensions, request) = request.into_parts();
+        let (headers, principal) = self.headers(metadata, &extensions);
+        let request = GenerationWithDetectionHttpRequest::try_from(request)?;
+        request.validate().map_err(Error::from)?;
+        let task =
+            GenerationWithDetectionTask::new(trace_id, request, headers).with_principal(principal);
+        match self.state.orchestrator.handle(task).await {
+            Ok(response) => Ok(Response::new(response.into())),
+            Err(error) => Err(Error::from(error).into()),
+        }
+    }
+}
+
+// Request conversions
+
+impl TryFrom<pb::TextContentDetectionRequest> for TextContentDetectionHttpRequest {
+    type Error = Status;
+
+    fn try_from(value: pb::TextContentDetectionRequest) -> Result<Self, Self::Error> {
+        Ok(Self {
+            content: value.content,
+            detectors: detectors(value.detectors)?,
+        })
+    }
+}
+
+impl TryFrom<pb::BatchContentDetectionRequest> for BatchContentDetectionHttpRequest {
+    type Error = Status;
+
+    fn try_from(value: pb::BatchContentDetectionRequest) -> Result<Self, Self::Error> {
+        Ok(Self {
+            detectors: detectors(value.detectors)?,
+            items: value
+                .items
+                .into_iter()
+                .map(|item| BatchContentDetectionItem {
+                    id: item.id,
+                    content: item.content,
+                    detectors: None,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl TryFrom<pb::StreamingContentDetectionRequest> for StreamingContentDetectionRequest {
+    type Error = Status;
+
+    fn try_from(value: pb::StreamingContentDetectionRequest) -> Result<Self, Self::Error> {
+        // Detectors are only set on the first message
+        let detectors = if value.detectors.is_empty() {
+            None
+        } else {
+            Some(detectors(value.detectors)?)
+        };
+        Ok(Self {
+            detectors,
+            content: value.content,
+        })
+    }
+}
+
+impl TryFrom<pb::ChatDetectionRequest> for ChatDetectionHttpRequest {
+    type Error = Status;
+
+    fn try_from(value: pb::ChatDetectionRequest) -> Result<Self, Self::Error> {
+        let messages = value
+            .messages
+            .into_iter()
+            .map(|message| {
+                let role: Role = serde_json::from_value(message.role.clone().into())
+                    .map_err(|_| invalid_argument(format!("invalid role `{}`", message.role)))?;
+                Ok(Message {
+                    role,
+                    content: (!message.content.is_empty()).then(|| Content::Text(message.content)),
+                    name: message.name,
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<_, Status>>()?;
+        Ok(Self {
+            detectors: detectors(value.detectors)?,
+            messages,
+            tools: Vec::new(),
+            session_id: value.session_id,
+        })
+    }
+}
+
+impl TryFrom<pb::ContextDocsDetectionRequest> for ContextDocsHttpRequest {
+    type Error = Status;
+
+    fn try_from(value: pb::ContextDocsDetectionRequest) -> Result<Self, Self::Error> {
+        let context_type = match value.context_type() {
+            pb::ContextType::Docs => ContextType::Document,
+            pb::ContextType::Url => ContextType::Url,
+        };
+        Ok(Self {
+            detectors: detectors(value.detectors)?,
+            content: value.content,
+            context_type,
+            context: value.context,
+        })
+    }
+}
+
+impl TryFrom<pb::GenerationWithDetectionRequest> for GenerationWithDetectionHttpRequest {
+    type Error = Status;
+
+    fn try_from(value: pb::GenerationWithDetectionRequest) -> Result<Self, Self::Error> {
+        let text_gen_parameters = value
+            .text_gen_parameters
+            .map(|params| {
+                serde_json::from_value(struct_to_json(params).into()).map_err(|error| {
+                    invalid_argument(format!("invalid `text_gen_parameters`: {error}"))
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            model_id: value.model_id,
+            prompt: value.prompt,
+            detectors: detectors(value.detectors)?,
+            text_gen_parameters,
+        })
+    }
+}
+
+/// Converts detector parameters of a request.
+fn detectors(
+    detectors: HashMap<String, prost_types::Struct>,
+) -> Result<HashMap<String, DetectorParams>, Status> {
+    detectors
+        .into_iter()
+        .map(|(detector_id, params)| {
+            let params =
+                serde_json::from_value(struct_to_json(params).into()).map_err(|error| {
+                    invalid_argument(format!("invalid params for `{detector_id}`: {error}"))
+                })?;
+            Ok((detector_id, params))
+        })
+        .collect()
+}
+
+fn invalid_argument(details: String) -> Status {
+    Error {
+        code: http::StatusCode::UNPROCESSABLE_ENTITY,
+        details,
+    }
+    .into()
+}
+
+// Response conversions
+
+impl From<TextContentDetectionResult> for pb::TextContentDetectionResponse {
+    fn from(value: TextContentDetectionResult) -> Self {
+        Self {
+            detections: value.detections.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<BatchContentDetectionResult> for pb::BatchContentDetectionResult {
+    fn from(value: BatchContentDetectionResult) -> Self {
+        Self {
+            index: value.index as u64,
+            id: value.id,
+            detections: value
+                .detections
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            error: value.error.map(Into::into),
+        }
+    }
+}
+
+impl From<BatchItemError> for pb::BatchItemError {
+    fn from(value: BatchItemError) -> Self {
+        Self {
+            code: value.code.into(),
+            details: value.details,
+        }
+    }
+}
+
+impl From<StreamingContentDetectionResponse> for pb::StreamingContentDetectionResponse {
+    fn from(value: StreamingContentDetectionResponse) -> Self {
+        Self {
+            detections: value.detections.into_iter().map(Into::into).collect(),
+            processed_index: value.processed_index,
+            start_index: value.start_index,
+        }
+    }
+}
+
+impl From<ChatDetectionResult> for pb::ChatDetectionResponse {
+    fn from(value: ChatDetectionResult) -> Self {
+        Self {
+            detections: value.detections.into_iter().map(Into::into).collect(),
+            session_id: value.session_id,
+            message_index: value.message_index.map(|index| index as u64),
+        }
+    }
+}
+
+impl From<ContextDocsResult> for pb::ContextDocsDetectionResponse {
+    fn from(value: ContextDocsResult) -> Self {
+        Self {
+            detections: value.detections.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<GenerationWithDetectionResult> for pb::GenerationWithDetectionResponse {
+    fn from(value: GenerationWithDetectionResult) -> Self {
+        Self {
+            generated_text: value.generated_text,
+            detections: value.detections.into_iter().map(Into::into).collect(),
+            input_token_count: value.input_token_count,
+        }
+    }
+}
+
+impl From<ContentAnalysisResponse> for pb::ContentAnalysisResponse {
+    fn from(value: ContentAnalysisResponse) -> Self {
+        Self {
+            start: value.start as u64,
+            end: value.end as u64,
+            text: value.text,
+            detection: value.detection,
+            detection_type: value.detection_type,
+            detector_id: value.detector_id,
+            score: value.score,
+            evidence: value
+                .evidence
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            metadata: (!value.metadata.is_empty()).then(|| json_to_struct(value.metadata)),
+        }
+    }
+}
+
+impl From<DetectionResult> for pb::DetectionResult {
+    fn from(value: DetectionResult) -> Self {
+        Self {
+            detection_type: value.detection_type,
+            detection: value.detection,
+            detector_id: value.detector_id,
+            score: value.score,
+            evidence: value
+                .evidence
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            metadata: (!value.metadata.is_empty()).then(|| json_to_struct(value.metadata)),
+        }
+    }
+}
+
+impl From<EvidenceObj> for pb::EvidenceObj {
+    fn from(value: EvidenceObj) -> Self {
+        Self {
+            name: value.name,
+            value: value.value,
+            score: value.score,
+            evidence: value
+                .evidence
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+impl From<Evidence> for pb::Evidence {
+    fn from(value: Evidence) -> Self {
+        Self {
+            name: value.name,
+            value: value.value,
+            score: value.score,
+        }
+    }
+}
+
+// JSON conversions
+
+/// Converts a protobuf struct to a JSON object.
+fn struct_to_json(value: prost_types::Struct) -> serde_json::Map<String, serde_json::Value> {
+    value
+        .fields
+        .into_iter()
+        .map(|(key, value)| (key, value_to_json(value)))
+        .collect()
+}
+
+fn value_to_json(value: prost_types::Value) -> serde_json::Value {
+    match value.kind {
+        Some(Kind::NumberValue(n)) => {
+            // Protobuf numbers are doubles, convert integral values to integers
+            if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+                (n as i64).into()
+            } else {
+                n.into()
+            }
+        }
+        Some(Kind::StringValue(s)) => s.into(),
+        Some(Kind::BoolValue(b)) => b.into(),
+        Some(Kind::StructValue(s)) => struct_to_json(s).into(),
+        Some(Kind::ListValue(list)) => list.values.into_iter().map(value_to_json).collect(),
+        Some(Kind::NullValue(_)) | None => serde_json::Value::Null,
+    }
+}
+
+/// Converts a JSON object to a protobuf struct.
+fn json_to_struct(
+    value: impl IntoIterator<Item = (String, serde_json::Value)>,
+) -> prost_types::Struct {
+    prost_types::Struct {
+        fields: value
+            .into_iter()
+            .map(|(key, value)| (key, json_to_value(value)))
+            .collect(),
+    }
+}
+
+fn json_to_value(value: serde_json::Value) -> prost_types::Value {
+    use serde_json::Value::*;
+    let kind = match value {
+        Null => Kind::NullValue(prost_types::NullValue::NullValue.into()),
+        Bool(b) => Kind::BoolValue(b),
+        Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        String(s) => Kind::StringValue(s),
+        Array(values) => Kind::ListValue(prost_types::ListValue {
+            values: values.into_iter().map(json_to_value).collect(),
+        }),
+        Object(map) => Kind::StructValue(json_to_struct(map)),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_json_struct_roundtrip() {
+        let value = json!({
+            "threshold": 0.5,
+            "top_k": 5,
+            "enabled": true,
+            "labels": ["a", "b"],
+            "nested": { "key": null },
+        });
+        let serde_json::Value::Object(map) = value.clone() else {
+            unreachable!()
+        };
+        let roundtrip: serde_json::Value = struct_to_json(json_to_struct(map)).into();
+        assert_eq!(roundtrip, value);
+    }
+
+    #[test]
+    fn test_detectors() {
+        let params = json_to_struct([("threshold".to_string(), json!(0.8))]);
+        let detectors = detectors(HashMap::from([("pii".to_string(), params)])).unwrap();
+        assert_eq!(detectors["pii"].get("threshold"), Some(&json!(0.8)));
+    }
+
+    #[test]
+    fn test_chat_detection_request_invalid_role() {
+        let request = pb::ChatDetectionRequest {
+            detectors: HashMap::new(),
+            messages: vec![pb::ChatMessage {
+                role: "robot".into(),
+                content: "hi".into(),
+                name: None,
+            }],
+            session_id: None,
+        };
+        let status = ChatDetectionHttpRequest::try_from(request).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
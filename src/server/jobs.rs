@@ -0,0 +1,469 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Asynchronous jobs for batch content detection
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use dashmap::DashMap;
+use futures::{StreamExt, stream};
+use http::{HeaderMap, StatusCode};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::Semaphore,
+    task::AbortHandle,
+};
+use tracing::{Instrument, error, info, info_span, warn};
+use uuid::Uuid;
+
+use super::{Error, Principal, ServerState, routes::batch_item_result};
+use crate::{
+    config::JobsConfig,
+    models::{
+        BatchContentDetectionItem, BatchContentDetectionResult, DetectorParams, JobInfo, JobStatus,
+    },
+    orchestrator::{
+        common::current_timestamp,
+        handlers::{BatchContentDetectionTask, Handle},
+    },
+    utils::{json::to_nd_string, trace::current_trace_id},
+};
+
+/// Tracks, runs and optionally persists asynchronous jobs.
+pub struct JobManager {
+    jobs: DashMap<String, Arc<Job>>,
+    workers: Arc<Semaphore>,
+    path: Option<PathBuf>,
+    max_jobs: usize,
+}
+
+impl JobManager {
+    /// Creates a job manager, loading persisted jobs if a path is configured.
+    ///
+    /// Persisted jobs that were queued or running are marked as failed.
+    pub async fn new(config: &JobsConfig) -> Result<Self, Error> {
+        let jobs = DashMap::new();
+        if let Some(path) = &config.path {
+            fs::create_dir_all(path).await?;
+            let mut entries = fs::read_dir(path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let entry_path = entry.path();
+                if entry_path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let mut info: JobInfo = match fs::read(&entry_path)
+                    .await
+                    .map_err(|error| error.to_string())
+                    .and_then(|bytes| {
+                        serde_json::from_slice(&bytes).map_err(|error| error.to_string())
+                    }) {
+                    Ok(info) => info,
+                    Err(error) => {
+                        warn!(path = ?entry_path, %error, "skipping invalid job file");
+                        continue;
+                    }
+                };
+                if !info.status.is_finished() {
+                    info.status = JobStatus::Failed;
+                    info.finished_at = Some(now_ms());
+                    info.error = Some("job interrupted by server restart".into());
+                    write_info(path, &info).await?;
+                }
+                jobs.insert(info.id.clone(), Arc::new(Job::new(info)));
+            }
+            info!(count = jobs.len(), ?path, "loaded persisted jobs");
+        }
+        Ok(Self {
+            jobs,
+            workers: Arc::new(Semaphore::new(config.workers)),
+            path: config.path.clone(),
+            max_jobs: config.max_jobs,
+        })
+    }
+
+    /// Submits a batch content detection job and returns its initial status.
+    pub async fn submit(
+        &self,
+        state: Arc<ServerState>,
+        headers: HeaderMap,
+        detectors: HashMap<String, DetectorParams>,
+        items: Vec<BatchContentDetectionItem>,
+        principal: Option<Principal>,
+    ) -> Result<JobInfo, Error> {
+        self.prune().await;
+        if self.jobs.len() >= self.max_jobs {
+            return Err(Error {
+                code: StatusCode::SERVICE_UNAVAILABLE,
+                details: "job limit reached".into(),
+            });
+        }
+        let id = Uuid::new_v4().simple().to_string();
+        let info = JobInfo {
+            id: id.clone(),
+            status: JobStatus::Queued,
+            total: items.len(),
+            processed: 0,
+            failed: 0,
+            created_at: now_ms(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+            owner: principal
+                .as_ref()
+                .map(|principal| principal.subject.clone()),
+        };
+        if let Some(path) = &self.path {
+            write_info(path, &info).await?;
+        }
+        let job = Arc::new(Job::new(info.clone()));
+        self.jobs.insert(id.clone(), job.clone());
+
+        let trace_id = current_trace_id();
+        let items = stream::iter(items.into_iter().map(Ok).enumerate()).boxed();
+        let task = BatchContentDetectionTask::new(trace_id, headers, detectors, items)
+            .with_principal(principal);
+        let handle = tokio::spawn(
+            run_job(
+                state,
+                job.clone(),
+                task,
+                self.workers.clone(),
+                self.path.clone(),
+            )
+            .instrument(info_span!("job", %id)),
+        );
+        *job.abort.lock().unwrap() = Some(handle.abort_handle());
+        info!(%id, total = info.total, "job submitted");
+        Ok(info)
+    }
+
+    /// Returns the status of a job.
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        self.jobs.get(id).map(|job| job.info())
+    }
+
+    /// Returns results of a job processed so far, in input order.
+    pub async fn results(
+        &self,
+        id: &str,
+    ) -> Result<Option<Vec<BatchContentDetectionResult>>, Error> {
+        let Some(job) = self.jobs.get(id).map(|job| job.clone()) else {
+            return Ok(None);
+        };
+        match &self.path {
+            Some(path) => read_results(path, id).await.map(Some),
+            None => Ok(Some(job.results.lock().unwrap().clone())),
+        }
+    }
+
+    /// Cancels a queued or running job and returns its status.
+    pub async fn cancel(&self, id: &str) -> Result<Option<JobInfo>, Error> {
+        let Some(job) = self.jobs.get(id).map(|job| job.clone()) else {
+            return Ok(None);
+        };
+        let info = {
+            let mut info = job.info.lock().unwrap();
+            if info.status.is_finished() {
+                return Ok(Some(info.clone()));
+            }
+            if let Some(handle) = job.abort.lock().unwrap().take() {
+                handle.abort();
+            }
+            info.status = JobStatus::Cancelled;
+            info.finished_at = Some(now_ms());
+            info.clone()
+        };
+        if let Some(path) = &self.path {
+            write_info(path, &info).await?;
+        }
+        info!(%id, "job cancelled");
+        Ok(Some(info))
+    }
+
+    /// Removes the oldest finished jobs while the job limit is reached.
+    async fn prune(&self) {
+        let excess = (self.jobs.len() + 1).saturating_sub(self.max_jobs);
+        if excess == 0 {
+            return;
+        }
+        let mut finished = self
+            .jobs
+            .iter()
+            .filter_map(|entry| {
+                let info = entry.info();
+                info.status
+                    .is_finished()
+                    .then(|| (info.finished_at.unwrap_or(info.created_at), info.id))
+            })
+            .collect::<Vec<_>>();
+        finished.sort_unstable();
+        for (_, id) in finished.into_iter().take(excess) {
+            self.jobs.remove(&id);
+            if let Some(path) = &self.path {
+                let _ = fs::remove_file(info_path(path, &id)).await;
+                let _ = fs::remove_file(results_path(path, &id)).await;
+            }
+        }
+    }
+}
+
+/// An asynchronous job.
+struct Job {
+    info: Mutex<JobInfo>,
+    /// Results, only kept in memory if jobs are not persisted
+    results: Mutex<Vec<BatchContentDetectionResult>>,
+    abort: Mutex<Option<AbortHandle>>,
+}
+
+impl Job {
+    fn new(info: JobInfo) -> Self {
+        Self {
+            info: Mutex::new(info),
+            results: Mutex::new(Vec::new()),
+            abort: Mutex::new(None),
+        }
+    }
+
+    fn info(&self) -> JobInfo {
+        self.info.lock().unwrap().clone()
+    }
+
+    /// Updates job status, returning `None` if the job was cancelled.
+    fn update(&self, f: impl FnOnce(&mut JobInfo)) -> Option<JobInfo> {
+        let mut info = self.info.lock().unwrap();
+        if info.status == JobStatus::Cancelled {
+            return None;
+        }
+        f(&mut info);
+        Some(info.clone())
+    }
+}
+
+/// Runs a job once a worker is available.
+async fn run_job(
+    state: Arc<ServerState>,
+    job: Arc<Job>,
+    task: BatchContentDetectionTask,
+    workers: Arc<Semaphore>,
+    path: Option<PathBuf>,
+) {
+    let _permit = workers.acquire_owned().await.unwrap();
+    let Some(info) = job.update(|info| {
+        info.status = JobStatus::Running;
+        info.started_at = Some(now_ms());
+    }) else {
+        return;
+    };
+    persist_info(path.as_deref(), &info).await;
+    info!("job started");
+
+    let result = async {
+        let mut results = state.orchestrator.handle(task).await?;
+        let mut file = match &path {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(results_path(path, &info.id))
+                    .await?,
+            ),
+            None => None,
+        };
+        while let Some(item) = results.next().await {
+            let result = batch_item_result(item);
+            let failed = result.error.is_some();
+            match &mut file {
+                Some(file) => {
+                    // Flush each line so that results can be read while the job runs
+                    file.write_all(to_nd_string(&result).unwrap().as_bytes())
+                        .await?;
+                    file.flush().await?;
+                }
+                None => job.results.lock().unwrap().push(result),
+            }
+            job.update(|info| {
+                info.processed += 1;
+                if failed {
+                    info.failed += 1;
+                }
+            });
+        }
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    let Some(info) = job.update(|info| {
+        info.finished_at = Some(now_ms());
+        match result {
+            Ok(()) => info.status = JobStatus::Completed,
+            Err(error) => {
+                info.status = JobStatus::Failed;
+                info.error = Some(error.details);
+            }
+        }
+    }) else {
+        return;
+    };
+    persist_info(path.as_deref(), &info).await;
+    info!(
+        status = ?info.status,
+        processed = info.processed,
+        failed = info.failed,
+        "job finished"
+    );
+}
+
+fn now_ms() -> u64 {
+    current_timestamp().as_millis() as u64
+}
+
+fn info_path(path: &Path, id: &str) -> PathBuf {
+    path.join(format!("{id}.json"))
+}
+
+fn results_path(path: &Path, id: &str) -> PathBuf {
+    path.join(format!("{id}.jsonl"))
+}
+
+async fn write_info(path: &Path, info: &JobInfo) -> Result<(), std::io::Error> {
+    // Write to a temporary file first so a crash doesn't leave a partial file
+    let tmp_path = path.join(format!("{}.json.tmp", info.id));
+    fs::write(&tmp_path, serde_json::to_vec(info).unwrap()).await?;
+    fs::rename(tmp_path, info_path(path, &info.id)).await
+}
+
+async fn persist_info(path: Option<&Path>, info: &JobInfo) {
+    if let Some(path) = path {
+        if let Err(error) = write_info(path, info).await {
+            error!(id = %info.id, %error, "failed to persist job");
+        }
+    }
+}
+
+async fn read_results(path: &Path, id: &str) -> Result<Vec<BatchContentDetectionResult>, Error> {
+    let file = match File::open(results_path(path, id)).await {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut results = Vec::new();
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+        // A last line without a newline is partially written, e.g. by a running job
+        if line.last() != Some(&b'\n') {
+            break;
+        }
+        results.push(serde_json::from_slice(&line).map_err(|error| Error {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            details: format!("invalid job result: {error}"),
+        })?);
+        line.clear();
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("jobs-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn job_info(id: &str, status: JobStatus, created_at: u64) -> JobInfo {
+        JobInfo {
+            id: id.into(),
+            status,
+            total: 1,
+            processed: 0,
+            failed: 0,
+            created_at,
+            started_at: None,
+            finished_at: None,
+            error: None,
+            owner: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_persisted_jobs() -> Result<(), Error> {
+        let path = temp_dir();
+        write_info(&path, &job_info("done", JobStatus::Completed, 1)).await?;
+        write_info(&path, &job_info("running", JobStatus::Running, 2)).await?;
+        // The partially written last line is skipped
+        std::fs::write(
+            results_path(&path, "done"),
+            "{\"index\":0,\"id\":\"a\",\"detections\":[]}\n{\"index\":1,",
+        )?;
+
+        let config = JobsConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let manager = JobManager::new(&config).await?;
+        assert_eq!(manager.get("done").unwrap().status, JobStatus::Completed);
+        let results = manager.results("done").await?.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id.as_deref(), Some("a"));
+
+        // Interrupted jobs are marked as failed, including on disk
+        let running = manager.get("running").unwrap();
+        assert_eq!(running.status, JobStatus::Failed);
+        assert!(running.error.is_some());
+        let persisted: JobInfo =
+            serde_json::from_slice(&std::fs::read(info_path(&path, "running"))?).unwrap();
+        assert_eq!(persisted.status, JobStatus::Failed);
+
+        assert!(manager.get("missing").is_none());
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prune_and_cancel() -> Result<(), Error> {
+        let manager = JobManager::new(&JobsConfig {
+            max_jobs: 2,
+            ..Default::default()
+        })
+        .await?;
+        for (id, status, created_at) in [
+            ("old", JobStatus::Completed, 1),
+            ("queued", JobStatus::Queued, 2),
+        ] {
+            let info = job_info(id, status, created_at);
+            manager.jobs.insert(id.into(), Arc::new(Job::new(info)));
+        }
+
+        // Oldest finished job is removed to make room
+        manager.prune().await;
+        assert!(manager.get("old").is_none());
+        assert!(manager.get("queued").is_some());
+
+        let info = manager.cancel("queued").await?.unwrap();
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert!(info.finished_at.is_some());
+        // Cancelled jobs are not updated by workers
+        assert!(manager.jobs.get("queued").unwrap().update(|_| {}).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use dashmap::DashMap;
+use http::{HeaderMap, HeaderValue, StatusCode, header::RETRY_AFTER};
+use http_body::{Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{Status, metadata::MetadataValue};
+use tracing::{debug, info};
+
+use super::Principal;
+use crate::config::{RateLimitBudget, RateLimitConfig, RateLimitKey};
+
+/// Key used for requests without a rate limit key.
+const ANONYMOUS_KEY: &str = "anonymous";
+/// Maximum number of tracked keys, requests with new keys share [`OVERFLOW_KEY`] beyond it.
+const MAX_TRACKED_KEYS: usize = 10_000;
+/// Key shared by requests with new keys once [`MAX_TRACKED_KEYS`] are tracked.
+const OVERFLOW_KEY: &str = "overflow";
+/// Interval at which state of idle keys is pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Per-key rate limiter for guardrails server routes and gRPC methods.
+#[derive(Debug)]
+pub struct RateLimiter {
+    key: RateLimitKey,
+    streaming_routes: HashSet<String>,
+    unary: Option<Budget>,
+    streaming: Option<Budget>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            key: config.key.clone(),
+            streaming_routes: config.streaming_routes.iter().cloned().collect(),
+            unary: config.unary.as_ref().map(Budget::new),
+            streaming: config.streaming.as_ref().map(Budget::new),
+        }
+    }
+
+    /// Returns the rate limit key for a request.
+    fn key(&self, headers: &HeaderMap, principal: Option<&Principal>) -> String {
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        match &self.key {
+            RateLimitKey::ApiKey => principal.map(|principal| principal.subject.clone()),
+            // Authenticated callers can't choose their key with the header
+            RateLimitKey::Tenant { header } => match principal {
+                Some(principal) => principal
+                    .tenant
+                    .clone()
+                    .or_else(|| Some(principal.subject.clone())),
+                None => header_value(header),
+            },
+            RateLimitKey::Header { header } => header_value(header),
+        }
+        .unwrap_or_else(|| ANONYMOUS_KEY.into())
+    }
+
+    /// Checks a request against the budget of its route.
+    ///
+    /// Returns a permit to hold while the request is in flight if concurrency
+    /// is limited, or the duration to wait before retrying.
+    fn check(&self, request: &Request) -> Result<Option<OwnedSemaphorePermit>, Duration> {
+        let streaming = self.streaming_routes.contains(request.uri().path());
+        let budget = if streaming {
+            &self.streaming
+        } else {
+            &self.unary
+        };
+        let Some(budget) = budget else {
+            return Ok(None);
+        };
+        let key = self.key(request.headers(), request.extensions().get::<Principal>());
+        budget.acquire(&key).inspect_err(|retry_after| {
+            debug!(%key, streaming, ?retry_after, "rate limit exceeded");
+            info!(monotonic_counter.rate_limited_request_count = 1, streaming);
+        })
+    }
+}
+
+/// Token bucket and concurrency limits for a set of routes.
+#[derive(Debug)]
+struct Budget {
+    rate: f64,
+    burst: f64,
+    max_concurrent: Option<usize>,
+    buckets: DashMap<String, TokenBucket>,
+    in_flight: DashMap<String, Arc<Semaphore>>,
+    last_pruned: Mutex<Instant>,
+}
+
+impl Budget {
+    fn new(config: &RateLimitBudget) -> Self {
+        let burst = config
+            .burst
+            .map(f64::from)
+            .unwrap_or_else(|| config.requests_per_second.ceil());
+        Self {
+            rate: config.requests_per_second,
+            burst,
+            max_concurrent: config.max_concurrent,
+            buckets: DashMap::new(),
+            in_flight: DashMap::new(),
+            last_pruned: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Attempts to admit a request for `key`.
+    ///
+    /// Returns a permit to hold while the request is in flight if concurrency
+    /// is limited, or the duration to wait before retrying.
+    fn acquire(&self, key: &str) -> Result<Option<OwnedSemaphorePermit>, Duration> {
+        let now = Instant::now();
+        self.prune(now);
+        // Cap tracked keys so that requests with arbitrary keys can't grow state unbounded
+        let key = if (self.buckets.len() < MAX_TRACKED_KEYS
+            && self.in_flight.len() < MAX_TRACKED_KEYS)
+            || self.buckets.contains_key(key)
+            || self.in_flight.contains_key(key)
+        {
+            key
+        } else {
+            OVERFLOW_KEY
+        };
+        // Check concurrency first so a rejected request doesn't consume a token
+        let permit = match self.max_concurrent {
+            Some(max_concurrent) => {
+                let semaphore = self
+                    .in_flight
+                    .entry(key.to_string())
+                    .or_insert_with(|| Arc::new(Semaphore::new(max_concurrent)))
+                    .clone();
+                match semaphore.try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => return Err(Duration::from_secs(1)),
+                }
+            }
+            None => None,
+        };
+        self.buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::new(self.burst, now))
+            .take(self.rate, self.burst, now)?;
+        Ok(permit)
+    }
+
+    /// Drops state for idle keys, at most once per [`PRUNE_INTERVAL`].
+    fn prune(&self, now: Instant) {
+        // Skip if another request is pruning
+        let Ok(mut last_pruned) = self.last_pruned.try_lock() else {
+            return;
+        };
+        if now.saturating_duration_since(*last_pruned) < PRUNE_INTERVAL {
+            return;
+        }
+        *last_pruned = now;
+        drop(last_pruned);
+        self.buckets
+            .retain(|_, bucket| bucket.tokens_at(self.rate, self.burst, now) < self.burst);
+        if let Some(max_concurrent) = self.max_concurrent {
+            self.in_flight
+                .retain(|_, semaphore| semaphore.available_permits() < max_concurrent);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// Returns the number of tokens available at `now`.
+    fn tokens_at(&self, rate: f64, burst: f64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * rate).min(burst)
+    }
+
+    /// Takes a token, or returns the duration until one is available.
+    fn take(&mut self, rate: f64, burst: f64, now: Instant) -> Result<(), Duration> {
+        self.tokens = self.tokens_at(rate, burst, now);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// Rate limit middleware.
+///
+/// Must run after the auth middleware to key requests by principal.
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(&request) {
+        Ok(permit) => run_with_permit(request, next, permit).await,
+        Err(retry_after) => {
+            let mut response = super::Error {
+                code: StatusCode::TOO_MANY_REQUESTS,
+                details: "rate limit exceeded".into(),
+            }
+            .into_response();
+            response.headers_mut().insert(
+                RETRY_AFTER,
+                HeaderValue::from(retry_after_secs(retry_after)),
+            );
+            response
+        }
+    }
+}
+
+/// Rate limit middleware for the gRPC router, rejected requests receive a
+/// `RESOURCE_EXHAUSTED` status.
+///
+/// Must run after the gRPC auth middleware to key requests by principal.
+pub async fn limit_grpc(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(&request) {
+        Ok(permit) => run_with_permit(request, next, permit).await,
+        Err(retry_after) => {
+            let mut status = Status::resource_exhausted("rate limit exceeded");
+            status.metadata_mut().insert(
+                "retry-after",
+                MetadataValue::from(retry_after_secs(retry_after)),
+            );
+            status.into_http()
+        }
+    }
+}
+
+/// Concurrency permit of a request, added to request extensions.
+///
+/// Handlers whose work outlives the response body, e.g. WebSockets, hold a
+/// clone to keep counting towards the concurrency limit.
+#[derive(Debug, Clone)]
+pub struct RateLimitPermit {
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+/// Runs a request, holding its permit until the response body ends so that
+/// streaming responses count towards the concurrency limit while open.
+async fn run_with_permit(
+    mut request: Request,
+    next: Next,
+    permit: Option<OwnedSemaphorePermit>,
+) -> Response {
+    let Some(permit) = permit else {
+        return next.run(request).await;
+    };
+    let permit = RateLimitPermit {
+        _permit: Arc::new(permit),
+    };
+    request.extensions_mut().insert(permit.clone());
+    next.run(request).await.map(|body| {
+        Body::new(PermitBody {
+            inner: body,
+            _permit: permit,
+        })
+    })
+}
+
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+pin_project! {
+    /// Response body that holds a concurrency permit until it is dropped.
+    struct PermitBody {
+        #[pin]
+        inner: Body,
+        _permit: RateLimitPermit,
+    }
+}
+
+impl http_body::Body for PermitBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.project().inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::AuthMethod;
+
+    fn budget(requests_per_second: f64, burst: u32, max_concurrent: Option<usize>) -> Budget {
+        Budget::new(&RateLimitBudget {
+            requests_per_second,
+            burst: Some(burst),
+            max_concurrent,
+        })
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let budget = budget(1.0, 2, None);
+        assert!(budget.acquire("a").is_ok());
+        assert!(budget.acquire("a").is_ok());
+        let retry_after = budget.acquire("a").unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(1));
+        // Keys have separate buckets
+        assert!(budget.acquire("b").is_ok());
+
+        // Tokens refill at the configured rate
+        let mut bucket = TokenBucket::new(2.0, Instant::now());
+        let now = bucket.updated;
+        assert!(bucket.take(1.0, 2.0, now).is_ok());
+        assert!(bucket.take(1.0, 2.0, now).is_ok());
+        assert!(bucket.take(1.0, 2.0, now).is_err());
+        assert!(bucket.take(1.0, 2.0, now + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_max_concurrent() {
+        let budget = budget(100.0, 100, Some(1));
+        let permit = budget.acquire("a").unwrap();
+        assert!(permit.is_some());
+        assert_eq!(budget.acquire("a").unwrap_err(), Duration::from_secs(1));
+        drop(permit);
+        assert!(budget.acquire("a").is_ok());
+    }
+
+    #[test]
+    fn test_max_tracked_keys() {
+        let budget = budget(1.0, 1, None);
+        for i in 0..MAX_TRACKED_KEYS {
+            assert!(budget.acquire(&i.to_string()).is_ok());
+        }
+        // New keys share a bucket once the limit is reached
+        assert!(budget.acquire("a").is_ok());
+        assert!(budget.acquire("b").is_err());
+        assert!(budget.buckets.contains_key(OVERFLOW_KEY));
+        assert!(!budget.buckets.contains_key("a"));
+        // Tracked keys keep their own bucket
+        assert!(budget.acquire("0").is_err());
+        assert_eq!(budget.buckets.len(), MAX_TRACKED_KEYS + 1);
+    }
+
+    #[test]
+    fn test_key() {
+        let principal = Principal {
+            subject: "svc-a".into(),
+            tenant: Some("team-a".into()),
+            method: AuthMethod::ApiKey,
+            scopes: vec![],
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant-id", HeaderValue::from_static("team-b"));
+
+        let mut limiter = RateLimiter::new(&RateLimitConfig::default());
+        assert_eq!(limiter.key(&headers, Some(&principal)), "svc-a");
+        assert_eq!(limiter.key(&headers, None), ANONYMOUS_KEY);
+
+        limiter.key = RateLimitKey::Tenant {
+            header: "x-tenant-id".into(),
+        };
+        assert_eq!(limiter.key(&headers, Some(&principal)), "team-a");
+        assert_eq!(limiter.key(&headers, None), "team-b");
+        // Principals without a tenant are keyed by subject, not by header
+        let principal = Principal {
+            tenant: None,
+            ..principal
+        };
+        assert_eq!(limiter.key(&headers, Some(&principal)), "svc-a");
+
+        limiter.key = RateLimitKey::Header {
+            header: "x-client-id".into(),
+        };
+        assert_eq!(limiter.key(&headers, Some(&principal)), ANONYMOUS_KEY);
+    }
+}
@@ -21,8 +21,8 @@ use std::{
 };
 
 use axum::{
-    Json, Router,
-    extract::{Query, State},
+    Extension, Json, Router,
+    extract::{FromRequest, Path, Query, Request, State},
     http::HeaderMap,
     response::{
         IntoResponse, Response,
@@ -39,20 +39,24 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::info;
 
-use super::{Error, ServerState};
+use super::{Error, Principal, ServerState, websocket};
 use crate::{
     clients::openai::{
         ChatCompletionsRequest, ChatCompletionsResponse, CompletionsRequest, CompletionsResponse,
     },
-    models::{self, InfoParams, InfoResponse, StreamingContentDetectionRequest},
+    models::{
+        self, BatchContentDetectionItem, BatchContentDetectionResult, InfoParams, InfoResponse,
+        JobInfo, StreamingContentDetectionRequest, ValidationError,
+    },
     orchestrator::{
         self,
         handlers::{
+            batch_content_detection::BatchItemResult,
             chat_completions_detection::ChatCompletionsDetectionTask,
             completions_detection::CompletionsDetectionTask, *,
         },
     },
-    utils::{self, trace::current_trace_id},
+    utils::{self, cert_expiry, tls, trace::current_trace_id},
 };
 
 const PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -83,17 +87,40 @@ pub fn guardrails_router(state: Arc<ServerState>) -> Router {
             "/api/v2/text/detection/stream-content",
             post(stream_content_detection),
         )
+        .route(
+            "/api/v2/text/detection/stream-content/ws",
+            get(websocket::stream_content_detection),
+        )
         .route(
             "/api/v2/text/generation-detection",
             post(generation_with_detection),
         )
+        .route(
+            "/api/v2/text/generation-detection/stream",
+            post(stream_generation_with_detection),
+        )
         .route("/api/v2/text/detection/content", post(detection_content))
+        .route(
+            "/api/v2/text/detection/content/batch",
+            post(detection_content_batch),
+        )
         .route("/api/v2/text/detection/chat", post(detect_chat))
         .route(
             "/api/v2/text/detection/context",
             post(detect_context_documents),
         )
         .route("/api/v2/text/detection/generated", post(detect_generated));
+    if state.jobs.is_some() {
+        info!("Enabling jobs endpoints");
+        router = router
+            .route(
+                "/api/v2/jobs/text/detection/content",
+                post(submit_content_detection_job),
+            )
+            .route("/api/v2/jobs/{id}", get(get_job))
+            .route("/api/v2/jobs/{id}/results", get(get_job_results))
+            .route("/api/v2/jobs/{id}/cancel", post(cancel_job));
+    }
     if state.orchestrator.config().openai.is_some() {
         info!("Enabling chat completions detection endpoint");
         router = router.route(
@@ -110,11 +137,25 @@ pub fn guardrails_router(state: Arc<ServerState>) -> Router {
     router.with_state(state)
 }
 
-async fn health() -> Result<impl IntoResponse, ()> {
+async fn health(State(state): State<Arc<ServerState>>) -> Result<impl IntoResponse, ()> {
     // NOTE: we are only adding the package information in the `health` endpoint to have this endpoint
     // provide a non empty 200 response. If we need to add more information regarding dependencies version
     // or such things, then we will add another `/info` endpoint accordingly. And those info
     // should not be added in `health` endpoint`
+    if state.orchestrator.config().cert_expiry.unhealthy_on_expiry {
+        let expired_certs = cert_expiry::expired_required_certs();
+        if !expired_certs.is_empty() {
+            let expired_certs = expired_certs
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            return Ok((
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                Json(HashMap::from([("expired_certs", expired_certs)])),
+            )
+                .into_response());
+        }
+    }
     let info_object = HashMap::from([(PACKAGE_NAME, PACKAGE_VERSION)]);
     Ok(Json(info_object).into_response())
 }
@@ -124,18 +165,24 @@ async fn info(
     Query(params): Query<InfoParams>,
 ) -> Result<Json<InfoResponse>, Error> {
     let services = state.orchestrator.client_health(params.probe).await;
-    Ok(Json(InfoResponse { services }))
+    let certs = tls::certs()
+        .into_iter()
+        .map(|(path, cert_info)| (path.display().to_string(), cert_info))
+        .collect();
+    Ok(Json(InfoResponse { services, certs }))
 }
 
 async fn classification_with_gen(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<Json<models::GuardrailsHttpRequest>, Error>,
 ) -> Result<impl IntoResponse, Error> {
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = ClassificationWithGenTask::new(trace_id, request, headers);
+    let task = ClassificationWithGenTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => Ok(Json(response).into_response()),
         Err(error) => Err(error.into()),
@@ -145,6 +192,7 @@ async fn classification_with_gen(
 async fn generation_with_detection(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<
         Json<models::GenerationWithDetectionHttpRequest>,
         Error,
@@ -153,7 +201,8 @@ async fn generation_with_detection(
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = GenerationWithDetectionTask::new(trace_id, request, headers);
+    let task = GenerationWithDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => Ok(Json(response).into_response()),
         Err(error) => Err(error.into()),
@@ -163,6 +212,7 @@ async fn generation_with_detection(
 async fn stream_classification_with_gen(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<Json<models::GuardrailsHttpRequest>, Error>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let trace_id = current_trace_id();
@@ -178,7 +228,8 @@ async fn stream_classification_with_gen(
         );
     }
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = StreamingClassificationWithGenTask::new(trace_id, request, headers);
+    let task = StreamingClassificationWithGenTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     let response_stream = state.orchestrator.handle(task).await.unwrap();
     // Convert response stream to a stream of SSE events
     let event_stream = response_stream
@@ -196,9 +247,48 @@ async fn stream_classification_with_gen(
     Sse::new(event_stream).keep_alive(KeepAlive::default())
 }
 
+async fn stream_generation_with_detection(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    WithRejection(Json(request), _): WithRejection<
+        Json<models::GenerationWithDetectionHttpRequest>,
+        Error,
+    >,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let trace_id = current_trace_id();
+    if let Err(error) = request.validate() {
+        // Request validation failed, return stream with single error SSE event
+        let error: Error = error.into();
+        return Sse::new(
+            stream::iter([Ok(Event::default()
+                .event("error")
+                .json_data(error)
+                .unwrap())])
+            .boxed(),
+        );
+    }
+    let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
+    let task = StreamingGenerationWithDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
+    let response_stream = state.orchestrator.handle(task).await.unwrap();
+    // Convert response stream to a stream of SSE events
+    let event_stream = response_stream
+        .map(|message| match message {
+            Ok(response) => Ok(Event::default().json_data(response).unwrap()),
+            Err(error) => {
+                let error: Error = error.into();
+                Ok(Event::default().event("error").json_data(error).unwrap())
+            }
+        })
+        .boxed();
+    Sse::new(event_stream).keep_alive(KeepAlive::default())
+}
+
 async fn stream_content_detection(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     json_lines: JsonLines<StreamingContentDetectionRequest>,
 ) -> Result<impl IntoResponse, Error> {
     let trace_id = current_trace_id();
@@ -231,7 +321,8 @@ async fn stream_content_detection(
         .boxed();
 
     // Create task and submit to handler
-    let task = StreamingContentDetectionTask::new(trace_id, headers, input_stream);
+    let task = StreamingContentDetectionTask::new(trace_id, headers, input_stream)
+        .with_principal(principal.map(|Extension(principal)| principal));
     let mut response_stream = state.orchestrator.handle(task).await?;
 
     // Create output stream
@@ -264,6 +355,7 @@ async fn stream_content_detection(
 async fn detection_content(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<
         Json<models::TextContentDetectionHttpRequest>,
         Error,
@@ -272,22 +364,201 @@ async fn detection_content(
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = TextContentDetectionTask::new(trace_id, request, headers);
+    let task = TextContentDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => Ok(Json(response).into_response()),
         Err(error) => Err(error.into()),
     }
 }
 
+async fn detection_content_batch(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    request: Request,
+) -> Result<impl IntoResponse, Error> {
+    let trace_id = current_trace_id();
+    let content_type = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let (detectors, items) = if content_type.starts_with("application/x-ndjson") {
+        // Each line is an item, detectors are read from the first item and later
+        // items with other detectors are rejected
+        let Ok(json_lines) =
+            JsonLines::<BatchContentDetectionItem>::from_request(request, &state).await;
+        let mut items = Box::pin(
+            json_lines
+                .map(|result| {
+                    result.map_err(|error| orchestrator::Error::Validation(error.to_string()))
+                })
+                .enumerate()
+                .peekable(),
+        );
+        let detectors = match items.as_mut().peek().await {
+            Some((_, Ok(item))) => item.validate_detectors()?.clone(),
+            Some((_, Err(error))) => return Err(error.clone().into()),
+            None => return Err(ValidationError::Required("items".into()).into()),
+        };
+        let first_detectors = detectors.clone();
+        let items = items.map(move |(index, result)| {
+            let result = result.and_then(|item| match &item.detectors {
+                Some(detectors) if *detectors != first_detectors => {
+                    Err(orchestrator::Error::Validation(
+                        "`detectors` must match the detectors of the first item".into(),
+                    ))
+                }
+                _ => Ok(item),
+            });
+            (index, result)
+        });
+        (detectors, items.boxed())
+    } else {
+        let WithRejection(Json(request), _) = WithRejection::<
+            Json<models::BatchContentDetectionHttpRequest>,
+            Error,
+        >::from_request(request, &state)
+        .await?;
+        request.validate()?;
+        let items = stream::iter(request.items.into_iter().map(Ok).enumerate()).boxed();
+        (request.detectors, items)
+    };
+    let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
+    let task = BatchContentDetectionTask::new(trace_id, headers, detectors, items)
+        .with_principal(principal.map(|Extension(principal)| principal));
+    let response_stream = state.orchestrator.handle(task).await?;
+
+    // Convert results to ND-JSON formatted messages
+    let output_stream = response_stream.map(|item| {
+        Ok::<_, Infallible>(utils::json::to_nd_string(&batch_item_result(item)).unwrap())
+    });
+    let mut response = Response::new(axum::body::Body::from_stream(output_stream));
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/x-ndjson"),
+    );
+    Ok(response)
+}
+
+/// Converts a batch item result to its HTTP representation.
+pub fn batch_item_result(item: BatchItemResult) -> BatchContentDetectionResult {
+    let (detections, error) = match item.result {
+        Ok(result) => (Some(result.detections), None),
+        // Convert orchestrator::Error to server::Error
+        Err(error) => (None, Some(Error::from(error).into())),
+    };
+    BatchContentDetectionResult {
+        index: item.index,
+        id: item.id,
+        detections,
+        error,
+    }
+}
+
+async fn submit_content_detection_job(
+    State(state): State<Arc<ServerState>>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    WithRejection(Json(request), _): WithRejection<
+        Json<models::BatchContentDetectionHttpRequest>,
+        Error,
+    >,
+) -> Result<impl IntoResponse, Error> {
+    request.validate()?;
+    let jobs = state.jobs.clone().expect("jobs enabled");
+    let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
+    let principal = principal.map(|Extension(principal)| principal);
+    let info = jobs
+        .submit(
+            state.clone(),
+            headers,
+            request.detectors,
+            request.items,
+            principal,
+        )
+        .await?;
+    Ok((http::StatusCode::ACCEPTED, Json(info)))
+}
+
+async fn get_job(
+    State(state): State<Arc<ServerState>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let info = find_job(&state, principal.as_deref(), &id)?;
+    Ok(Json(info))
+}
+
+async fn get_job_results(
+    State(state): State<Arc<ServerState>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    find_job(&state, principal.as_deref(), &id)?;
+    let jobs = state.jobs.as_ref().ok_or_else(|| job_not_found(&id))?;
+    let results = jobs.results(&id).await?.ok_or_else(|| job_not_found(&id))?;
+    let body = results
+        .iter()
+        .map(|result| utils::json::to_nd_string(result).unwrap())
+        .collect::<String>();
+    Ok((
+        [(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("application/x-ndjson"),
+        )],
+        body,
+    ))
+}
+
+async fn cancel_job(
+    State(state): State<Arc<ServerState>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    find_job(&state, principal.as_deref(), &id)?;
+    let jobs = state.jobs.as_ref().ok_or_else(|| job_not_found(&id))?;
+    let info = jobs.cancel(&id).await?.ok_or_else(|| job_not_found(&id))?;
+    Ok(Json(info))
+}
+
+/// Returns a job's status if it exists and is visible to the caller.
+///
+/// Jobs submitted by an authenticated caller are only visible to that caller.
+fn find_job(
+    state: &ServerState,
+    principal: Option<&Principal>,
+    id: &str,
+) -> Result<JobInfo, Error> {
+    state
+        .jobs
+        .as_ref()
+        .and_then(|jobs| jobs.get(id))
+        .filter(|info| {
+            info.owner.is_none()
+                || info.owner.as_deref() == principal.map(|principal| principal.subject.as_str())
+        })
+        .ok_or_else(|| job_not_found(id))
+}
+
+fn job_not_found(id: &str) -> Error {
+    Error {
+        code: http::StatusCode::NOT_FOUND,
+        details: format!("job `{id}` not found"),
+    }
+}
+
 async fn detect_context_documents(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<Json<models::ContextDocsHttpRequest>, Error>,
 ) -> Result<impl IntoResponse, Error> {
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = ContextDocsDetectionTask::new(trace_id, request, headers);
+    let task = ContextDocsDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => Ok(Json(response).into_response()),
         Err(error) => Err(error.into()),
@@ -297,12 +568,14 @@ async fn detect_context_documents(
 async fn detect_chat(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<Json<models::ChatDetectionHttpRequest>, Error>,
 ) -> Result<impl IntoResponse, Error> {
     let trace_id = current_trace_id();
     request.validate_for_text()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = ChatDetectionTask::new(trace_id, request, headers);
+    let task = ChatDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => Ok(Json(response).into_response()),
         Err(error) => Err(error.into()),
@@ -312,6 +585,7 @@ async fn detect_chat(
 async fn detect_generated(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<
         Json<models::DetectionOnGeneratedHttpRequest>,
         Error,
@@ -320,7 +594,8 @@ async fn detect_generated(
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = DetectionOnGenerationTask::new(trace_id, request, headers);
+    let task = DetectionOnGenerationTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => Ok(Json(response).into_response()),
         Err(error) => Err(error.into()),
@@ -329,6 +604,7 @@ async fn detect_generated(
 
 async fn chat_completions_detection(
     State(state): State<Arc<ServerState>>,
+    principal: Option<Extension<Principal>>,
     headers: HeaderMap,
     WithRejection(Json(request), _): WithRejection<Json<ChatCompletionsRequest>, Error>,
 ) -> Result<impl IntoResponse, Error> {
@@ -336,7 +612,8 @@ async fn chat_completions_detection(
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = ChatCompletionsDetectionTask::new(trace_id, request, headers);
+    let task = ChatCompletionsDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => match response {
             Unary(response) => Ok(Json(response).into_response()),
@@ -367,13 +644,15 @@ async fn chat_completions_detection(
 async fn completions_detection(
     State(state): State<Arc<ServerState>>,
     headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     WithRejection(Json(request), _): WithRejection<Json<CompletionsRequest>, Error>,
 ) -> Result<impl IntoResponse, Error> {
     use CompletionsResponse::*;
     let trace_id = current_trace_id();
     request.validate()?;
     let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
-    let task = CompletionsDetectionTask::new(trace_id, request, headers);
+    let task = CompletionsDetectionTask::new(trace_id, request, headers)
+        .with_principal(principal.map(|Extension(principal)| principal));
     match state.orchestrator.handle(task).await {
         Ok(response) => match response {
             Unary(response) => Ok(Json(response).into_response()),
@@ -14,7 +14,11 @@
  limitations under the License.
 
 */
-use std::{fs::File, io::BufReader, path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
 
 use axum::{Router, extract::Request};
 use hyper::body::Incoming;
@@ -26,41 +30,128 @@ use tokio_rustls::TlsAcceptor;
 use tower::Service;
 use tracing::{debug, error, info, warn};
 
+use super::Error;
+use crate::utils::tls;
+
+/// Interval at which certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// TLS errors.
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("error reading `{path}`: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("invalid TLS material: {0}")]
+    Invalid(String),
+}
+
+/// Server TLS config that is reloaded when certificate files change.
+///
+/// The cert, key and client CA files are checked for changes every [`RELOAD_INTERVAL`].
+/// Changed files are loaded into a new [`ServerConfig`], which is swapped in atomically
+/// and used for new handshakes. If the new material is invalid, e.g. a cert that does not
+/// match the key during a rotation, the error is logged and the current config is kept.
+#[derive(Debug)]
+pub struct ReloadingServerConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    client_ca_cert_path: Option<PathBuf>,
+    /// File contents of the current config
+    contents: Mutex<TlsFiles>,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+/// Contents of cert, key and client CA files.
+#[derive(Debug, Clone, PartialEq)]
+struct TlsFiles {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    client_ca_cert: Option<Vec<u8>>,
+}
+
+impl ReloadingServerConfig {
+    /// Loads the server config, returning an error if the files are invalid.
+    pub fn load(
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        client_ca_cert_path: Option<PathBuf>,
+    ) -> Result<Self, TlsError> {
+        let files = read_files(&cert_path, &key_path, client_ca_cert_path.as_deref())?;
+        let server_config = build_server_config(&files)?;
+        record_certs(&cert_path, client_ca_cert_path.as_deref(), &files);
+        Ok(Self {
+            cert_path,
+            key_path,
+            client_ca_cert_path,
+            contents: Mutex::new(files),
+            current: RwLock::new(Arc::new(server_config)),
+        })
+    }
+
+    /// Returns the current server config.
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reloads the server config if certificate files have changed.
+    /// Returns `true` if a new config was swapped in.
+    pub fn reload(&self) -> Result<bool, TlsError> {
+        let files = read_files(
+            &self.cert_path,
+            &self.key_path,
+            self.client_ca_cert_path.as_deref(),
+        )?;
+        let mut contents = self.contents.lock().unwrap();
+        if *contents == files {
+            return Ok(false);
+        }
+        let server_config = build_server_config(&files)?;
+        record_certs(&self.cert_path, self.client_ca_cert_path.as_deref(), &files);
+        *self.current.write().unwrap() = Arc::new(server_config);
+        *contents = files;
+        Ok(true)
+    }
+
+    /// Spawns a task that periodically reloads the server config.
+    pub fn spawn_reloader(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match self.reload() {
+                    Ok(true) => info!("TLS certificates reloaded"),
+                    Ok(false) => (),
+                    Err(error) => {
+                        error!(%error, "error reloading TLS certificates, keeping current certificates")
+                    }
+                }
+            }
+        })
+    }
+}
+
 /// Loads certificates and configures TLS.
 pub fn configure_tls(
     tls_cert_path: Option<PathBuf>,
     tls_key_path: Option<PathBuf>,
     tls_client_ca_cert_path: Option<PathBuf>,
-) -> Option<Arc<ServerConfig>> {
+) -> Result<Option<Arc<ReloadingServerConfig>>, Error> {
     if let (Some(cert_path), Some(key_path)) = (tls_cert_path, tls_key_path) {
         let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
-        let cert = load_certs(&cert_path);
-        let key = load_private_key(&key_path);
-        // Configure mTLS if client CA is provided
-        let client_auth = if let Some(client_ca_cert_path) = tls_client_ca_cert_path {
-            let client_certs = load_certs(&client_ca_cert_path);
-            let mut client_auth_certs = RootCertStore::empty();
-            for client_cert in client_certs {
-                client_auth_certs
-                    .add(client_cert.clone())
-                    .unwrap_or_else(|e| panic!("error adding client cert {client_cert:?}: {e}"));
-            }
+        if tls_client_ca_cert_path.is_some() {
             info!("mTLS enabled");
-            WebPkiClientVerifier::builder(client_auth_certs.into())
-                .build()
-                .unwrap_or_else(|e| panic!("error building client verifier: {e}"))
         } else {
             info!("TLS enabled");
-            WebPkiClientVerifier::no_client_auth()
-        };
-        let server_config = ServerConfig::builder()
-            .with_client_cert_verifier(client_auth)
-            .with_single_cert(cert, key)
-            .expect("bad server certificate or key");
-        Some(Arc::new(server_config))
+        }
+        let tls_config = ReloadingServerConfig::load(cert_path, key_path, tls_client_ca_cert_path)?;
+        Ok(Some(Arc::new(tls_config)))
     } else {
         info!("TLS not enabled");
-        None
+        Ok(None)
     }
 }
 
@@ -69,20 +160,20 @@ pub fn configure_tls(
 pub fn serve_with_tls<F>(
     app: Router,
     listener: TcpListener,
-    tls_config: Arc<rustls::ServerConfig>,
+    tls_config: Arc<ReloadingServerConfig>,
     shutdown_signal: F,
 ) -> tokio::task::JoinHandle<()>
 where
     F: Future<Output = ()> + Send + 'static,
 {
-    let tls_acceptor = TlsAcceptor::from(tls_config);
     tokio::spawn(async move {
         let graceful = hyper_util::server::graceful::GracefulShutdown::new();
         let builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
         let mut signal = std::pin::pin!(shutdown_signal);
         loop {
             let tower_service = app.clone();
-            let tls_acceptor = tls_acceptor.clone();
+            // New handshakes use the current server config
+            let tls_acceptor = TlsAcceptor::from(tls_config.current());
             // Wait for new tcp connection
             let (cnx, addr) = tokio::select! {
                 res = listener.accept() => {
@@ -141,27 +232,121 @@ where
     })
 }
 
-/// Load certificates from a file
-fn load_certs(filename: &PathBuf) -> Vec<CertificateDer<'static>> {
-    let cert_file = File::open(filename).expect("cannot open certificate file");
-    let mut reader = BufReader::new(cert_file);
-    rustls_pemfile::certs(&mut reader)
-        .map(|result| result.unwrap())
-        .collect()
+/// Reads cert, key and client CA files.
+fn read_files(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca_cert_path: Option<&Path>,
+) -> Result<TlsFiles, TlsError> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|error| TlsError::Io {
+            path: path.into(),
+            error,
+        })
+    };
+    Ok(TlsFiles {
+        cert: read(cert_path)?,
+        key: read(key_path)?,
+        client_ca_cert: client_ca_cert_path.map(read).transpose()?,
+    })
+}
+
+/// Records server cert and client CA expiry.
+fn record_certs(cert_path: &Path, client_ca_cert_path: Option<&Path>, files: &TlsFiles) {
+    tls::record_pem_certs(cert_path, &files.cert, true);
+    if let (Some(path), Some(client_ca_cert)) = (client_ca_cert_path, &files.client_ca_cert) {
+        tls::record_pem_certs(path, client_ca_cert, true);
+    }
 }
 
-/// Load private key from a file
-fn load_private_key(filename: &PathBuf) -> PrivateKeyDer<'static> {
-    let key_file = File::open(filename).expect("cannot open private key file");
-    let mut reader = BufReader::new(key_file);
+/// Builds a server config from cert, key and client CA files.
+fn build_server_config(files: &TlsFiles) -> Result<ServerConfig, TlsError> {
+    let certs = load_certs(&files.cert)?;
+    let key = load_private_key(&files.key)?;
+    // Configure mTLS if client CA is provided
+    let client_auth = if let Some(client_ca_cert) = &files.client_ca_cert {
+        let mut client_auth_certs = RootCertStore::empty();
+        for client_cert in load_certs(client_ca_cert)? {
+            client_auth_certs
+                .add(client_cert)
+                .map_err(|e| TlsError::Invalid(format!("error adding client CA cert: {e}")))?;
+        }
+        WebPkiClientVerifier::builder(client_auth_certs.into())
+            .build()
+            .map_err(|e| TlsError::Invalid(format!("error building client verifier: {e}")))?
+    } else {
+        WebPkiClientVerifier::no_client_auth()
+    };
+    let mut server_config = ServerConfig::builder()
+        .with_client_cert_verifier(client_auth)
+        .with_single_cert(certs, key)
+        .map_err(|e| TlsError::Invalid(format!("bad server certificate or key: {e}")))?;
+    // HTTP/2 is required by gRPC clients
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+/// Load certificates from PEM file contents
+fn load_certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Invalid(format!("cannot parse certificate .pem file: {e}")))?;
+    if certs.is_empty() {
+        return Err(TlsError::Invalid("no certificates found".into()));
+    }
+    Ok(certs)
+}
+
+/// Load private key from PEM file contents
+fn load_private_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let mut reader = &pem[..];
     loop {
-        match rustls_pemfile::read_one(&mut reader).expect("cannot parse private key .pem file") {
-            Some(rustls_pemfile::Item::Pkcs1Key(key)) => return key.into(),
-            Some(rustls_pemfile::Item::Pkcs8Key(key)) => return key.into(),
-            Some(rustls_pemfile::Item::Sec1Key(key)) => return key.into(),
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| TlsError::Invalid(format!("cannot parse private key .pem file: {e}")))?
+        {
+            Some(rustls_pemfile::Item::Pkcs1Key(key)) => return Ok(key.into()),
+            Some(rustls_pemfile::Item::Pkcs8Key(key)) => return Ok(key.into()),
+            Some(rustls_pemfile::Item::Sec1Key(key)) => return Ok(key.into()),
             None => break,
             _ => {}
         }
     }
-    panic!("no keys found in {filename:?} (encrypted keys not supported)");
+    Err(TlsError::Invalid(
+        "no keys found (encrypted keys not supported)".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reloading_server_config() -> Result<(), TlsError> {
+        let dir = std::env::temp_dir().join(format!("orchestrator-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("tls.crt");
+        let key_path = dir.join("tls.key");
+        let cert = std::fs::read("tests/resources/localhost.crt").unwrap();
+        let key = std::fs::read("tests/resources/localhost.key").unwrap();
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let tls_config = ReloadingServerConfig::load(cert_path.clone(), key_path.clone(), None)?;
+        let initial = tls_config.current();
+        assert!(!tls_config.reload()?);
+
+        // Invalid material is not swapped in
+        std::fs::write(&key_path, "invalid").unwrap();
+        assert!(matches!(tls_config.reload(), Err(TlsError::Invalid(_))));
+        assert!(Arc::ptr_eq(&tls_config.current(), &initial));
+
+        // Valid material is swapped in
+        std::fs::write(&key_path, [key.as_slice(), b"\n"].concat()).unwrap();
+        assert!(tls_config.reload()?);
+        assert!(!Arc::ptr_eq(&tls_config.current(), &initial));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
 }
@@ -0,0 +1,291 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! WebSocket transport for streaming content detection.
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Extension,
+    extract::{
+        State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use futures::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use opentelemetry::trace::TraceId;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{Instrument, Span, debug, info, warn};
+
+use super::{Error, Principal, ServerState, rate_limit::RateLimitPermit, routes::filter_headers};
+use crate::{
+    models::{StreamingContentDetectionRequest, StreamingContentDetectionResponse},
+    orchestrator::{
+        self,
+        handlers::{Handle, StreamingContentDetectionTask},
+    },
+    utils::trace::current_trace_id,
+};
+
+/// Interval between keepalive pings sent to the client.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum length in bytes of a close frame reason.
+const MAX_CLOSE_REASON_LEN: usize = 123;
+
+/// Number of request frames buffered before the client is backpressured.
+const INPUT_CHANNEL_CAPACITY: usize = 32;
+
+/// Upgrades the connection to a WebSocket for streaming content detection.
+///
+/// Clients send [`StreamingContentDetectionRequest`] frames and receive
+/// `StreamingContentDetectionResponse` frames, with the same semantics as the
+/// ND-JSON `/api/v2/text/detection/stream-content` endpoint.
+pub async fn stream_content_detection(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    permit: Option<Extension<RateLimitPermit>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let trace_id = current_trace_id();
+    let headers = filter_headers(&state.orchestrator.config().passthrough_headers, headers);
+    let principal = principal.map(|Extension(principal)| principal);
+    let span = Span::current();
+    ws.on_upgrade(move |socket| {
+        async move {
+            // Hold the rate limit permit for the life of the socket
+            let _permit = permit;
+            handle_stream_content_detection(state, trace_id, headers, principal, socket).await
+        }
+        .instrument(span)
+    })
+}
+
+async fn handle_stream_content_detection(
+    state: Arc<ServerState>,
+    trace_id: TraceId,
+    headers: HeaderMap,
+    principal: Option<Principal>,
+    socket: WebSocket,
+) {
+    let (mut sender, receiver) = socket.split();
+    let liveness = Arc::new(Liveness {
+        alive: AtomicBool::new(true),
+        backpressured: AtomicBool::new(false),
+    });
+
+    // Read frames on a separate task, so that pongs are received while input is
+    // backpressured and after it has ended
+    // The input stream ends when the client closes the connection
+    let (input_tx, input_rx) = mpsc::channel(INPUT_CHANNEL_CAPACITY);
+    let receiver_task =
+        tokio::spawn(read_frames(receiver, input_tx, liveness.clone()).in_current_span());
+    let input_stream = ReceiverStream::new(input_rx).enumerate().boxed();
+
+    // Create task and submit to handler
+    let task = StreamingContentDetectionTask::new(trace_id, headers, input_stream)
+        .with_principal(principal);
+    match state.orchestrator.handle(task).await {
+        Ok(response_stream) => {
+            send_responses(trace_id, sender, response_stream, &receiver_task, &liveness).await
+        }
+        Err(error) => close_with_error(&mut sender, error.into()).await,
+    }
+    receiver_task.abort();
+}
+
+/// Liveness of a client, checked by keepalive pings.
+#[derive(Debug)]
+struct Liveness {
+    /// Set when a frame is received from the client, reset when a ping is sent
+    alive: AtomicBool,
+    /// Set while a request frame waits for the input stream to be polled
+    backpressured: AtomicBool,
+}
+
+impl Liveness {
+    /// Returns `true` if the client responded since the last check.
+    fn check(&self) -> bool {
+        self.alive.swap(false, Ordering::Relaxed) || self.backpressured.load(Ordering::Relaxed)
+    }
+}
+
+/// Reads frames from the client, sending requests to the input stream.
+///
+/// Frames are read until the client closes the connection, frames received after
+/// the input stream is dropped are discarded.
+async fn read_frames(
+    mut receiver: SplitStream<WebSocket>,
+    input_tx: mpsc::Sender<Result<StreamingContentDetectionRequest, orchestrator::Error>>,
+    liveness: Arc<Liveness>,
+) {
+    let mut input_tx = Some(input_tx);
+    while let Some(Ok(message)) = receiver.next().await {
+        liveness.alive.store(true, Ordering::Relaxed);
+        let request = match message {
+            Message::Text(text) => parse_request(text.as_str().as_bytes()),
+            Message::Binary(bytes) => parse_request(&bytes),
+            Message::Close(_) => break,
+            // Pings are answered automatically
+            _ => continue,
+        };
+        if let Some(tx) = &input_tx {
+            liveness.backpressured.store(true, Ordering::Relaxed);
+            if tx.send(request).await.is_err() {
+                input_tx = None;
+            }
+            liveness.backpressured.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Sends responses to the client and keepalive pings, until the response stream
+/// completes or the client disconnects.
+async fn send_responses(
+    trace_id: TraceId,
+    mut sender: SplitSink<WebSocket, Message>,
+    mut response_stream: ReceiverStream<
+        Result<StreamingContentDetectionResponse, orchestrator::Error>,
+    >,
+    receiver_task: &JoinHandle<()>,
+    liveness: &Liveness,
+) {
+    let mut keepalive = tokio::time::interval_at(
+        tokio::time::Instant::now() + KEEPALIVE_INTERVAL,
+        KEEPALIVE_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            result = response_stream.next() => match result {
+                Some(Ok(response)) => {
+                    let msg = serde_json::to_string(&response).unwrap();
+                    if sender.send(Message::Text(msg.into())).await.is_err() {
+                        info!(%trace_id, "websocket closed: client disconnected");
+                        return;
+                    }
+                }
+                Some(Err(error)) => {
+                    // Convert orchestrator::Error to server::Error
+                    close_with_error(&mut sender, error.into()).await;
+                    return;
+                }
+                None => {
+                    debug!(%trace_id, "response stream completed, closing websocket");
+                    close(&mut sender, close_code::NORMAL, "").await;
+                    return;
+                }
+            },
+            // Pongs can't be received once the client has closed the connection
+            _ = keepalive.tick(), if !receiver_task.is_finished() => {
+                if !liveness.check() {
+                    warn!(%trace_id, "websocket closed: keepalive timeout");
+                    close(&mut sender, close_code::AWAY, "keepalive timeout").await;
+                    return;
+                }
+                if sender.send(Message::Ping(Default::default())).await.is_err() {
+                    info!(%trace_id, "websocket closed: client disconnected");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Parses and validates a request frame.
+fn parse_request(bytes: &[u8]) -> Result<StreamingContentDetectionRequest, orchestrator::Error> {
+    let request = serde_json::from_slice::<StreamingContentDetectionRequest>(bytes)
+        .map_err(|error| orchestrator::Error::Validation(error.to_string()))?;
+    request.validate()?;
+    Ok(request)
+}
+
+/// Sends an error frame and closes the connection with a matching close code.
+async fn close_with_error(sender: &mut SplitSink<WebSocket, Message>, error: Error) {
+    let msg = serde_json::to_string(&error).unwrap();
+    let _ = sender.send(Message::Text(msg.into())).await;
+    close(sender, error_close_code(&error), &error.details).await;
+}
+
+async fn close(sender: &mut SplitSink<WebSocket, Message>, code: u16, reason: &str) {
+    let _ = sender
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: truncate(reason, MAX_CLOSE_REASON_LEN).into(),
+        })))
+        .await;
+}
+
+/// Maps an error to a close code, using the policy violation code for client errors.
+fn error_close_code(error: &Error) -> u16 {
+    if error.code.is_client_error() {
+        close_code::POLICY
+    } else {
+        close_code::ERROR
+    }
+}
+
+/// Truncates a string to at most `max_len` bytes on a char boundary.
+fn truncate(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_error_close_code() {
+        let error = Error {
+            code: StatusCode::UNPROCESSABLE_ENTITY,
+            details: "`content` cannot be empty".into(),
+        };
+        assert_eq!(error_close_code(&error), close_code::POLICY);
+        let error = Error {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            details: "unexpected error occurred while processing request".into(),
+        };
+        assert_eq!(error_close_code(&error), close_code::ERROR);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 3), "hel");
+        // Does not split multi-byte chars
+        assert_eq!(truncate("héllo", 2), "h");
+    }
+}
@@ -1,7 +1,10 @@
 use hyper::Uri;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
 use serde::{Deserialize, Deserializer, de::DeserializeOwned};
 use url::Url;
+pub mod cert_expiry;
 pub mod json;
+pub mod lru;
 pub mod tls;
 pub mod trace;
 
@@ -34,3 +37,10 @@ where
         OneOrMany::Many(values) => Ok(values),
     }
 }
+
+/// JSON Schema for fields deserialized with [`one_or_many`].
+pub fn one_or_many_schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    let one = generator.subschema_for::<T>();
+    let many = generator.subschema_for::<Vec<T>>();
+    json_schema!({ "anyOf": [one, many] })
+}
@@ -0,0 +1,146 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! TLS certificate expiry monitoring.
+//!
+//! Certificates are recorded in the [`tls`] registry when they are loaded by the
+//! server and by client services. Certificates of named TLS configs that are not
+//! referenced by any service are also recorded, but are not required.
+//!
+//! Expiry is checked every [`CHECK_INTERVAL`], logging a warning for certificates
+//! expiring within the configured warning period and an error for expired
+//! certificates. Time to expiry is exported as the `tls_cert_expiry_seconds` gauge.
+use std::{path::PathBuf, time::Duration};
+
+use opentelemetry::{KeyValue, global};
+use tracing::{error, warn};
+
+use super::tls;
+use crate::config::{CertExpiryConfig, OrchestratorConfig};
+
+/// Interval at which certificate expiry is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+const SECS_PER_DAY: i64 = 86400;
+
+/// Records the certificates of named TLS configs.
+/// Certificates of configs referenced by a service are required.
+pub fn record_config_certs(config: &OrchestratorConfig) {
+    for (name, tls_config) in config.tls.iter().flatten() {
+        let required = config.is_tls_config_used(name);
+        let paths = [&tls_config.cert_path, &tls_config.client_ca_cert_path];
+        for path in paths.into_iter().flatten() {
+            match std::fs::read(path) {
+                Ok(pem) => tls::record_pem_certs(path, &pem, required),
+                Err(error) => warn!(
+                    tls_config = name,
+                    path = %path.display(),
+                    %error,
+                    "error reading TLS certificate"
+                ),
+            }
+        }
+    }
+}
+
+/// Returns the paths of required certificates that have expired.
+pub fn expired_required_certs() -> Vec<PathBuf> {
+    tls::certs()
+        .into_iter()
+        .filter(|(_, cert_info)| cert_info.required && cert_info.is_expired())
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Logs certificates that have expired or expire within the warning period.
+pub fn check_certs(config: &CertExpiryConfig) {
+    let warning_secs = (config.warning_days as i64).saturating_mul(SECS_PER_DAY);
+    for (path, cert_info) in tls::certs() {
+        let path = path.display();
+        if cert_info.is_expired() {
+            error!(
+                %path,
+                subject = cert_info.subject,
+                required = cert_info.required,
+                "TLS certificate has expired"
+            );
+        } else if cert_info.expires_in <= warning_secs {
+            warn!(
+                %path,
+                subject = cert_info.subject,
+                required = cert_info.required,
+                expires_in_days = cert_info.expires_in / SECS_PER_DAY,
+                "TLS certificate expires soon"
+            );
+        }
+    }
+}
+
+/// Registers the `tls_cert_expiry_seconds` gauge and spawns a task that checks
+/// certificate expiry every [`CHECK_INTERVAL`].
+pub fn spawn_monitor(config: CertExpiryConfig) -> tokio::task::JoinHandle<()> {
+    let gauge = global::meter(env!("CARGO_PKG_NAME"))
+        .i64_observable_gauge("tls_cert_expiry_seconds")
+        .with_description("Seconds until TLS certificate expiry, negative if expired")
+        .with_unit("s")
+        .with_callback(|observer| {
+            for (path, cert_info) in tls::certs() {
+                observer.observe(
+                    cert_info.expires_in,
+                    &[
+                        KeyValue::new("path", path.display().to_string()),
+                        KeyValue::new("required", cert_info.required),
+                    ],
+                );
+            }
+        })
+        .build();
+    tokio::spawn(async move {
+        let _gauge = gauge;
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            check_certs(&config);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_record_config_certs() {
+        let s = r#"
+detectors: {}
+tls:
+    unused:
+        cert_path: tests/resources/localhost.crt
+        key_path: tests/resources/localhost.key
+        "#;
+        let config: OrchestratorConfig = serde_yml::from_str(s).unwrap();
+        record_config_certs(&config);
+        let cert_info = &tls::certs()[Path::new("tests/resources/localhost.crt")];
+        assert_eq!(cert_info.subject, "CN=localhost");
+        assert!(cert_info.expires_in > 0);
+        // Certs of TLS configs not referenced by a service are not required
+        assert!(!cert_info.required);
+        assert!(expired_required_certs().is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+//! Map ordered by last use.
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    time::Instant,
+};
+
+/// Map that tracks the order in which entries are used, so that the least
+/// recently used entry is found and removed in logarithmic time.
+///
+/// Entries are used when inserted or accessed with [`LruMap::get_mut`].
+#[derive(Debug)]
+pub struct LruMap<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Keys by use order
+    order: BTreeMap<u64, K>,
+    next_tick: u64,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    tick: u64,
+    last_used: Instant,
+}
+
+impl<K: Clone + Eq + Hash, V> LruMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns a value and when it was last used, without using it.
+    pub fn peek(&self, key: &K) -> Option<(&V, Instant)> {
+        self.entries
+            .get(key)
+            .map(|entry| (&entry.value, entry.last_used))
+    }
+
+    /// Returns a value, using it.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.tick);
+        self.order.insert(tick, key.clone());
+        entry.tick = tick;
+        entry.last_used = Instant::now();
+        Some(&mut entry.value)
+    }
+
+    /// Inserts a value, returning the previous value, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let tick = self.tick();
+        self.order.insert(tick, key.clone());
+        let entry = Entry {
+            value,
+            tick,
+            last_used: Instant::now(),
+        };
+        let previous = self.entries.insert(key, entry)?;
+        self.order.remove(&previous.tick);
+        Some(previous.value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        Some(entry.value)
+    }
+
+    /// Returns the least recently used entry and when it was last used.
+    pub fn peek_lru(&self) -> Option<(&K, &V, Instant)> {
+        let (_, key) = self.order.first_key_value()?;
+        let entry = &self.entries[key];
+        Some((key, &entry.value, entry.last_used))
+    }
+
+    /// Removes the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        let (_, key) = self.order.pop_first()?;
+        let entry = self.entries.remove(&key)?;
+        Some((key, entry.value))
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Default for LruMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_map() {
+        let mut map = LruMap::new();
+        assert!(map.is_empty());
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.insert("c", 3);
+        assert_eq!(map.peek_lru().map(|(key, _, _)| *key), Some("a"));
+
+        // Using an entry moves it to the back
+        *map.get_mut(&"a").unwrap() += 10;
+        assert_eq!(map.peek(&"a").map(|(value, _)| *value), Some(11));
+        assert_eq!(map.pop_lru(), Some(("b", 2)));
+
+        // Peeking does not use an entry
+        assert!(map.peek(&"c").is_some());
+        assert_eq!(map.peek_lru().map(|(key, _, _)| *key), Some("c"));
+
+        // Replacing an entry uses it
+        assert_eq!(map.insert("c", 4), Some(3));
+        assert_eq!(map.pop_lru(), Some(("a", 11)));
+        assert_eq!(map.remove(&"c"), Some(4));
+        assert!(map.is_empty());
+        assert_eq!(map.pop_lru(), None);
+    }
+}
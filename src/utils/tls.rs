@@ -1,13 +1,26 @@
-use std::{fs::File, io, path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, RwLock},
+    time::Duration,
+};
 
 use http_serde::http::StatusCode;
 use hyper_rustls::ConfigBuilderExt;
 use rustls::{
-    ClientConfig, DigitallySignedStruct, SignatureScheme,
-    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        ResolvesClientCert, VerifierBuilderError, WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::CryptoProvider,
     pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    sign::CertifiedKey,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
 
 use crate::{clients, config::TlsConfig};
 
@@ -24,6 +37,8 @@ pub enum Error {
     MissingTlsKey,
     #[error("TLS configuration error: {0}")]
     RustlsError(#[from] rustls::Error),
+    #[error("failed to build TLS server verifier: {0}")]
+    VerifierBuilderError(#[from] VerifierBuilderError),
 }
 
 impl Error {
@@ -101,7 +116,7 @@ pub struct TlsConfigBuilder {
 }
 
 /// A resolved TLS config, built by the `TlsConfigBuilder`.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ResolvedTlsConfig<'a> {
     pub cert: Vec<CertificateDer<'a>>,
     pub key: Option<PrivateKeyDer<'a>>,
@@ -199,41 +214,38 @@ pub fn build_insecure_client_config() -> ClientConfig {
 }
 
 /// Builds a TLS client config based on the provided `TlsConfig`.
+///
+/// The client cert, key and CA certs are reloaded when their files change, see
+/// [`ReloadingClientTls`].
 pub async fn build_client_config(tls_config: &TlsConfig) -> Result<ClientConfig, Error> {
-    // Resolve the TLS config
-    let tls_config = TlsConfigBuilder::from_parts(
+    let builder = TlsConfigBuilder::from_parts(
         tls_config.cert_path.clone().unwrap(),
         tls_config.key_path.clone(),
         tls_config.client_ca_cert_path.clone(),
         tls_config.insecure,
-    )
-    .build()
-    .await?;
-
-    // Add CA certs, if any
-    let client_config_builder = match &tls_config.ca_cert {
-        Some(ca_cert) if !ca_cert.is_empty() => {
-            let mut root = rustls::RootCertStore::empty();
-            ca_cert.clone().iter().for_each(|certs| {
-                let (_, _) = root.add_parsable_certificates([certs.to_owned()]);
-            });
-            ClientConfig::builder().with_root_certificates(root)
-        }
-        _ => ClientConfig::builder()
+    );
+    let insecure = builder.insecure.unwrap_or(false);
+    let client_config_builder = ClientConfig::builder();
+    let provider = client_config_builder.crypto_provider().clone();
+    let reloading_tls = Arc::new(ReloadingClientTls::load(builder, provider).await?);
+    reloading_tls.spawn_reloader();
+
+    // Verify server certs with CA certs, if any
+    let client_config_builder = if reloading_tls.has_ca_cert() {
+        client_config_builder
+            .dangerous()
+            .with_custom_certificate_verifier(reloading_tls.clone())
+    } else {
+        client_config_builder
             .with_native_roots()
-            .unwrap_or(ClientConfig::builder().with_webpki_roots()),
+            .unwrap_or(ClientConfig::builder().with_webpki_roots())
     };
 
-    // Add certs and private key, if any
-    let mut client_config = match &tls_config.key {
-        Some(key) if !&tls_config.cert.is_empty() => {
-            client_config_builder.with_client_auth_cert(tls_config.cert.clone(), key.clone_key())?
-        }
-        _ => client_config_builder.with_no_client_auth(),
-    };
+    // Resolve client cert and private key, if any
+    let mut client_config = client_config_builder.with_client_cert_resolver(reloading_tls);
 
     // Remove verification if insecure
-    if tls_config.insecure {
+    if insecure {
         client_config
             .dangerous()
             .set_certificate_verifier(Arc::new(NoVerifier));
@@ -241,3 +253,344 @@ pub async fn build_client_config(tls_config: &TlsConfig) -> Result<ClientConfig,
 
     Ok(client_config)
 }
+
+/// Interval at which client TLS files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// TLS certificates currently loaded, by path.
+static CERTS: LazyLock<RwLock<BTreeMap<PathBuf, CertInfo>>> = LazyLock::new(Default::default);
+
+/// Info of the first certificate to expire in a PEM file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CertInfo {
+    /// Subject of the certificate
+    pub subject: String,
+    /// Expiry of the certificate as a Unix timestamp in seconds
+    pub not_after: i64,
+    /// Seconds until the certificate expires, negative if expired
+    pub expires_in: i64,
+    /// Whether the certificate is used by the server or by a client service
+    pub required: bool,
+}
+
+impl CertInfo {
+    /// Parses info of the first certificate to expire.
+    pub fn from_certs(certs: &[CertificateDer], required: bool) -> Option<Self> {
+        let cert = certs
+            .iter()
+            .filter_map(|cert| x509_parser::parse_x509_certificate(cert.as_ref()).ok())
+            .map(|(_, cert)| cert)
+            .min_by_key(|cert| cert.validity().not_after.timestamp())?;
+        let not_after = cert.validity().not_after.timestamp();
+        Some(Self {
+            subject: cert.subject().to_string(),
+            not_after,
+            expires_in: not_after - unix_now(),
+            required,
+        })
+    }
+
+    /// Returns `true` if the certificate has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_in <= 0
+    }
+}
+
+/// Returns the current Unix timestamp in seconds.
+fn unix_now() -> i64 {
+    UnixTime::now().as_secs() as i64
+}
+
+/// Records the certificates loaded from a path.
+///
+/// A path recorded as required remains required.
+pub fn record_certs(path: &Path, certs: &[CertificateDer], required: bool) {
+    if let Some(mut cert_info) = CertInfo::from_certs(certs, required) {
+        let mut recorded = CERTS.write().unwrap();
+        if let Some(previous) = recorded.get(path) {
+            cert_info.required |= previous.required;
+        }
+        recorded.insert(path.to_path_buf(), cert_info);
+    }
+}
+
+/// Records the certificates of PEM file contents loaded from a path.
+pub fn record_pem_certs(path: &Path, pem: &[u8], required: bool) {
+    if let Ok(certs) = rustls_pemfile::certs(&mut &pem[..]).collect::<Result<Vec<_>, _>>() {
+        record_certs(path, &certs, required);
+    }
+}
+
+/// Returns the TLS certificates currently loaded, by path.
+pub fn certs() -> BTreeMap<PathBuf, CertInfo> {
+    let now = unix_now();
+    CERTS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(path, cert_info)| {
+            let cert_info = CertInfo {
+                expires_in: cert_info.not_after - now,
+                ..cert_info.clone()
+            };
+            (path.clone(), cert_info)
+        })
+        .collect()
+}
+
+/// Client TLS material that is reloaded when files change.
+///
+/// Used as the client cert resolver and, if CA certs are configured, the server cert
+/// verifier of a client config. The files are checked for changes every [`RELOAD_INTERVAL`].
+/// Reloaded material is used for new handshakes, so established connections are not
+/// dropped. If the new material is invalid, the error is logged and the current material
+/// is kept.
+#[derive(Debug)]
+pub struct ReloadingClientTls {
+    builder: TlsConfigBuilder,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<ClientTlsMaterial>>,
+}
+
+/// Loaded client TLS material.
+#[derive(Debug)]
+struct ClientTlsMaterial {
+    resolved: ResolvedTlsConfig<'static>,
+    client_cert: Option<Arc<CertifiedKey>>,
+    verifier: Option<Arc<WebPkiServerVerifier>>,
+}
+
+impl ClientTlsMaterial {
+    fn new(
+        resolved: ResolvedTlsConfig<'static>,
+        provider: &Arc<CryptoProvider>,
+    ) -> Result<Self, Error> {
+        let client_cert = match &resolved.key {
+            Some(key) if !resolved.cert.is_empty() => Some(Arc::new(CertifiedKey::from_der(
+                resolved.cert.clone(),
+                key.clone_key(),
+                provider,
+            )?)),
+            _ => None,
+        };
+        let verifier = match &resolved.ca_cert {
+            Some(ca_cert) if !ca_cert.is_empty() => {
+                let mut roots = RootCertStore::empty();
+                roots.add_parsable_certificates(ca_cert.iter().cloned());
+                Some(
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()?,
+                )
+            }
+            _ => None,
+        };
+        Ok(Self {
+            resolved,
+            client_cert,
+            verifier,
+        })
+    }
+
+    /// Records the client cert and CA certs.
+    fn record_certs(&self, builder: &TlsConfigBuilder) {
+        record_certs(&builder.cert_path, &self.resolved.cert, true);
+        if let (Some(path), Some(ca_cert)) = (&builder.ca_cert_path, &self.resolved.ca_cert) {
+            record_certs(path, ca_cert, true);
+        }
+    }
+}
+
+impl ReloadingClientTls {
+    /// Loads client TLS material, returning an error if the files are invalid.
+    pub async fn load(
+        builder: TlsConfigBuilder,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, Error> {
+        let resolved = builder.clone().build().await?;
+        let material = ClientTlsMaterial::new(resolved, &provider)?;
+        material.record_certs(&builder);
+        Ok(Self {
+            builder,
+            provider,
+            current: RwLock::new(Arc::new(material)),
+        })
+    }
+
+    /// Returns `true` if CA certs are used to verify server certs.
+    pub fn has_ca_cert(&self) -> bool {
+        self.current().verifier.is_some()
+    }
+
+    fn current(&self) -> Arc<ClientTlsMaterial> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reloads client TLS material if files have changed.
+    /// Returns `true` if new material was swapped in.
+    pub async fn reload(&self) -> Result<bool, Error> {
+        let resolved = self.builder.clone().build().await?;
+        let current = self.current();
+        if current.resolved == resolved {
+            return Ok(false);
+        }
+        let material = ClientTlsMaterial::new(resolved, &self.provider)?;
+        // Client cert and CA certs cannot be removed by a reload
+        if current.client_cert.is_some() && material.client_cert.is_none() {
+            return Err(Error::FailedReadCerts(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no certificates found",
+            )));
+        }
+        if current.verifier.is_some() && material.verifier.is_none() {
+            return Err(Error::FailedReadCaCerts(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no certificates found",
+            )));
+        }
+        material.record_certs(&self.builder);
+        *self.current.write().unwrap() = Arc::new(material);
+        Ok(true)
+    }
+
+    /// Spawns a task that periodically reloads client TLS material.
+    /// The task ends when the client config using it is dropped.
+    fn spawn_reloader(self: &Arc<Self>) {
+        let this = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(this) = this.upgrade() else {
+                    break;
+                };
+                let cert_path = this.builder.cert_path.display();
+                match this.reload().await {
+                    Ok(true) => info!(%cert_path, "client TLS certificates reloaded"),
+                    Ok(false) => (),
+                    Err(error) => error!(
+                        %cert_path,
+                        %error,
+                        "error reloading client TLS certificates, keeping current certificates"
+                    ),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesClientCert for ReloadingClientTls {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        self.current().client_cert.clone()
+    }
+
+    fn has_certs(&self) -> bool {
+        self.current().client_cert.is_some()
+    }
+}
+
+impl ServerCertVerifier for ReloadingClientTls {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        intermediates: &[CertificateDer],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verifier()?.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier()?.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier()?.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl ReloadingClientTls {
+    /// Returns the current server cert verifier.
+    fn verifier(&self) -> Result<Arc<WebPkiServerVerifier>, rustls::Error> {
+        self.current()
+            .verifier
+            .clone()
+            .ok_or_else(|| rustls::Error::General("no CA certificates loaded".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reloading_client_tls() -> Result<(), Error> {
+        let dir =
+            std::env::temp_dir().join(format!("orchestrator-client-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("tls.crt");
+        let key_path = dir.join("tls.key");
+        let cert = std::fs::read("tests/resources/localhost.crt").unwrap();
+        let key = std::fs::read("tests/resources/localhost.key").unwrap();
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let provider = ClientConfig::builder().crypto_provider().clone();
+        let builder = TlsConfigBuilder::from_parts(
+            cert_path.clone(),
+            Some(key_path.clone()),
+            Some(cert_path.clone()),
+            None,
+        );
+        let tls = ReloadingClientTls::load(builder, provider).await?;
+        assert!(tls.has_ca_cert());
+        assert!(tls.has_certs());
+        assert!(certs()[&cert_path].required);
+        let initial = tls.current();
+        assert!(!tls.reload().await?);
+
+        // Invalid material is not swapped in
+        std::fs::write(&key_path, "invalid").unwrap();
+        assert!(tls.reload().await.is_err());
+        assert!(Arc::ptr_eq(&tls.current(), &initial));
+
+        // Valid material is swapped in
+        std::fs::write(&key_path, [key.as_slice(), b"\n"].concat()).unwrap();
+        std::fs::write(&cert_path, [cert.as_slice(), b"\n"].concat()).unwrap();
+        assert!(tls.reload().await?);
+        assert!(!Arc::ptr_eq(&tls.current(), &initial));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
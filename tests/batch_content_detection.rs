@@ -0,0 +1,268 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+
+use std::collections::HashMap;
+
+use common::{
+    detectors::{DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC, TEXT_CONTENTS_DETECTOR_ENDPOINT},
+    errors::DetectorError,
+    orchestrator::{
+        ORCHESTRATOR_BATCH_CONTENT_DETECTION_ENDPOINT, ORCHESTRATOR_CONFIG_FILE_PATH,
+        TestOrchestratorServer, json_lines_stream,
+    },
+};
+use fms_guardrails_orchestr8::{
+    clients::detector::{ContentAnalysisRequest, ContentAnalysisResponse},
+    models::{
+        BatchContentDetectionHttpRequest, BatchContentDetectionItem, BatchContentDetectionResult,
+        BatchItemError, DetectorParams, Metadata,
+    },
+};
+use hyper::StatusCode;
+use mocktail::prelude::*;
+use test_log::test;
+use tracing::debug;
+
+pub mod common;
+
+fn detector_mocks(detector_name: &str) -> MockSet {
+    let mut mocks = MockSet::new();
+    mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hi <there>".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([vec![ContentAnalysisResponse {
+            start: 3,
+            end: 10,
+            text: "<there>".into(),
+            detection: "has_angle_brackets".into(),
+            detection_type: "angle_brackets".into(),
+            detector_id: Some(detector_name.into()),
+            score: 1.0,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]]);
+    });
+    mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["No detections".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([Vec::<ContentAnalysisResponse>::new()]);
+    });
+    mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["This should return a 500".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json(DetectorError {
+            code: 500,
+            message: "Internal error on detector call.".into(),
+        })
+        .internal_server_error();
+    });
+    mocks
+}
+
+fn item(id: &str, content: &str) -> BatchContentDetectionItem {
+    BatchContentDetectionItem {
+        id: id.into(),
+        content: content.into(),
+        detectors: None,
+    }
+}
+
+fn parse_results(body: &str) -> Result<Vec<BatchContentDetectionResult>, serde_json::Error> {
+    body.lines().map(serde_json::from_str).collect()
+}
+
+fn expected_results(detector_name: &str) -> Vec<BatchContentDetectionResult> {
+    vec![
+        BatchContentDetectionResult {
+            index: 0,
+            id: Some("a".into()),
+            detections: Some(vec![ContentAnalysisResponse {
+                start: 3,
+                end: 10,
+                text: "<there>".into(),
+                detection: "has_angle_brackets".into(),
+                detection_type: "angle_brackets".into(),
+                detector_id: Some(detector_name.into()),
+                score: 1.0,
+                evidence: None,
+                metadata: Metadata::new(),
+            }]),
+            error: None,
+        },
+        BatchContentDetectionResult {
+            index: 1,
+            id: Some("b".into()),
+            detections: None,
+            error: Some(BatchItemError {
+                code: 500,
+                details: "unexpected error occurred while processing request".into(),
+            }),
+        },
+        BatchContentDetectionResult {
+            index: 2,
+            id: Some("c".into()),
+            detections: Some(vec![]),
+            error: None,
+        },
+        BatchContentDetectionResult {
+            index: 3,
+            id: Some("d".into()),
+            detections: None,
+            error: Some(BatchItemError {
+                code: 422,
+                details: "`content` cannot be empty".into(),
+            }),
+        },
+    ]
+}
+
+/// Asserts batch detection with a JSON request.
+#[test(tokio::test)]
+async fn json_request() -> Result<(), anyhow::Error> {
+    let detector_name = DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC;
+
+    // Start orchestrator server and its dependencies
+    let mock_detector_server =
+        MockServer::new_http(detector_name).with_mocks(detector_mocks(detector_name));
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_detector_server])
+        .build()
+        .await?;
+
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_BATCH_CONTENT_DETECTION_ENDPOINT)
+        .json(&BatchContentDetectionHttpRequest {
+            detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
+            items: vec![
+                item("a", "Hi <there>"),
+                item("b", "This should return a 500"),
+                item("c", "No detections"),
+                item("d", ""),
+            ],
+        })
+        .send()
+        .await?;
+    debug!(?response);
+
+    // Results are returned in input order with per-item errors
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = parse_results(&response.text().await?)?;
+    assert_eq!(results, expected_results(detector_name));
+
+    Ok(())
+}
+
+/// Asserts batch detection with a ND-JSON request.
+#[test(tokio::test)]
+async fn ndjson_request() -> Result<(), anyhow::Error> {
+    let detector_name = DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC;
+
+    // Start orchestrator server and its dependencies
+    let mock_detector_server =
+        MockServer::new_http(detector_name).with_mocks(detector_mocks(detector_name));
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_detector_server])
+        .build()
+        .await?;
+
+    let first = BatchContentDetectionItem {
+        detectors: Some(HashMap::from([(
+            detector_name.into(),
+            DetectorParams::new(),
+        )])),
+        ..item("a", "Hi <there>")
+    };
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_BATCH_CONTENT_DETECTION_ENDPOINT)
+        .header("content-type", "application/x-ndjson")
+        .body(reqwest::Body::wrap_stream(json_lines_stream([
+            first,
+            item("b", "This should return a 500"),
+            item("c", "No detections"),
+            item("d", ""),
+        ])))
+        .send()
+        .await?;
+    debug!(?response);
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = parse_results(&response.text().await?)?;
+    assert_eq!(results, expected_results(detector_name));
+
+    // Detectors are required on the first item
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_BATCH_CONTENT_DETECTION_ENDPOINT)
+        .header("content-type", "application/x-ndjson")
+        .body(reqwest::Body::wrap_stream(json_lines_stream([item(
+            "a",
+            "Hi <there>",
+        )])))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Later items with other detectors are rejected
+    let detectors = HashMap::from([(detector_name.into(), DetectorParams::new())]);
+    let items = [
+        BatchContentDetectionItem {
+            detectors: Some(detectors.clone()),
+            ..item("a", "Hi <there>")
+        },
+        BatchContentDetectionItem {
+            detectors: Some(HashMap::new()),
+            ..item("b", "No detections")
+        },
+        BatchContentDetectionItem {
+            detectors: Some(detectors),
+            ..item("c", "No detections")
+        },
+    ];
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_BATCH_CONTENT_DETECTION_ENDPOINT)
+        .header("content-type", "application/x-ndjson")
+        .body(reqwest::Body::wrap_stream(json_lines_stream(items)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = parse_results(&response.text().await?)?;
+    assert_eq!(results.len(), 3);
+    assert!(results[0].error.is_none());
+    assert_eq!(
+        results[1].error,
+        Some(BatchItemError {
+            code: 422,
+            details: "`detectors` must match the detectors of the first item".into(),
+        })
+    );
+    assert_eq!(results[2].detections, Some(vec![]));
+
+    Ok(())
+}
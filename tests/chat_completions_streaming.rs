@@ -2,7 +2,7 @@ pub mod common;
 use common::orchestrator::*;
 use fms_guardrails_orchestr8::{
     clients::{
-        detector::{ContentAnalysisRequest, ContentAnalysisResponse},
+        detector::{ContentAnalysisRequest, ContentAnalysisResponse, GenerationDetectionRequest},
         openai::{
             ChatCompletionChunk, ChatCompletionChunkChoice, ChatCompletionDelta,
             ChatCompletionLogprob, ChatCompletionLogprobs, CompletionDetections,
@@ -10,7 +10,10 @@ use fms_guardrails_orchestr8::{
             OpenAiErrorMessage, Role, Usage,
         },
     },
-    models::DetectorParams,
+    models::{
+        DEFAULT_REFUSAL_MESSAGE, DetectionResult, DetectorParams, GUARDRAILS_STOP_FINISH_REASON,
+        Metadata,
+    },
     pb::{
         caikit::runtime::chunkers::{
             BidiStreamingChunkerTokenizationTaskRequest, ChunkerTokenizationTaskRequest,
@@ -26,7 +29,10 @@ use tracing::debug;
 
 use crate::common::{
     chunker::{CHUNKER_MODEL_ID_HEADER_NAME, CHUNKER_STREAMING_ENDPOINT, CHUNKER_UNARY_ENDPOINT},
-    detectors::{PII_DETECTOR_SENTENCE, PII_DETECTOR_WHOLE_DOC, TEXT_CONTENTS_DETECTOR_ENDPOINT},
+    detectors::{
+        ANSWER_RELEVANCE_DETECTOR, DETECTION_ON_GENERATION_DETECTOR_ENDPOINT,
+        PII_DETECTOR_SENTENCE, PII_DETECTOR_WHOLE_DOC, TEXT_CONTENTS_DETECTOR_ENDPOINT,
+    },
     openai::CHAT_COMPLETIONS_ENDPOINT,
     sse,
 };
@@ -826,6 +832,8 @@ async fn output_detectors() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -854,6 +862,8 @@ async fn output_detectors() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 5,
                     end: 19,
@@ -891,6 +901,8 @@ async fn output_detectors() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 4,
                     end: 18,
@@ -1453,6 +1465,8 @@ async fn output_detectors_with_logprobs() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -1490,6 +1504,8 @@ async fn output_detectors_with_logprobs() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 5,
                     end: 19,
@@ -1536,6 +1552,8 @@ async fn output_detectors_with_logprobs() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 4,
                     end: 18,
@@ -1972,6 +1990,8 @@ async fn output_detectors_with_usage() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -2000,6 +2020,8 @@ async fn output_detectors_with_usage() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 5,
                     end: 19,
@@ -2037,6 +2059,8 @@ async fn output_detectors_with_usage() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 4,
                     end: 18,
@@ -2559,6 +2583,8 @@ async fn output_detectors_with_continuous_usage_stats() -> Result<(), anyhow::Er
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -2598,6 +2624,8 @@ async fn output_detectors_with_continuous_usage_stats() -> Result<(), anyhow::Er
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 5,
                     end: 19,
@@ -2646,6 +2674,8 @@ async fn output_detectors_with_continuous_usage_stats() -> Result<(), anyhow::Er
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 4,
                     end: 18,
@@ -3404,6 +3434,8 @@ async fn output_detectors_n2() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -3446,6 +3478,8 @@ async fn output_detectors_n2() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 1,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -3740,6 +3774,8 @@ async fn whole_doc_output_detectors() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![
                     ContentAnalysisResponse {
                         start: 37,
@@ -3770,6 +3806,168 @@ async fn whole_doc_output_detectors() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn text_generation_output_detectors() -> Result<(), anyhow::Error> {
+    let mut openai_server = MockServer::new_http("openai");
+    openai_server.mock(|when, then| {
+        when.post()
+            .path(CHAT_COMPLETIONS_ENDPOINT)
+            .json(json!({
+                "stream": true,
+                "model": "test-0B",
+                "messages": [
+                    Message { role: Role::User, content: Some(Content::Text("What is the capital of France?".into())), ..Default::default()},
+                ]
+            })
+        );
+        then.text_stream(sse([
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        role: Some(Role::Assistant),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("Paris".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some(" is the capital.".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    finish_reason: Some("stop".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ]));
+    });
+
+    let mut answer_relevance_detector_server = MockServer::new_http(ANSWER_RELEVANCE_DETECTOR);
+    answer_relevance_detector_server.mock(|when, then| {
+        when.post()
+            .path(DETECTION_ON_GENERATION_DETECTOR_ENDPOINT)
+            .json(GenerationDetectionRequest {
+                prompt: "What is the capital of France?".into(),
+                generated_text: "Paris is the capital.".into(),
+                detector_params: DetectorParams::default(),
+            });
+        then.json([DetectionResult {
+            detection_type: "relevance".into(),
+            detection: "is_relevant".into(),
+            detector_id: Some(ANSWER_RELEVANCE_DETECTOR.into()),
+            score: 0.9,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]);
+    });
+
+    let test_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .openai_server(&openai_server)
+        .detector_servers([&answer_relevance_detector_server])
+        .build()
+        .await?;
+
+    let response = test_server
+        .post(ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT)
+        .json(&json!({
+            "stream": true,
+            "model": "test-0B",
+            "detectors": {
+                "input": {},
+                "output": {
+                    ANSWER_RELEVANCE_DETECTOR: {},
+                },
+            },
+            "messages": [
+                Message { role: Role::User, content: Some(Content::Text("What is the capital of France?".into())), ..Default::default()},
+            ],
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<ChatCompletionChunk> = SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    // Validate length
+    assert_eq!(messages.len(), 5, "unexpected number of messages");
+
+    // Validate finish reason message
+    assert_eq!(
+        messages[3].choices[0].finish_reason,
+        Some("stop".into()),
+        "missing finish reason message"
+    );
+
+    // Validate text generation detections message, spanning the whole output
+    let last = &messages[4];
+    assert_eq!(
+        last.detections,
+        Some(CompletionDetections {
+            input: vec![],
+            output: vec![CompletionOutputDetections {
+                choice_index: 0,
+                path: None,
+                offset: None,
+                results: vec![ContentAnalysisResponse {
+                    start: 0,
+                    end: 21,
+                    text: "Paris is the capital.".into(),
+                    detection: "is_relevant".into(),
+                    detection_type: "relevance".into(),
+                    detector_id: Some(ANSWER_RELEVANCE_DETECTOR.into()),
+                    score: 0.9,
+                    ..Default::default()
+                }],
+            }],
+        }),
+        "unexpected text generation detections message"
+    );
+
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn output_detectors_and_whole_doc_output_detectors() -> Result<(), anyhow::Error> {
     let mut openai_server = MockServer::new_http("openai");
@@ -4201,6 +4399,8 @@ async fn output_detectors_and_whole_doc_output_detectors() -> Result<(), anyhow:
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![],
             }],
         }),
@@ -4229,6 +4429,8 @@ async fn output_detectors_and_whole_doc_output_detectors() -> Result<(), anyhow:
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 5,
                     end: 19,
@@ -4266,6 +4468,8 @@ async fn output_detectors_and_whole_doc_output_detectors() -> Result<(), anyhow:
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![ContentAnalysisResponse {
                     start: 4,
                     end: 18,
@@ -4296,6 +4500,8 @@ async fn output_detectors_and_whole_doc_output_detectors() -> Result<(), anyhow:
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 0,
+                path: None,
+                offset: None,
                 results: vec![
                     ContentAnalysisResponse {
                         start: 37,
@@ -4326,6 +4532,1055 @@ async fn output_detectors_and_whole_doc_output_detectors() -> Result<(), anyhow:
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn stop_on_output_detectors() -> Result<(), anyhow::Error> {
+    let mut openai_server = MockServer::new_http("openai");
+    openai_server.mock(|when, then| {
+        when.post()
+            .path(CHAT_COMPLETIONS_ENDPOINT)
+            .json(json!({
+                "stream": true,
+                "model": "test-0B",
+                "messages": [
+                    Message { role: Role::User, content: Some(Content::Text("Can you generate 2 random phone numbers?".into())), ..Default::default()},
+                ]
+            })
+        );
+        then.text_stream(sse([
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        role: Some(Role::Assistant),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("Here are 2 random phone numbers:".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("\\n\\n1. (503) 272-8192".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("\\n2. (617) 985-3519.".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    finish_reason: Some("stop".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ]));
+    });
+
+    let mut sentence_chunker_server = MockServer::new_grpc("sentence_chunker");
+    sentence_chunker_server.mock(|when, then| {
+        when.post()
+            .path(CHUNKER_STREAMING_ENDPOINT)
+            .header(CHUNKER_MODEL_ID_HEADER_NAME, "sentence_chunker")
+            .pb_stream(vec![
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "Here are 2 random phone numbers:".into(),
+                    input_index_stream: 1,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "\n\n1. (503) 272-8192".into(),
+                    input_index_stream: 2,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "\n2. (617) 985-3519.".into(),
+                    input_index_stream: 3,
+                },
+            ]);
+        then.pb_stream(vec![
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: 32,
+                    text: "Here are 2 random phone numbers:".into(),
+                }],
+                token_count: 0,
+                processed_index: 32,
+                start_index: 0,
+                input_start_index: 1,
+                input_end_index: 1,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 32,
+                    end: 51,
+                    text: "\n\n1. (503) 272-8192".into(),
+                }],
+                token_count: 0,
+                processed_index: 51,
+                start_index: 32,
+                input_start_index: 2,
+                input_end_index: 2,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 51,
+                    end: 70,
+                    text: "\n2. (617) 985-3519.".into(),
+                }],
+                token_count: 0,
+                processed_index: 70,
+                start_index: 51,
+                input_start_index: 3,
+                input_end_index: 3,
+            },
+        ]);
+    });
+
+    let mut pii_detector_sentence_server = MockServer::new_http("pii_detector_sentence");
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Here are 2 random phone numbers:".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["\n\n1. (503) 272-8192".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([
+        [
+            {
+                "start": 5,
+                "end": 19,
+                "detection": "PhoneNumber",
+                "detection_type": "pii",
+                "score": 0.8,
+                "text": "(503) 272-8192",
+                "evidences": []
+            }
+        ]]));
+    });
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["\n2. (617) 985-3519.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+
+    let test_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .openai_server(&openai_server)
+        .chunker_servers([&sentence_chunker_server])
+        .detector_servers([&pii_detector_sentence_server])
+        .build()
+        .await?;
+
+    let response = test_server
+        .post(ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT)
+        .json(&json!({
+            "stream": true,
+            "model": "test-0B",
+            "detectors": {
+                "input": {},
+                "output": {
+                    "pii_detector_sentence": {
+                        "stop_on": 0.5,
+                    },
+                },
+            },
+            "messages": [
+                Message { role: Role::User, content: Some(Content::Text("Can you generate 2 random phone numbers?".into())), ..Default::default()},
+            ],
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<ChatCompletionChunk> = SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    // Validate length, the stream is stopped at the first detection triggering the stop_on policy
+    assert_eq!(messages.len(), 2, "unexpected number of messages");
+
+    // Validate passing chunk is released
+    assert_eq!(
+        messages[0].choices[0].delta.content,
+        Some("Here are 2 random phone numbers:".into())
+    );
+    assert_eq!(
+        messages[0].detections,
+        Some(CompletionDetections {
+            input: vec![],
+            output: vec![CompletionOutputDetections {
+                choice_index: 0,
+                path: None,
+                offset: None,
+                results: vec![],
+            }],
+        })
+    );
+
+    // Validate triggering chunk is sent with guardrails finish reason
+    let choice = &messages[1].choices[0];
+    assert_eq!(choice.delta.content, Some("\n\n1. (503) 272-8192".into()));
+    assert_eq!(
+        choice.finish_reason,
+        Some(GUARDRAILS_STOP_FINISH_REASON.into())
+    );
+    assert_eq!(
+        messages[1].detections,
+        Some(CompletionDetections {
+            input: vec![],
+            output: vec![CompletionOutputDetections {
+                choice_index: 0,
+                path: None,
+                offset: None,
+                results: vec![ContentAnalysisResponse {
+                    start: 5,
+                    end: 19,
+                    text: "(503) 272-8192".into(),
+                    detection: "PhoneNumber".into(),
+                    detection_type: "pii".into(),
+                    detector_id: Some("pii_detector_sentence".into()),
+                    score: 0.8,
+                    ..Default::default()
+                }],
+            }],
+        })
+    );
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn gated_output_detectors() -> Result<(), anyhow::Error> {
+    let mut openai_server = MockServer::new_http("openai");
+    openai_server.mock(|when, then| {
+        when.post()
+            .path(CHAT_COMPLETIONS_ENDPOINT)
+            .json(json!({
+                "stream": true,
+                "model": "test-0B",
+                "messages": [
+                    Message { role: Role::User, content: Some(Content::Text("Can you generate 2 random phone numbers?".into())), ..Default::default()},
+                ]
+            })
+        );
+        then.text_stream(sse([
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        role: Some(Role::Assistant),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("Here are 2 random phone numbers:".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("\\n\\n1. (503) 272-8192".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("\\n2. (617) 985-3519.".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    finish_reason: Some("stop".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ]));
+    });
+
+    let mut sentence_chunker_server = MockServer::new_grpc("sentence_chunker");
+    sentence_chunker_server.mock(|when, then| {
+        when.post()
+            .path(CHUNKER_STREAMING_ENDPOINT)
+            .header(CHUNKER_MODEL_ID_HEADER_NAME, "sentence_chunker")
+            .pb_stream(vec![
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "Here are 2 random phone numbers:".into(),
+                    input_index_stream: 1,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "\n\n1. (503) 272-8192".into(),
+                    input_index_stream: 2,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "\n2. (617) 985-3519.".into(),
+                    input_index_stream: 3,
+                },
+            ]);
+        then.pb_stream(vec![
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: 32,
+                    text: "Here are 2 random phone numbers:".into(),
+                }],
+                token_count: 0,
+                processed_index: 32,
+                start_index: 0,
+                input_start_index: 1,
+                input_end_index: 1,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 32,
+                    end: 51,
+                    text: "\n\n1. (503) 272-8192".into(),
+                }],
+                token_count: 0,
+                processed_index: 51,
+                start_index: 32,
+                input_start_index: 2,
+                input_end_index: 2,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 51,
+                    end: 70,
+                    text: "\n2. (617) 985-3519.".into(),
+                }],
+                token_count: 0,
+                processed_index: 70,
+                start_index: 51,
+                input_start_index: 3,
+                input_end_index: 3,
+            },
+        ]);
+    });
+
+    let mut pii_detector_sentence_server = MockServer::new_http("pii_detector_sentence");
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Here are 2 random phone numbers:".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["\n\n1. (503) 272-8192".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([
+        [
+            {
+                "start": 5,
+                "end": 19,
+                "detection": "PhoneNumber",
+                "detection_type": "pii",
+                "score": 0.8,
+                "text": "(503) 272-8192",
+                "evidences": []
+            }
+        ]]));
+    });
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["\n2. (617) 985-3519.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+
+    let test_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .openai_server(&openai_server)
+        .chunker_servers([&sentence_chunker_server])
+        .detector_servers([&pii_detector_sentence_server])
+        .build()
+        .await?;
+
+    let response = test_server
+        .post(ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT)
+        .json(&json!({
+            "stream": true,
+            "stream_mode": "gated",
+            "model": "test-0B",
+            "detectors": {
+                "input": {},
+                "output": {
+                    "pii_detector_sentence": {},
+                },
+            },
+            "messages": [
+                Message { role: Role::User, content: Some(Content::Text("Can you generate 2 random phone numbers?".into())), ..Default::default()},
+            ],
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<ChatCompletionChunk> = SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    // Validate length, the stream is terminated at the first chunk with detections
+    assert_eq!(messages.len(), 2, "unexpected number of messages");
+
+    // Validate passing chunk is released
+    assert_eq!(
+        messages[0].choices[0].delta.content,
+        Some("Here are 2 random phone numbers:".into())
+    );
+    assert_eq!(
+        messages[0].detections,
+        Some(CompletionDetections {
+            input: vec![],
+            output: vec![CompletionOutputDetections {
+                choice_index: 0,
+                path: None,
+                offset: None,
+                results: vec![],
+            }],
+        })
+    );
+
+    // Validate failing chunk is replaced with refusal message
+    let choice = &messages[1].choices[0];
+    assert_eq!(choice.delta.content, None);
+    assert_eq!(choice.delta.refusal, Some(DEFAULT_REFUSAL_MESSAGE.into()));
+    assert_eq!(choice.finish_reason, Some("content_filter".into()));
+    assert_eq!(
+        messages[1].detections,
+        Some(CompletionDetections {
+            input: vec![],
+            output: vec![CompletionOutputDetections {
+                choice_index: 0,
+                path: None,
+                offset: None,
+                results: vec![ContentAnalysisResponse {
+                    start: 5,
+                    end: 19,
+                    text: "(503) 272-8192".into(),
+                    detection: "PhoneNumber".into(),
+                    detection_type: "pii".into(),
+                    detector_id: Some("pii_detector_sentence".into()),
+                    score: 0.8,
+                    ..Default::default()
+                }],
+            }],
+        })
+    );
+
+    // Whole doc detectors are not supported in gated mode
+    let response = test_server
+        .post(ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT)
+        .json(&json!({
+            "stream": true,
+            "stream_mode": "gated",
+            "model": "test-0B",
+            "detectors": {
+                "input": {},
+                "output": {
+                    PII_DETECTOR_WHOLE_DOC: {},
+                },
+            },
+            "messages": [
+                Message { role: Role::User, content: Some(Content::Text("Can you generate 2 random phone numbers?".into())), ..Default::default()},
+            ],
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<ChatCompletionChunk> = SseStream::new(response.bytes_stream());
+    let messages = sse_stream.collect::<Vec<_>>().await;
+    assert_eq!(messages.len(), 1, "unexpected number of messages");
+    assert!(
+        messages[0]
+            .as_ref()
+            .is_err_and(|e| e.code == http::StatusCode::UNPROCESSABLE_ENTITY)
+    );
+
+    Ok(())
+}
+
+// Validates that in gated mode with n > 1, a chunk with detections terminates only its choice
+#[test(tokio::test)]
+async fn gated_output_detectors_n2() -> Result<(), anyhow::Error> {
+    let mut openai_server = MockServer::new_http("openai");
+    openai_server.mock(|when, then| {
+        when.post()
+            .path(CHAT_COMPLETIONS_ENDPOINT)
+            .json(json!({
+                "stream": true,
+                "model": "test-0B",
+                "messages": [
+                    Message { role: Role::User, content: Some(Content::Text("Say something.".into())), ..Default::default()},
+                ],
+                "n": 2,
+            })
+        );
+        then.text_stream(sse([
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        role: Some(Role::Assistant),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 1,
+                    delta: ChatCompletionDelta {
+                        role: Some(Role::Assistant),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        content: Some("Call (503) 272-8192.".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 1,
+                    delta: ChatCompletionDelta {
+                        content: Some("Hello there.".into()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    finish_reason: Some("stop".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 1,
+                    finish_reason: Some("stop".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ]));
+    });
+
+    let mut sentence_chunker_server = MockServer::new_grpc("sentence_chunker");
+    for (text, index) in [("Call (503) 272-8192.", 2), ("Hello there.", 3)] {
+        sentence_chunker_server.mock(|when, then| {
+            when.post()
+                .path(CHUNKER_STREAMING_ENDPOINT)
+                .header(CHUNKER_MODEL_ID_HEADER_NAME, "sentence_chunker")
+                .pb_stream(vec![BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: text.into(),
+                    input_index_stream: index,
+                }]);
+            then.pb_stream(vec![ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: text.chars().count() as i64,
+                    text: text.into(),
+                }],
+                token_count: 0,
+                processed_index: text.chars().count() as i64,
+                start_index: 0,
+                input_start_index: index,
+                input_end_index: index,
+            }]);
+        });
+    }
+
+    let mut pii_detector_sentence_server = MockServer::new_http("pii_detector_sentence");
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Call (503) 272-8192.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([
+        [
+            {
+                "start": 5,
+                "end": 19,
+                "detection": "PhoneNumber",
+                "detection_type": "pii",
+                "score": 0.8,
+                "text": "(503) 272-8192",
+                "evidences": []
+            }
+        ]]));
+    });
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hello there.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+
+    let test_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .openai_server(&openai_server)
+        .chunker_servers([&sentence_chunker_server])
+        .detector_servers([&pii_detector_sentence_server])
+        .build()
+        .await?;
+
+    let response = test_server
+        .post(ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT)
+        .json(&json!({
+            "stream": true,
+            "stream_mode": "gated",
+            "model": "test-0B",
+            "detectors": {
+                "input": {},
+                "output": {
+                    "pii_detector_sentence": {},
+                },
+            },
+            "messages": [
+                Message { role: Role::User, content: Some(Content::Text("Say something.".into())), ..Default::default()},
+            ],
+            "n": 2,
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<ChatCompletionChunk> = SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    // Choices are processed independently, so only the order within a choice is deterministic
+    let choice_messages = |index: u32| {
+        messages
+            .iter()
+            .filter(|message| message.choices[0].index == index)
+            .collect::<Vec<_>>()
+    };
+
+    // Validate choice 0 is refused and terminated
+    let choice_0 = choice_messages(0);
+    assert_eq!(
+        choice_0.len(),
+        1,
+        "unexpected number of messages for choice 0"
+    );
+    let choice = &choice_0[0].choices[0];
+    assert_eq!(choice.delta.content, None);
+    assert_eq!(choice.delta.refusal, Some(DEFAULT_REFUSAL_MESSAGE.into()));
+    assert_eq!(choice.finish_reason, Some("content_filter".into()));
+
+    // Validate choice 1 is released and finished
+    let choice_1 = choice_messages(1);
+    assert_eq!(
+        choice_1.len(),
+        2,
+        "unexpected number of messages for choice 1"
+    );
+    assert_eq!(
+        choice_1[0].choices[0].delta.content,
+        Some("Hello there.".into())
+    );
+    assert_eq!(choice_1[1].choices[0].finish_reason, Some("stop".into()));
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn stop_on_output_detectors_n2_with_whole_doc_and_usage() -> Result<(), anyhow::Error> {
+    let chunk =
+        |index: u32, delta: ChatCompletionDelta, finish_reason: Option<&str>| ChatCompletionChunk {
+            id: "chatcmpl-test".into(),
+            object: "chat.completion.chunk".into(),
+            created: 1749227854,
+            model: "test-0B".into(),
+            choices: vec![ChatCompletionChunkChoice {
+                index,
+                delta,
+                finish_reason: finish_reason.map(Into::into),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+    let role = || ChatCompletionDelta {
+        role: Some(Role::Assistant),
+        ..Default::default()
+    };
+    let content = |text: &str| ChatCompletionDelta {
+        content: Some(text.into()),
+        ..Default::default()
+    };
+    let usage = Usage {
+        prompt_tokens: 4,
+        total_tokens: 20,
+        completion_tokens: 16,
+        ..Default::default()
+    };
+
+    let mut openai_server = MockServer::new_http("openai");
+    openai_server.mock(|when, then| {
+        when.post()
+            .path(CHAT_COMPLETIONS_ENDPOINT)
+            .json(json!({
+                "stream": true,
+                "model": "test-0B",
+                "messages": [
+                    Message { role: Role::User, content: Some(Content::Text("Say something.".into())), ..Default::default()},
+                ],
+                "n": 2,
+                "stream_options": {
+                    "include_usage": true
+                }
+            })
+        );
+        then.text_stream(sse([
+            chunk(0, role(), None),
+            chunk(1, role(), None),
+            chunk(0, content("Call (503) 272-8192."), None),
+            chunk(1, content("Hello there."), None),
+            chunk(0, ChatCompletionDelta::default(), Some("stop")),
+            chunk(1, ChatCompletionDelta::default(), Some("stop")),
+            ChatCompletionChunk {
+                id: "chatcmpl-test".into(),
+                object: "chat.completion.chunk".into(),
+                created: 1749227854,
+                model: "test-0B".into(),
+                usage: Some(usage.clone()),
+                ..Default::default()
+            },
+        ]));
+    });
+
+    let mut sentence_chunker_server = MockServer::new_grpc("sentence_chunker");
+    for (text, index) in [("Call (503) 272-8192.", 2), ("Hello there.", 3)] {
+        sentence_chunker_server.mock(|when, then| {
+            when.post()
+                .path(CHUNKER_STREAMING_ENDPOINT)
+                .header(CHUNKER_MODEL_ID_HEADER_NAME, "sentence_chunker")
+                .pb_stream(vec![BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: text.into(),
+                    input_index_stream: index,
+                }]);
+            then.pb_stream(vec![ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: text.chars().count() as i64,
+                    text: text.into(),
+                }],
+                token_count: 0,
+                processed_index: text.chars().count() as i64,
+                start_index: 0,
+                input_start_index: index,
+                input_end_index: index,
+            }]);
+        });
+    }
+
+    let mut pii_detector_sentence_server = MockServer::new_http("pii_detector_sentence");
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Call (503) 272-8192.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([
+        [
+            {
+                "start": 5,
+                "end": 19,
+                "detection": "PhoneNumber",
+                "detection_type": "pii",
+                "score": 0.8,
+                "text": "(503) 272-8192",
+                "evidences": []
+            }
+        ]]));
+    });
+    pii_detector_sentence_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_SENTENCE)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hello there.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+
+    // Only the choice that was not stopped is sent to whole doc detectors
+    let mut pii_detector_whole_doc_server = MockServer::new_http("pii_detector_whole_doc");
+    pii_detector_whole_doc_server.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .header("detector-id", PII_DETECTOR_WHOLE_DOC)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hello there.".into()],
+                detector_params: DetectorParams::default(),
+            });
+        then.json(json!([[]]));
+    });
+
+    let test_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .openai_server(&openai_server)
+        .chunker_servers([&sentence_chunker_server])
+        .detector_servers([
+            &pii_detector_sentence_server,
+            &pii_detector_whole_doc_server,
+        ])
+        .build()
+        .await?;
+
+    let response = test_server
+        .post(ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT)
+        .json(&json!({
+            "stream": true,
+            "model": "test-0B",
+            "detectors": {
+                "input": {},
+                "output": {
+                    "pii_detector_sentence": {
+                        "stop_on": 0.5,
+                    },
+                    "pii_detector_whole_doc": {},
+                },
+            },
+            "messages": [
+                Message { role: Role::User, content: Some(Content::Text("Say something.".into())), ..Default::default()},
+            ],
+            "n": 2,
+            "stream_options": {
+                "include_usage": true
+            }
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<ChatCompletionChunk> = SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    // Choices are processed independently, so only the order within a choice is deterministic
+    let choice_messages = |index: u32| {
+        messages
+            .iter()
+            .filter(|message| {
+                message
+                    .choices
+                    .first()
+                    .is_some_and(|choice| choice.index == index)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Validate choice 0 is stopped
+    let choice_0 = choice_messages(0);
+    assert_eq!(
+        choice_0.len(),
+        1,
+        "unexpected number of messages for choice 0"
+    );
+    assert_eq!(
+        choice_0[0].choices[0].finish_reason,
+        Some(GUARDRAILS_STOP_FINISH_REASON.into())
+    );
+
+    // Validate choice 1 is released and finished
+    let choice_1 = choice_messages(1);
+    assert_eq!(
+        choice_1.len(),
+        2,
+        "unexpected number of messages for choice 1"
+    );
+    assert_eq!(
+        choice_1[0].choices[0].delta.content,
+        Some("Hello there.".into())
+    );
+    assert_eq!(choice_1[1].choices[0].finish_reason, Some("stop".into()));
+
+    // Validate final message with usage and whole doc detections of choice 1 only
+    let last = messages.last().unwrap();
+    assert!(last.choices.is_empty());
+    assert_eq!(last.usage, Some(usage));
+    assert_eq!(
+        last.detections,
+        Some(CompletionDetections {
+            input: vec![],
+            output: vec![CompletionOutputDetections {
+                choice_index: 1,
+                path: None,
+                offset: None,
+                results: vec![],
+            }],
+        })
+    );
+
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn openai_bad_request_error() -> Result<(), anyhow::Error> {
     let mut openai_server = MockServer::new_http("openai");
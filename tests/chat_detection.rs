@@ -108,6 +108,7 @@ async fn no_detections() -> Result<(), anyhow::Error> {
             detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
             messages,
             tools,
+            session_id: None,
         })
         .send()
         .await?;
@@ -177,6 +178,7 @@ async fn detections() -> Result<(), anyhow::Error> {
             detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
             messages,
             tools: vec![],
+            session_id: None,
         })
         .send()
         .await?;
@@ -188,7 +190,112 @@ async fn detections() -> Result<(), anyhow::Error> {
     assert_eq!(
         response.json::<ChatDetectionResult>().await?,
         ChatDetectionResult {
-            detections: vec![detection]
+            detections: vec![detection],
+            ..Default::default()
+        }
+    );
+
+    Ok(())
+}
+
+/// Asserts detectors receive the accumulated conversation of a session.
+#[test(tokio::test)]
+async fn session_detections() -> Result<(), anyhow::Error> {
+    let detector_name = PII_DETECTOR;
+    let session_id = "session-1";
+    let messages = vec![
+        Message {
+            role: Role::User,
+            content: Some(Content::Text("What is his cellphone?".into())),
+            ..Default::default()
+        },
+        Message {
+            role: Role::Assistant,
+            content: Some(Content::Text("It's +1 (123) 123-4567.".into())),
+            ..Default::default()
+        },
+    ];
+    let detection = DetectionResult {
+        detection_type: "pii".into(),
+        detection: "is_pii".into(),
+        detector_id: Some(detector_name.into()),
+        score: 0.97,
+        evidence: None,
+        metadata: Metadata::new(),
+    };
+
+    // Add detector mocks
+    let mut mocks = MockSet::new();
+    mocks.mock(|when, then| {
+        when.post()
+            .path(CHAT_DETECTOR_ENDPOINT)
+            .json(ChatDetectionRequest {
+                messages: messages[..1].to_vec(),
+                tools: vec![],
+                detector_params: DetectorParams::new(),
+            });
+        then.json(Vec::<DetectionResult>::new());
+    });
+    mocks.mock(|when, then| {
+        when.post()
+            .path(CHAT_DETECTOR_ENDPOINT)
+            .json(ChatDetectionRequest {
+                messages: messages.clone(),
+                tools: vec![],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([&detection]);
+    });
+
+    // Start orchestrator server and its dependencies
+    let mock_detector_server = MockServer::new_http(detector_name).with_mocks(mocks);
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_detector_server])
+        .build()
+        .await?;
+
+    // First turn, only the user message is sent
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_CHAT_DETECTION_ENDPOINT)
+        .json(&ChatDetectionHttpRequest {
+            detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
+            messages: messages[..1].to_vec(),
+            tools: vec![],
+            session_id: Some(session_id.into()),
+        })
+        .send()
+        .await?;
+    debug!("{response:#?}");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<ChatDetectionResult>().await?,
+        ChatDetectionResult {
+            detections: vec![],
+            session_id: Some(session_id.into()),
+            message_index: Some(0),
+        }
+    );
+
+    // Second turn, only the new assistant message is sent
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_CHAT_DETECTION_ENDPOINT)
+        .json(&ChatDetectionHttpRequest {
+            detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
+            messages: messages[1..].to_vec(),
+            tools: vec![],
+            session_id: Some(session_id.into()),
+        })
+        .send()
+        .await?;
+    debug!("{response:#?}");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<ChatDetectionResult>().await?,
+        ChatDetectionResult {
+            detections: vec![detection],
+            session_id: Some(session_id.into()),
+            message_index: Some(1),
         }
     );
 
@@ -244,6 +351,7 @@ async fn client_errors() -> Result<(), anyhow::Error> {
             detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
             messages,
             tools: vec![],
+            session_id: None,
         })
         .send()
         .await?;
@@ -417,6 +525,7 @@ async fn orchestrator_validation_error() -> Result<(), anyhow::Error> {
             )]),
             messages: messages.clone(),
             tools: vec![],
+            session_id: None,
         })
         .send()
         .await?;
@@ -442,6 +551,7 @@ async fn orchestrator_validation_error() -> Result<(), anyhow::Error> {
             detectors: HashMap::from([(NON_EXISTING_DETECTOR.into(), DetectorParams::new())]),
             messages: messages.clone(),
             tools: vec![],
+            session_id: None,
         })
         .send()
         .await?;
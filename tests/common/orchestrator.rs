@@ -48,13 +48,22 @@ pub const ORCHESTRATOR_STREAMING_ENDPOINT: &str =
     "/api/v1/task/server-streaming-classification-with-text-generation";
 pub const ORCHESTRATOR_GENERATION_WITH_DETECTION_ENDPOINT: &str =
     "/api/v2/text/generation-detection";
+pub const ORCHESTRATOR_STREAMING_GENERATION_WITH_DETECTION_ENDPOINT: &str =
+    "/api/v2/text/generation-detection/stream";
 
 pub const ORCHESTRATOR_CONTENT_DETECTION_ENDPOINT: &str = "/api/v2/text/detection/content";
+pub const ORCHESTRATOR_BATCH_CONTENT_DETECTION_ENDPOINT: &str =
+    "/api/v2/text/detection/content/batch";
 pub const ORCHESTRATOR_STREAM_CONTENT_DETECTION_ENDPOINT: &str =
     "/api/v2/text/detection/stream-content";
+pub const ORCHESTRATOR_STREAM_CONTENT_DETECTION_WS_ENDPOINT: &str =
+    "/api/v2/text/detection/stream-content/ws";
 pub const ORCHESTRATOR_DETECTION_ON_GENERATION_ENDPOINT: &str = "/api/v2/text/detection/generated";
 pub const ORCHESTRATOR_CONTEXT_DOCS_DETECTION_ENDPOINT: &str = "/api/v2/text/detection/context";
 pub const ORCHESTRATOR_CHAT_DETECTION_ENDPOINT: &str = "/api/v2/text/detection/chat";
+pub const ORCHESTRATOR_CONTENT_DETECTION_JOBS_ENDPOINT: &str =
+    "/api/v2/jobs/text/detection/content";
+pub const ORCHESTRATOR_JOBS_ENDPOINT: &str = "/api/v2/jobs";
 
 pub const ORCHESTRATOR_CHAT_COMPLETIONS_DETECTION_ENDPOINT: &str =
     "/api/v2/chat/completions-detection";
@@ -123,7 +132,7 @@ impl<'a> TestOrchestratorServerBuilder<'a> {
         ensure_global_rustls_state();
 
         // Load orchestrator config
-        let mut config = OrchestratorConfig::load(self.config_path).await?;
+        let mut config = OrchestratorConfig::load(self.config_path, true).await?;
 
         // Start & configure mock servers
         initialize_generation_server(self.generation_server, &mut config).await?;
@@ -141,6 +150,7 @@ impl<'a> TestOrchestratorServerBuilder<'a> {
 pub struct TestOrchestratorServer {
     base_url: Url,
     health_url: Url,
+    grpc_url: Url,
     client: reqwest::Client,
 }
 
@@ -155,11 +165,23 @@ impl TestOrchestratorServer {
         loop {
             let port = rng.random_range(10000..60000);
             let health_port = rng.random_range(10000..60000);
+            let grpc_port = rng.random_range(10000..60000);
             let orchestrator = Orchestrator::new(config.clone(), false).await?;
             let http_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
             let health_http_addr: SocketAddr =
                 SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), health_port);
-            match server::run(http_addr, health_http_addr, None, None, None, orchestrator).await {
+            let grpc_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), grpc_port);
+            match server::run(
+                http_addr,
+                health_http_addr,
+                Some(grpc_addr),
+                None,
+                None,
+                None,
+                orchestrator,
+            )
+            .await
+            {
                 Ok(_) => {
                     // Give the server time to become ready.
                     tokio::time::sleep(Duration::from_millis(10)).await;
@@ -167,6 +189,7 @@ impl TestOrchestratorServer {
                         base_url: Url::parse(&format!("http://0.0.0.0:{port}")).unwrap(),
                         health_url: Url::parse(&format!("http://0.0.0.0:{health_port}/health"))
                             .unwrap(),
+                        grpc_url: Url::parse(&format!("http://0.0.0.0:{grpc_port}")).unwrap(),
                         client: reqwest::Client::builder().build().unwrap(),
                     });
                 }
@@ -186,6 +209,16 @@ impl TestOrchestratorServer {
         self.base_url.join(path).unwrap()
     }
 
+    pub fn grpc_url(&self) -> &Url {
+        &self.grpc_url
+    }
+
+    pub fn ws_url(&self, path: &str) -> Url {
+        let mut url = self.server_url(path);
+        url.set_scheme("ws").unwrap();
+        url
+    }
+
     pub fn health_url(&self) -> Url {
         self.health_url.clone()
     }
@@ -735,6 +735,8 @@ async fn output_detections() -> Result<(), anyhow::Error> {
             input: vec![],
             output: vec![CompletionOutputDetections {
                 choice_index: 1,
+                path: None,
+                offset: None,
                 results: expected_detections.clone(),
             }],
         }),
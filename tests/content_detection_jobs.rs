@@ -0,0 +1,176 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+
+use std::{collections::HashMap, time::Duration};
+
+use common::{
+    detectors::{DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC, TEXT_CONTENTS_DETECTOR_ENDPOINT},
+    orchestrator::{
+        ORCHESTRATOR_CONFIG_FILE_PATH, ORCHESTRATOR_CONTENT_DETECTION_JOBS_ENDPOINT,
+        ORCHESTRATOR_JOBS_ENDPOINT, TestOrchestratorServer,
+    },
+};
+use fms_guardrails_orchestr8::{
+    clients::detector::{ContentAnalysisRequest, ContentAnalysisResponse},
+    models::{
+        BatchContentDetectionHttpRequest, BatchContentDetectionItem, BatchContentDetectionResult,
+        DetectorParams, JobInfo, JobStatus, Metadata,
+    },
+};
+use hyper::StatusCode;
+use mocktail::prelude::*;
+use test_log::test;
+use tracing::debug;
+
+pub mod common;
+
+/// Asserts a job is submitted, completed and its results fetched.
+#[test(tokio::test)]
+async fn submit_and_fetch_results() -> Result<(), anyhow::Error> {
+    let detector_name = DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC;
+    let detection = ContentAnalysisResponse {
+        start: 3,
+        end: 10,
+        text: "<there>".into(),
+        detection: "has_angle_brackets".into(),
+        detection_type: "angle_brackets".into(),
+        detector_id: Some(detector_name.into()),
+        score: 1.0,
+        evidence: None,
+        metadata: Metadata::new(),
+    };
+
+    let mut mocks = MockSet::new();
+    mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hi <there>".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([vec![detection.clone()]]);
+    });
+    mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["No detections".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([Vec::<ContentAnalysisResponse>::new()]);
+    });
+
+    // Start orchestrator server and its dependencies
+    let mock_detector_server = MockServer::new_http(detector_name).with_mocks(mocks);
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_detector_server])
+        .build()
+        .await?;
+
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_CONTENT_DETECTION_JOBS_ENDPOINT)
+        .json(&BatchContentDetectionHttpRequest {
+            detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
+            items: vec![
+                BatchContentDetectionItem {
+                    id: "a".into(),
+                    content: "Hi <there>".into(),
+                    detectors: None,
+                },
+                BatchContentDetectionItem {
+                    id: "b".into(),
+                    content: "No detections".into(),
+                    detectors: None,
+                },
+            ],
+        })
+        .send()
+        .await?;
+    debug!(?response);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let info = response.json::<JobInfo>().await?;
+    assert_eq!(info.total, 2);
+
+    // Poll until the job completes
+    let job_url = format!("{ORCHESTRATOR_JOBS_ENDPOINT}/{}", info.id);
+    let mut info = info;
+    for _ in 0..50 {
+        info = orchestrator_server
+            .get(&job_url)
+            .send()
+            .await?
+            .json()
+            .await?;
+        if info.status.is_finished() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(info.status, JobStatus::Completed);
+    assert_eq!(info.processed, 2);
+    assert_eq!(info.failed, 0);
+
+    let response = orchestrator_server
+        .get(&format!("{job_url}/results"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = response
+        .text()
+        .await?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<BatchContentDetectionResult>, _>>()?;
+    assert_eq!(
+        results,
+        vec![
+            BatchContentDetectionResult {
+                index: 0,
+                id: Some("a".into()),
+                detections: Some(vec![detection]),
+                error: None,
+            },
+            BatchContentDetectionResult {
+                index: 1,
+                id: Some("b".into()),
+                detections: Some(vec![]),
+                error: None,
+            },
+        ]
+    );
+
+    // Cancelling a finished job leaves it unchanged
+    let response = orchestrator_server
+        .post(&format!("{job_url}/cancel"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<JobInfo>().await?.status,
+        JobStatus::Completed
+    );
+
+    // Unknown jobs are not found
+    let response = orchestrator_server
+        .get(&format!("{ORCHESTRATOR_JOBS_ENDPOINT}/unknown"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
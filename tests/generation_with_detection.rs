@@ -22,9 +22,13 @@ use common::{
         FACT_CHECKING_DETECTOR_SENTENCE, NON_EXISTING_DETECTOR,
     },
     errors::DetectorError,
-    generation::{GENERATION_NLP_MODEL_ID_HEADER_NAME, GENERATION_NLP_UNARY_ENDPOINT},
+    generation::{
+        GENERATION_NLP_MODEL_ID_HEADER_NAME, GENERATION_NLP_STREAMING_ENDPOINT,
+        GENERATION_NLP_UNARY_ENDPOINT,
+    },
     orchestrator::{
         ORCHESTRATOR_CONFIG_FILE_PATH, ORCHESTRATOR_GENERATION_WITH_DETECTION_ENDPOINT,
+        ORCHESTRATOR_STREAMING_GENERATION_WITH_DETECTION_ENDPOINT, SseStream,
         TestOrchestratorServer,
     },
 };
@@ -32,14 +36,17 @@ use fms_guardrails_orchestr8::{
     clients::detector::GenerationDetectionRequest,
     models::{
         DetectionResult, DetectorParams, GenerationWithDetectionHttpRequest,
-        GenerationWithDetectionResult, Metadata,
+        GenerationWithDetectionResult, GenerationWithDetectionStreamResult, Metadata,
     },
     pb::{
-        caikit::runtime::nlp::TextGenerationTaskRequest,
-        caikit_data_model::nlp::GeneratedTextResult,
+        caikit::runtime::nlp::{
+            ServerStreamingTextGenerationTaskRequest, TextGenerationTaskRequest,
+        },
+        caikit_data_model::nlp::{GeneratedTextResult, GeneratedTextStreamResult},
     },
     server,
 };
+use futures::TryStreamExt;
 use http::StatusCode;
 use mocktail::{MockSet, server::MockServer};
 use serde_json::json;
@@ -486,3 +493,115 @@ async fn orchestrator_validation_error() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+/// Asserts generated text is streamed and text generation detections are
+/// returned in a final message.
+#[test(tokio::test)]
+async fn streaming_generation_detections() -> Result<(), anyhow::Error> {
+    let detector_name = ANSWER_RELEVANCE_DETECTOR;
+    let prompt = "In 2014, what was the average height of men who were born in 1996?";
+    let generated_text = "The average height of men who were born in 1996 was 171cm.";
+    let detection = DetectionResult {
+        detection_type: "relevance".into(),
+        detection: "is_relevant".into(),
+        detector_id: Some(detector_name.into()),
+        score: 0.89,
+        evidence: None,
+        metadata: Metadata::new(),
+    };
+
+    // Add generation mock
+    let model_id = "my-super-model-8B";
+
+    let mut generation_mocks = MockSet::new();
+    generation_mocks.mock(|when, then| {
+        when.path(GENERATION_NLP_STREAMING_ENDPOINT)
+            .header(GENERATION_NLP_MODEL_ID_HEADER_NAME, model_id)
+            .pb(ServerStreamingTextGenerationTaskRequest {
+                text: prompt.into(),
+                ..Default::default()
+            });
+        then.pb_stream([
+            GeneratedTextStreamResult {
+                generated_text: "The average height of men".into(),
+                ..Default::default()
+            },
+            GeneratedTextStreamResult {
+                generated_text: " who were born in 1996 was 171cm.".into(),
+                ..Default::default()
+            },
+        ]);
+    });
+
+    // Add detection mock
+    let mut detection_mocks = MockSet::new();
+    detection_mocks.mock(|when, then| {
+        when.post()
+            .path(DETECTION_ON_GENERATION_DETECTOR_ENDPOINT)
+            .json(GenerationDetectionRequest {
+                prompt: prompt.into(),
+                generated_text: generated_text.into(),
+                detector_params: DetectorParams::new(),
+            });
+        then.json([&detection]);
+    });
+
+    // Start orchestrator server and its dependencies
+    let mock_generation_server = MockServer::new_grpc("nlp").with_mocks(generation_mocks);
+    let mock_detector_server = MockServer::new_http(detector_name).with_mocks(detection_mocks);
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .generation_server(&mock_generation_server)
+        .detector_servers([&mock_detector_server])
+        .build()
+        .await?;
+
+    // Make orchestrator call
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_STREAMING_GENERATION_WITH_DETECTION_ENDPOINT)
+        .json(&GenerationWithDetectionHttpRequest {
+            model_id: model_id.into(),
+            prompt: prompt.into(),
+            detectors: HashMap::from([(detector_name.into(), DetectorParams::new())]),
+            text_gen_parameters: None,
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sse_stream: SseStream<GenerationWithDetectionStreamResult> =
+        SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    // assertions
+    assert_eq!(messages.len(), 3);
+    assert_eq!(
+        messages[0],
+        GenerationWithDetectionStreamResult {
+            generated_text: Some("The average height of men".into()),
+            start_index: Some(0),
+            processed_index: Some(25),
+            input_token_count: Some(0),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        messages[1],
+        GenerationWithDetectionStreamResult {
+            generated_text: Some(" who were born in 1996 was 171cm.".into()),
+            start_index: Some(25),
+            processed_index: Some(58),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        messages[2],
+        GenerationWithDetectionStreamResult {
+            generation_detections: Some(vec![detection]),
+            ..Default::default()
+        }
+    );
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+/*
+ Copyright FMS Guardrails Orchestrator Authors
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+     http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+
+*/
+
+use std::collections::HashMap;
+
+use common::{
+    chunker::{CHUNKER_MODEL_ID_HEADER_NAME, CHUNKER_NAME_SENTENCE, CHUNKER_STREAMING_ENDPOINT},
+    detectors::{
+        DETECTOR_NAME_ANGLE_BRACKETS_SENTENCE, DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC,
+        TEXT_CONTENTS_DETECTOR_ENDPOINT,
+    },
+    orchestrator::{ORCHESTRATOR_CONFIG_FILE_PATH, TestOrchestratorServer},
+};
+use fms_guardrails_orchestr8::{
+    clients::detector::{ContentAnalysisRequest, ContentAnalysisResponse},
+    models::{DetectorParams, Metadata},
+    pb::{
+        caikit::runtime::chunkers::BidiStreamingChunkerTokenizationTaskRequest,
+        caikit_data_model::nlp::{ChunkerTokenizationStreamResult, Token},
+        orchestrator::{self as pb, guardrails_orchestrator_client::GuardrailsOrchestratorClient},
+    },
+};
+use futures::{StreamExt, TryStreamExt, stream};
+use mocktail::prelude::*;
+use test_log::test;
+use tracing::debug;
+
+pub mod common;
+
+#[test(tokio::test)]
+async fn detect_text_content() -> Result<(), anyhow::Error> {
+    let detector_name = DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC;
+
+    let mut mocks = MockSet::new();
+    mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["This sentence has <a detection>.".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([[ContentAnalysisResponse {
+            start: 18,
+            end: 31,
+            text: "<a detection>".into(),
+            detection: "has_angle_brackets".into(),
+            detection_type: "angle_brackets".into(),
+            detector_id: Some(detector_name.into()),
+            score: 1.0,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]]);
+    });
+
+    let mock_detector_server = MockServer::new_http(detector_name).with_mocks(mocks);
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_detector_server])
+        .build()
+        .await?;
+    let mut client =
+        GuardrailsOrchestratorClient::connect(orchestrator_server.grpc_url().to_string()).await?;
+
+    // Detections scenario
+    let response = client
+        .detect_text_content(pb::TextContentDetectionRequest {
+            content: "This sentence has <a detection>.".into(),
+            detectors: HashMap::from([(detector_name.into(), Default::default())]),
+        })
+        .await?
+        .into_inner();
+    debug!("{response:#?}");
+    assert_eq!(
+        response,
+        pb::TextContentDetectionResponse {
+            detections: vec![pb::ContentAnalysisResponse {
+                start: 18,
+                end: 31,
+                text: "<a detection>".into(),
+                detection: "has_angle_brackets".into(),
+                detection_type: "angle_brackets".into(),
+                detector_id: Some(detector_name.into()),
+                score: 1.0,
+                evidence: vec![],
+                metadata: None,
+            }],
+        }
+    );
+
+    // Validation error scenario
+    let status = client
+        .detect_text_content(pb::TextContentDetectionRequest {
+            content: "This sentence has <a detection>.".into(),
+            detectors: HashMap::new(),
+        })
+        .await
+        .unwrap_err();
+    debug!("{status:#?}");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(status.message(), "`detectors` is required");
+
+    // Detector not found scenario
+    let status = client
+        .detect_text_content(pb::TextContentDetectionRequest {
+            content: "This sentence has <a detection>.".into(),
+            detectors: HashMap::from([("non_existing_detector".into(), Default::default())]),
+        })
+        .await
+        .unwrap_err();
+    debug!("{status:#?}");
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn stream_detect_text_content() -> Result<(), anyhow::Error> {
+    let chunker_id = CHUNKER_NAME_SENTENCE;
+    let detector_name = DETECTOR_NAME_ANGLE_BRACKETS_SENTENCE;
+
+    let mut chunker_mocks = MockSet::new();
+    chunker_mocks.mock(|when, then| {
+        when.path(CHUNKER_STREAMING_ENDPOINT)
+            .header(CHUNKER_MODEL_ID_HEADER_NAME, chunker_id)
+            .pb_stream(vec![
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "Hi there!".into(),
+                    input_index_stream: 0,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: " How <are> you?".into(),
+                    input_index_stream: 1,
+                },
+            ]);
+        then.pb_stream(vec![
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: 9,
+                    text: "Hi there!".into(),
+                }],
+                token_count: 0,
+                processed_index: 9,
+                start_index: 0,
+                input_start_index: 0,
+                input_end_index: 0,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 9,
+                    end: 24,
+                    text: " How <are> you?".into(),
+                }],
+                token_count: 0,
+                processed_index: 24,
+                start_index: 9,
+                input_start_index: 1,
+                input_end_index: 1,
+            },
+        ]);
+    });
+
+    let mut detection_mocks = MockSet::new();
+    detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hi there!".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([Vec::<ContentAnalysisResponse>::new()]);
+    });
+    detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec![" How <are> you?".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([vec![ContentAnalysisResponse {
+            start: 5,
+            end: 10,
+            text: "<are>".into(),
+            detection: "has_angle_brackets".into(),
+            detection_type: "angle_brackets".into(),
+            detector_id: Some(detector_name.into()),
+            score: 1.0,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]]);
+    });
+
+    let mock_chunker_server = MockServer::new_grpc(chunker_id).with_mocks(chunker_mocks);
+    let mock_detector_server = MockServer::new_http(detector_name).with_mocks(detection_mocks);
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_detector_server])
+        .chunker_servers([&mock_chunker_server])
+        .build()
+        .await?;
+    let mut client =
+        GuardrailsOrchestratorClient::connect(orchestrator_server.grpc_url().to_string()).await?;
+
+    let requests = stream::iter([
+        pb::StreamingContentDetectionRequest {
+            detectors: HashMap::from([(detector_name.into(), Default::default())]),
+            content: "Hi there!".into(),
+        },
+        pb::StreamingContentDetectionRequest {
+            detectors: HashMap::new(),
+            content: " How <are> you?".into(),
+        },
+    ]);
+    let messages = client
+        .stream_detect_text_content(requests)
+        .await?
+        .into_inner()
+        .try_collect::<Vec<_>>()
+        .await?;
+    debug!("{messages:#?}");
+    assert_eq!(
+        messages,
+        vec![
+            pb::StreamingContentDetectionResponse {
+                detections: vec![],
+                processed_index: 9,
+                start_index: 0,
+            },
+            pb::StreamingContentDetectionResponse {
+                detections: vec![pb::ContentAnalysisResponse {
+                    start: 5,
+                    end: 10,
+                    text: "<are>".into(),
+                    detection: "has_angle_brackets".into(),
+                    detection_type: "angle_brackets".into(),
+                    detector_id: Some(detector_name.into()),
+                    score: 1.0,
+                    evidence: vec![],
+                    metadata: None,
+                }],
+                processed_index: 24,
+                start_index: 9,
+            },
+        ]
+    );
+
+    // Missing detectors scenario
+    let requests = stream::iter([pb::StreamingContentDetectionRequest {
+        detectors: HashMap::new(),
+        content: "Hi there!".into(),
+    }]);
+    let mut response_stream = client
+        .stream_detect_text_content(requests)
+        .await?
+        .into_inner();
+    let status = response_stream.next().await.unwrap().unwrap_err();
+    debug!("{status:#?}");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "`detectors` is required for the first message"
+    );
+
+    Ok(())
+}
@@ -908,44 +908,6 @@ async fn orchestrator_validation_error() -> Result<(), anyhow::Error> {
         "failed at invalid output detector scenario"
     );
 
-    // Invalid chunker on output detector scenario
-    let response = orchestrator_server
-        .post(ORCHESTRATOR_STREAMING_ENDPOINT)
-        .json(&GuardrailsHttpRequest {
-            model_id: model_id.into(),
-            inputs: "This request contains a detector with an invalid chunker".into(),
-            guardrail_config: Some(GuardrailsConfig {
-                input: None,
-                output: Some(GuardrailsConfigOutput {
-                    models: HashMap::from([(
-                        DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC.into(),
-                        DetectorParams::new(),
-                    )]),
-                }),
-            }),
-            text_gen_parameters: None,
-        })
-        .send()
-        .await?;
-    debug!("{response:#?}");
-
-    assert_eq!(response.status(), 200);
-    let sse_stream: SseStream<ClassifiedGeneratedTextStreamResult> =
-        SseStream::new(response.bytes_stream());
-    let messages = sse_stream.collect::<Vec<_>>().await;
-    debug!("{messages:#?}");
-    assert_eq!(messages.len(), 1);
-    assert_eq!(
-        messages[0],
-        Err(server::Error {
-            code: http::StatusCode::UNPROCESSABLE_ENTITY,
-            details: format!(
-                "detector `{DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC}` uses chunker `whole_doc_chunker`, which is not supported by this endpoint"
-            )
-        }),
-        "failed on output detector with invalid chunker scenario"
-    );
-
     // Non-existing output detector scenario
     let response = orchestrator_server
         .post(ORCHESTRATOR_STREAMING_ENDPOINT)
@@ -1831,3 +1793,222 @@ async fn output_detector_client_error() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+/// Asserts that detections from output detectors using different chunkers are returned,
+/// with whole doc detections returned in a final message.
+#[test(tokio::test)]
+async fn output_detectors_mixed_chunkers() -> Result<(), anyhow::Error> {
+    let sentence_detector = DETECTOR_NAME_ANGLE_BRACKETS_SENTENCE;
+    let whole_doc_detector = DETECTOR_NAME_ANGLE_BRACKETS_WHOLE_DOC;
+
+    // Add generation mock
+    let model_id = "my-super-model-8B";
+
+    let mut generation_mocks = MockSet::new();
+    generation_mocks.mock(|when, then| {
+        when.path(GENERATION_NLP_STREAMING_ENDPOINT)
+            .header(GENERATION_NLP_MODEL_ID_HEADER_NAME, model_id)
+            .pb(ServerStreamingTextGenerationTaskRequest {
+                text: "Hi there! How are you?".into(),
+                ..Default::default()
+            });
+        then.pb_stream(vec![
+            GeneratedTextStreamResult {
+                generated_text: "I am great!".into(),
+                ..Default::default()
+            },
+            GeneratedTextStreamResult {
+                generated_text: " What about <you>?".into(),
+                ..Default::default()
+            },
+        ]);
+    });
+
+    // Add output chunker mock
+    let chunker_id = CHUNKER_NAME_SENTENCE;
+
+    let mut chunker_mocks = MockSet::new();
+    chunker_mocks.mock(|when, then| {
+        when.path(CHUNKER_STREAMING_ENDPOINT)
+            .header(CHUNKER_MODEL_ID_HEADER_NAME, chunker_id)
+            .pb_stream(vec![
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "I am great!".into(),
+                    input_index_stream: 0,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: " What about <you>?".into(),
+                    input_index_stream: 1,
+                },
+            ]);
+
+        then.pb_stream(vec![
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: 11,
+                    text: "I am great!".into(),
+                }],
+                token_count: 0,
+                processed_index: 11,
+                start_index: 0,
+                input_start_index: 0,
+                input_end_index: 0,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 11,
+                    end: 29,
+                    text: " What about <you>?".into(),
+                }],
+                token_count: 0,
+                processed_index: 29,
+                start_index: 11,
+                input_start_index: 1,
+                input_end_index: 1,
+            },
+        ]);
+    });
+
+    // Add detector mocks
+    let mut sentence_detection_mocks = MockSet::new();
+    sentence_detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["I am great!".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([Vec::<ContentAnalysisResponse>::new()]);
+    });
+    sentence_detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec![" What about <you>?".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([vec![ContentAnalysisResponse {
+            start: 13,
+            end: 16,
+            text: "you".into(),
+            detection: "has_angle_brackets".into(),
+            detection_type: "angle_brackets".into(),
+            detector_id: Some(sentence_detector.into()),
+            score: 1.0,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]]);
+    });
+    let whole_doc_detection = ContentAnalysisResponse {
+        start: 24,
+        end: 27,
+        text: "you".into(),
+        detection: "has_angle_brackets".into(),
+        detection_type: "angle_brackets".into(),
+        detector_id: Some(whole_doc_detector.into()),
+        score: 1.0,
+        evidence: None,
+        metadata: Metadata::new(),
+    };
+    let mut whole_doc_detection_mocks = MockSet::new();
+    whole_doc_detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["I am great! What about <you>?".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([vec![whole_doc_detection.clone()]]);
+    });
+
+    // Start orchestrator server and its dependencies
+    let mock_chunker_server = MockServer::new_grpc(chunker_id).with_mocks(chunker_mocks);
+    let mock_sentence_detector_server =
+        MockServer::new_http(sentence_detector).with_mocks(sentence_detection_mocks);
+    let mock_whole_doc_detector_server =
+        MockServer::new_http(whole_doc_detector).with_mocks(whole_doc_detection_mocks);
+    let generation_server = MockServer::new_grpc("nlp").with_mocks(generation_mocks);
+
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .generation_server(&generation_server)
+        .chunker_servers([&mock_chunker_server])
+        .detector_servers([
+            &mock_sentence_detector_server,
+            &mock_whole_doc_detector_server,
+        ])
+        .build()
+        .await?;
+
+    let response = orchestrator_server
+        .post(ORCHESTRATOR_STREAMING_ENDPOINT)
+        .json(&GuardrailsHttpRequest {
+            model_id: model_id.into(),
+            inputs: "Hi there! How are you?".into(),
+            guardrail_config: Some(GuardrailsConfig {
+                input: None,
+                output: Some(GuardrailsConfigOutput {
+                    models: HashMap::from([
+                        (sentence_detector.into(), DetectorParams::new()),
+                        (whole_doc_detector.into(), DetectorParams::new()),
+                    ]),
+                }),
+            }),
+            text_gen_parameters: None,
+        })
+        .send()
+        .await?;
+    debug!("{response:#?}");
+
+    let sse_stream: SseStream<ClassifiedGeneratedTextStreamResult> =
+        SseStream::new(response.bytes_stream());
+    let messages = sse_stream.try_collect::<Vec<_>>().await?;
+    debug!("{messages:#?}");
+
+    assert_eq!(messages.len(), 3);
+
+    // Sentence detections are not blocked by the whole doc detector
+    assert_eq!(messages[0].generated_text, Some("I am great!".into()));
+    assert_eq!(
+        messages[0].token_classification_results.output,
+        Some(vec![])
+    );
+    assert_eq!(
+        messages[1].generated_text,
+        Some(" What about <you>?".into())
+    );
+    assert_eq!(messages[1].start_index, Some(11));
+    assert_eq!(messages[1].processed_index, Some(29));
+    assert_eq!(
+        messages[1]
+            .token_classification_results
+            .output
+            .as_ref()
+            .map(|detections| detections.len()),
+        Some(1)
+    );
+
+    // Whole doc detections are returned in a final message
+    assert_eq!(
+        messages[2].generated_text,
+        Some("I am great! What about <you>?".into())
+    );
+    assert_eq!(messages[2].start_index, Some(0));
+    assert_eq!(messages[2].processed_index, Some(29));
+    assert_eq!(
+        messages[2].token_classification_results.output,
+        Some(vec![TokenClassificationResult {
+            start: 24,
+            end: 27,
+            word: whole_doc_detection.text,
+            entity: whole_doc_detection.detection,
+            entity_group: whole_doc_detection.detection_type,
+            detector_id: whole_doc_detection.detector_id,
+            score: whole_doc_detection.score,
+            token_count: None
+        }])
+    );
+
+    Ok(())
+}
@@ -26,7 +26,8 @@ use common::{
     errors::DetectorError,
     orchestrator::{
         ORCHESTRATOR_CONFIG_FILE_PATH, ORCHESTRATOR_STREAM_CONTENT_DETECTION_ENDPOINT,
-        TestOrchestratorServer, json_lines_stream,
+        ORCHESTRATOR_STREAM_CONTENT_DETECTION_WS_ENDPOINT, TestOrchestratorServer,
+        json_lines_stream,
     },
 };
 use fms_guardrails_orchestr8::{
@@ -41,10 +42,11 @@ use fms_guardrails_orchestr8::{
     },
     server,
 };
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use mocktail::{MockSet, server::MockServer};
 use serde_json::json;
 use test_log::test;
+use tokio_tungstenite::tungstenite::{Message as WsMessage, protocol::frame::coding::CloseCode};
 use tracing::debug;
 
 pub mod common;
@@ -830,3 +832,211 @@ async fn orchestrator_validation_error() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+#[test(tokio::test)]
+async fn websocket_transport() -> Result<(), anyhow::Error> {
+    let chunker_id = CHUNKER_NAME_SENTENCE;
+    let angle_brackets_detector = DETECTOR_NAME_ANGLE_BRACKETS_SENTENCE;
+
+    let mut chunker_mocks = MockSet::new();
+    chunker_mocks.mock(|when, then| {
+        when.path(CHUNKER_STREAMING_ENDPOINT)
+            .header(CHUNKER_MODEL_ID_HEADER_NAME, chunker_id)
+            .pb_stream(vec![
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: "Hi".into(),
+                    input_index_stream: 0,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: " there!".into(),
+                    input_index_stream: 1,
+                },
+                BidiStreamingChunkerTokenizationTaskRequest {
+                    text_stream: " How <are> you?".into(),
+                    input_index_stream: 2,
+                },
+            ]);
+
+        then.pb_stream(vec![
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 0,
+                    end: 9,
+                    text: "Hi there!".into(),
+                }],
+                token_count: 0,
+                processed_index: 9,
+                start_index: 0,
+                input_start_index: 0,
+                input_end_index: 1,
+            },
+            ChunkerTokenizationStreamResult {
+                results: vec![Token {
+                    start: 9,
+                    end: 24,
+                    text: " How <are> you?".into(),
+                }],
+                token_count: 0,
+                processed_index: 24,
+                start_index: 9,
+                input_start_index: 2,
+                input_end_index: 2,
+            },
+        ]);
+    });
+
+    let mut detection_mocks = MockSet::new();
+    detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec!["Hi there!".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([Vec::<ContentAnalysisResponse>::new()]);
+    });
+    detection_mocks.mock(|when, then| {
+        when.post()
+            .path(TEXT_CONTENTS_DETECTOR_ENDPOINT)
+            .json(ContentAnalysisRequest {
+                contents: vec![" How <are> you?".into()],
+                detector_params: DetectorParams::new(),
+            });
+        then.json([vec![ContentAnalysisResponse {
+            start: 5,
+            end: 10,
+            text: "<are>".into(),
+            detection: "has_angle_brackets".into(),
+            detection_type: "angle_brackets".into(),
+            detector_id: Some(angle_brackets_detector.into()),
+            score: 1.0,
+            evidence: None,
+            metadata: Metadata::new(),
+        }]]);
+    });
+
+    let mock_chunker_server = MockServer::new_grpc(chunker_id).with_mocks(chunker_mocks);
+    let mock_angle_brackets_detector_server =
+        MockServer::new_http(angle_brackets_detector).with_mocks(detection_mocks);
+    let orchestrator_server = TestOrchestratorServer::builder()
+        .config_path(ORCHESTRATOR_CONFIG_FILE_PATH)
+        .detector_servers([&mock_angle_brackets_detector_server])
+        .chunker_servers([&mock_chunker_server])
+        .build()
+        .await?;
+
+    // Detections scenario
+    let (mut socket, _) = tokio_tungstenite::connect_async(
+        orchestrator_server
+            .ws_url(ORCHESTRATOR_STREAM_CONTENT_DETECTION_WS_ENDPOINT)
+            .as_str(),
+    )
+    .await?;
+    let requests = [
+        StreamingContentDetectionRequest {
+            detectors: Some(HashMap::from([(
+                angle_brackets_detector.into(),
+                DetectorParams::new(),
+            )])),
+            content: "Hi".into(),
+        },
+        StreamingContentDetectionRequest {
+            detectors: None,
+            content: " there!".into(),
+        },
+        StreamingContentDetectionRequest {
+            detectors: None,
+            content: " How <are> you?".into(),
+        },
+    ];
+    for request in requests {
+        socket
+            .send(WsMessage::text(serde_json::to_string(&request)?))
+            .await?;
+    }
+    socket.close(None).await?;
+
+    let mut messages = Vec::<StreamingContentDetectionResponse>::with_capacity(2);
+    let mut close_frame = None;
+    while let Some(Ok(msg)) = socket.next().await {
+        debug!("recv: {msg:?}");
+        match msg {
+            WsMessage::Text(text) => messages.push(serde_json::from_str(&text)?),
+            WsMessage::Close(frame) => close_frame = frame,
+            _ => {}
+        }
+    }
+    let expected_messages = [
+        StreamingContentDetectionResponse {
+            detections: vec![],
+            start_index: 0,
+            processed_index: 9,
+        },
+        StreamingContentDetectionResponse {
+            detections: vec![ContentAnalysisResponse {
+                start: 5,
+                end: 10,
+                text: "<are>".into(),
+                detection: "has_angle_brackets".into(),
+                detection_type: "angle_brackets".into(),
+                detector_id: Some(angle_brackets_detector.into()),
+                score: 1.0,
+                evidence: None,
+                metadata: Metadata::new(),
+            }],
+            start_index: 9,
+            processed_index: 24,
+        },
+    ];
+    assert_eq!(messages, expected_messages, "failed on detections scenario");
+    assert_eq!(
+        close_frame.map(|frame| frame.code),
+        Some(CloseCode::Normal),
+        "failed on detections scenario"
+    );
+
+    // Invalid request scenario
+    let (mut socket, _) = tokio_tungstenite::connect_async(
+        orchestrator_server
+            .ws_url(ORCHESTRATOR_STREAM_CONTENT_DETECTION_WS_ENDPOINT)
+            .as_str(),
+    )
+    .await?;
+    socket
+        .send(WsMessage::text(serde_json::to_string(
+            &StreamingContentDetectionRequest {
+                detectors: Some(HashMap::from([(
+                    angle_brackets_detector.into(),
+                    DetectorParams::new(),
+                )])),
+                content: "".into(),
+            },
+        )?))
+        .await?;
+
+    let mut messages = Vec::<server::Error>::with_capacity(1);
+    let mut close_frame = None;
+    while let Some(Ok(msg)) = socket.next().await {
+        debug!("recv: {msg:?}");
+        match msg {
+            WsMessage::Text(text) => messages.push(serde_json::from_str(&text)?),
+            WsMessage::Close(frame) => close_frame = frame,
+            _ => {}
+        }
+    }
+    assert_eq!(
+        messages,
+        [server::Error {
+            code: http::StatusCode::UNPROCESSABLE_ENTITY,
+            details: "`content` cannot be empty".into(),
+        }],
+        "failed on invalid request scenario"
+    );
+    assert_eq!(
+        close_frame.map(|frame| frame.code),
+        Some(CloseCode::Policy),
+        "failed on invalid request scenario"
+    );
+
+    Ok(())
+}